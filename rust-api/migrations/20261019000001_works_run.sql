@@ -0,0 +1,4 @@
+-- A run is published at most once; works.meta.run_id names the source run.
+CREATE UNIQUE INDEX IF NOT EXISTS works_run_uniq
+  ON works ((meta->>'run_id'))
+  WHERE meta ? 'run_id';
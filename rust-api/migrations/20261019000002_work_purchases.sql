@@ -0,0 +1,19 @@
+-- Purchases of listed works; a row entitles the buyer to the work's assets. Bought works
+-- are unlisted rather than deleted.
+CREATE TABLE IF NOT EXISTS work_purchases (
+  id            UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  created_at    TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+  work_id       UUID NOT NULL REFERENCES works(id) ON DELETE RESTRICT,
+  buyer_id      UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  seller_id     UUID REFERENCES users(id) ON DELETE SET NULL,
+
+  price_cents   BIGINT NOT NULL,
+  fee_cents     BIGINT NOT NULL DEFAULT 0,
+  currency      TEXT NOT NULL DEFAULT 'USD',
+  meta          JSONB NOT NULL DEFAULT '{}'::jsonb
+);
+CREATE UNIQUE INDEX IF NOT EXISTS work_purchases_buyer_work_uniq ON work_purchases (buyer_id, work_id);
+CREATE INDEX IF NOT EXISTS work_purchases_work_idx ON work_purchases (work_id);
+
+CREATE INDEX IF NOT EXISTS works_listed_time_idx ON works (created_at DESC) WHERE is_listed;
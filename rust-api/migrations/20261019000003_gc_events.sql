@@ -0,0 +1,14 @@
+-- What the run-directory GC removed, and why.
+CREATE TABLE IF NOT EXISTS gc_events (
+  id            UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  created_at    TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+  run_id        TEXT NOT NULL,
+  user_id       UUID REFERENCES users(id) ON DELETE SET NULL,
+  -- prune_intermediates | expired | over_cap
+  action        TEXT NOT NULL,
+  bytes         BIGINT NOT NULL DEFAULT 0,
+  meta          JSONB NOT NULL DEFAULT '{}'::jsonb
+);
+CREATE INDEX IF NOT EXISTS gc_events_time_idx ON gc_events (created_at DESC);
+CREATE INDEX IF NOT EXISTS gc_events_run_idx ON gc_events (run_id);
@@ -0,0 +1,31 @@
+-- Durable run queue shared by every API and worker process. Workers claim jobs with
+-- FOR UPDATE SKIP LOCKED and hold them under a lease they keep extending; a job whose
+-- lease runs out is handed to another worker.
+CREATE TABLE IF NOT EXISTS run_jobs (
+  id                UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  created_at        TIMESTAMPTZ NOT NULL DEFAULT now(),
+  updated_at        TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+  -- run (stages of a cssapi run) | pipeline (compiled commands for run_worker)
+  kind              TEXT NOT NULL,
+  run_id            TEXT NOT NULL,
+  payload           JSONB NOT NULL DEFAULT '{}'::jsonb,
+
+  -- queued | running | succeeded | failed
+  status            TEXT NOT NULL DEFAULT 'queued',
+  attempts          INT NOT NULL DEFAULT 0,
+  max_attempts      INT NOT NULL DEFAULT 3,
+  run_after         TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+  locked_by         TEXT,
+  lease_expires_at  TIMESTAMPTZ,
+  heartbeat_at      TIMESTAMPTZ,
+  last_error        TEXT
+);
+-- At most one live job per run.
+CREATE UNIQUE INDEX IF NOT EXISTS run_jobs_live_run_uniq
+  ON run_jobs (run_id) WHERE status IN ('queued', 'running');
+CREATE INDEX IF NOT EXISTS run_jobs_ready_idx
+  ON run_jobs (run_after, created_at) WHERE status = 'queued';
+CREATE INDEX IF NOT EXISTS run_jobs_lease_idx
+  ON run_jobs (lease_expires_at) WHERE status = 'running';
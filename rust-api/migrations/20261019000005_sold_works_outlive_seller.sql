@@ -0,0 +1,20 @@
+-- Deleting a seller keeps the works others bought: they lose their owner and listing but
+-- stay available to their buyers. Unsold works are still deleted with the seller.
+ALTER TABLE works ALTER COLUMN user_id DROP NOT NULL;
+ALTER TABLE works DROP CONSTRAINT IF EXISTS works_user_id_fkey;
+ALTER TABLE works ADD CONSTRAINT works_user_id_fkey
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE SET NULL;
+
+CREATE OR REPLACE FUNCTION works_release_on_seller_delete() RETURNS trigger AS $$
+BEGIN
+  DELETE FROM works w
+   WHERE w.user_id = OLD.id
+     AND NOT EXISTS (SELECT 1 FROM work_purchases p WHERE p.work_id = w.id);
+  UPDATE works SET is_listed = false, updated_at = now() WHERE user_id = OLD.id;
+  RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS users_release_works ON users;
+CREATE TRIGGER users_release_works BEFORE DELETE ON users
+  FOR EACH ROW EXECUTE FUNCTION works_release_on_seller_delete();
@@ -0,0 +1,222 @@
+use crate::auth::AuthSession;
+use crate::retention::{plan, protected_runs, run_gc, scan_runs, RunUsage};
+use crate::routes::AppState;
+use axum::{
+    extract::{Json, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+pub const DISK_USAGE_SCHEMA: &str = "css.admin.disk_usage.v1";
+pub const GC_SCHEMA: &str = "css.admin.gc.v1";
+const DEFAULT_RUNS_LIMIT: usize = 100;
+const MAX_RUNS_LIMIT: usize = 1000;
+const RECENT_GC_EVENTS: i64 = 50;
+
+fn error(status: StatusCode, code: &str, message: impl Into<String>) -> Response {
+    (
+        status,
+        Json(json!({
+            "schema":"css.error.v1",
+            "code": code,
+            "message": message.into()
+        })),
+    )
+        .into_response()
+}
+
+/// Only users with `role = 'admin'`.
+async fn require_admin(state: &AppState, user_id: Option<Uuid>) -> Result<Uuid, Response> {
+    let Some(user_id) = user_id else {
+        return Err(error(
+            StatusCode::UNAUTHORIZED,
+            "AUTH_REQUIRED",
+            "sign in as an admin",
+        ));
+    };
+    let role = sqlx::query_scalar::<_, String>("SELECT role FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(&state.pool)
+        .await
+        .map_err(|e| {
+            error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "ADMIN_DB_FAILED",
+                e.to_string(),
+            )
+        })?;
+    if role.as_deref() != Some("admin") {
+        return Err(error(
+            StatusCode::FORBIDDEN,
+            "ADMIN_REQUIRED",
+            "admins only",
+        ));
+    }
+    Ok(user_id)
+}
+
+async fn scan(state: &AppState) -> Result<Vec<RunUsage>, Response> {
+    let root = state.config.runs_dir.clone();
+    tokio::task::spawn_blocking(move || match scan_runs(&root) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        r => r,
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|r| r.map_err(|e| e.to_string()))
+    .map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, "DISK_SCAN_FAILED", e))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DiskUsageQuery {
+    /// Largest runs to list; totals always cover every run.
+    pub limit: Option<usize>,
+}
+
+/// Bytes under `runs_dir` per run, per user and per tier, what the next GC pass would
+/// remove, and what recent passes did.
+pub async fn disk_usage(
+    State(state): State<AppState>,
+    AuthSession { user_id }: AuthSession,
+    Query(q): Query<DiskUsageQuery>,
+) -> Response {
+    if let Err(resp) = require_admin(&state, user_id).await {
+        return resp;
+    }
+    let runs = match scan(&state).await {
+        Ok(r) => r,
+        Err(resp) => return resp,
+    };
+    let policy = &state.config.retention;
+    let loaded = async {
+        let protected = protected_runs(&state.pool).await?;
+        let recent = sqlx::query_as::<_, (String, Option<Uuid>, String, i64, Value, chrono::DateTime<chrono::Utc>)>(
+            "SELECT run_id, user_id, action, bytes, meta, created_at FROM gc_events ORDER BY created_at DESC LIMIT $1",
+        )
+        .bind(RECENT_GC_EVENTS)
+        .fetch_all(&state.pool)
+        .await?;
+        Ok::<_, sqlx::Error>((protected, recent))
+    }
+    .await;
+    let (protected, recent) = match loaded {
+        Ok(x) => x,
+        Err(e) => {
+            return error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "ADMIN_DB_FAILED",
+                e.to_string(),
+            )
+        }
+    };
+    let pending = plan(policy, &runs, &protected, chrono::Utc::now());
+
+    let mut users: BTreeMap<Option<Uuid>, (usize, u64, &RunUsage)> = BTreeMap::new();
+    let mut tiers: BTreeMap<&str, (usize, u64)> = BTreeMap::new();
+    for r in &runs {
+        let u = users.entry(r.user_id).or_insert((0, 0, r));
+        u.0 += 1;
+        u.1 += r.bytes;
+        if r.updated_at > u.2.updated_at {
+            u.2 = r;
+        }
+        let t = tiers.entry(r.tier.as_str()).or_default();
+        t.0 += 1;
+        t.1 += r.bytes;
+    }
+    let mut users: Vec<Value> = users
+        .into_iter()
+        .map(|(user_id, (n, bytes, latest))| {
+            json!({
+                "user_id": user_id,
+                "tier": latest.tier,
+                "runs": n,
+                "bytes": bytes,
+                "cap_bytes": policy.cap_for(&latest.tier),
+            })
+        })
+        .collect();
+    users.sort_by_key(|u| std::cmp::Reverse(u["bytes"].as_u64()));
+
+    let limit = q
+        .limit
+        .unwrap_or(DEFAULT_RUNS_LIMIT)
+        .clamp(1, MAX_RUNS_LIMIT);
+    let items: Vec<Value> = runs
+        .iter()
+        .take(limit)
+        .map(|r| {
+            let mut v = json!(r);
+            v["protected"] = json!(protected.contains(&r.run_id));
+            v
+        })
+        .collect();
+    (
+        StatusCode::OK,
+        Json(json!({
+            "schema": DISK_USAGE_SCHEMA,
+            "runs_dir": state.config.runs_dir.display().to_string(),
+            "policy": policy.to_json(),
+            "total_bytes": runs.iter().map(|r| r.bytes).sum::<u64>(),
+            "intermediate_bytes": runs.iter().map(|r| r.intermediate_bytes).sum::<u64>(),
+            "run_count": runs.len(),
+            "runs": items,
+            "users": users,
+            "tiers": tiers
+                .into_iter()
+                .map(|(tier, (n, bytes))| json!({ "tier": tier, "runs": n, "bytes": bytes }))
+                .collect::<Vec<_>>(),
+            "pending_gc": pending,
+            "recent_gc": recent
+                .into_iter()
+                .map(|(run_id, user_id, action, bytes, meta, at)| json!({
+                    "run_id": run_id,
+                    "user_id": user_id,
+                    "action": action,
+                    "bytes": bytes,
+                    "meta": meta,
+                    "created_at": at.to_rfc3339(),
+                }))
+                .collect::<Vec<_>>(),
+        })),
+    )
+        .into_response()
+}
+
+/// Runs a GC pass now instead of waiting for the next tick.
+pub async fn gc_now(
+    State(state): State<AppState>,
+    AuthSession { user_id }: AuthSession,
+) -> Response {
+    if let Err(resp) = require_admin(&state, user_id).await {
+        return resp;
+    }
+    match run_gc(&state.pool, &state.config.runs_dir, &state.config.retention).await {
+        Ok(actions) => (
+            StatusCode::OK,
+            Json(json!({
+                "schema": GC_SCHEMA,
+                "freed_bytes": actions.iter().map(|a| a.bytes).sum::<u64>(),
+                "actions": actions,
+            })),
+        )
+            .into_response(),
+        Err(e) => error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "GC_FAILED",
+            e.to_string(),
+        ),
+    }
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/api/admin/disk-usage", get(disk_usage))
+        .route("/api/admin/gc", post(gc_now))
+}
@@ -0,0 +1,74 @@
+use super::{check_key, ArtifactStore, StorageLocation, StoreError};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Objects as files under `root`. Rooted at the runs directory (the default) it stores
+/// nothing twice: every key already names the file it would copy.
+#[derive(Debug, Clone)]
+pub struct LocalStore {
+    root: PathBuf,
+}
+
+impl LocalStore {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn path_for(&self, key: &str) -> Result<PathBuf, StoreError> {
+        check_key(key)?;
+        Ok(self.root.join(key))
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[axum::async_trait]
+impl ArtifactStore for LocalStore {
+    fn location(&self, run_id: &str) -> StorageLocation {
+        StorageLocation {
+            backend: "local".to_string(),
+            root: self.root.display().to_string(),
+            bucket: None,
+            prefix: format!("{run_id}/"),
+        }
+    }
+
+    async fn put_file(&self, key: &str, path: &Path, _mime: &str) -> Result<(), StoreError> {
+        let dst = self.path_for(key)?;
+        if same_file(path, &dst) {
+            return Ok(());
+        }
+        let io = |e| StoreError::Io(dst.display().to_string(), e);
+        if let Some(dir) = dst.parent() {
+            tokio::fs::create_dir_all(dir).await.map_err(io)?;
+        }
+        // Copy then rename, so readers never see a partial object.
+        let mut tmp = dst.clone().into_os_string();
+        tmp.push(".part");
+        tokio::fs::copy(path, &tmp).await.map_err(io)?;
+        tokio::fs::rename(&tmp, &dst).await.map_err(io)?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        let path = self.path_for(key)?;
+        match tokio::fs::read(&path).await {
+            Ok(b) => Ok(Some(b)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(StoreError::Io(path.display().to_string(), e)),
+        }
+    }
+
+    fn presign_get(&self, _key: &str, _ttl: Duration) -> Option<String> {
+        None
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        self.path_for(key).ok()
+    }
+}
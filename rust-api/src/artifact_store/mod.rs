@@ -0,0 +1,161 @@
+pub mod local;
+pub mod s3;
+
+use crate::artifacts::ArtifactRegistry;
+use crate::http_client::HttpError;
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub use local::LocalStore;
+pub use s3::{S3Config, S3Store};
+
+/// Object key of a run's own state file.
+pub const RUN_JSON: &str = "run.json";
+
+#[derive(thiserror::Error, Debug)]
+pub enum StoreError {
+    #[error("invalid object key: {0}")]
+    InvalidKey(String),
+    #[error("artifact store io error on {0}: {1}")]
+    Io(String, #[source] std::io::Error),
+    #[error("artifact store request failed: {0}")]
+    Http(#[from] HttpError),
+    #[error("artifact store returned {status} for {key}: {body}")]
+    Status {
+        key: String,
+        status: u16,
+        body: String,
+    },
+}
+
+/// Where a run's artifacts were uploaded; recorded in run.json so another API VM can find
+/// them after this one is gone.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageLocation {
+    /// `local` or `s3`.
+    pub backend: String,
+    /// Root directory for `local`, endpoint URL for `s3`.
+    pub root: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bucket: Option<String>,
+    /// Prefix of every object of the run, ending in `/`.
+    pub prefix: String,
+}
+
+/// Durable home for run artifacts. Keys are `<run_id>/<path inside the run>`; backends add
+/// their own root or prefix.
+#[axum::async_trait]
+pub trait ArtifactStore: Send + Sync + std::fmt::Debug {
+    fn location(&self, run_id: &str) -> StorageLocation;
+    async fn put_file(&self, key: &str, path: &Path, mime: &str) -> Result<(), StoreError>;
+    /// `Ok(None)` when the object does not exist.
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StoreError>;
+    /// URL a client can fetch without going through the API, when the backend has one.
+    fn presign_get(&self, key: &str, ttl: Duration) -> Option<String>;
+    /// File behind `key` for backends that keep objects on this machine.
+    fn local_path(&self, key: &str) -> Option<PathBuf>;
+}
+
+/// Backend selection, read from `ARTIFACT_STORE` (`local`, the default, or `s3`).
+#[derive(Debug, Clone)]
+pub enum StoreConfig {
+    Local { root: PathBuf },
+    S3(S3Config),
+}
+
+impl StoreConfig {
+    pub fn from_env(runs_dir: &Path) -> Result<Self, String> {
+        match std::env::var("ARTIFACT_STORE").unwrap_or_default().as_str() {
+            "" | "local" => Ok(Self::Local {
+                root: std::env::var("ARTIFACT_STORE_DIR")
+                    .ok()
+                    .filter(|v| !v.is_empty())
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| runs_dir.to_path_buf()),
+            }),
+            "s3" => S3Config::from_env().map(Self::S3),
+            other => Err(format!("ARTIFACT_STORE={other} is not local or s3")),
+        }
+    }
+
+    pub fn open(&self) -> Arc<dyn ArtifactStore> {
+        match self {
+            Self::Local { root } => Arc::new(LocalStore::new(root.clone())),
+            Self::S3(cfg) => Arc::new(S3Store::new(cfg.clone())),
+        }
+    }
+}
+
+/// `<run_id>/<rel>` for paths inside the run; `None` for absolute or escaping paths.
+pub fn object_key(run_id: &str, rel: &Path) -> Option<String> {
+    let mut parts = vec![run_id.to_string()];
+    for c in rel.components() {
+        match c {
+            Component::Normal(p) => parts.push(p.to_str()?.to_string()),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (parts.len() > 1).then(|| parts.join("/"))
+}
+
+pub(crate) fn check_key(key: &str) -> Result<(), StoreError> {
+    let ok = !key.is_empty()
+        && !key.starts_with('/')
+        && key
+            .split('/')
+            .all(|p| !p.is_empty() && p != "." && p != "..");
+    if ok {
+        Ok(())
+    } else {
+        Err(StoreError::InvalidKey(key.to_string()))
+    }
+}
+
+/// Uploads every file artifact not yet in the store (or changed since) and sets its
+/// `object_key`. Files are resolved against `base`, the run's workdir. Returns the number
+/// uploaded.
+pub async fn upload_pending(
+    store: &dyn ArtifactStore,
+    run_id: &str,
+    artifacts: &mut ArtifactRegistry,
+    base: &Path,
+) -> Result<usize, StoreError> {
+    let mut uploaded = 0;
+    for a in artifacts.iter_mut() {
+        if !a.is_file() || a.object_key.is_some() {
+            continue;
+        }
+        let Some(rel) = a.path.clone() else {
+            continue;
+        };
+        let Some(key) = object_key(run_id, &rel) else {
+            continue;
+        };
+        let path = base.join(&rel);
+        if !path.is_file() {
+            continue;
+        }
+        let mime = a
+            .mime
+            .clone()
+            .unwrap_or_else(|| crate::artifacts::kind_and_mime(&path).1.to_string());
+        store.put_file(&key, &path, &mime).await?;
+        a.object_key = Some(key);
+        uploaded += 1;
+    }
+    Ok(uploaded)
+}
+
+/// The run's state file, from the store when this machine does not have it.
+pub async fn fetch_run_json(
+    store: &dyn ArtifactStore,
+    run_id: &str,
+) -> Result<Option<serde_json::Value>, StoreError> {
+    let Some(bytes) = store.get(&format!("{run_id}/{RUN_JSON}")).await? else {
+        return Ok(None);
+    };
+    Ok(serde_json::from_slice(&bytes).ok())
+}
@@ -0,0 +1,426 @@
+use crate::render_manifest::sha256_file;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::path::{Component, Path, PathBuf};
+
+pub const ARTIFACTS_SCHEMA: &str = "css.run.artifacts.v1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArtifactKind {
+    Audio,
+    Video,
+    Image,
+    Subtitles,
+    /// HLS playlists and DASH manifests.
+    Playlist,
+    Archive,
+    Json,
+    Html,
+    Text,
+    Directory,
+    /// A file of a type not listed above.
+    File,
+    /// Counts, metrics, backend names: no file behind it.
+    Value,
+}
+
+impl ArtifactKind {
+    pub fn parse(s: &str) -> Option<Self> {
+        serde_json::from_value(Value::String(s.trim().to_ascii_lowercase())).ok()
+    }
+}
+
+/// Kind and MIME type from a file extension; unknown extensions are plain files.
+pub fn kind_and_mime(path: &Path) -> (ArtifactKind, &'static str) {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "wav" => (ArtifactKind::Audio, "audio/wav"),
+        "mp3" => (ArtifactKind::Audio, "audio/mpeg"),
+        "flac" => (ArtifactKind::Audio, "audio/flac"),
+        "m4a" | "aac" => (ArtifactKind::Audio, "audio/mp4"),
+        "mp4" => (ArtifactKind::Video, "video/mp4"),
+        "webm" => (ArtifactKind::Video, "video/webm"),
+        "mov" => (ArtifactKind::Video, "video/quicktime"),
+        "ts" => (ArtifactKind::Video, "video/mp2t"),
+        "m4s" => (ArtifactKind::Video, "video/iso.segment"),
+        "jpg" | "jpeg" => (ArtifactKind::Image, "image/jpeg"),
+        "png" => (ArtifactKind::Image, "image/png"),
+        "webp" => (ArtifactKind::Image, "image/webp"),
+        "gif" => (ArtifactKind::Image, "image/gif"),
+        "svg" => (ArtifactKind::Image, "image/svg+xml"),
+        "ass" | "ssa" => (ArtifactKind::Subtitles, "text/x-ssa"),
+        "srt" => (ArtifactKind::Subtitles, "application/x-subrip"),
+        "vtt" => (ArtifactKind::Subtitles, "text/vtt"),
+        "m3u8" => (ArtifactKind::Playlist, "application/vnd.apple.mpegurl"),
+        "mpd" => (ArtifactKind::Playlist, "application/dash+xml"),
+        "zip" => (ArtifactKind::Archive, "application/zip"),
+        "json" => (ArtifactKind::Json, "application/json"),
+        "html" | "htm" => (ArtifactKind::Html, "text/html"),
+        "txt" | "log" => (ArtifactKind::Text, "text/plain"),
+        _ => (ArtifactKind::File, "application/octet-stream"),
+    }
+}
+
+/// One thing a run produced: a file (with size and hash once it exists) or a plain value.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Artifact {
+    /// `music.wav`, `video.storyboard`; stage outputs use their path (`build/vocals.wav`).
+    pub key: String,
+    pub kind: ArtifactKind,
+    /// Relative to the run's workdir when the file lives inside it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub produced_by_stage: Option<String>,
+    pub created_at: String,
+    /// The value itself for `kind: value`; extra details (subtitle tracks, ...) for files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+    /// Key in the run's artifact store once uploaded; cleared when the file changes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object_key: Option<String>,
+}
+
+impl Artifact {
+    pub fn is_file(&self) -> bool {
+        self.path.is_some() && !matches!(self.kind, ArtifactKind::Directory | ArtifactKind::Value)
+    }
+}
+
+/// `./build/x.wav` and `build/x.wav` are the same artifact.
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .collect()
+}
+
+/// Paths inside `base` become relative to it; anything else is kept as given.
+fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    if path.is_absolute() {
+        let canon = |p: &Path| std::fs::canonicalize(p).unwrap_or_else(|_| p.to_path_buf());
+        if let Ok(rel) = canon(path).strip_prefix(canon(base)) {
+            return rel.to_path_buf();
+        }
+        if let Ok(rel) = path.strip_prefix(base) {
+            return rel.to_path_buf();
+        }
+    }
+    normalize(path)
+}
+
+/// Strings that name files: anything with a separator or a known extension.
+fn looks_like_path(s: &str) -> bool {
+    !s.contains(char::is_whitespace)
+        && (s.contains('/') || kind_and_mime(Path::new(s)).0 != ArtifactKind::File)
+}
+
+/// Every artifact of a run, in the order they were recorded. Serialized as a list; the
+/// nested-object form older runs wrote (`{"video": {"storyboard": "..."}}`) still reads.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(transparent)]
+pub struct ArtifactRegistry {
+    items: Vec<Artifact>,
+}
+
+impl<'de> Deserialize<'de> for ArtifactRegistry {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let v = Value::deserialize(d)?;
+        Ok(Self::from_value(&v))
+    }
+}
+
+impl ArtifactRegistry {
+    /// The `artifacts` field of a run, current or legacy; anything unreadable is empty.
+    pub fn from_value(v: &Value) -> Self {
+        match v {
+            Value::Array(_) => Self {
+                items: serde_json::from_value(v.clone()).unwrap_or_default(),
+            },
+            Value::Object(groups) => {
+                let mut reg = Self::default();
+                for (group, inner) in groups {
+                    match inner {
+                        Value::Object(m) => {
+                            for (name, value) in m {
+                                reg.insert(legacy(format!("{group}.{name}"), value));
+                            }
+                        }
+                        other => reg.insert(legacy(group.clone(), other)),
+                    }
+                }
+                reg
+            }
+            _ => Self::default(),
+        }
+    }
+
+    /// Registry of a `run.json` document.
+    pub fn from_run_json(run: &Value) -> Self {
+        run.get("artifacts")
+            .map(Self::from_value)
+            .unwrap_or_default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Artifact> {
+        self.items.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Artifact> {
+        self.items.iter_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Artifact> {
+        self.items.iter().find(|a| a.key == key)
+    }
+
+    /// By key, or else by path, so `build/final_mv.mp4` finds `video.final_mv`.
+    pub fn find(&self, key_or_path: &str) -> Option<&Artifact> {
+        self.get(key_or_path).or_else(|| {
+            let p = normalize(Path::new(key_or_path));
+            self.items.iter().find(|a| a.path.as_deref() == Some(p.as_path()))
+        })
+    }
+
+    /// Path of a file artifact, relative to the workdir when it lives inside it.
+    pub fn path_of(&self, key: &str) -> Option<&Path> {
+        self.get(key).and_then(|a| a.path.as_deref())
+    }
+
+    pub fn value_of(&self, key: &str) -> Option<&Value> {
+        self.get(key).and_then(|a| a.value.as_ref())
+    }
+
+    /// Replaces the artifact with the same key, keeping its place in the list. A file
+    /// recorded again unchanged keeps its original stage and time.
+    pub fn insert(&mut self, artifact: Artifact) {
+        match self.items.iter_mut().find(|a| a.key == artifact.key) {
+            Some(slot)
+                if slot.sha256.is_some()
+                    && slot.sha256 == artifact.sha256
+                    && slot.path == artifact.path
+                    && slot.value == artifact.value => {}
+            Some(slot) => *slot = artifact,
+            None => self.items.push(artifact),
+        }
+    }
+
+    /// Records what a stage reported under `key`. A string naming a file, or an object
+    /// with a `path`, becomes a file artifact measured under `base`; anything else is kept
+    /// as a value.
+    pub fn record(&mut self, key: &str, value: Value, stage: Option<&str>, base: &Path) {
+        let path = match &value {
+            Value::String(s) if looks_like_path(s) => Some(PathBuf::from(s)),
+            Value::Object(m) => m.get("path").and_then(|p| p.as_str()).map(PathBuf::from),
+            _ => None,
+        };
+        let artifact = match path {
+            Some(p) => {
+                let extra = value.is_object().then_some(value);
+                file_artifact(key, &p, stage, base, extra)
+            }
+            None => Artifact {
+                key: key.to_string(),
+                kind: ArtifactKind::Value,
+                path: None,
+                mime: None,
+                size: None,
+                sha256: None,
+                produced_by_stage: stage.map(|s| s.to_string()),
+                created_at: chrono::Utc::now().to_rfc3339(),
+                value: Some(value),
+                object_key: None,
+            },
+        };
+        // A named artifact supersedes the unnamed stage-output entry for the same file.
+        if let Some(p) = &artifact.path {
+            let as_key = p.display().to_string();
+            self.items
+                .retain(|a| a.key != as_key || a.key == artifact.key);
+        }
+        self.insert(artifact);
+    }
+
+    /// Records a stage's declared outputs that no named artifact already covers, keyed by
+    /// their relative path.
+    pub fn record_outputs(&mut self, stage: &str, outputs: &[PathBuf], base: &Path) {
+        for out in outputs {
+            let rel = relative_to(out, base);
+            let taken = self.items.iter().any(|a| {
+                a.path.as_deref() == Some(rel.as_path()) && a.key != rel.display().to_string()
+            });
+            if !taken {
+                let key = rel.display().to_string();
+                self.insert(file_artifact(&key, out, Some(stage), base, None));
+            }
+        }
+    }
+
+    /// `{key: artifact}`, for consumers that look artifacts up by key.
+    pub fn to_map_value(&self) -> Value {
+        Value::Object(
+            self.items
+                .iter()
+                .map(|a| {
+                    (
+                        a.key.clone(),
+                        serde_json::to_value(a).unwrap_or(Value::Null),
+                    )
+                })
+                .collect(),
+        )
+    }
+}
+
+fn file_artifact(
+    key: &str,
+    path: &Path,
+    stage: Option<&str>,
+    base: &Path,
+    extra: Option<Value>,
+) -> Artifact {
+    let rel = relative_to(path, base);
+    let abs = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        base.join(&rel)
+    };
+    let (kind, mime) = if abs.is_dir() {
+        (ArtifactKind::Directory, None)
+    } else {
+        let (k, m) = kind_and_mime(&rel);
+        (k, Some(m.to_string()))
+    };
+    let hashed = (kind != ArtifactKind::Directory)
+        .then(|| sha256_file(&abs).ok())
+        .flatten();
+    Artifact {
+        key: key.to_string(),
+        kind,
+        path: Some(rel),
+        mime,
+        size: hashed.as_ref().map(|(_, n)| *n),
+        sha256: hashed.map(|(h, _)| h),
+        produced_by_stage: stage.map(|s| s.to_string()),
+        created_at: chrono::Utc::now().to_rfc3339(),
+        value: extra,
+        object_key: None,
+    }
+}
+
+/// Legacy entries carry no stage, size or hash; paths keep whatever form was stored.
+fn legacy(key: String, value: &Value) -> Artifact {
+    let path = match value {
+        Value::String(s) if looks_like_path(s) => Some(normalize(Path::new(s))),
+        Value::Object(m) => m
+            .get("path")
+            .and_then(|p| p.as_str())
+            .map(|s| normalize(Path::new(s))),
+        _ => None,
+    };
+    let (kind, mime) = match &path {
+        Some(p) => {
+            let (k, m) = kind_and_mime(p);
+            (k, Some(m.to_string()))
+        }
+        None => (ArtifactKind::Value, None),
+    };
+    Artifact {
+        key,
+        kind,
+        mime,
+        size: None,
+        sha256: None,
+        produced_by_stage: None,
+        created_at: String::new(),
+        value: (path.is_none() || value.is_object()).then(|| value.clone()),
+        path,
+        object_key: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn records_files_values_and_legacy_runs() {
+        let dir = std::env::temp_dir().join(format!("css_artifacts_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("build")).unwrap();
+        std::fs::write(dir.join("build/music.wav"), b"abc").unwrap();
+        std::fs::write(dir.join("build/lyrics.json"), b"{}").unwrap();
+
+        let mut reg = ArtifactRegistry::default();
+        reg.record_outputs(
+            "music",
+            &[
+                PathBuf::from("./build/music.wav"),
+                dir.join("build/lyrics.json"),
+            ],
+            &dir,
+        );
+        assert_eq!(reg.len(), 2);
+        assert_eq!(reg.get("build/lyrics.json").unwrap().size, Some(2));
+        reg.record("music.wav", json!("build/music.wav"), Some("music"), &dir);
+        reg.record("music.backend", json!("procedural"), Some("music"), &dir);
+        reg.record_outputs("music", &[PathBuf::from("build/music.wav")], &dir);
+
+        reg.record_outputs("vocals", &[PathBuf::from("build/lyrics.json")], &dir);
+        let lyrics = reg.get("build/lyrics.json").unwrap();
+        assert_eq!(lyrics.produced_by_stage.as_deref(), Some("music"));
+        let keys: Vec<&str> = reg.iter().map(|a| a.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec!["build/lyrics.json", "music.wav", "music.backend"]
+        );
+        let wav = reg.get("music.wav").unwrap();
+        assert_eq!(wav.kind, ArtifactKind::Audio);
+        assert_eq!(wav.mime.as_deref(), Some("audio/wav"));
+        assert_eq!(wav.size, Some(3));
+        assert_eq!(
+            wav.sha256.as_deref(),
+            Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+        assert_eq!(reg.get("music.backend").unwrap().kind, ArtifactKind::Value);
+
+        let round: ArtifactRegistry =
+            serde_json::from_value(serde_json::to_value(&reg).unwrap()).unwrap();
+        assert_eq!(round.len(), 3);
+        assert_eq!(
+            round.path_of("music.wav"),
+            Some(Path::new("build/music.wav"))
+        );
+
+        let old = ArtifactRegistry::from_run_json(&json!({"artifacts": {
+            "video": {"storyboard": "./build/video/storyboard.json", "shots_count": 8},
+            "graph": {"dag_json": "/abs/build/dag.json"},
+        }}));
+        assert_eq!(
+            old.path_of("video.storyboard"),
+            Some(Path::new("build/video/storyboard.json"))
+        );
+        assert_eq!(old.value_of("video.shots_count"), Some(&json!(8)));
+        assert_eq!(old.get("graph.dag_json").unwrap().kind, ArtifactKind::Json);
+        assert_eq!(ArtifactKind::parse("Audio"), Some(ArtifactKind::Audio));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
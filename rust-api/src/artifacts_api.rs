@@ -0,0 +1,655 @@
+use crate::artifact_store::{fetch_run_json, ArtifactStore, StorageLocation};
+use crate::artifacts::{kind_and_mime, Artifact, ArtifactRegistry};
+use crate::auth::AuthSession;
+use crate::routes::AppState;
+use crate::run_state_io::is_valid_run_id;
+use axum::{
+    body::Body,
+    extract::{Json, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Router,
+};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::Sha256;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+pub const SHARE_SCHEMA: &str = "css.artifact.share.v1";
+const DEFAULT_SHARE_TTL_S: i64 = 3600;
+const MAX_SHARE_TTL_S: i64 = 7 * 24 * 3600;
+/// Signed responses may be cached up to their expiry, but not longer than this.
+const MAX_SIGNED_CACHE_S: i64 = 24 * 3600;
+/// Lifetime of the presigned store URL a signed-in download is redirected to.
+const STORED_LINK_TTL_S: i64 = 300;
+
+fn error(status: StatusCode, code: &str, message: impl Into<String>) -> Response {
+    (
+        status,
+        Json(json!({
+            "schema":"css.error.v1",
+            "code": code,
+            "message": message.into()
+        })),
+    )
+        .into_response()
+}
+
+fn now_unix() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+/// Percent-encodes everything but unreserved characters and `/`, which keys may contain.
+fn encode_key(key: &str) -> String {
+    let mut out = String::with_capacity(key.len());
+    for b in key.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~' | b'/') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
+
+pub fn artifact_path(run_id: &str, key: &str) -> String {
+    format!("/cssapi/v1/runs/{run_id}/artifacts/{}", encode_key(key))
+}
+
+fn mac(secret: &[u8], run_id: &str, key: &str, expires: i64) -> Hmac<Sha256> {
+    let mut m = Hmac::<Sha256>::new_from_slice(secret).expect("hmac accepts any key length");
+    m.update(format!("{run_id}\n{key}\n{expires}").as_bytes());
+    m
+}
+
+/// Hex HMAC-SHA256 of run id, key and expiry (unix seconds).
+pub fn sign(secret: &[u8], run_id: &str, key: &str, expires: i64) -> String {
+    hex::encode(mac(secret, run_id, key, expires).finalize().into_bytes())
+}
+
+/// Constant-time check of a signature that has not expired at `now`.
+pub fn verify(secret: &[u8], run_id: &str, key: &str, expires: i64, sig: &str, now: i64) -> bool {
+    let Ok(sig) = hex::decode(sig) else {
+        return false;
+    };
+    expires > now && mac(secret, run_id, key, expires).verify_slice(&sig).is_ok()
+}
+
+pub fn signed_path(secret: &[u8], run_id: &str, key: &str, expires: i64) -> String {
+    format!(
+        "{}?expires={expires}&sig={}",
+        artifact_path(run_id, key),
+        sign(secret, run_id, key, expires)
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    Full,
+    /// Inclusive byte offsets.
+    Partial {
+        start: u64,
+        end: u64,
+    },
+    Unsatisfiable,
+}
+
+/// A single `bytes=` range (`a-b`, `a-`, `-n`). Multiple ranges and other units are
+/// answered with the whole file, which RFC 9110 allows.
+pub fn parse_range(header: Option<&str>, size: u64) -> ByteRange {
+    let Some(spec) = header.and_then(|h| h.trim().strip_prefix("bytes=")) else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((a, b)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+    let (a, b) = (a.trim(), b.trim());
+    let range = if a.is_empty() {
+        let Ok(n) = b.parse::<u64>() else {
+            return ByteRange::Full;
+        };
+        (n > 0).then(|| (size.saturating_sub(n), size.saturating_sub(1)))
+    } else {
+        let Ok(start) = a.parse::<u64>() else {
+            return ByteRange::Full;
+        };
+        let end = if b.is_empty() {
+            size.saturating_sub(1)
+        } else {
+            match b.parse::<u64>() {
+                Ok(e) if e >= start => e.min(size.saturating_sub(1)),
+                _ => return ByteRange::Full,
+            }
+        };
+        Some((start, end))
+    };
+    match range {
+        Some((start, end)) if size > 0 && start < size => ByteRange::Partial { start, end },
+        _ => ByteRange::Unsatisfiable,
+    }
+}
+
+fn http_date(t: SystemTime) -> String {
+    chrono::DateTime::<chrono::Utc>::from(t)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+/// The recorded sha256 while the file still has the recorded size, else a weak tag from
+/// size and modification time.
+fn etag_for(artifact: &Artifact, meta: &std::fs::Metadata) -> String {
+    match &artifact.sha256 {
+        Some(sha) if artifact.size == Some(meta.len()) => format!("\"{sha}\""),
+        _ => {
+            let mtime = meta
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            format!("W/\"{:x}-{mtime:x}\"", meta.len())
+        }
+    }
+}
+
+fn etag_matches(list: &str, etag: &str) -> bool {
+    let bare = |t: &str| t.trim().trim_start_matches("W/").to_string();
+    list.split(',')
+        .any(|t| t.trim() == "*" || bare(t) == bare(etag))
+}
+
+fn run_dir(state: &AppState, run_id: &str) -> PathBuf {
+    state.config.runs_dir.join(run_id)
+}
+
+/// Where the bytes of a file artifact are.
+enum Located {
+    File(PathBuf),
+    /// Object key in a store that serves it directly.
+    Stored(String),
+}
+
+/// A run's `run.json`, from the store when this machine does not have it.
+pub(crate) async fn load_run(
+    state: &AppState,
+    store: &dyn ArtifactStore,
+    run_id: &str,
+) -> Result<serde_json::Value, Response> {
+    if !is_valid_run_id(run_id) {
+        return Err(error(
+            StatusCode::BAD_REQUEST,
+            "RUN_INVALID_ID",
+            "bad run id",
+        ));
+    }
+    let local = tokio::fs::read_to_string(run_dir(state, run_id).join("run.json"))
+        .await
+        .ok()
+        .and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok());
+    let run = match local {
+        Some(v) => Some(v),
+        None => fetch_run_json(store, run_id).await.unwrap_or_else(|e| {
+            tracing::warn!("run {run_id} not readable from artifact store: {e}");
+            None
+        }),
+    };
+    run.ok_or_else(|| {
+        error(
+            StatusCode::NOT_FOUND,
+            "RUN_NOT_FOUND",
+            format!("run {run_id} not found"),
+        )
+    })
+}
+
+/// What a caller may read of a run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum RunAccess {
+    /// The run's creator, or a signed link: every artifact.
+    Full,
+    /// Owners and buyers of a work the run backs: the artifacts the work's assets list.
+    Keys(BTreeSet<String>),
+}
+
+impl RunAccess {
+    pub(crate) fn allows(&self, key: &str) -> bool {
+        match self {
+            RunAccess::Full => true,
+            RunAccess::Keys(keys) => keys.contains(key),
+        }
+    }
+}
+
+/// Session access to a run's files: all of them for the run's creator, the work's assets
+/// for the owner or a buyer of a work the run backs. Signed links were only handed out for
+/// artifacts the user could read.
+pub(crate) async fn check_access(
+    state: &AppState,
+    user_id: Uuid,
+    run_id: &str,
+    run: &serde_json::Value,
+) -> Result<RunAccess, Response> {
+    let creator = run
+        .get("user_id")
+        .and_then(|v| v.as_str())
+        .and_then(|s| Uuid::parse_str(s).ok());
+    if creator == Some(user_id) {
+        return Ok(RunAccess::Full);
+    }
+    match crate::billing::entitled_artifact_keys(&state.pool, user_id, run_id).await {
+        Ok(keys) if !keys.is_empty() => Ok(RunAccess::Keys(keys)),
+        Ok(_) => Err(error(
+            StatusCode::FORBIDDEN,
+            "RUN_ACCESS_DENIED",
+            format!("run {run_id} is not yours and backs no work you own or bought"),
+        )),
+        Err(e) => Err(error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "ENTITLEMENT_CHECK_FAILED",
+            e.to_string(),
+        )),
+    }
+}
+
+/// The file behind `key` in the run's registry: the store's copy when the run was
+/// uploaded to the configured store, else the file inside the run directory. Artifacts
+/// outside `access` are refused.
+async fn resolve(
+    state: &AppState,
+    store: &dyn ArtifactStore,
+    run_id: &str,
+    run: &serde_json::Value,
+    key: &str,
+    access: &RunAccess,
+) -> Result<(Artifact, Located), Response> {
+    let dir = run_dir(state, run_id);
+    let registry = ArtifactRegistry::from_run_json(run);
+    let not_found = || {
+        error(
+            StatusCode::NOT_FOUND,
+            "ARTIFACT_NOT_FOUND",
+            format!("no file artifact {key} in run {run_id}"),
+        )
+    };
+    let artifact = registry
+        .find(key)
+        .filter(|a| a.is_file())
+        .cloned()
+        .ok_or_else(not_found)?;
+    if !access.allows(&artifact.key) {
+        return Err(error(
+            StatusCode::FORBIDDEN,
+            "ARTIFACT_ACCESS_DENIED",
+            format!("artifact {} is not part of a work you own or bought", artifact.key),
+        ));
+    }
+
+    let in_store = run
+        .get("storage")
+        .and_then(|v| serde_json::from_value::<StorageLocation>(v.clone()).ok())
+        .is_some_and(|loc| loc == store.location(run_id));
+    if let Some(obj) = artifact.object_key.clone().filter(|_| in_store) {
+        match store.local_path(&obj) {
+            Some(p) if p.is_file() => return Ok((artifact, Located::File(p))),
+            Some(_) => {}
+            None => return Ok((artifact, Located::Stored(obj))),
+        }
+    }
+
+    let rel = artifact.path.clone().ok_or_else(not_found)?;
+    let path = if rel.is_absolute() {
+        rel
+    } else {
+        dir.join(rel)
+    };
+    let (Ok(root), Ok(path)) = (
+        tokio::fs::canonicalize(&dir).await,
+        tokio::fs::canonicalize(&path).await,
+    ) else {
+        return Err(not_found());
+    };
+    if !path.starts_with(&root) {
+        return Err(error(
+            StatusCode::FORBIDDEN,
+            "ARTIFACT_FORBIDDEN",
+            format!("artifact {key} is outside the run directory"),
+        ));
+    }
+    Ok((artifact, Located::File(path)))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DownloadQuery {
+    pub expires: Option<i64>,
+    pub sig: Option<String>,
+    /// Any value sends `Content-Disposition: attachment`.
+    pub download: Option<String>,
+}
+
+/// Streams a file artifact to a signed-in user or to anyone holding a valid signed URL.
+/// Supports single byte ranges, `If-None-Match` and `If-Range`. Artifacts in a remote
+/// store are redirected to a presigned URL instead.
+pub async fn download_artifact(
+    State(state): State<AppState>,
+    Path((run_id, key)): Path<(String, String)>,
+    Query(q): Query<DownloadQuery>,
+    auth: AuthSession,
+    headers: HeaderMap,
+) -> Response {
+    let signed_expires = match (&q.sig, q.expires) {
+        (Some(sig), Some(expires)) => {
+            let ok = state
+                .config
+                .artifact_signing_key
+                .as_deref()
+                .is_some_and(|k| verify(k.as_bytes(), &run_id, &key, expires, sig, now_unix()));
+            if !ok {
+                return error(
+                    StatusCode::FORBIDDEN,
+                    "ARTIFACT_LINK_INVALID",
+                    "signature invalid or expired",
+                );
+            }
+            Some(expires)
+        }
+        (Some(_), None) | (None, Some(_)) => {
+            return error(
+                StatusCode::BAD_REQUEST,
+                "ARTIFACT_LINK_INVALID",
+                "signed links need both expires and sig",
+            );
+        }
+        (None, None) if auth.user_id.is_none() => {
+            return error(
+                StatusCode::UNAUTHORIZED,
+                "AUTH_REQUIRED",
+                "sign in or use a signed link",
+            );
+        }
+        (None, None) => None,
+    };
+
+    let store = state.config.artifact_store.open();
+    let run = match load_run(&state, store.as_ref(), &run_id).await {
+        Ok(r) => r,
+        Err(resp) => return resp,
+    };
+    let access = match (signed_expires, auth.user_id) {
+        (None, Some(user_id)) => match check_access(&state, user_id, &run_id, &run).await {
+            Ok(a) => a,
+            Err(resp) => return resp,
+        },
+        _ => RunAccess::Full,
+    };
+    let (artifact, path) = match resolve(&state, store.as_ref(), &run_id, &run, &key, &access)
+        .await
+    {
+        Ok((a, Located::File(p))) => (a, p),
+        Ok((_, Located::Stored(obj))) => {
+            let ttl = signed_expires.map_or(STORED_LINK_TTL_S, |exp| exp - now_unix());
+            return match store.presign_get(&obj, Duration::from_secs(ttl.max(1) as u64)) {
+                Some(url) => (
+                    [(header::CACHE_CONTROL, "private, no-store")],
+                    Redirect::temporary(&url),
+                )
+                    .into_response(),
+                None => error(
+                    StatusCode::NOT_FOUND,
+                    "ARTIFACT_NOT_FOUND",
+                    format!("artifact {key} has no download url"),
+                ),
+            };
+        }
+        Err(resp) => return resp,
+    };
+    let mut file = match tokio::fs::File::open(&path).await {
+        Ok(f) => f,
+        Err(e) => return error(StatusCode::NOT_FOUND, "ARTIFACT_NOT_FOUND", e.to_string()),
+    };
+    let meta = match file.metadata().await {
+        Ok(m) => m,
+        Err(e) => {
+            return error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "ARTIFACT_READ_FAILED",
+                e.to_string(),
+            )
+        }
+    };
+    let size = meta.len();
+    let etag = etag_for(&artifact, &meta);
+    let cache_control = match signed_expires {
+        Some(exp) => format!(
+            "public, max-age={}",
+            (exp - now_unix()).clamp(0, MAX_SIGNED_CACHE_S)
+        ),
+        None => "private, no-cache".to_string(),
+    };
+    let mime = artifact
+        .mime
+        .clone()
+        .unwrap_or_else(|| kind_and_mime(&path).1.to_string());
+    let file_name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("artifact")
+        .replace('"', "");
+    let disposition = if q.download.is_some() {
+        "attachment"
+    } else {
+        "inline"
+    };
+
+    let mut builder = Response::builder()
+        .header(header::ETAG, &etag)
+        .header(header::CACHE_CONTROL, &cache_control)
+        .header(header::ACCEPT_RANGES, "bytes");
+    if let Ok(t) = meta.modified() {
+        builder = builder.header(header::LAST_MODIFIED, http_date(t));
+    }
+
+    let header_str = |h: header::HeaderName| headers.get(h).and_then(|v| v.to_str().ok());
+    if header_str(header::IF_NONE_MATCH).is_some_and(|l| etag_matches(l, &etag)) {
+        return builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .unwrap_or_else(|_| StatusCode::NOT_MODIFIED.into_response());
+    }
+    // A range only applies to the representation the client already has part of; weak
+    // tags never match.
+    let if_range_ok = header_str(header::IF_RANGE)
+        .map(|t| !etag.starts_with("W/") && t.trim() == etag)
+        .unwrap_or(true);
+    let range = if if_range_ok {
+        parse_range(header_str(header::RANGE), size)
+    } else {
+        ByteRange::Full
+    };
+
+    builder = builder.header(header::CONTENT_TYPE, mime).header(
+        header::CONTENT_DISPOSITION,
+        format!("{disposition}; filename=\"{file_name}\""),
+    );
+    let resp = match range {
+        ByteRange::Full => builder
+            .status(StatusCode::OK)
+            .header(header::CONTENT_LENGTH, size)
+            .body(Body::from_stream(ReaderStream::new(file))),
+        ByteRange::Partial { start, end } => {
+            if let Err(e) = file.seek(std::io::SeekFrom::Start(start)).await {
+                return error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "ARTIFACT_READ_FAILED",
+                    e.to_string(),
+                );
+            }
+            let len = end - start + 1;
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_LENGTH, len)
+                .header(header::CONTENT_RANGE, format!("bytes {start}-{end}/{size}"))
+                .body(Body::from_stream(ReaderStream::new(file.take(len))))
+        }
+        ByteRange::Unsatisfiable => builder
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{size}"))
+            .body(Body::empty()),
+    };
+    resp.unwrap_or_else(|e| {
+        error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "ARTIFACT_READ_FAILED",
+            e.to_string(),
+        )
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ShareRequest {
+    /// Artifact key or path, as for the download endpoint.
+    pub key: String,
+    #[serde(default)]
+    pub ttl_s: Option<i64>,
+}
+
+/// Issues an expiring signed URL for one file artifact.
+pub async fn share_artifact(
+    State(state): State<AppState>,
+    Path(run_id): Path<String>,
+    auth: AuthSession,
+    Json(req): Json<ShareRequest>,
+) -> Response {
+    let Some(user_id) = auth.user_id else {
+        return error(
+            StatusCode::UNAUTHORIZED,
+            "AUTH_REQUIRED",
+            "sign in to share artifacts",
+        );
+    };
+    let Some(secret) = state.config.artifact_signing_key.clone() else {
+        return error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "ARTIFACT_SHARING_DISABLED",
+            "ARTIFACT_SIGNING_KEY is not configured",
+        );
+    };
+    let store = state.config.artifact_store.open();
+    let run = match load_run(&state, store.as_ref(), &run_id).await {
+        Ok(r) => r,
+        Err(resp) => return resp,
+    };
+    let access = match check_access(&state, user_id, &run_id, &run).await {
+        Ok(a) => a,
+        Err(resp) => return resp,
+    };
+    let (artifact, _) = match resolve(&state, store.as_ref(), &run_id, &run, &req.key, &access)
+        .await
+    {
+        Ok(x) => x,
+        Err(resp) => return resp,
+    };
+    let ttl = req
+        .ttl_s
+        .unwrap_or(DEFAULT_SHARE_TTL_S)
+        .clamp(1, MAX_SHARE_TTL_S);
+    let expires = now_unix() + ttl;
+    let expires_at = chrono::DateTime::from_timestamp(expires, 0)
+        .map(|t| t.to_rfc3339())
+        .unwrap_or_default();
+    (
+        StatusCode::CREATED,
+        Json(json!({
+            "schema": SHARE_SCHEMA,
+            "run_id": run_id,
+            "key": artifact.key,
+            "url": signed_path(secret.as_bytes(), &run_id, &artifact.key, expires),
+            "expires_at": expires_at,
+        })),
+    )
+        .into_response()
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/cssapi/v1/runs/:run_id/artifacts/*key",
+            get(download_artifact),
+        )
+        .route("/cssapi/v1/runs/:run_id/share", post(share_artifact))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges() {
+        use ByteRange::*;
+        assert_eq!(parse_range(None, 100), Full);
+        assert_eq!(
+            parse_range(Some("bytes=0-9"), 100),
+            Partial { start: 0, end: 9 }
+        );
+        assert_eq!(
+            parse_range(Some("bytes=90-"), 100),
+            Partial { start: 90, end: 99 }
+        );
+        assert_eq!(
+            parse_range(Some("bytes=-10"), 100),
+            Partial { start: 90, end: 99 }
+        );
+        assert_eq!(
+            parse_range(Some("bytes=-500"), 100),
+            Partial { start: 0, end: 99 }
+        );
+        assert_eq!(
+            parse_range(Some("bytes=50-5000"), 100),
+            Partial { start: 50, end: 99 }
+        );
+        assert_eq!(parse_range(Some("bytes=100-"), 100), Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=-0"), 100), Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=0-"), 0), Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=9-3"), 100), Full);
+        assert_eq!(parse_range(Some("bytes=0-1,5-6"), 100), Full);
+        assert_eq!(parse_range(Some("items=0-1"), 100), Full);
+    }
+
+    #[test]
+    fn signed_links() {
+        let key = b"k3y";
+        let sig = sign(key, "run-1", "video.final_mv", 2_000);
+        assert!(verify(key, "run-1", "video.final_mv", 2_000, &sig, 1_000));
+        assert!(!verify(key, "run-1", "video.final_mv", 2_000, &sig, 2_000));
+        assert!(!verify(key, "run-1", "video.final_mv", 2_001, &sig, 1_000));
+        assert!(!verify(key, "run-2", "video.final_mv", 2_000, &sig, 1_000));
+        assert!(!verify(
+            b"other",
+            "run-1",
+            "video.final_mv",
+            2_000,
+            &sig,
+            1_000
+        ));
+        assert!(!verify(key, "run-1", "video.final_mv", 2_000, "zz", 1_000));
+        assert_eq!(
+            signed_path(key, "run-1", "build/stems/a b.wav", 5),
+            format!(
+                "/cssapi/v1/runs/run-1/artifacts/build/stems/a%20b.wav?expires=5&sig={}",
+                sign(key, "run-1", "build/stems/a b.wav", 5)
+            )
+        );
+        assert!(etag_matches("\"a\", W/\"b\"", "\"b\""));
+        assert!(!etag_matches("\"a\"", "\"b\""));
+        assert!(is_valid_run_id("0b6e-4f_x") && !is_valid_run_id("../x"));
+    }
+}
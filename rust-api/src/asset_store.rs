@@ -0,0 +1,386 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock, Weak},
+};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+pub const ASSET_SCHEMA: &str = "css.asset.v1";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssetMeta {
+    pub schema: String,
+    pub id: String,
+    pub mime: String,
+    pub ext: String,
+    pub size: u64,
+    pub original_name: Option<String>,
+    pub created_at: String,
+    /// Users who uploaded this content; only they may look it up or render it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub owners: Vec<Uuid>,
+}
+
+impl AssetMeta {
+    /// The metadata shown to API callers, without the other owners.
+    pub fn public(mut self) -> Self {
+        self.owners.clear();
+        self
+    }
+}
+
+/// Whose assets a lookup may see.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssetScope {
+    /// Local tools: every asset under the root.
+    Any,
+    /// API requests and runs: only assets this user uploaded; anonymous callers see none.
+    User(Option<Uuid>),
+}
+
+impl AssetScope {
+    pub fn allows(&self, meta: &AssetMeta) -> bool {
+        match self {
+            AssetScope::Any => true,
+            AssetScope::User(Some(user)) => meta.owners.contains(user),
+            AssetScope::User(None) => false,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AssetError {
+    #[error("invalid asset id: {0}")]
+    InvalidId(String),
+    #[error("asset not found: {0}")]
+    NotFound(String),
+    #[error("asset too large: {size} bytes exceeds limit of {limit} bytes")]
+    TooLarge { size: u64, limit: u64 },
+    #[error("unsupported media type")]
+    UnsupportedType,
+    #[error("asset path escapes asset root: {0}")]
+    OutsideRoot(String),
+    #[error("asset io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("asset metadata error: {0}")]
+    Meta(#[from] serde_json::Error),
+}
+
+pub fn default_root() -> PathBuf {
+    std::env::var("ASSETS_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("build/assets"))
+}
+
+/// Asset ids are the lowercase hex sha256 of the content, nothing else is accepted.
+pub fn is_valid_asset_id(id: &str) -> bool {
+    id.len() == 64 && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Sniffs the media type from magic bytes; the client-declared content type is ignored.
+pub fn sniff_mime(bytes: &[u8]) -> Option<(&'static str, &'static str)> {
+    if bytes.starts_with(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]) {
+        return Some(("image/png", "png"));
+    }
+    if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        return Some(("image/jpeg", "jpg"));
+    }
+    if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        return Some(("image/gif", "gif"));
+    }
+    if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        return Some(("image/webp", "webp"));
+    }
+    if bytes.len() >= 12 && &bytes[4..8] == b"ftyp" {
+        // `ftyp` also heads HEIC/AVIF stills, so only known video brands count.
+        return match &bytes[8..12] {
+            b"qt  " => Some(("video/quicktime", "mov")),
+            b"isom" | b"iso2" | b"iso4" | b"iso5" | b"iso6" | b"mp41" | b"mp42" | b"avc1"
+            | b"dash" | b"M4V " => Some(("video/mp4", "mp4")),
+            _ => None,
+        };
+    }
+    if bytes.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        return Some(("video/webm", "webm"));
+    }
+    None
+}
+
+fn shard_dir(root: &Path, id: &str) -> PathBuf {
+    root.join(&id[0..2])
+}
+
+fn meta_path(root: &Path, id: &str) -> PathBuf {
+    shard_dir(root, id).join(format!("{id}.json"))
+}
+
+/// The asset's metadata; assets outside `scope` are reported as not found.
+pub fn load_asset_meta(
+    root: &Path,
+    id: &str,
+    scope: AssetScope,
+) -> Result<AssetMeta, AssetError> {
+    if !is_valid_asset_id(id) {
+        return Err(AssetError::InvalidId(id.to_string()));
+    }
+    let p = meta_path(root, id);
+    let s = fs::read_to_string(&p).map_err(|_| AssetError::NotFound(id.to_string()))?;
+    let meta: AssetMeta = serde_json::from_str(&s)?;
+    if !scope.allows(&meta) {
+        return Err(AssetError::NotFound(id.to_string()));
+    }
+    Ok(meta)
+}
+
+/// Resolves an asset id within `scope` to its content file, guaranteeing the result lies
+/// inside `root`.
+pub fn resolve_asset_path(
+    root: &Path,
+    id: &str,
+    scope: AssetScope,
+) -> Result<PathBuf, AssetError> {
+    let meta = load_asset_meta(root, id, scope)?;
+    if !meta.ext.bytes().all(|b| b.is_ascii_alphanumeric()) {
+        return Err(AssetError::InvalidId(id.to_string()));
+    }
+    let p = shard_dir(root, id).join(format!("{id}.{}", meta.ext));
+    let root_abs = fs::canonicalize(root)?;
+    let abs = fs::canonicalize(&p).map_err(|_| AssetError::NotFound(id.to_string()))?;
+    if !abs.starts_with(&root_abs) {
+        return Err(AssetError::OutsideRoot(abs.display().to_string()));
+    }
+    Ok(abs)
+}
+
+/// Leading bytes of an upload kept for [`sniff_mime`].
+const SNIFF_LEN: usize = 16;
+
+/// An upload streamed to a temp file under the asset root and hashed as it arrives;
+/// [`AssetUpload::finish`] moves it to its content address. The temp file is removed when
+/// the upload is dropped unfinished.
+pub struct AssetUpload {
+    root: PathBuf,
+    tmp: PathBuf,
+    file: tokio::fs::File,
+    hasher: Sha256,
+    head: Vec<u8>,
+    size: u64,
+    max_bytes: u64,
+}
+
+impl AssetUpload {
+    pub async fn create(root: &Path, max_bytes: u64) -> Result<Self, AssetError> {
+        tokio::fs::create_dir_all(root).await?;
+        let tmp = root.join(format!(".upload-{}.tmp", uuid::Uuid::new_v4()));
+        let file = tokio::fs::File::create(&tmp).await?;
+        Ok(Self {
+            root: root.to_path_buf(),
+            tmp,
+            file,
+            hasher: Sha256::new(),
+            head: Vec::with_capacity(SNIFF_LEN),
+            size: 0,
+            max_bytes,
+        })
+    }
+
+    /// Appends `chunk`, failing once the upload grows past the size limit.
+    pub async fn write(&mut self, chunk: &[u8]) -> Result<(), AssetError> {
+        let size = self.size + chunk.len() as u64;
+        if size > self.max_bytes {
+            return Err(AssetError::TooLarge { size, limit: self.max_bytes });
+        }
+        let keep = SNIFF_LEN.saturating_sub(self.head.len()).min(chunk.len());
+        self.head.extend_from_slice(&chunk[..keep]);
+        self.hasher.update(chunk);
+        self.file.write_all(chunk).await?;
+        self.size = size;
+        Ok(())
+    }
+
+    /// Stores the upload under its sha256 with `owner` as an owner. Content stored before
+    /// keeps its metadata and gains `owner`.
+    pub async fn finish(
+        mut self,
+        owner: Uuid,
+        original_name: Option<String>,
+    ) -> Result<AssetMeta, AssetError> {
+        self.file.flush().await?;
+        let (mime, ext) = sniff_mime(&self.head).ok_or(AssetError::UnsupportedType)?;
+        let id = hex::encode(self.hasher.clone().finalize());
+
+        let dir = shard_dir(&self.root, &id);
+        tokio::fs::create_dir_all(&dir).await?;
+        let content_path = dir.join(format!("{id}.{ext}"));
+        if !content_path.exists() {
+            tokio::fs::rename(&self.tmp, &content_path).await?;
+        }
+
+        let lock = meta_lock(&id);
+        let _guard = lock.lock().await;
+        if let Ok(mut existing) = load_asset_meta(&self.root, &id, AssetScope::Any) {
+            if !existing.owners.contains(&owner) {
+                existing.owners.push(owner);
+                write_meta(&self.root, &existing).await?;
+            }
+            return Ok(existing);
+        }
+
+        let meta = AssetMeta {
+            schema: ASSET_SCHEMA.to_string(),
+            id: id.clone(),
+            mime: mime.to_string(),
+            ext: ext.to_string(),
+            size: self.size,
+            original_name: original_name.map(|n| sanitize_name(&n)),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            owners: vec![owner],
+        };
+        write_meta(&self.root, &meta).await?;
+        Ok(meta)
+    }
+}
+
+/// Lock held while an upload reads and rewrites the metadata of `id`, so concurrent uploads
+/// of the same content all end up in `owners`.
+fn meta_lock(id: &str) -> Arc<tokio::sync::Mutex<()>> {
+    static LOCKS: OnceLock<Mutex<HashMap<String, Weak<tokio::sync::Mutex<()>>>>> =
+        OnceLock::new();
+    let mut locks = LOCKS.get_or_init(Default::default).lock().unwrap();
+    locks.retain(|_, l| l.strong_count() > 0);
+    if let Some(lock) = locks.get(id).and_then(Weak::upgrade) {
+        return lock;
+    }
+    let lock = Arc::new(tokio::sync::Mutex::new(()));
+    locks.insert(id.to_string(), Arc::downgrade(&lock));
+    lock
+}
+
+/// Writes the metadata through a temp file so readers never see it half written.
+async fn write_meta(root: &Path, meta: &AssetMeta) -> Result<(), AssetError> {
+    let path = meta_path(root, &meta.id);
+    let tmp = path.with_extension(format!("{}.tmp", Uuid::new_v4()));
+    tokio::fs::write(&tmp, serde_json::to_vec_pretty(meta)?).await?;
+    if let Err(e) = tokio::fs::rename(&tmp, &path).await {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+impl Drop for AssetUpload {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.tmp);
+    }
+}
+
+fn sanitize_name(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or("");
+    base.chars()
+        .filter(|c| !c.is_control())
+        .take(200)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ftyp(brand: &[u8; 4]) -> Vec<u8> {
+        let mut b = vec![0, 0, 0, 0x18];
+        b.extend_from_slice(b"ftyp");
+        b.extend_from_slice(brand);
+        b.extend_from_slice(&[0; 12]);
+        b
+    }
+
+    #[test]
+    fn ftyp_brands_are_told_apart() {
+        assert_eq!(sniff_mime(&ftyp(b"isom")), Some(("video/mp4", "mp4")));
+        assert_eq!(sniff_mime(&ftyp(b"qt  ")), Some(("video/quicktime", "mov")));
+        assert_eq!(sniff_mime(&ftyp(b"heic")), None);
+        assert_eq!(sniff_mime(&ftyp(b"avif")), None);
+        assert_eq!(sniff_mime(&ftyp(b"mif1")), None);
+    }
+
+    #[tokio::test]
+    async fn uploads_are_stored_under_their_sha256() {
+        let root = std::env::temp_dir().join(format!("css_assets_{}", uuid::Uuid::new_v4()));
+        let png = [&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A][..], &[7; 40]].concat();
+
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let mut up = AssetUpload::create(&root, 64).await.unwrap();
+        for chunk in png.chunks(5) {
+            up.write(chunk).await.unwrap();
+        }
+        let meta = up.finish(alice, Some("dir/cover.png".to_string())).await.unwrap();
+        assert_eq!(meta.id, hex::encode(Sha256::digest(&png)));
+        assert_eq!((meta.mime.as_str(), meta.size), ("image/png", 48));
+        assert_eq!(meta.original_name.as_deref(), Some("cover.png"));
+        let path = resolve_asset_path(&root, &meta.id, AssetScope::User(Some(alice))).unwrap();
+        assert_eq!(fs::read(path).unwrap(), png);
+        for scope in [AssetScope::User(Some(bob)), AssetScope::User(None)] {
+            assert!(matches!(
+                resolve_asset_path(&root, &meta.id, scope),
+                Err(AssetError::NotFound(_))
+            ));
+        }
+        assert!(resolve_asset_path(&root, &meta.id, AssetScope::Any).is_ok());
+
+        let mut up = AssetUpload::create(&root, 64).await.unwrap();
+        up.write(&png).await.unwrap();
+        let again = up.finish(bob, None).await.unwrap();
+        assert_eq!(again.owners, [alice, bob]);
+        assert!(load_asset_meta(&root, &meta.id, AssetScope::User(Some(bob))).is_ok());
+
+        let mut up = AssetUpload::create(&root, 64).await.unwrap();
+        up.write(&png).await.unwrap();
+        assert!(matches!(
+            up.write(&png).await,
+            Err(AssetError::TooLarge { size: 96, limit: 64 })
+        ));
+        drop(up);
+        let leftovers = fs::read_dir(&root)
+            .unwrap()
+            .filter(|e| e.as_ref().unwrap().file_name().to_string_lossy().ends_with(".tmp"))
+            .count();
+        assert_eq!(leftovers, 0);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn concurrent_uploads_of_the_same_content_keep_every_owner() {
+        let root = std::env::temp_dir().join(format!("css_assets_{}", uuid::Uuid::new_v4()));
+        let png = [&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A][..], &[9; 40]].concat();
+        let owners: Vec<Uuid> = (0..16).map(|_| Uuid::new_v4()).collect();
+
+        let uploads: Vec<_> = owners
+            .iter()
+            .map(|&owner| {
+                let (root, png) = (root.clone(), png.clone());
+                tokio::spawn(async move {
+                    let mut up = AssetUpload::create(&root, 64).await.unwrap();
+                    up.write(&png).await.unwrap();
+                    up.finish(owner, None).await.unwrap()
+                })
+            })
+            .collect();
+        let mut id = String::new();
+        for upload in uploads {
+            id = upload.await.unwrap().id;
+        }
+
+        let stored = load_asset_meta(&root, &id, AssetScope::Any).unwrap();
+        let mut got = stored.owners.clone();
+        got.sort();
+        let mut want = owners.clone();
+        want.sort();
+        assert_eq!(got, want);
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,122 @@
+use crate::asset_store::{self, AssetError, AssetScope, AssetUpload};
+use crate::auth::AuthSession;
+use crate::routes::AppState;
+use axum::{
+    extract::{DefaultBodyLimit, Json, Multipart, Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{get, post},
+    Router,
+};
+use serde_json::json;
+
+fn asset_error_response(e: AssetError) -> (StatusCode, Json<serde_json::Value>) {
+    let (status, code) = match &e {
+        AssetError::InvalidId(_) => (StatusCode::BAD_REQUEST, "ASSET_INVALID_ID"),
+        AssetError::NotFound(_) => (StatusCode::NOT_FOUND, "ASSET_NOT_FOUND"),
+        AssetError::TooLarge { .. } => (StatusCode::PAYLOAD_TOO_LARGE, "ASSET_TOO_LARGE"),
+        AssetError::UnsupportedType => (StatusCode::UNSUPPORTED_MEDIA_TYPE, "ASSET_UNSUPPORTED_TYPE"),
+        AssetError::OutsideRoot(_) => (StatusCode::FORBIDDEN, "ASSET_FORBIDDEN"),
+        AssetError::Io(_) | AssetError::Meta(_) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, "ASSET_STORE_FAILED")
+        }
+    };
+    (
+        status,
+        Json(json!({
+            "schema":"css.error.v1",
+            "code": code,
+            "message": e.to_string()
+        })),
+    )
+}
+
+fn auth_required(message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(json!({
+            "schema":"css.error.v1",
+            "code":"AUTH_REQUIRED",
+            "message": message
+        })),
+    )
+}
+
+fn bad_upload(message: String) -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({
+            "schema":"css.error.v1",
+            "code":"ASSET_UPLOAD_INVALID",
+            "message": message
+        })),
+    )
+}
+
+/// Streams the `file` field to the asset store, hashing it on the way; nothing is buffered
+/// beyond the current chunk.
+pub async fn upload_asset(
+    State(state): State<AppState>,
+    AuthSession { user_id }: AuthSession,
+    mut multipart: Multipart,
+) -> impl IntoResponse {
+    let Some(user_id) = user_id else {
+        return auth_required("sign in to upload assets");
+    };
+    let mut field = loop {
+        match multipart.next_field().await {
+            Ok(Some(f)) if f.name() == Some("file") => break f,
+            Ok(Some(_)) => continue,
+            Ok(None) => return bad_upload("missing multipart field `file`".to_string()),
+            Err(e) => return bad_upload(e.to_string()),
+        }
+    };
+    let original_name = field.file_name().map(|s| s.to_string());
+
+    let mut upload =
+        match AssetUpload::create(&state.config.assets_dir, state.config.assets_max_bytes).await {
+            Ok(u) => u,
+            Err(e) => return asset_error_response(e),
+        };
+    loop {
+        match field.chunk().await {
+            Ok(Some(chunk)) => {
+                if let Err(e) = upload.write(&chunk).await {
+                    return asset_error_response(e);
+                }
+            }
+            Ok(None) => break,
+            Err(e) => return bad_upload(e.to_string()),
+        }
+    }
+
+    match upload.finish(user_id, original_name).await {
+        Ok(meta) => (StatusCode::CREATED, Json(json!(meta.public()))),
+        Err(e) => asset_error_response(e),
+    }
+}
+
+/// Metadata of an asset the caller uploaded; other users' assets are not found.
+pub async fn get_asset(
+    State(state): State<AppState>,
+    AuthSession { user_id }: AuthSession,
+    Path(asset_id): Path<String>,
+) -> impl IntoResponse {
+    if user_id.is_none() {
+        return auth_required("sign in to read assets");
+    }
+    let scope = AssetScope::User(user_id);
+    match asset_store::load_asset_meta(&state.config.assets_dir, &asset_id, scope) {
+        Ok(meta) => (StatusCode::OK, Json(json!(meta.public()))),
+        Err(e) => asset_error_response(e),
+    }
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/cssapi/v1/assets",
+            post(upload_asset).layer(DefaultBodyLimit::disable()),
+        )
+        .route("/cssapi/v1/assets/:asset_id", get(get_asset))
+}
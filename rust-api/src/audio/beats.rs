@@ -0,0 +1,352 @@
+use crate::audio::wav::{decode_wav_mono, MonoPcm};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+pub const BEATS_SCHEMA: &str = "css.audio.beats.v1";
+
+const HOP: usize = 512;
+const WIN: usize = 1024;
+const MIN_BPM: f64 = 60.0;
+const MAX_BPM: f64 = 200.0;
+const PRIOR_BPM: f64 = 120.0;
+const TIGHTNESS: f64 = 100.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BeatsV1 {
+    pub schema: String,
+    pub source: String,
+    pub sample_rate: u32,
+    pub duration_s: f64,
+    pub tempo_bpm: f64,
+    pub beats_per_bar: u32,
+    pub beats: Vec<f64>,
+    pub downbeats: Vec<f64>,
+    pub onsets: Vec<f64>,
+}
+
+impl BeatsV1 {
+    /// Phrases are groups of `bars_per_phrase` bars starting at the first downbeat.
+    pub fn phrases(&self, bars_per_phrase: usize) -> Vec<f64> {
+        self.downbeats
+            .iter()
+            .step_by(bars_per_phrase.max(1))
+            .copied()
+            .collect()
+    }
+}
+
+/// Onset strength per analysis frame, sampled at `frame_rate` frames per second.
+#[derive(Debug, Clone)]
+pub struct OnsetEnvelope {
+    pub frame_rate: f64,
+    pub values: Vec<f32>,
+}
+
+impl OnsetEnvelope {
+    /// Flux at `frame` reflects the hop that just entered the window, so report its centre.
+    fn time_of(&self, frame: usize) -> f64 {
+        (frame as f64 + (WIN - HOP / 2) as f64 / HOP as f64) / self.frame_rate
+    }
+}
+
+/// Half-wave rectified log-energy flux over a broadband and a pre-emphasised (transient) band.
+pub fn onset_envelope(samples: &[f32], sample_rate: u32) -> OnsetEnvelope {
+    let frame_rate = sample_rate as f64 / HOP as f64;
+    if samples.len() < WIN {
+        return OnsetEnvelope { frame_rate, values: Vec::new() };
+    }
+
+    let n_frames = (samples.len() - WIN) / HOP + 1;
+    let mut low = Vec::with_capacity(n_frames);
+    let mut high = Vec::with_capacity(n_frames);
+    for f in 0..n_frames {
+        let start = f * HOP;
+        let mut e_low = 0.0f64;
+        let mut e_high = 0.0f64;
+        let mut prev = if start > 0 { samples[start - 1] } else { 0.0 };
+        for &x in &samples[start..start + WIN] {
+            let hp = x - 0.97 * prev;
+            prev = x;
+            e_low += (x as f64) * (x as f64);
+            e_high += (hp as f64) * (hp as f64);
+        }
+        low.push((1e-6 + e_low / WIN as f64).ln());
+        high.push((1e-6 + e_high / WIN as f64).ln());
+    }
+
+    let mut flux = vec![0.0f64; n_frames];
+    for f in 1..n_frames {
+        flux[f] = (low[f] - low[f - 1]).max(0.0) + (high[f] - high[f - 1]).max(0.0);
+    }
+
+    // Remove the slowly varying part so sustained loud passages do not dominate.
+    let half = (frame_rate * 0.25).round().max(1.0) as usize;
+    let mut values = vec![0.0f32; n_frames];
+    for f in 0..n_frames {
+        let a = f.saturating_sub(half);
+        let b = (f + half + 1).min(n_frames);
+        let mean = flux[a..b].iter().sum::<f64>() / (b - a) as f64;
+        values[f] = (flux[f] - mean).max(0.0) as f32;
+    }
+
+    let mean = values.iter().map(|&v| v as f64).sum::<f64>() / n_frames as f64;
+    let var = values
+        .iter()
+        .map(|&v| (v as f64 - mean).powi(2))
+        .sum::<f64>()
+        / n_frames as f64;
+    let std = var.sqrt();
+    if std > 0.0 {
+        for v in &mut values {
+            *v = (*v as f64 / std) as f32;
+        }
+    }
+
+    OnsetEnvelope { frame_rate, values }
+}
+
+/// Autocorrelation tempo estimate weighted by a log-Gaussian prior around 120 BPM.
+pub fn estimate_tempo(env: &OnsetEnvelope) -> f64 {
+    let v = &env.values;
+    let min_lag = (env.frame_rate * 60.0 / MAX_BPM).floor().max(1.0) as usize;
+    let max_lag = (env.frame_rate * 60.0 / MIN_BPM).ceil() as usize;
+    if v.len() <= max_lag + 1 {
+        return PRIOR_BPM;
+    }
+
+    let ac = |lag: usize| -> f64 {
+        v.iter()
+            .zip(v[lag..].iter())
+            .map(|(&a, &b)| a as f64 * b as f64)
+            .sum::<f64>()
+            / (v.len() - lag) as f64
+    };
+
+    let mut scores = Vec::with_capacity(max_lag + 2);
+    scores.push(0.0);
+    for lag in 1..=max_lag + 1 {
+        scores.push(ac(lag));
+    }
+
+    let mut best_lag = min_lag;
+    let mut best = f64::MIN;
+    for (lag, &score) in scores.iter().enumerate().take(max_lag + 1).skip(min_lag) {
+        let bpm = 60.0 * env.frame_rate / lag as f64;
+        let w = (-0.5 * ((bpm / PRIOR_BPM).log2()).powi(2)).exp();
+        let s = score * w;
+        if s > best {
+            best = s;
+            best_lag = lag;
+        }
+    }
+
+    // Parabolic interpolation around the peak for sub-frame lag precision.
+    let (a, b, c) = (scores[best_lag - 1], scores[best_lag], scores[best_lag + 1]);
+    let denom = a - 2.0 * b + c;
+    let offset = if denom.abs() > 1e-12 {
+        (0.5 * (a - c) / denom).clamp(-0.5, 0.5)
+    } else {
+        0.0
+    };
+    60.0 * env.frame_rate / (best_lag as f64 + offset)
+}
+
+/// Dynamic-programming beat tracker: maximises onset strength while penalising
+/// deviations from the estimated beat period.
+pub fn track_beats(env: &OnsetEnvelope, tempo_bpm: f64) -> Vec<usize> {
+    let v = &env.values;
+    let n = v.len();
+    let period = env.frame_rate * 60.0 / tempo_bpm.max(1.0);
+    if n == 0 || period < 1.0 {
+        return Vec::new();
+    }
+
+    let mut score = vec![0.0f64; n];
+    let mut back = vec![usize::MAX; n];
+    let lo = (period / 2.0).round() as usize;
+    let hi = (period * 2.0).round() as usize;
+    for t in 0..n {
+        let mut best = 0.0f64;
+        let mut best_prev = usize::MAX;
+        if t >= lo {
+            let from = t.saturating_sub(hi);
+            for (prev, &prev_score) in score.iter().enumerate().take(t - lo + 1).skip(from) {
+                let ratio = (t - prev) as f64 / period;
+                let s = prev_score - TIGHTNESS * ratio.ln().powi(2);
+                if best_prev == usize::MAX || s > best {
+                    best = s;
+                    best_prev = prev;
+                }
+            }
+        }
+        score[t] = v[t] as f64 + if best_prev == usize::MAX { 0.0 } else { best };
+        back[t] = best_prev;
+    }
+
+    let tail_start = n.saturating_sub(period.ceil() as usize);
+    let mut t = (tail_start..n)
+        .max_by(|&a, &b| score[a].total_cmp(&score[b]))
+        .unwrap_or(n - 1);
+    let mut beats = vec![t];
+    while back[t] != usize::MAX {
+        t = back[t];
+        beats.push(t);
+    }
+    beats.reverse();
+    beats
+}
+
+/// Local maxima of the envelope above an adaptive (mean + delta) threshold.
+pub fn pick_onsets(env: &OnsetEnvelope) -> Vec<usize> {
+    let v = &env.values;
+    let w = 3usize;
+    let avg_w = (env.frame_rate * 0.1).round().max(1.0) as usize;
+    let min_gap = (env.frame_rate * 0.05).round().max(1.0) as usize;
+    let delta = 0.5f32;
+
+    let mut out: Vec<usize> = Vec::new();
+    for t in 0..v.len() {
+        let a = t.saturating_sub(w);
+        let b = (t + w + 1).min(v.len());
+        if v[a..b].iter().any(|&x| x > v[t]) {
+            continue;
+        }
+        let ma = t.saturating_sub(avg_w);
+        let mb = (t + avg_w + 1).min(v.len());
+        let mean = v[ma..mb].iter().sum::<f32>() / (mb - ma) as f32;
+        if v[t] < mean + delta {
+            continue;
+        }
+        if out.last().map(|&p| t - p < min_gap).unwrap_or(false) {
+            continue;
+        }
+        out.push(t);
+    }
+    out
+}
+
+fn downbeat_phase(env: &OnsetEnvelope, beats: &[usize], beats_per_bar: usize) -> usize {
+    (0..beats_per_bar)
+        .max_by(|&a, &b| {
+            let sa: f32 = beats.iter().skip(a).step_by(beats_per_bar).map(|&f| env.values[f]).sum();
+            let sb: f32 = beats.iter().skip(b).step_by(beats_per_bar).map(|&f| env.values[f]).sum();
+            sa.total_cmp(&sb)
+        })
+        .unwrap_or(0)
+}
+
+fn round_ms(t: f64) -> f64 {
+    (t * 1000.0).round() / 1000.0
+}
+
+pub fn analyze_pcm(pcm: &MonoPcm, source: &str) -> Result<BeatsV1> {
+    let sr = pcm.info.sample_rate;
+    let duration_s = pcm.info.duration_s();
+    if duration_s < 2.0 {
+        bail!("audio too short for beat analysis: {duration_s:.2}s");
+    }
+
+    let env = onset_envelope(&pcm.samples, sr);
+    let tempo_bpm = estimate_tempo(&env);
+    let beat_frames = track_beats(&env, tempo_bpm);
+    let onset_frames = pick_onsets(&env);
+
+    let beats_per_bar = 4usize;
+    let phase = downbeat_phase(&env, &beat_frames, beats_per_bar);
+
+    let beats: Vec<f64> = beat_frames.iter().map(|&f| round_ms(env.time_of(f))).collect();
+    let downbeats: Vec<f64> = beats.iter().skip(phase).step_by(beats_per_bar).copied().collect();
+    let onsets: Vec<f64> = onset_frames.iter().map(|&f| round_ms(env.time_of(f))).collect();
+
+    Ok(BeatsV1 {
+        schema: BEATS_SCHEMA.to_string(),
+        source: source.to_string(),
+        sample_rate: sr,
+        duration_s: round_ms(duration_s),
+        tempo_bpm: (tempo_bpm * 100.0).round() / 100.0,
+        beats_per_bar: beats_per_bar as u32,
+        beats,
+        downbeats,
+        onsets,
+    })
+}
+
+/// Onset times in seconds, without tempo tracking; usable on material of any length.
+pub fn onset_times(pcm: &MonoPcm) -> Vec<f64> {
+    let env = onset_envelope(&pcm.samples, pcm.info.sample_rate);
+    pick_onsets(&env)
+        .into_iter()
+        .map(|f| round_ms(env.time_of(f)))
+        .collect()
+}
+
+pub fn analyze_wav(wav_path: &Path) -> Result<BeatsV1> {
+    let pcm = decode_wav_mono(wav_path)?;
+    analyze_pcm(&pcm, &wav_path.display().to_string())
+}
+
+/// Loads `out_path` if it is newer than `wav_path`, otherwise analyses the wav and writes it.
+pub fn ensure_beats_json(wav_path: &Path, out_path: &Path) -> Result<BeatsV1> {
+    let fresh = match (fs::metadata(wav_path), fs::metadata(out_path)) {
+        (Ok(w), Ok(o)) => match (w.modified(), o.modified()) {
+            (Ok(wm), Ok(om)) => om >= wm,
+            _ => false,
+        },
+        _ => false,
+    };
+    if fresh {
+        if let Ok(b) = fs::read_to_string(out_path)
+            .map_err(anyhow::Error::from)
+            .and_then(|s| Ok(serde_json::from_str::<BeatsV1>(&s)?))
+        {
+            return Ok(b);
+        }
+    }
+
+    let beats = analyze_wav(wav_path)?;
+    if let Some(parent) = out_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(out_path, serde_json::to_vec_pretty(&beats)?)
+        .with_context(|| format!("write beats: {}", out_path.display()))?;
+    Ok(beats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::wav::WavInfo;
+
+    /// 10 s of short 1 kHz clicks every 60/`bpm` seconds.
+    fn click_track(bpm: f64) -> MonoPcm {
+        let sr = 22_050u32;
+        let mut samples = vec![0.0f32; sr as usize * 10];
+        let period = (sr as f64 * 60.0 / bpm) as usize;
+        for start in (0..samples.len()).step_by(period) {
+            for (i, s) in samples[start..].iter_mut().take(400).enumerate() {
+                *s = 0.8 * (i as f32 * 2.0 * std::f32::consts::PI * 1000.0 / sr as f32).sin();
+            }
+        }
+        let info = WavInfo {
+            sample_rate: sr,
+            channels: 1,
+            bits_per_sample: 16,
+            format: 1,
+            frames: samples.len() as u64,
+        };
+        MonoPcm { info, samples }
+    }
+
+    #[test]
+    fn click_track_tempo_and_beats() {
+        let b = analyze_pcm(&click_track(120.0), "clicks").unwrap();
+        assert!((b.tempo_bpm - 120.0).abs() < 3.0, "{}", b.tempo_bpm);
+        assert!(b.beats.len() >= 16, "{:?}", b.beats);
+        for w in b.beats.windows(2) {
+            assert!((w[1] - w[0] - 0.5).abs() < 0.05, "{:?}", b.beats);
+        }
+        assert!(b.downbeats.len() >= b.beats.len() / 4);
+        assert!(b.downbeats.iter().all(|d| b.beats.contains(d)));
+    }
+}
@@ -0,0 +1,317 @@
+use crate::audio::stems::{RebalanceReport, VOCALS_STEM};
+use crate::audio::wav::read_wav_info;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use tokio::process::Command;
+
+/// Sidechain compression of the music bed, keyed by the vocals.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DuckSpec {
+    /// Linear level (0..1) above which the vocals start ducking the music.
+    pub threshold: f32,
+    pub ratio: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+}
+
+impl Default for DuckSpec {
+    fn default() -> Self {
+        Self {
+            threshold: 0.05,
+            ratio: 6.0,
+            attack_ms: 20.0,
+            release_ms: 300.0,
+        }
+    }
+}
+
+/// `commands.video.mix`. Missing fields take the defaults; `"duck": null` disables ducking.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MixSpec {
+    pub music_gain_db: f32,
+    pub vocals_gain_db: f32,
+    pub duck: Option<DuckSpec>,
+    pub fade_in_s: f32,
+    pub fade_out_s: f32,
+    /// Integrated loudness target (EBU R128).
+    pub target_lufs: f32,
+    pub true_peak_db: f32,
+    pub lra: f32,
+    pub sample_rate: u32,
+    /// Per-stem gain in dB (`{"drums": -6, "vocals": 2}`). Music stems are re-summed into
+    /// the bed when the music stage wrote stems; `vocals` adds to `vocals_gain_db`.
+    pub stems: BTreeMap<String, f32>,
+}
+
+impl Default for MixSpec {
+    fn default() -> Self {
+        Self {
+            music_gain_db: -3.0,
+            vocals_gain_db: 0.0,
+            duck: Some(DuckSpec::default()),
+            fade_in_s: 0.5,
+            fade_out_s: 1.5,
+            target_lufs: -14.0,
+            true_peak_db: -1.0,
+            lra: 11.0,
+            sample_rate: 48_000,
+            stems: BTreeMap::new(),
+        }
+    }
+}
+
+impl MixSpec {
+    /// Defaults when absent or null; errors when present but not a valid spec.
+    pub fn from_value(v: Option<&serde_json::Value>) -> Result<Self, String> {
+        match v {
+            None | Some(serde_json::Value::Null) => Ok(Self::default()),
+            Some(x) => serde_json::from_value(x.clone()).map_err(|e| format!("video.mix: {e}")),
+        }
+    }
+
+    fn loudnorm(&self) -> String {
+        format!(
+            "loudnorm=I={}:TP={}:LRA={}",
+            self.target_lufs, self.true_peak_db, self.lra
+        )
+    }
+
+    /// Second-pass `loudnorm` with the first pass's measurements. Silent input measures as
+    /// `-inf`, which loudnorm rejects, so then it normalizes in one pass instead.
+    fn loudnorm_apply(&self, m: &LoudnessStats) -> String {
+        let measured = [m.input_i, m.input_tp, m.input_lra, m.input_thresh, m.target_offset];
+        if !measured.iter().all(|v| v.is_finite()) {
+            return format!("{}:print_format=json", self.loudnorm());
+        }
+        format!(
+            "{}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true:print_format=json",
+            self.loudnorm(),
+            m.input_i,
+            m.input_tp,
+            m.input_lra,
+            m.input_thresh,
+            m.target_offset
+        )
+    }
+}
+
+/// Values printed by `loudnorm=print_format=json`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LoudnessStats {
+    pub input_i: f64,
+    pub input_tp: f64,
+    pub input_lra: f64,
+    pub input_thresh: f64,
+    pub output_i: f64,
+    pub output_tp: f64,
+    pub output_lra: f64,
+    pub output_thresh: f64,
+    pub target_offset: f64,
+    pub normalization_type: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MixReport {
+    pub spec: MixSpec,
+    pub inputs: Vec<String>,
+    pub duration_s: f64,
+    pub ducking: bool,
+    pub loudness: LoudnessStats,
+    /// Set when the music bed was rebuilt from stems with `spec.stems` gains.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stems: Option<RebalanceReport>,
+}
+
+/// Parses the last JSON object ffmpeg's loudnorm filter printed to stderr.
+pub fn parse_loudnorm_json(stderr: &str) -> Option<LoudnessStats> {
+    let start = stderr.rfind('{')?;
+    let end = start + stderr[start..].find('}')? + 1;
+    let v: serde_json::Value = serde_json::from_str(&stderr[start..end]).ok()?;
+    let num = |k: &str| -> f64 {
+        match v.get(k) {
+            Some(serde_json::Value::String(s)) => s.trim().parse().unwrap_or(f64::NAN),
+            Some(serde_json::Value::Number(n)) => n.as_f64().unwrap_or(f64::NAN),
+            _ => f64::NAN,
+        }
+    };
+    Some(LoudnessStats {
+        input_i: num("input_i"),
+        input_tp: num("input_tp"),
+        input_lra: num("input_lra"),
+        input_thresh: num("input_thresh"),
+        output_i: num("output_i"),
+        output_tp: num("output_tp"),
+        output_lra: num("output_lra"),
+        output_thresh: num("output_thresh"),
+        target_offset: num("target_offset"),
+        normalization_type: v
+            .get("normalization_type")
+            .and_then(|x| x.as_str())
+            .unwrap_or_default()
+            .to_string(),
+    })
+}
+
+/// Gain, ducking, summing and fades for ffmpeg inputs `0` (music) and `1` (vocals); either
+/// may be absent. The result is labelled `[mix]`.
+pub fn pre_mix_graph(spec: &MixSpec, music: bool, vocals: bool, duration_s: f64) -> String {
+    let fmt = format!("aformat=sample_rates={}:channel_layouts=stereo", spec.sample_rate);
+    let mut fades = Vec::new();
+    if spec.fade_in_s > 0.0 {
+        fades.push(format!("afade=t=in:st=0:d={}", spec.fade_in_s));
+    }
+    if spec.fade_out_s > 0.0 && duration_s > 0.0 {
+        let d = (spec.fade_out_s as f64).min(duration_s);
+        fades.push(format!("afade=t=out:st={:.3}:d={:.3}", duration_s - d, d));
+    }
+    let tail = if fades.is_empty() {
+        "anull".to_string()
+    } else {
+        fades.join(",")
+    };
+
+    let music_in = format!("[0:a]{fmt},volume={}dB", spec.music_gain_db);
+    let vocals_idx = if music { 1 } else { 0 };
+    let vocals_gain_db = spec.vocals_gain_db + spec.stems.get(VOCALS_STEM).copied().unwrap_or(0.0);
+    let vocals_in = format!("[{vocals_idx}:a]{fmt},volume={vocals_gain_db}dB");
+    match (music, vocals, &spec.duck) {
+        (true, true, Some(d)) => format!(
+            "{music_in}[mus];{vocals_in},asplit=2[voc][sc];\
+             [mus][sc]sidechaincompress=threshold={}:ratio={}:attack={}:release={}[duck];\
+             [duck][voc]amix=inputs=2:duration=longest:normalize=0,{tail}[mix]",
+            d.threshold, d.ratio, d.attack_ms, d.release_ms
+        ),
+        (true, true, None) => format!(
+            "{music_in}[mus];{vocals_in}[voc];\
+             [mus][voc]amix=inputs=2:duration=longest:normalize=0,{tail}[mix]"
+        ),
+        (true, false, _) => format!("{music_in},{tail}[mix]"),
+        (false, true, _) => format!("{vocals_in},{tail}[mix]"),
+        (false, false, _) => String::new(),
+    }
+}
+
+async fn run_ffmpeg_stderr(cmd: &mut Command, what: &str) -> Result<String, String> {
+    let out = cmd
+        .output()
+        .await
+        .map_err(|e| format!("spawn ffmpeg {what}: {e}"))?;
+    let stderr = String::from_utf8_lossy(&out.stderr).to_string();
+    if !out.status.success() {
+        let tail: String = stderr.lines().rev().take(5).collect::<Vec<_>>().join(" | ");
+        return Err(format!("ffmpeg {what} failed: exit={:?} {tail}", out.status.code()));
+    }
+    Ok(stderr)
+}
+
+/// Mixes music and vocals into `out_wav` and normalizes it to the target loudness with a
+/// two-pass `loudnorm` (measure, then apply the measured values linearly).
+pub async fn mix_tracks(
+    ffmpeg: &str,
+    music: Option<&Path>,
+    vocals: Option<&Path>,
+    out_wav: &Path,
+    spec: &MixSpec,
+) -> Result<MixReport, String> {
+    let inputs: Vec<&Path> = [music, vocals].into_iter().flatten().collect();
+    if inputs.is_empty() {
+        return Err("mix: no audio inputs".to_string());
+    }
+    let duration_s = inputs
+        .iter()
+        .filter_map(|p| read_wav_info(p).ok())
+        .map(|i| i.duration_s())
+        .fold(0.0f64, f64::max);
+    let graph = pre_mix_graph(spec, music.is_some(), vocals.is_some(), duration_s);
+
+    if let Some(parent) = out_wav.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| format!("create mix dir: {e}"))?;
+    }
+
+    let base = |cmd: &mut Command| {
+        cmd.args(["-hide_banner", "-nostats", "-y"]);
+        for p in &inputs {
+            cmd.arg("-i").arg(p);
+        }
+    };
+
+    let mut pass1 = Command::new(ffmpeg);
+    pass1.kill_on_drop(true);
+    base(&mut pass1);
+    pass1
+        .arg("-filter_complex")
+        .arg(format!("{graph};[mix]{}:print_format=json[out]", spec.loudnorm()))
+        .args(["-map", "[out]", "-f", "null", "-"]);
+    let measured = parse_loudnorm_json(&run_ffmpeg_stderr(&mut pass1, "loudnorm measure").await?)
+        .ok_or_else(|| "loudnorm measure: no stats in ffmpeg output".to_string())?;
+
+    let mut pass2 = Command::new(ffmpeg);
+    pass2.kill_on_drop(true);
+    base(&mut pass2);
+    pass2
+        .arg("-filter_complex")
+        .arg(format!(
+            "{graph};[mix]{},aresample={}[out]",
+            spec.loudnorm_apply(&measured),
+            spec.sample_rate
+        ))
+        .args(["-map", "[out]", "-c:a", "pcm_s16le"])
+        .arg(out_wav);
+    let loudness = parse_loudnorm_json(&run_ffmpeg_stderr(&mut pass2, "loudnorm apply").await?)
+        .unwrap_or(measured);
+
+    Ok(MixReport {
+        spec: spec.clone(),
+        inputs: inputs.iter().map(|p| p.display().to_string()).collect(),
+        duration_s,
+        ducking: music.is_some() && vocals.is_some() && spec.duck.is_some(),
+        loudness,
+        stems: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn silent_input_skips_measured_values() {
+        let spec = MixSpec::default();
+        let silent = parse_loudnorm_json(
+            r#"{"input_i" : "-inf", "input_tp" : "-inf", "input_lra" : "0.00",
+                "input_thresh" : "-inf", "target_offset" : "inf"}"#,
+        )
+        .unwrap();
+        let f = spec.loudnorm_apply(&silent);
+        assert!(!f.contains("measured_") && !f.contains("inf"), "{f}");
+
+        let loud = LoudnessStats {
+            input_i: -20.5,
+            input_tp: -3.0,
+            input_lra: 6.0,
+            input_thresh: -31.0,
+            target_offset: 0.4,
+            ..Default::default()
+        };
+        assert!(spec.loudnorm_apply(&loud).contains("measured_I=-20.5:"));
+    }
+
+    #[test]
+    fn malformed_mix_specs_are_rejected() {
+        let spec = MixSpec::from_value(Some(&serde_json::json!({"music_gain_db": -6}))).unwrap();
+        assert_eq!((spec.music_gain_db, spec.target_lufs), (-6.0, -14.0));
+        assert!(MixSpec::from_value(None).is_ok());
+        for bad in [
+            serde_json::json!({"music_gain_db": "loud"}),
+            serde_json::json!({"duck": {"ratio": [6]}}),
+            serde_json::json!("quiet"),
+        ] {
+            assert!(MixSpec::from_value(Some(&bad)).is_err(), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,4 @@
+pub mod beats;
+pub mod mix;
+pub mod wav;
+pub mod stems;
@@ -0,0 +1,332 @@
+use crate::audio::wav::{decode_wav, encode_wav_pcm16};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+pub const STEMS_SCHEMA: &str = "css.music.stems.v1";
+
+/// Stem the vocals stage adds; every other stem is part of the music bed.
+pub const VOCALS_STEM: &str = "vocals";
+
+/// Level of silence, so empty stems still serialize as a number.
+const FLOOR_DB: f64 = -120.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StemInfo {
+    pub name: String,
+    /// Relative to the run directory, like stage outputs.
+    pub path: PathBuf,
+    pub duration_s: f64,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub peak_dbfs: f64,
+    /// RMS over the whole stem.
+    pub rms_dbfs: f64,
+}
+
+/// `build/stems/stems.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StemsManifest {
+    pub schema: String,
+    pub stems: Vec<StemInfo>,
+}
+
+impl Default for StemsManifest {
+    fn default() -> Self {
+        Self {
+            schema: STEMS_SCHEMA.to_string(),
+            stems: Vec::new(),
+        }
+    }
+}
+
+fn db(x: f64) -> f64 {
+    if x > 0.0 {
+        (20.0 * x.log10()).max(FLOOR_DB)
+    } else {
+        FLOOR_DB
+    }
+}
+
+fn round2(x: f64) -> f64 {
+    (x * 100.0).round() / 100.0
+}
+
+impl StemsManifest {
+    pub fn read(path: &Path) -> Result<Self> {
+        let s = std::fs::read_to_string(path)
+            .with_context(|| format!("read stems manifest: {}", path.display()))?;
+        serde_json::from_str(&s)
+            .with_context(|| format!("parse stems manifest: {}", path.display()))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("write stems manifest: {}", path.display()))
+    }
+
+    /// Measures the WAV at `base.join(rel)` and adds or replaces the stem called `name`.
+    pub fn upsert(&mut self, base: &Path, name: &str, rel: &Path) -> Result<&StemInfo> {
+        let (info, samples) = decode_wav(&base.join(rel))?;
+        let peak = samples.iter().fold(0.0f32, |m, s| m.max(s.abs())) as f64;
+        let rms = if samples.is_empty() {
+            0.0
+        } else {
+            (samples.iter().map(|s| (*s as f64).powi(2)).sum::<f64>() / samples.len() as f64).sqrt()
+        };
+        let stem = StemInfo {
+            name: name.to_string(),
+            path: rel.to_path_buf(),
+            duration_s: (info.duration_s() * 1000.0).round() / 1000.0,
+            sample_rate: info.sample_rate,
+            channels: info.channels,
+            peak_dbfs: round2(db(peak)),
+            rms_dbfs: round2(db(rms)),
+        };
+        self.stems.retain(|s| s.name != name);
+        self.stems.push(stem);
+        Ok(self.stems.last().expect("just pushed"))
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.stems.iter().map(|s| s.name.as_str()).collect()
+    }
+}
+
+/// What [`rebalance_music`] did, for the mix report.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RebalanceReport {
+    pub stems: Vec<String>,
+    pub gains_db: BTreeMap<String, f32>,
+    /// Extra attenuation applied because the re-summed bed would have clipped.
+    pub headroom_db: f64,
+}
+
+/// Sums the music stems (everything but vocals) into a stereo `out_wav`, each scaled by its
+/// `gains_db` entry. Stems must share a sample rate; mono stems are spread to both sides.
+pub fn rebalance_music(
+    manifest: &StemsManifest,
+    base: &Path,
+    gains_db: &BTreeMap<String, f32>,
+    out_wav: &Path,
+) -> Result<RebalanceReport> {
+    let music: Vec<&StemInfo> = manifest
+        .stems
+        .iter()
+        .filter(|s| s.name != VOCALS_STEM)
+        .collect();
+    if music.is_empty() {
+        bail!("stems manifest has no music stems");
+    }
+    let mut sample_rate = None;
+    let mut bed: Vec<f32> = Vec::new();
+    for stem in &music {
+        let (info, samples) = decode_wav(&base.join(&stem.path))?;
+        if *sample_rate.get_or_insert(info.sample_rate) != info.sample_rate {
+            bail!(
+                "stem {} is {} Hz, others are {} Hz",
+                stem.name,
+                info.sample_rate,
+                sample_rate.unwrap_or_default()
+            );
+        }
+        let gain = 10f32.powf(gains_db.get(&stem.name).copied().unwrap_or(0.0) / 20.0);
+        let ch = info.channels.max(1) as usize;
+        let frames = samples.len() / ch;
+        if bed.len() < frames * 2 {
+            bed.resize(frames * 2, 0.0);
+        }
+        for f in 0..frames {
+            let l = samples[f * ch];
+            let r = samples[f * ch + (ch > 1) as usize];
+            bed[2 * f] += gain * l;
+            bed[2 * f + 1] += gain * r;
+        }
+    }
+    let peak = bed.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+    let scale = if peak > 0.99 { 0.99 / peak } else { 1.0 };
+    if scale < 1.0 {
+        bed.iter_mut().for_each(|s| *s *= scale);
+    }
+    if let Some(dir) = out_wav.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(
+        out_wav,
+        encode_wav_pcm16(sample_rate.unwrap_or(44_100), 2, &bed),
+    )
+    .with_context(|| format!("write rebalanced music: {}", out_wav.display()))?;
+    Ok(RebalanceReport {
+        stems: music.iter().map(|s| s.name.clone()).collect(),
+        gains_db: gains_db
+            .iter()
+            .filter(|(k, _)| music.iter().any(|s| &s.name == *k))
+            .map(|(k, v)| (k.clone(), *v))
+            .collect(),
+        headroom_db: round2(-db(scale as f64)),
+    })
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in bytes {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// Uncompressed zip of the manifest and every stem, named by file name; WAV barely
+/// compresses, so storing keeps this dependency-free and fast.
+pub fn write_stems_zip(manifest_path: &Path, base: &Path, out_zip: &Path) -> Result<()> {
+    let manifest = StemsManifest::read(manifest_path)?;
+    let mut entries: Vec<(String, Vec<u8>)> =
+        vec![("stems.json".to_string(), std::fs::read(manifest_path)?)];
+    for s in &manifest.stems {
+        let p = base.join(&s.path);
+        let name = p
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map(|n| n.to_string())
+            .unwrap_or_else(|| format!("{}.wav", s.name));
+        entries.push((
+            name,
+            std::fs::read(&p).with_context(|| format!("read stem: {}", p.display()))?,
+        ));
+    }
+
+    let mut out = Vec::new();
+    let mut central = Vec::new();
+    for (name, data) in &entries {
+        if data.len() > u32::MAX as usize || out.len() > u32::MAX as usize {
+            bail!("stems zip would exceed 4 GiB");
+        }
+        let crc = crc32(data);
+        let offset = out.len() as u32;
+        // Fixed 1980-01-01 timestamp keeps the zip byte-identical across renders.
+        let header = |sig: u32, central: bool| {
+            let mut h = Vec::new();
+            h.extend_from_slice(&sig.to_le_bytes());
+            if central {
+                h.extend_from_slice(&20u16.to_le_bytes());
+            }
+            h.extend_from_slice(&20u16.to_le_bytes());
+            h.extend_from_slice(&0u16.to_le_bytes());
+            h.extend_from_slice(&0u16.to_le_bytes());
+            h.extend_from_slice(&0u16.to_le_bytes());
+            h.extend_from_slice(&0x21u16.to_le_bytes());
+            h.extend_from_slice(&crc.to_le_bytes());
+            h.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            h.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            h.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            h.extend_from_slice(&0u16.to_le_bytes());
+            h
+        };
+        out.extend_from_slice(&header(0x0403_4b50, false));
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(data);
+
+        central.extend_from_slice(&header(0x0201_4b50, true));
+        // Comment length, disk number, internal and external attributes.
+        central.extend_from_slice(&[0u8; 10]);
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+    }
+    let cd_offset = out.len() as u32;
+    let cd_len = central.len() as u32;
+    out.extend_from_slice(&central);
+    out.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+    out.extend_from_slice(&[0u8; 4]);
+    out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    out.extend_from_slice(&cd_len.to_le_bytes());
+    out.extend_from_slice(&cd_offset.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes());
+
+    if let Some(dir) = out_zip.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(out_zip, out).with_context(|| format!("write stems zip: {}", out_zip.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(b: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn manifest_rebalance_and_zip() {
+        let dir = std::env::temp_dir().join(format!("css_stems_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("build/stems")).unwrap();
+        let tone: Vec<f32> = (0..8_000).map(|i| 0.5 * (i as f32 * 0.05).sin()).collect();
+        let stereo: Vec<f32> = tone.iter().flat_map(|s| [*s, *s]).collect();
+        std::fs::write(
+            dir.join("build/stems/drums.wav"),
+            encode_wav_pcm16(8_000, 2, &stereo),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("build/stems/vocals.wav"),
+            encode_wav_pcm16(8_000, 1, &tone),
+        )
+        .unwrap();
+
+        let mut m = StemsManifest::default();
+        let drums = m
+            .upsert(&dir, "drums", Path::new("build/stems/drums.wav"))
+            .unwrap();
+        assert_eq!(drums.duration_s, 1.0);
+        assert!((drums.peak_dbfs + 6.02).abs() < 0.05, "{}", drums.peak_dbfs);
+        assert!((drums.rms_dbfs + 9.03).abs() < 0.1, "{}", drums.rms_dbfs);
+        m.upsert(&dir, VOCALS_STEM, Path::new("build/stems/vocals.wav"))
+            .unwrap();
+        m.upsert(&dir, "drums", Path::new("build/stems/drums.wav"))
+            .unwrap();
+        assert_eq!(m.names(), vec!["vocals", "drums"]);
+        let manifest_path = dir.join("build/stems/stems.json");
+        m.write(&manifest_path).unwrap();
+
+        // Vocals stay out of the bed; +12 dB on a -6 dBFS stem has to be pulled back.
+        let gains = BTreeMap::from([("drums".to_string(), 12.0), ("vocals".to_string(), -3.0)]);
+        let out = dir.join("audio/music_stems.wav");
+        let r = rebalance_music(&m, &dir, &gains, &out).unwrap();
+        assert_eq!(r.stems, vec!["drums"]);
+        assert_eq!(r.gains_db.len(), 1);
+        assert!(r.headroom_db > 5.0, "{}", r.headroom_db);
+        let (info, bed) = decode_wav(&out).unwrap();
+        assert_eq!((info.channels, info.frames), (2, 8_000));
+        assert!(bed.iter().all(|s| s.abs() <= 0.99));
+
+        let zip = dir.join("build/stems.zip");
+        write_stems_zip(&manifest_path, &dir, &zip).unwrap();
+        let b = std::fs::read(&zip).unwrap();
+        let eocd = b.len() - 22;
+        assert_eq!(u32_at(&b, eocd), 0x0605_4b50);
+        assert_eq!(u16::from_le_bytes([b[eocd + 10], b[eocd + 11]]), 3);
+        let mut at = u32_at(&b, eocd + 16) as usize;
+        let mut names = Vec::new();
+        for _ in 0..3 {
+            assert_eq!(u32_at(&b, at), 0x0201_4b50);
+            let len = u16::from_le_bytes([b[at + 28], b[at + 29]]) as usize;
+            let local = u32_at(&b, at + 42) as usize;
+            assert_eq!(u32_at(&b, local), 0x0403_4b50);
+            let size = u32_at(&b, local + 18) as usize;
+            let data = &b[local + 30 + len..local + 30 + len + size];
+            assert_eq!(crc32(data), u32_at(&b, at + 16));
+            names.push(String::from_utf8(b[at + 46..at + 46 + len].to_vec()).unwrap());
+            at += 46 + len;
+        }
+        assert_eq!(names, vec!["stems.json", "vocals.wav", "drums.wav"]);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
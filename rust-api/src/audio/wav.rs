@@ -0,0 +1,179 @@
+use anyhow::{bail, Context, Result};
+use std::{fs, path::Path};
+
+#[derive(Debug, Clone)]
+pub struct WavInfo {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bits_per_sample: u16,
+    pub format: u16,
+    pub frames: u64,
+}
+
+impl WavInfo {
+    pub fn duration_s(&self) -> f64 {
+        if self.sample_rate == 0 {
+            return 0.0;
+        }
+        self.frames as f64 / self.sample_rate as f64
+    }
+
+    /// ffprobe-style codec name, e.g. `pcm_s16le`.
+    pub fn codec_name(&self) -> &'static str {
+        match (self.format, self.bits_per_sample) {
+            (WAVE_FORMAT_PCM, 8) => "pcm_u8",
+            (WAVE_FORMAT_PCM, 16) => "pcm_s16le",
+            (WAVE_FORMAT_PCM, 24) => "pcm_s24le",
+            (WAVE_FORMAT_PCM, 32) => "pcm_s32le",
+            (WAVE_FORMAT_IEEE_FLOAT, 32) => "pcm_f32le",
+            (WAVE_FORMAT_IEEE_FLOAT, 64) => "pcm_f64le",
+            _ => "unknown",
+        }
+    }
+}
+
+/// Decoded audio downmixed to mono, samples in [-1.0, 1.0].
+#[derive(Debug, Clone)]
+pub struct MonoPcm {
+    pub info: WavInfo,
+    pub samples: Vec<f32>,
+}
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+fn u16_le(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([b[at], b[at + 1]])
+}
+
+fn u32_le(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]])
+}
+
+/// Walks the RIFF chunks and returns the format info plus the byte range of the `data` chunk.
+fn parse_chunks(bytes: &[u8]) -> Result<(WavInfo, std::ops::Range<usize>)> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        bail!("not a RIFF/WAVE file");
+    }
+
+    let mut fmt: Option<(u16, u16, u32, u16)> = None;
+    let mut data: Option<std::ops::Range<usize>> = None;
+    let mut at = 12usize;
+    while at + 8 <= bytes.len() {
+        let id = &bytes[at..at + 4];
+        let size = u32_le(bytes, at + 4) as usize;
+        let body = at + 8;
+        let end = body.saturating_add(size).min(bytes.len());
+        match id {
+            b"fmt " => {
+                if end - body < 16 {
+                    bail!("fmt chunk too short");
+                }
+                let mut format = u16_le(bytes, body);
+                let channels = u16_le(bytes, body + 2);
+                let sample_rate = u32_le(bytes, body + 4);
+                let bits = u16_le(bytes, body + 14);
+                if format == WAVE_FORMAT_EXTENSIBLE && end - body >= 26 {
+                    format = u16_le(bytes, body + 24);
+                }
+                fmt = Some((format, channels, sample_rate, bits));
+            }
+            b"data" => {
+                data = Some(body..end);
+            }
+            _ => {}
+        }
+        at = body + size + (size & 1);
+    }
+
+    let (format, channels, sample_rate, bits) = fmt.context("missing fmt chunk")?;
+    let data = data.context("missing data chunk")?;
+    if channels == 0 || sample_rate == 0 {
+        bail!("invalid wav format: channels={channels} sample_rate={sample_rate}");
+    }
+    let frame_bytes = channels as usize * (bits as usize / 8);
+    if frame_bytes == 0 {
+        bail!("invalid wav format: bits_per_sample={bits}");
+    }
+    let info = WavInfo {
+        sample_rate,
+        channels,
+        bits_per_sample: bits,
+        format,
+        frames: (data.len() / frame_bytes) as u64,
+    };
+    Ok((info, data))
+}
+
+pub fn read_wav_info(path: &Path) -> Result<WavInfo> {
+    let bytes = fs::read(path).with_context(|| format!("read wav: {}", path.display()))?;
+    Ok(parse_chunks(&bytes)?.0)
+}
+
+pub fn decode_wav_mono(path: &Path) -> Result<MonoPcm> {
+    let (info, interleaved) = decode_wav(path)?;
+    let ch = info.channels as usize;
+    let samples = interleaved
+        .chunks_exact(ch)
+        .map(|frame| frame.iter().sum::<f32>() / ch as f32)
+        .collect();
+    Ok(MonoPcm { info, samples })
+}
+
+/// Interleaved samples in [-1.0, 1.0], all channels kept.
+pub fn decode_wav(path: &Path) -> Result<(WavInfo, Vec<f32>)> {
+    let bytes = fs::read(path).with_context(|| format!("read wav: {}", path.display()))?;
+    let (info, range) = parse_chunks(&bytes)?;
+    let data = &bytes[range];
+
+    let ch = info.channels as usize;
+    let width = info.bits_per_sample as usize / 8;
+    let sample_at = |i: usize| -> Result<f32> {
+        let b = &data[i * width..(i + 1) * width];
+        Ok(match (info.format, info.bits_per_sample) {
+            (WAVE_FORMAT_PCM, 8) => (b[0] as f32 - 128.0) / 128.0,
+            (WAVE_FORMAT_PCM, 16) => i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+            (WAVE_FORMAT_PCM, 24) => {
+                let v = i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8;
+                v as f32 / 8_388_608.0
+            }
+            (WAVE_FORMAT_PCM, 32) => {
+                i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0
+            }
+            (WAVE_FORMAT_IEEE_FLOAT, 32) => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            (f, bits) => bail!("unsupported wav encoding: format={f} bits={bits}"),
+        })
+    };
+
+    let n = info.frames as usize * ch;
+    let mut samples = Vec::with_capacity(n);
+    for i in 0..n {
+        samples.push(sample_at(i)?);
+    }
+    Ok((info, samples))
+}
+
+/// 16-bit PCM WAV from interleaved samples in [-1.0, 1.0]; out-of-range samples are clipped.
+pub fn encode_wav_pcm16(sample_rate: u32, channels: u16, interleaved: &[f32]) -> Vec<u8> {
+    let data_len = (interleaved.len() * 2) as u32;
+    let block_align = channels * 2;
+    let mut out = Vec::with_capacity(44 + data_len as usize);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data_len).to_le_bytes());
+    out.extend_from_slice(b"WAVEfmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    out.extend_from_slice(&WAVE_FORMAT_PCM.to_le_bytes());
+    out.extend_from_slice(&channels.to_le_bytes());
+    out.extend_from_slice(&sample_rate.to_le_bytes());
+    out.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    out.extend_from_slice(&block_align.to_le_bytes());
+    out.extend_from_slice(&16u16.to_le_bytes());
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data_len.to_le_bytes());
+    for x in interleaved {
+        let v = (x.clamp(-1.0, 1.0) * 32767.0).round() as i16;
+        out.extend_from_slice(&v.to_le_bytes());
+    }
+    out
+}
@@ -37,9 +37,7 @@ where
             .split(';')
             .map(|c| c.trim())
             .find_map(|c| {
-                let mut parts = c.splitn(2, '=');
-                let name = parts.next()?;
-                let value = parts.next()?;
+                let (name, value) = c.split_once('=')?;
                 if name == session_cookie { Some(value.to_string()) } else { None }
             })
             .and_then(|id| Uuid::parse_str(&id).ok());
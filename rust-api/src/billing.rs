@@ -1,12 +1,16 @@
 use chrono::Utc;
 use sqlx::PgPool;
+use std::collections::{BTreeMap, BTreeSet};
 use uuid::Uuid;
 
-use crate::models::{BillingAccount, UsageEvent};
+use crate::models::{BillingAccount, UsageEvent, Work, WorkPurchase};
 
 #[derive(Debug, serde::Serialize)]
 pub struct MeterResult {
     pub allowed: bool,
+    /// `monthly_limit` or `insufficient_balance` when the charge was refused.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocked_reason: Option<&'static str>,
     pub balance_cents: i64,
     pub month_spend_cents: i64,
     pub monthly_limit_cents: i64,
@@ -46,6 +50,15 @@ pub async fn reset_month(pool: &PgPool, user_id: Uuid) -> Result<(), sqlx::Error
     Ok(())
 }
 
+/// A metered request: what a charge records in `usage_events`.
+pub struct Usage<'a> {
+    pub route: &'a str,
+    pub units: i64,
+    pub unit_price_cents: i64,
+    pub request_id: Option<String>,
+    pub meta: serde_json::Value,
+}
+
 pub async fn meter_usage(
     pool: &PgPool,
     user_id: Uuid,
@@ -55,7 +68,6 @@ pub async fn meter_usage(
     request_id: Option<String>,
     meta: serde_json::Value,
 ) -> Result<MeterResult, sqlx::Error> {
-    let cost = units * unit_price_cents;
     let mut tx = pool.begin().await?;
 
     let mut account = sqlx::query_as::<_, BillingAccount>(
@@ -77,6 +89,24 @@ pub async fn meter_usage(
     }
 
     let mut account = account.expect("account");
+    let usage = Usage { route, units, unit_price_cents, request_id, meta };
+    let result = charge(&mut tx, &mut account, &usage, "debit", None).await?;
+    tx.commit().await?;
+    Ok(result)
+}
+
+/// Charges `usage` to `account`, which `tx` holds locked: rolls the month over, enforces the
+/// monthly limit, auto-recharges a short balance, then records the usage event and posts the
+/// debit as a `kind` entry. A refused charge only records the blocked usage event.
+async fn charge(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    account: &mut BillingAccount,
+    usage: &Usage<'_>,
+    kind: &str,
+    note: Option<&str>,
+) -> Result<MeterResult, sqlx::Error> {
+    let user_id = account.user_id;
+    let cost = usage.units * usage.unit_price_cents;
     let current_month = Utc::now().format("%Y-%m").to_string();
     if account.month_key != current_month {
         account.month_key = current_month.clone();
@@ -86,110 +116,362 @@ pub async fn meter_usage(
         )
         .bind(user_id)
         .bind(current_month)
-        .execute(&mut *tx)
+        .execute(&mut **tx)
         .await?;
     }
 
-    if account.monthly_limit_cents > 0 && account.month_spend_cents + cost > account.monthly_limit_cents {
+    let blocked = if account.monthly_limit_cents > 0
+        && account.month_spend_cents + cost > account.monthly_limit_cents
+    {
+        Some("monthly_limit")
+    } else if account.balance_cents < cost {
+        if account.auto_recharge_enabled && account.has_payment_method && account.auto_recharge_amount_cents > 0 {
+            let amount = account.auto_recharge_amount_cents;
+            post_entry(tx, account, "credit", amount, Some("auto_recharge_simulated"), None, &usage.meta).await?;
+            None
+        } else {
+            Some("insufficient_balance")
+        }
+    } else {
+        None
+    };
+
+    if let Some(reason) = blocked {
         sqlx::query(
             "INSERT INTO usage_events (user_id, route, units, unit_price_cents, cost_cents, allowed, blocked_reason, request_id, meta) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9)",
         )
         .bind(user_id)
-        .bind(route)
-        .bind(units)
-        .bind(unit_price_cents)
+        .bind(usage.route)
+        .bind(usage.units)
+        .bind(usage.unit_price_cents)
         .bind(cost)
         .bind(false)
-        .bind("monthly_limit")
-        .bind(request_id)
-        .bind(meta.clone())
-        .execute(&mut *tx)
+        .bind(reason)
+        .bind(&usage.request_id)
+        .bind(&usage.meta)
+        .execute(&mut **tx)
         .await?;
-        tx.commit().await?;
-        return Ok(MeterResult { allowed: false, balance_cents: account.balance_cents, month_spend_cents: account.month_spend_cents, monthly_limit_cents: account.monthly_limit_cents });
-    }
-
-    if account.balance_cents < cost {
-        if account.auto_recharge_enabled && account.has_payment_method && account.auto_recharge_amount_cents > 0 {
-            let new_balance = account.balance_cents + account.auto_recharge_amount_cents;
-            sqlx::query(
-                "INSERT INTO ledger_entries (user_id, type, amount_cents, balance_after_cents, currency, note, meta) VALUES ($1,$2,$3,$4,$5,$6,$7)",
-            )
-            .bind(user_id)
-            .bind("credit")
-            .bind(account.auto_recharge_amount_cents)
-            .bind(new_balance)
-            .bind(&account.currency)
-            .bind("auto_recharge_simulated")
-            .bind(meta.clone())
-            .execute(&mut *tx)
-            .await?;
-            account.balance_cents = new_balance;
-            sqlx::query("UPDATE billing_accounts SET balance_cents = $2 WHERE user_id = $1")
-                .bind(user_id)
-                .bind(new_balance)
-                .execute(&mut *tx)
-                .await?;
-        } else {
-            sqlx::query(
-                "INSERT INTO usage_events (user_id, route, units, unit_price_cents, cost_cents, allowed, blocked_reason, request_id, meta) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9)",
-            )
-            .bind(user_id)
-            .bind(route)
-            .bind(units)
-            .bind(unit_price_cents)
-            .bind(cost)
-            .bind(false)
-            .bind("insufficient_balance")
-            .bind(request_id)
-            .bind(meta.clone())
-            .execute(&mut *tx)
-            .await?;
-            tx.commit().await?;
-            return Ok(MeterResult { allowed: false, balance_cents: account.balance_cents, month_spend_cents: account.month_spend_cents, monthly_limit_cents: account.monthly_limit_cents });
-        }
+        return Ok(MeterResult { allowed: false, blocked_reason: Some(reason), balance_cents: account.balance_cents, month_spend_cents: account.month_spend_cents, monthly_limit_cents: account.monthly_limit_cents });
     }
 
-    let usage: UsageEvent = sqlx::query_as::<_, UsageEvent>(
+    let event: UsageEvent = sqlx::query_as::<_, UsageEvent>(
         "INSERT INTO usage_events (user_id, route, units, unit_price_cents, cost_cents, allowed, request_id, meta) VALUES ($1,$2,$3,$4,$5,$6,$7,$8) RETURNING *",
     )
     .bind(user_id)
-    .bind(route)
-    .bind(units)
-    .bind(unit_price_cents)
+    .bind(usage.route)
+    .bind(usage.units)
+    .bind(usage.unit_price_cents)
     .bind(cost)
     .bind(true)
-    .bind(request_id)
-    .bind(meta.clone())
-    .fetch_one(&mut *tx)
+    .bind(&usage.request_id)
+    .bind(&usage.meta)
+    .fetch_one(&mut **tx)
     .await?;
 
-    let new_balance = account.balance_cents - cost;
-    let new_spend = account.month_spend_cents + cost;
+    account.month_spend_cents += cost;
+    post_entry(tx, account, kind, -cost, note, Some(event.id), &usage.meta).await?;
 
+    Ok(MeterResult { allowed: true, blocked_reason: None, balance_cents: account.balance_cents, month_spend_cents: account.month_spend_cents, monthly_limit_cents: account.monthly_limit_cents })
+}
+
+/// Adds `amount_cents` to the balance of `account`, which `tx` holds locked, and records the
+/// ledger entry. Also writes back the month's spend, which charges raise first.
+async fn post_entry(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    account: &mut BillingAccount,
+    kind: &str,
+    amount_cents: i64,
+    note: Option<&str>,
+    usage_event_id: Option<Uuid>,
+    meta: &serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    account.balance_cents += amount_cents;
     sqlx::query(
         "UPDATE billing_accounts SET balance_cents = $2, month_spend_cents = $3, updated_at = now() WHERE user_id = $1",
     )
-    .bind(user_id)
-    .bind(new_balance)
-    .bind(new_spend)
-    .execute(&mut *tx)
+    .bind(account.user_id)
+    .bind(account.balance_cents)
+    .bind(account.month_spend_cents)
+    .execute(&mut **tx)
     .await?;
-
     sqlx::query(
-        "INSERT INTO ledger_entries (user_id, type, amount_cents, balance_after_cents, currency, ref_usage_event_id, meta) VALUES ($1,$2,$3,$4,$5,$6,$7)",
+        "INSERT INTO ledger_entries (user_id, type, amount_cents, balance_after_cents, currency, ref_usage_event_id, note, meta) VALUES ($1,$2,$3,$4,$5,$6,$7,$8)",
     )
-    .bind(user_id)
-    .bind("debit")
-    .bind(-cost)
-    .bind(new_balance)
+    .bind(account.user_id)
+    .bind(kind)
+    .bind(amount_cents)
+    .bind(account.balance_cents)
     .bind(&account.currency)
-    .bind(usage.id)
+    .bind(usage_event_id)
+    .bind(note)
     .bind(meta)
-    .execute(&mut *tx)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum PurchaseError {
+    #[error("work not found")]
+    NotFound,
+    #[error("work is not listed")]
+    NotListed,
+    #[error("you own this work")]
+    OwnWork,
+    #[error("work already purchased")]
+    AlreadyPurchased,
+    #[error("work is priced in {work} but the account uses {account}")]
+    CurrencyMismatch { work: String, account: String },
+    #[error("balance {balance_cents} is below the price {price_cents}")]
+    InsufficientBalance { balance_cents: i64, price_cents: i64 },
+    #[error("the price would take this month's spend of {month_spend_cents} past the limit {monthly_limit_cents}")]
+    MonthlyLimit { month_spend_cents: i64, monthly_limit_cents: i64 },
+    #[error(transparent)]
+    Db(#[from] sqlx::Error),
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct PurchaseReceipt {
+    pub purchase: WorkPurchase,
+    pub balance_cents: i64,
+}
+
+/// `(seller_cents, fee_cents)` of a sale: the fee is `fee_bps` of the price, rounded half up.
+pub fn split_sale(price_cents: i64, fee_bps: i64) -> (i64, i64) {
+    let fee = ((price_cents * fee_bps.clamp(0, 10_000) + 5_000) / 10_000).min(price_cents);
+    (price_cents - fee, fee)
+}
+
+/// Buys a listed work in one transaction: charges the buyer (`purchase`) like metered
+/// usage, so the monthly limit and spend apply, credits the seller (`sale`) less the
+/// platform fee, credits the fee to `platform_user_id` (`fee`) and records the purchase.
+/// Without a platform account no fee is taken, so the ledger always balances. Free works
+/// only record the purchase.
+pub async fn purchase_work(
+    pool: &PgPool,
+    buyer_id: Uuid,
+    work_id: Uuid,
+    fee_bps: i64,
+    platform_user_id: Option<Uuid>,
+) -> Result<PurchaseReceipt, PurchaseError> {
+    let mut tx = pool.begin().await?;
+
+    // FOR SHARE keeps the price and listing fixed until the purchase commits.
+    let work = sqlx::query_as::<_, Work>("SELECT * FROM works WHERE id = $1 FOR SHARE")
+        .bind(work_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(PurchaseError::NotFound)?;
+    // Works whose seller was deleted are unlisted as well.
+    let Some(seller_id) = work.user_id.filter(|_| work.is_listed) else {
+        return Err(PurchaseError::NotListed);
+    };
+    if seller_id == buyer_id {
+        return Err(PurchaseError::OwnWork);
+    }
+    let owned = sqlx::query_scalar::<_, Uuid>(
+        "SELECT id FROM work_purchases WHERE buyer_id = $1 AND work_id = $2",
+    )
+    .bind(buyer_id)
+    .bind(work_id)
+    .fetch_optional(&mut *tx)
     .await?;
+    if owned.is_some() {
+        return Err(PurchaseError::AlreadyPurchased);
+    }
+
+    let price = work.price_cents;
+    let fee_bps = if platform_user_id.is_some() { fee_bps } else { 0 };
+    let (seller_cents, fee_cents) = split_sale(price, fee_bps);
+    let platform = platform_user_id.filter(|_| fee_cents > 0);
+
+    let mut parties = vec![buyer_id, seller_id];
+    parties.extend(platform);
+    parties.sort();
+    parties.dedup();
+    for user_id in &parties {
+        sqlx::query("INSERT INTO billing_accounts (user_id) VALUES ($1) ON CONFLICT (user_id) DO NOTHING")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    // Locked in user_id order so concurrent purchases between the same accounts cannot deadlock.
+    let mut accounts: BTreeMap<Uuid, BillingAccount> = sqlx::query_as::<_, BillingAccount>(
+        "SELECT * FROM billing_accounts WHERE user_id = ANY($1) ORDER BY user_id FOR UPDATE",
+    )
+    .bind(&parties)
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|a| (a.user_id, a))
+    .collect();
+    if price > 0 {
+        if let Some(a) = accounts.values().find(|a| a.currency != work.currency) {
+            return Err(PurchaseError::CurrencyMismatch {
+                work: work.currency.clone(),
+                account: a.currency.clone(),
+            });
+        }
+    }
 
+    let purchase_id = Uuid::new_v4();
+    let meta = serde_json::json!({
+        "work_id": work.id,
+        "purchase_id": purchase_id,
+        "buyer_id": buyer_id,
+        "seller_id": seller_id,
+        "price_cents": price,
+        "fee_cents": fee_cents,
+    });
+    if price > 0 {
+        let usage = Usage {
+            route: "/api/works/:work_id/purchase",
+            units: 1,
+            unit_price_cents: price,
+            request_id: None,
+            meta: meta.clone(),
+        };
+        let account = accounts.get_mut(&buyer_id).expect("buyer account");
+        let charged = charge(&mut tx, account, &usage, "purchase", Some("work_purchase")).await?;
+        if let Some(reason) = charged.blocked_reason {
+            // Keep the refused usage event, like metered requests do.
+            tx.commit().await?;
+            return Err(if reason == "monthly_limit" {
+                PurchaseError::MonthlyLimit {
+                    month_spend_cents: charged.month_spend_cents,
+                    monthly_limit_cents: charged.monthly_limit_cents,
+                }
+            } else {
+                PurchaseError::InsufficientBalance {
+                    balance_cents: charged.balance_cents,
+                    price_cents: price,
+                }
+            });
+        }
+    }
+
+    let purchase = sqlx::query_as::<_, WorkPurchase>(
+        "INSERT INTO work_purchases (id, work_id, buyer_id, seller_id, price_cents, fee_cents, currency, meta) VALUES ($1,$2,$3,$4,$5,$6,$7,$8) RETURNING *",
+    )
+    .bind(purchase_id)
+    .bind(work.id)
+    .bind(buyer_id)
+    .bind(seller_id)
+    .bind(price)
+    .bind(fee_cents)
+    .bind(&work.currency)
+    .bind(serde_json::json!({ "title": work.title, "fee_bps": fee_bps }))
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(d) if d.is_unique_violation() => PurchaseError::AlreadyPurchased,
+        e => PurchaseError::Db(e),
+    })?;
+
+    if price > 0 {
+        let account = accounts.get_mut(&seller_id).expect("seller account");
+        post_entry(&mut tx, account, "sale", seller_cents, Some("work_sale"), None, &meta).await?;
+        if let Some(platform) = platform {
+            let account = accounts.get_mut(&platform).expect("platform account");
+            post_entry(&mut tx, account, "fee", fee_cents, Some("work_sale_fee"), None, &meta).await?;
+        }
+    }
     tx.commit().await?;
 
-    Ok(MeterResult { allowed: true, balance_cents: new_balance, month_spend_cents: new_spend, monthly_limit_cents: account.monthly_limit_cents })
+    Ok(PurchaseReceipt {
+        balance_cents: accounts[&buyer_id].balance_cents,
+        purchase,
+    })
+}
+
+/// Artifact keys of `run_id` that `user_id` may read as the owner or a buyer of a work the
+/// run backs: the ones the work's assets point at. Empty when they have no such work.
+pub async fn entitled_artifact_keys(
+    pool: &PgPool,
+    user_id: Uuid,
+    run_id: &str,
+) -> Result<BTreeSet<String>, sqlx::Error> {
+    let keys = sqlx::query_scalar::<_, String>(
+        "SELECT DISTINCT a.meta->>'artifact_key' FROM works w
+         JOIN work_assets a ON a.work_id = w.id
+         LEFT JOIN work_purchases p ON p.work_id = w.id AND p.buyer_id = $1
+         WHERE w.meta->>'run_id' = $2 AND (w.user_id = $1 OR p.id IS NOT NULL)
+           AND a.meta->>'run_id' = $2 AND a.meta ? 'artifact_key'",
+    )
+    .bind(user_id)
+    .bind(run_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(keys.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sale_split_rounds_fee_half_up() {
+        assert_eq!(split_sale(1000, 0), (1000, 0));
+        assert_eq!(split_sale(1000, 1500), (850, 150));
+        assert_eq!(split_sale(199, 1000), (179, 20));
+        assert_eq!(split_sale(5, 1000), (4, 1));
+        assert_eq!(split_sale(4, 1000), (4, 0));
+        assert_eq!(split_sale(300, 10_000), (0, 300));
+        assert_eq!(split_sale(300, 20_000), (0, 300));
+    }
+
+    #[tokio::test]
+    async fn purchases_are_refused_past_the_monthly_limit() {
+        let Some(pool) = crate::db::test_pool().await else { return };
+        let buyer = crate::db::test_user(&pool).await;
+        let seller = crate::db::test_user(&pool).await;
+        ensure_account(&pool, buyer).await.unwrap();
+        sqlx::query(
+            "UPDATE billing_accounts SET balance_cents = 10000, monthly_limit_cents = 500 WHERE user_id = $1",
+        )
+        .bind(buyer)
+        .execute(&pool)
+        .await
+        .unwrap();
+        let mut works = Vec::new();
+        for _ in 0..2 {
+            let id: Uuid = sqlx::query_scalar(
+                "INSERT INTO works (user_id, kind, price_cents, is_listed) VALUES ($1, 'mv', 300, true) RETURNING id",
+            )
+            .bind(seller)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+            works.push(id);
+        }
+
+        let receipt = purchase_work(&pool, buyer, works[0], 0, None).await.unwrap();
+        assert_eq!(receipt.balance_cents, 9700);
+        let refused = purchase_work(&pool, buyer, works[1], 0, None).await.unwrap_err();
+        assert!(
+            matches!(
+                refused,
+                PurchaseError::MonthlyLimit { month_spend_cents: 300, monthly_limit_cents: 500 }
+            ),
+            "{refused:?}"
+        );
+
+        let (account, _) = ensure_account(&pool, buyer).await.unwrap();
+        assert_eq!((account.balance_cents, account.month_spend_cents), (9700, 300));
+        let bought: i64 =
+            sqlx::query_scalar("SELECT count(*) FROM work_purchases WHERE buyer_id = $1")
+                .bind(buyer)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(bought, 1);
+        let blocked: Vec<String> = sqlx::query_scalar(
+            "SELECT blocked_reason FROM usage_events WHERE user_id = $1 AND NOT allowed",
+        )
+        .bind(buyer)
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(blocked, ["monthly_limit"]);
+    }
 }
@@ -1,17 +1,554 @@
-use std::path::PathBuf;
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
-#[path = "../video_executor.rs"]
-mod video_executor;
+use cssos_rust_api::{
+    artifact_store, asset_store, audio, dag, dag_export, dag_viz_html, dsl, encoder, run_state,
+    run_state_io, runner, video, video_executor,
+};
 
-fn main() -> anyhow::Result<()> {
-    let out = video_executor::run_video_executor_v1(
-        PathBuf::from("build/storyboard.json").as_path(),
+const USAGE: &str = "\
+usage: video_exec [global flags] <command> [args]
+
+commands:
+  render [storyboard]      render shots and concat (default build/storyboard.json)
+                           --profile draft|standard|master  --reproducible
+  verify [manifest]        re-render and diff against a render manifest
+  validate [storyboard]    check a storyboard without rendering
+  plan                     write an auto storyboard
+                           --duration S --shots N --seed N --fps N --w N --h N
+                           --cuts even|beat|bar|phrase --music WAV
+                           --outputs vertical,square  -o PATH
+  run <dsl-file>           run the whole pipeline locally in build/runs/<ID>
+                           --run-id ID --profile P --reproducible
+  status <run_dir>         print stage status of a run
+  dag [--html]             write build/dag.json (and build/dag.html)
+                           --run RUN_DIR for stage status  -o PATH
+
+global flags:
+  --ffmpeg PATH            ffmpeg binary (default ffmpeg; ffprobe is taken from its directory)
+  --concurrency N          parallel shot renders (default 2)
+  --workdir DIR            project directory holding build/ (default .)
+  --assets DIR             asset store root
+";
+
+/// Flags that take a value; everything else starting with `-` is a switch.
+const VALUE_FLAGS: [&str; 18] = [
+    "ffmpeg",
+    "concurrency",
+    "workdir",
+    "assets",
+    "profile",
+    "duration",
+    "shots",
+    "seed",
+    "fps",
+    "w",
+    "h",
+    "cuts",
+    "music",
+    "outputs",
+    "o",
+    "out",
+    "run-id",
+    "run",
+];
+
+struct Args {
+    positional: Vec<String>,
+    flags: BTreeMap<String, String>,
+    switches: Vec<String>,
+}
+
+impl Args {
+    fn parse(raw: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut out = Args {
+            positional: Vec::new(),
+            flags: BTreeMap::new(),
+            switches: Vec::new(),
+        };
+        let mut it = raw.into_iter();
+        while let Some(a) = it.next() {
+            let Some(name) = a.strip_prefix("--").or_else(|| a.strip_prefix('-')) else {
+                out.positional.push(a);
+                continue;
+            };
+            if name.is_empty() {
+                out.positional.extend(it.by_ref());
+                break;
+            }
+            if let Some((k, v)) = name.split_once('=') {
+                out.flags.insert(k.to_string(), v.to_string());
+            } else if VALUE_FLAGS.contains(&name) {
+                let v = it
+                    .next()
+                    .with_context(|| format!("flag --{name} needs a value"))?;
+                out.flags.insert(name.to_string(), v);
+            } else {
+                out.switches.push(name.to_string());
+            }
+        }
+        Ok(out)
+    }
+
+    fn flag(&self, name: &str) -> Option<&str> {
+        self.flags.get(name).map(|s| s.as_str())
+    }
+
+    fn switch(&self, name: &str) -> bool {
+        self.switches.iter().any(|s| s == name)
+    }
+
+    fn num<T: std::str::FromStr>(&self, name: &str, default: T) -> Result<T> {
+        match self.flag(name) {
+            Some(v) => v
+                .parse()
+                .map_err(|_| anyhow::anyhow!("--{name}: not a number: {v}")),
+            None => Ok(default),
+        }
+    }
+
+    fn path_or(&self, idx: usize, default: &str) -> PathBuf {
+        PathBuf::from(
+            self.positional
+                .get(idx)
+                .map(|s| s.as_str())
+                .unwrap_or(default),
+        )
+    }
+}
+
+struct Ctx {
+    ffmpeg: String,
+    concurrency: usize,
+    assets_root: PathBuf,
+}
+
+impl Ctx {
+    /// Tool flags, with paths resolved against `cwd`.
+    fn from_args(args: &Args, cwd: &Path) -> Result<Self> {
+        let abs = |p: &str| cwd.join(p);
+        Ok(Self {
+            ffmpeg: match args.flag("ffmpeg") {
+                Some(p) if p.contains('/') => abs(p).display().to_string(),
+                Some(p) => p.to_string(),
+                None => "ffmpeg".to_string(),
+            },
+            concurrency: args.num("concurrency", 2usize)?.max(1),
+            assets_root: args
+                .flag("assets")
+                .map(abs)
+                .unwrap_or_else(asset_store::default_root),
+        })
+    }
+
+    fn stage_options(
+        &self,
+        store: Option<std::sync::Arc<dyn artifact_store::ArtifactStore>>,
+    ) -> runner::VideoStageOptions {
+        runner::VideoStageOptions {
+            ffmpeg_path: self.ffmpeg.clone(),
+            concurrency: self.concurrency,
+            assets_root: self.assets_root.clone(),
+            store,
+            ..Default::default()
+        }
+    }
+
+    fn exec_config(&self, args: &Args) -> video_executor::VideoExecConfig {
         video_executor::VideoExecConfig {
-            ffmpeg_path: "ffmpeg".to_string(),
-            concurrency: 2,
+            ffmpeg_path: self.ffmpeg.clone(),
+            concurrency: self.concurrency,
             workdir: PathBuf::from("build/video"),
-        },
-    )?;
-    println!("{}", out.video_mp4.display());
+            assets_root: self.assets_root.clone(),
+            asset_scope: asset_store::AssetScope::Any,
+            profile: profile_arg(args),
+            reproducible: args.switch("reproducible"),
+        }
+    }
+}
+
+fn profile_arg(args: &Args) -> encoder::EncoderProfile {
+    encoder::EncoderProfile::resolve(
+        args.flag("profile")
+            .map(|s| serde_json::Value::String(s.to_string()))
+            .as_ref(),
+        "local",
+    )
+}
+
+fn print_json<T: serde::Serialize>(v: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(v)?);
     Ok(())
 }
+
+fn main() {
+    let raw: Vec<String> = std::env::args().skip(1).collect();
+    match run(raw) {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("video_exec: {e:#}");
+            std::process::exit(2);
+        }
+    }
+}
+
+fn run(raw: Vec<String>) -> Result<i32> {
+    let args = Args::parse(raw)?;
+    let Some(cmd) = args.positional.first().cloned() else {
+        eprint!("{USAGE}");
+        return Ok(2);
+    };
+    if args.switch("help") || cmd == "help" {
+        print!("{USAGE}");
+        return Ok(0);
+    }
+
+    // Paths on the command line are relative to where we were started, not to --workdir.
+    let cwd = std::env::current_dir()?;
+    let abs = |p: &str| {
+        let p = PathBuf::from(p);
+        if p.is_absolute() {
+            p
+        } else {
+            cwd.join(p)
+        }
+    };
+    let ctx = Ctx::from_args(&args, &cwd)?;
+    let mut args = args;
+    args.positional = args.positional[1..]
+        .iter()
+        .map(|p| abs(p).display().to_string())
+        .collect();
+    for k in ["o", "out", "run", "music"] {
+        if let Some(v) = args.flags.get_mut(k) {
+            *v = abs(v).display().to_string();
+        }
+    }
+
+    let workdir = abs(args.flag("workdir").unwrap_or("."));
+    std::fs::create_dir_all(&workdir)
+        .with_context(|| format!("create workdir {}", workdir.display()))?;
+    std::env::set_current_dir(&workdir)
+        .with_context(|| format!("enter workdir {}", workdir.display()))?;
+
+    match cmd.as_str() {
+        "render" => cmd_render(&ctx, &args),
+        "verify" => cmd_verify(&ctx, &args),
+        "validate" => cmd_validate(&ctx, &args),
+        "plan" => cmd_plan(&args),
+        "run" => cmd_run(&ctx, &args),
+        "status" => cmd_status(&args),
+        "dag" => cmd_dag(&args),
+        other => {
+            eprint!("unknown command: {other}\n\n{USAGE}");
+            Ok(2)
+        }
+    }
+}
+
+fn cmd_render(ctx: &Ctx, args: &Args) -> Result<i32> {
+    let storyboard = args.path_or(0, "build/storyboard.json");
+    let out = video_executor::run_video_executor_v1(&storyboard, ctx.exec_config(args))?;
+    print_json(&out)?;
+    Ok(0)
+}
+
+fn cmd_verify(ctx: &Ctx, args: &Args) -> Result<i32> {
+    let cfg = ctx.exec_config(args);
+    let manifest = args.path_or(0, "build/video/render_manifest.json");
+    let storyboard = args.path_or(1, "build/storyboard.json");
+    let report = video_executor::verify_render_v1(&storyboard, cfg, &manifest)?;
+    print_json(&report)?;
+    Ok(if report.matched { 0 } else { 1 })
+}
+
+fn cmd_validate(ctx: &Ctx, args: &Args) -> Result<i32> {
+    let storyboard = args.path_or(0, "build/storyboard.json");
+    let errors = match std::fs::read_to_string(&storyboard)
+        .map_err(anyhow::Error::from)
+        .and_then(|s| Ok(serde_json::from_str::<video_executor::StoryboardV1>(&s)?))
+    {
+        Ok(sb) => video_executor::validate_storyboard_v1(&sb, &ctx.assets_root),
+        Err(e) => vec![format!("read storyboard: {e}")],
+    };
+    print_json(&serde_json::json!({
+        "schema": "css.video.storyboard_check.v1",
+        "storyboard": storyboard.display().to_string(),
+        "ok": errors.is_empty(),
+        "errors": errors,
+    }))?;
+    Ok(if errors.is_empty() { 0 } else { 1 })
+}
+
+fn cmd_plan(args: &Args) -> Result<i32> {
+    use video::storyboard::{build_storyboard_auto, output_targets_from_value, AutoPlan, CutMode};
+
+    let cut_mode = match args.flag("cuts") {
+        Some(s) => CutMode::parse(s).with_context(|| format!("--cuts: unknown mode {s}"))?,
+        None => CutMode::Even,
+    };
+    let beats = match args.flag("music") {
+        Some(p) => Some(audio::beats::analyze_wav(Path::new(p))?),
+        None => None,
+    };
+    let outputs = args.flag("outputs").map(|s| {
+        serde_json::Value::Array(
+            s.split(',')
+                .filter(|x| !x.trim().is_empty())
+                .map(|x| serde_json::Value::String(x.trim().to_string()))
+                .collect(),
+        )
+    });
+    let plan = AutoPlan {
+        seed: args.num("seed", 0u64)?,
+        duration_s: args.num("duration", 8.0f64)?,
+        shots_n: args.num("shots", 8usize)?.max(1),
+        fps: args.num("fps", 30u32)?,
+        w: args.num("w", 1280u32)?,
+        h: args.num("h", 720u32)?,
+        cut_mode,
+        outputs: output_targets_from_value(outputs.as_ref()).map_err(anyhow::Error::msg)?,
+    };
+    if !(plan.duration_s.is_finite() && plan.duration_s > 0.0) {
+        bail!("--duration must be positive");
+    }
+    let sb = build_storyboard_auto(&plan, beats.as_ref());
+    let out = PathBuf::from(
+        args.flag("o")
+            .or(args.flag("out"))
+            .unwrap_or("build/storyboard.json"),
+    );
+    if let Some(dir) = out.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&out, serde_json::to_vec_pretty(&sb)?)
+        .with_context(|| format!("write storyboard: {}", out.display()))?;
+    println!("{}", out.display());
+    Ok(0)
+}
+
+fn cmd_run(ctx: &Ctx, args: &Args) -> Result<i32> {
+    use run_state::{RetryPolicy, RunConfig, RunState, RunStatus};
+
+    let now = chrono::Utc::now();
+    let run_id = args
+        .flag("run-id")
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| format!("run_{}", now.format("%Y%m%d_%H%M%S")));
+    if !run_state_io::is_valid_run_id(&run_id) {
+        bail!("--run-id: only letters, digits, '-' and '_' are allowed: {run_id}");
+    }
+
+    let dsl_path = args
+        .positional
+        .first()
+        .context("run needs a DSL file")?;
+    let src = std::fs::read_to_string(dsl_path).with_context(|| format!("read {dsl_path}"))?;
+    let compiled = dsl::compile::compile_from_dsl(&src)?;
+    let out_dir = PathBuf::from("build/runs").join(&run_id);
+    std::fs::create_dir_all(&out_dir)?;
+    let topo_order = dag::cssmv_dag_v1()
+        .topo_order()
+        .map_err(|e| anyhow::anyhow!("{e}"))?
+        .into_iter()
+        .map(|s| s.to_string())
+        .collect();
+    let state = RunState {
+        schema: "css.pipeline.run.v1".to_string(),
+        run_id,
+        created_at: now.to_rfc3339(),
+        updated_at: now.to_rfc3339(),
+        status: RunStatus::INIT,
+        ui_lang: "auto".to_string(),
+        tier: "local".to_string(),
+        user_id: None,
+        cssl: "video_exec".to_string(),
+        commands: serde_json::json!({
+            "schema": "css.pipeline.commands.v1",
+            "song": compiled.song.clone(),
+            "video": {
+                "encoder": profile_arg(args),
+                "reproducible": args.switch("reproducible"),
+            },
+        }),
+        config: RunConfig {
+            out_dir: out_dir.clone(),
+            wiki_enabled: false,
+            civ_linked: false,
+        },
+        retry_policy: RetryPolicy {
+            max_retries: 3,
+            backoff_base_seconds: 2,
+            strategy: "exponential".to_string(),
+        },
+        dag: dag::cssmv_dag_v1().meta(),
+        topo_order,
+        artifacts: Default::default(),
+        storage: None,
+        stages: Default::default(),
+        video_shots_total: 0,
+        video_shots_ready: 0,
+        video_shots_running: 0,
+    };
+    // Uploading is opt-in here; without ARTIFACT_STORE outputs stay in the run directory.
+    let store = match std::env::var_os("ARTIFACT_STORE") {
+        Some(_) => Some(
+            artifact_store::StoreConfig::from_env(Path::new("build/runs"))
+                .map_err(|e| anyhow::anyhow!(e))?
+                .open(),
+        ),
+        None => None,
+    };
+    let opts = ctx.stage_options(store);
+    let state = runner::run_pipeline_with(&out_dir.join("run.json"), state, compiled, &opts)?;
+    print_status(&serde_json::to_value(&state)?, &out_dir);
+    Ok(if matches!(state.status, RunStatus::SUCCEEDED) { 0 } else { 1 })
+}
+
+fn cmd_status(args: &Args) -> Result<i32> {
+    let dir = args.path_or(0, "build");
+    let path = if dir.is_dir() { dir.join("run.json") } else { dir.clone() };
+    let s = std::fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))?;
+    let v: serde_json::Value = serde_json::from_str(&s)?;
+    print_status(&v, &path);
+    let ok = v.get("status").and_then(|x| x.as_str()) == Some("SUCCEEDED");
+    Ok(if ok { 0 } else { 1 })
+}
+
+/// One line per stage in run order, failures indented below.
+fn print_status(v: &serde_json::Value, from: &Path) {
+    let str_at = |v: &serde_json::Value, k: &str| {
+        v.get(k)
+            .and_then(|x| x.as_str())
+            .unwrap_or("-")
+            .to_string()
+    };
+    println!(
+        "run {} ({}) {}",
+        str_at(v, "run_id"),
+        from.display(),
+        str_at(v, "status")
+    );
+    let stages = v
+        .get("stages")
+        .and_then(|x| x.as_object())
+        .cloned()
+        .unwrap_or_default();
+    let mut order: Vec<String> = v
+        .get("topo_order")
+        .and_then(|x| x.as_array())
+        .map(|a| {
+            a.iter()
+                .filter_map(|s| s.as_str().map(|s| s.to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+    for k in stages.keys() {
+        if !order.contains(k) {
+            order.push(k.clone());
+        }
+    }
+    for name in order {
+        let Some(rec) = stages.get(&name) else {
+            println!("  {name:<12} PENDING");
+            continue;
+        };
+        let retries = rec.get("retries").and_then(|x| x.as_u64()).unwrap_or(0);
+        let retries = if retries > 0 {
+            format!(" retries={retries}")
+        } else {
+            String::new()
+        };
+        println!("  {name:<12} {}{retries}", str_at(rec, "status"));
+        if let Some(f) = rec.get("failure").filter(|f| !f.is_null()) {
+            println!("      {}: {}", str_at(f, "reason"), str_at(f, "message"));
+        } else if let Some(e) = rec.get("error").and_then(|x| x.as_str()) {
+            println!("      {e}");
+        }
+    }
+}
+
+fn cmd_dag(args: &Args) -> Result<i32> {
+    let state = match args.flag("run") {
+        Some(p) => {
+            let p = Path::new(p);
+            let path = if p.is_dir() { p.join("run.json") } else { p.to_path_buf() };
+            let s = std::fs::read_to_string(&path)
+                .with_context(|| format!("read {}", path.display()))?;
+            serde_json::from_str(&s)?
+        }
+        None => serde_json::json!({}),
+    };
+    let json_path = PathBuf::from(
+        args.flag("o")
+            .or(args.flag("out"))
+            .unwrap_or("build/dag.json"),
+    );
+    dag_export::write_dag_json(&json_path, &dag::cssmv_dag_v1(), &state)?;
+    println!("{}", json_path.display());
+    if args.switch("html") {
+        let export: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&json_path)?)?;
+        let html_path = json_path.with_extension("html");
+        dag_viz_html::write_dag_html(&html_path, &export)?;
+        println!("{}", html_path.display());
+    }
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args> {
+        Args::parse(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn args_split_flags_switches_and_positionals() {
+        let raw = "plan --shots 6 --cuts=beat --reproducible -o sb.json -- --not-a-flag";
+        let a = parse(&raw.split(' ').collect::<Vec<_>>()).unwrap();
+        assert_eq!(a.positional, ["plan", "--not-a-flag"]);
+        assert_eq!(a.flag("cuts"), Some("beat"));
+        assert_eq!(a.flag("o"), Some("sb.json"));
+        assert!(a.switch("reproducible") && !a.switch("html"));
+        assert_eq!(a.num("shots", 1u32).unwrap(), 6);
+        assert_eq!(a.num("seed", 7u64).unwrap(), 7);
+        assert_eq!(a.path_or(1, "x"), PathBuf::from("--not-a-flag"));
+    }
+
+    #[test]
+    fn bad_args_are_errors() {
+        assert!(parse(&["render", "--profile"]).is_err());
+        let a = parse(&["plan", "--shots", "many"]).unwrap();
+        assert!(a.num("shots", 1u32).is_err());
+    }
+
+    #[test]
+    fn h_is_the_height_flag_and_run_ids_stay_in_build_runs() {
+        let a = parse(&["plan", "-h", "1080"]).unwrap();
+        assert_eq!(a.num("h", 720u32).unwrap(), 1080);
+        assert!(parse(&["plan", "-h"]).is_err());
+
+        let ctx = Ctx::from_args(&parse(&[]).unwrap(), Path::new("/work")).unwrap();
+        for id in ["../x", "a/b", ""] {
+            let a = parse(&["mv.dsl", "--run-id", id]).unwrap();
+            let err = cmd_run(&ctx, &a).unwrap_err().to_string();
+            assert!(err.starts_with("--run-id"), "{id}: {err}");
+        }
+    }
+
+    #[test]
+    fn run_takes_ffmpeg_and_assets_flags() {
+        let a = parse(&["run", "mv.dsl", "--ffmpeg", "bin/ffmpeg", "--assets", "assets"]).unwrap();
+        let opts = Ctx::from_args(&a, Path::new("/work")).unwrap().stage_options(None);
+        assert_eq!(opts.ffmpeg_path, "/work/bin/ffmpeg");
+        assert_eq!(opts.assets_root, PathBuf::from("/work/assets"));
+
+        let a = parse(&["run", "mv.dsl", "--ffmpeg", "ffmpeg7"]).unwrap();
+        let opts = Ctx::from_args(&a, Path::new("/work")).unwrap().stage_options(None);
+        assert_eq!(opts.ffmpeg_path, "ffmpeg7");
+        assert_eq!(opts.assets_root, asset_store::default_root());
+    }
+}
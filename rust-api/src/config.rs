@@ -1,4 +1,5 @@
 use std::env;
+use std::path::PathBuf;
 
 #[derive(Clone)]
 pub struct Config {
@@ -8,6 +9,23 @@ pub struct Config {
     pub session_ttl_days: i64,
     pub billing_unit_price_cents: i64,
     pub env: String,
+    pub assets_dir: PathBuf,
+    pub assets_max_bytes: u64,
+    pub fonts_dir: Option<PathBuf>,
+    pub ffmpeg_path: String,
+    pub ffprobe_path: String,
+    /// Shots each video stage renders at once.
+    pub video_concurrency: usize,
+    /// HMAC key for shareable artifact URLs; sharing is off without it.
+    pub artifact_signing_key: Option<String>,
+    pub runs_dir: PathBuf,
+    /// Share of each work sale kept by the platform, in basis points (0..=10000).
+    pub platform_fee_bps: i64,
+    /// Billing account credited with platform fees; required when `platform_fee_bps > 0`.
+    pub platform_fee_user_id: Option<uuid::Uuid>,
+    pub artifact_store: crate::artifact_store::StoreConfig,
+    pub retention: crate::retention::RetentionPolicy,
+    pub jobs: crate::jobs::QueueConfig,
 }
 
 impl Config {
@@ -25,6 +43,57 @@ impl Config {
             .and_then(|v| v.parse().ok())
             .unwrap_or(1);
         let env = env::var("RUST_ENV").unwrap_or_else(|_| "production".to_string());
+        let assets_dir = crate::asset_store::default_root();
+        let assets_max_bytes = env::var("ASSETS_MAX_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(200 * 1024 * 1024);
+        let fonts_dir = env::var("FONTS_DIR")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from);
+        let ffmpeg_path = env::var("FFMPEG")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| "ffmpeg".to_string());
+        let ffprobe_path = env::var("FFPROBE")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| crate::media_probe::ffprobe_for(&ffmpeg_path));
+        let video_concurrency = env::var("VIDEO_CONCURRENCY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n| n > 0)
+            .unwrap_or(2);
+        let artifact_signing_key = env::var("ARTIFACT_SIGNING_KEY")
+            .ok()
+            .filter(|v| !v.is_empty());
+        let runs_dir = env::var("RUNS_DIR")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("build/runs"));
+        let platform_fee_bps = match env::var("PLATFORM_FEE_BPS") {
+            Ok(v) if !v.is_empty() => v
+                .parse()
+                .ok()
+                .filter(|bps| (0..=10_000).contains(bps))
+                .ok_or_else(|| format!("PLATFORM_FEE_BPS={v} is not between 0 and 10000"))?,
+            _ => 0,
+        };
+        let platform_fee_user_id = match env::var("PLATFORM_FEE_USER_ID") {
+            Ok(v) if !v.is_empty() => Some(
+                uuid::Uuid::parse_str(&v)
+                    .map_err(|_| format!("PLATFORM_FEE_USER_ID={v} is not a uuid"))?,
+            ),
+            _ => None,
+        };
+        if platform_fee_bps > 0 && platform_fee_user_id.is_none() {
+            return Err("PLATFORM_FEE_BPS needs PLATFORM_FEE_USER_ID".to_string());
+        }
+        let artifact_store = crate::artifact_store::StoreConfig::from_env(&runs_dir)?;
+        let retention = crate::retention::RetentionPolicy::from_env()?;
+        let jobs = crate::jobs::QueueConfig::from_env()?;
         Ok(Self {
             database_url,
             bind_addr,
@@ -32,6 +101,19 @@ impl Config {
             session_ttl_days,
             billing_unit_price_cents,
             env,
+            assets_dir,
+            assets_max_bytes,
+            fonts_dir,
+            ffmpeg_path,
+            ffprobe_path,
+            video_concurrency,
+            artifact_signing_key,
+            runs_dir,
+            platform_fee_bps,
+            platform_fee_user_id,
+            artifact_store,
+            retention,
+            jobs,
         })
     }
 }
@@ -45,6 +45,9 @@ pub struct CreateRunRequestV1 {
     pub input: serde_json::Value,
     #[serde(default)]
     pub commands: serde_json::Value,
+    /// Default subtitle language when `video.subtitles.langs` is not given.
+    #[serde(default)]
+    pub ui_lang: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
@@ -72,6 +75,186 @@ pub struct RunsListV1 {
     pub items: Vec<RunsListItemV1>,
 }
 
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ArtifactV1 {
+    pub key: String,
+    pub kind: String,
+    /// Relative to the run directory.
+    pub path: Option<String>,
+    pub mime: Option<String>,
+    pub size: Option<u64>,
+    pub sha256: Option<String>,
+    pub produced_by_stage: Option<String>,
+    pub created_at: String,
+    /// Set for `kind: value` (counts, metrics) and as extra detail on some files.
+    pub value: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ArtifactsV1 {
+    pub schema: String,
+    pub run_id: String,
+    pub artifacts: Vec<ArtifactV1>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ShareArtifactRequestV1 {
+    /// Artifact key or path.
+    pub key: String,
+    /// Link lifetime in seconds; default 3600, at most 7 days.
+    pub ttl_s: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ArtifactShareV1 {
+    pub schema: String,
+    pub run_id: String,
+    pub key: String,
+    /// Path with `expires` and `sig` query parameters; usable without a session.
+    pub url: String,
+    pub expires_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct WorkAssetV1 {
+    pub id: String,
+    /// `video`, `stream`, `thumbnail`, `subtitles` or `audio`.
+    pub asset_type: String,
+    /// Artifact download path of the source run.
+    pub url: String,
+    /// Artifact key, mime, size and sha256, plus `role`, `lang` etc. by type.
+    pub meta: serde_json::Value,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct WorkV1 {
+    pub schema: String,
+    pub id: String,
+    /// `mv` or `opera`.
+    pub kind: String,
+    pub title: String,
+    /// `published` when listed, otherwise `ready`.
+    pub status: String,
+    pub run_id: Option<String>,
+    pub meta: serde_json::Value,
+    pub price_cents: i64,
+    pub currency: String,
+    pub is_listed: bool,
+    pub created_at: String,
+    pub updated_at: String,
+    pub assets: Vec<WorkAssetV1>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct WorksV1 {
+    pub schema: String,
+    pub limit: i64,
+    pub items: Vec<WorkV1>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PublishRunRequestV1 {
+    /// `mv` (default) or `opera`.
+    pub kind: Option<String>,
+    /// Defaults to the song title of the run.
+    pub title: Option<String>,
+    pub meta: Option<serde_json::Value>,
+    pub price_cents: Option<i64>,
+    pub currency: Option<String>,
+    pub is_listed: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UpdateWorkRequestV1 {
+    pub title: Option<String>,
+    pub kind: Option<String>,
+    /// Merged key by key; `null` removes a key. `run_id` and `source` are kept.
+    pub meta: Option<serde_json::Value>,
+    pub price_cents: Option<i64>,
+    pub currency: Option<String>,
+    pub is_listed: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CatalogAssetV1 {
+    pub id: String,
+    pub asset_type: String,
+    /// Redirects to a short-lived signed artifact link.
+    pub url: String,
+    /// Needs the work bought first; thumbnails are never locked.
+    pub locked: bool,
+    pub role: Option<String>,
+    pub lang: Option<String>,
+    pub mime: Option<String>,
+    pub size: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CatalogSellerV1 {
+    /// Null once the seller's account is deleted; their bought works stay with the buyers.
+    pub id: Option<String>,
+    pub display_name: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CatalogWorkV1 {
+    pub schema: String,
+    pub id: String,
+    pub kind: String,
+    pub title: String,
+    pub meta: serde_json::Value,
+    pub price_cents: i64,
+    pub currency: String,
+    pub seller: CatalogSellerV1,
+    pub poster_url: Option<String>,
+    pub owned: bool,
+    pub purchased: bool,
+    /// Owned, bought or free.
+    pub entitled: bool,
+    pub created_at: String,
+    /// Only on the single-work endpoint.
+    pub assets: Option<Vec<CatalogAssetV1>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CatalogV1 {
+    pub schema: String,
+    pub q: Option<String>,
+    pub kind: Option<String>,
+    pub sort: String,
+    pub limit: i64,
+    pub offset: i64,
+    pub total: i64,
+    pub next_offset: Option<i64>,
+    pub items: Vec<CatalogWorkV1>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct WorkPurchaseV1 {
+    pub schema: String,
+    pub id: String,
+    pub work_id: String,
+    pub price_cents: i64,
+    /// Platform share of the price.
+    pub fee_cents: i64,
+    pub currency: String,
+    /// Buyer's balance after the purchase.
+    pub balance_cents: i64,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AssetV1 {
+    pub schema: String,
+    pub id: String,
+    pub mime: String,
+    pub ext: String,
+    pub size: u64,
+    pub original_name: Option<String>,
+    pub created_at: String,
+}
+
 #[utoipa::path(
     get,
     path = "/api/pipeline/status",
@@ -90,7 +273,7 @@ fn _doc_pipeline_status() {}
     path = "/api/pipeline/start",
     request_body = serde_json::Value,
     responses(
-        (status = 200, description = "Start pipeline", body = serde_json::Value),
+        (status = 200, description = "Pipeline run queued", body = serde_json::Value),
         (status = 400, description = "Error", body = ErrorV1)
     )
 )]
@@ -147,6 +330,235 @@ fn _doc_runs_get() {}
 )]
 fn _doc_runs_status() {}
 
+#[utoipa::path(
+    get,
+    path = "/cssapi/v1/runs/{run_id}/artifacts",
+    params(
+        ("run_id" = String, Path, description = "Run id"),
+        ("stage" = Option<String>, Query, description = "only artifacts produced by this stage"),
+        ("kind" = Option<String>, Query, description = "audio, video, image, subtitles, playlist, archive, json, html, text, directory, file or value")
+    ),
+    responses(
+        (status = 200, description = "Typed artifact registry of the run", body = ArtifactsV1),
+        (status = 400, description = "Unknown kind", body = ErrorV1),
+        (status = 401, description = "Not signed in", body = ErrorV1),
+        (status = 403, description = "Not the run's creator nor owner or buyer of its work", body = ErrorV1),
+        (status = 404, description = "Not found", body = ErrorV1)
+    )
+)]
+fn _doc_runs_artifacts() {}
+
+#[utoipa::path(
+    get,
+    path = "/cssapi/v1/runs/{run_id}/artifacts/{key}",
+    params(
+        ("run_id" = String, Path, description = "Run id"),
+        ("key" = String, Path, description = "Artifact key or path (may contain `/`)"),
+        ("expires" = Option<i64>, Query, description = "signed link expiry (unix seconds)"),
+        ("sig" = Option<String>, Query, description = "signed link HMAC"),
+        ("download" = Option<String>, Query, description = "any value sends Content-Disposition: attachment"),
+        ("Range" = Option<String>, Header, description = "single byte range, e.g. bytes=0-1023"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag from an earlier response")
+    ),
+    responses(
+        (status = 200, description = "File contents", content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 206, description = "Requested byte range", content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 304, description = "Not modified"),
+        (status = 307, description = "Presigned URL of the artifact in the remote artifact store"),
+        (status = 401, description = "No session and no signed link", body = ErrorV1),
+        (status = 403, description = "Invalid or expired signature, or not the run's creator nor owner or buyer of its work", body = ErrorV1),
+        (status = 404, description = "Not found", body = ErrorV1),
+        (status = 416, description = "Range not satisfiable")
+    )
+)]
+fn _doc_runs_artifact_download() {}
+
+#[utoipa::path(
+    post,
+    path = "/cssapi/v1/runs/{run_id}/share",
+    params(
+        ("run_id" = String, Path, description = "Run id")
+    ),
+    request_body = ShareArtifactRequestV1,
+    responses(
+        (status = 201, description = "Expiring signed URL for a file artifact", body = ArtifactShareV1),
+        (status = 401, description = "Not signed in", body = ErrorV1),
+        (status = 403, description = "Not the run's creator nor owner or buyer of its work", body = ErrorV1),
+        (status = 404, description = "Not found", body = ErrorV1),
+        (status = 503, description = "ARTIFACT_SIGNING_KEY not configured", body = ErrorV1)
+    )
+)]
+fn _doc_runs_share() {}
+
+#[utoipa::path(
+    post,
+    path = "/cssapi/v1/runs/{run_id}/publish",
+    params(
+        ("run_id" = String, Path, description = "Run id")
+    ),
+    request_body = Option<PublishRunRequestV1>,
+    responses(
+        (status = 201, description = "Work created from the run's final video, thumbnails, subtitles and audio", body = WorkV1),
+        (status = 400, description = "Invalid title, kind, price or currency", body = ErrorV1),
+        (status = 401, description = "Not signed in", body = ErrorV1),
+        (status = 403, description = "Run created by another user", body = ErrorV1),
+        (status = 404, description = "Run not found", body = ErrorV1),
+        (status = 409, description = "Run not succeeded, has no final video, or already published", body = ErrorV1)
+    )
+)]
+fn _doc_runs_publish() {}
+
+#[utoipa::path(
+    get,
+    path = "/cssapi/v1/works",
+    params(
+        ("listed" = Option<bool>, Query, description = "only listed (true) or unlisted (false) works"),
+        ("limit" = Option<i64>, Query, description = "default 50, max 200")
+    ),
+    responses(
+        (status = 200, description = "The caller's works, newest first", body = WorksV1),
+        (status = 401, description = "Not signed in", body = ErrorV1)
+    )
+)]
+fn _doc_works_list() {}
+
+#[utoipa::path(
+    get,
+    path = "/cssapi/v1/works/{work_id}",
+    params(
+        ("work_id" = String, Path, description = "Work id")
+    ),
+    responses(
+        (status = 200, description = "Work with its assets", body = WorkV1),
+        (status = 401, description = "Not signed in", body = ErrorV1),
+        (status = 404, description = "Not found", body = ErrorV1)
+    )
+)]
+fn _doc_works_get() {}
+
+#[utoipa::path(
+    patch,
+    path = "/cssapi/v1/works/{work_id}",
+    params(
+        ("work_id" = String, Path, description = "Work id")
+    ),
+    request_body = UpdateWorkRequestV1,
+    responses(
+        (status = 200, description = "Updated work", body = WorkV1),
+        (status = 400, description = "Invalid title, kind, price or currency", body = ErrorV1),
+        (status = 401, description = "Not signed in", body = ErrorV1),
+        (status = 404, description = "Not found", body = ErrorV1),
+        (status = 409, description = "Listing a work without a video asset", body = ErrorV1)
+    )
+)]
+fn _doc_works_update() {}
+
+#[utoipa::path(
+    delete,
+    path = "/cssapi/v1/works/{work_id}",
+    params(
+        ("work_id" = String, Path, description = "Work id")
+    ),
+    responses(
+        (status = 200, description = "Bought by someone, so only unlisted", body = WorkV1),
+        (status = 204, description = "Deleted"),
+        (status = 401, description = "Not signed in", body = ErrorV1),
+        (status = 404, description = "Not found", body = ErrorV1)
+    )
+)]
+fn _doc_works_delete() {}
+
+#[utoipa::path(
+    get,
+    path = "/api/works",
+    params(
+        ("q" = Option<String>, Query, description = "search in titles"),
+        ("kind" = Option<String>, Query, description = "mv or opera"),
+        ("sort" = Option<String>, Query, description = "newest (default), oldest, price_asc, price_desc"),
+        ("limit" = Option<i64>, Query, description = "default 24, max 100"),
+        ("offset" = Option<i64>, Query, description = "items to skip")
+    ),
+    responses(
+        (status = 200, description = "Listed works; no session needed", body = CatalogV1),
+        (status = 400, description = "Unknown sort", body = ErrorV1)
+    )
+)]
+fn _doc_catalog_list() {}
+
+#[utoipa::path(
+    get,
+    path = "/api/works/{work_id}",
+    params(
+        ("work_id" = String, Path, description = "Work id")
+    ),
+    responses(
+        (status = 200, description = "Listed work with its assets", body = CatalogWorkV1),
+        (status = 404, description = "Not found or not listed", body = ErrorV1)
+    )
+)]
+fn _doc_catalog_get() {}
+
+#[utoipa::path(
+    post,
+    path = "/api/works/{work_id}/purchase",
+    params(
+        ("work_id" = String, Path, description = "Work id")
+    ),
+    responses(
+        (status = 201, description = "Bought: buyer debited, seller credited less the platform fee", body = WorkPurchaseV1),
+        (status = 401, description = "Not signed in", body = ErrorV1),
+        (status = 402, description = "Balance below the price", body = ErrorV1),
+        (status = 404, description = "Not found or not listed", body = ErrorV1),
+        (status = 409, description = "Own work, already bought, or currency mismatch", body = ErrorV1)
+    )
+)]
+fn _doc_catalog_purchase() {}
+
+#[utoipa::path(
+    get,
+    path = "/api/works/{work_id}/assets/{asset_id}",
+    params(
+        ("work_id" = String, Path, description = "Work id"),
+        ("asset_id" = String, Path, description = "Work asset id")
+    ),
+    responses(
+        (status = 307, description = "Signed artifact link"),
+        (status = 401, description = "Not signed in and the asset is locked", body = ErrorV1),
+        (status = 403, description = "Work not bought", body = ErrorV1),
+        (status = 404, description = "Not found", body = ErrorV1),
+        (status = 503, description = "ARTIFACT_SIGNING_KEY not configured", body = ErrorV1)
+    )
+)]
+fn _doc_catalog_asset() {}
+
+#[utoipa::path(
+    post,
+    path = "/cssapi/v1/assets",
+    request_body(content = String, description = "multipart/form-data with a `file` field", content_type = "multipart/form-data"),
+    responses(
+        (status = 201, description = "Asset stored (content-addressed by sha256)", body = AssetV1),
+        (status = 400, description = "Error", body = ErrorV1),
+        (status = 401, description = "Not signed in", body = ErrorV1),
+        (status = 413, description = "Too large", body = ErrorV1),
+        (status = 415, description = "Unsupported media type", body = ErrorV1)
+    )
+)]
+fn _doc_assets_upload() {}
+
+#[utoipa::path(
+    get,
+    path = "/cssapi/v1/assets/{asset_id}",
+    params(
+        ("asset_id" = String, Path, description = "Asset id (sha256 hex)")
+    ),
+    responses(
+        (status = 200, description = "Asset metadata", body = AssetV1),
+        (status = 401, description = "Not signed in", body = ErrorV1),
+        (status = 404, description = "Not found or not uploaded by the caller", body = ErrorV1)
+    )
+)]
+fn _doc_assets_get() {}
+
 #[derive(OpenApi)]
 #[openapi(
     paths(
@@ -155,7 +567,21 @@ fn _doc_runs_status() {}
         _doc_runs_create,
         _doc_runs_list,
         _doc_runs_get,
-        _doc_runs_status
+        _doc_runs_status,
+        _doc_runs_artifacts,
+        _doc_runs_artifact_download,
+        _doc_runs_share,
+        _doc_runs_publish,
+        _doc_works_list,
+        _doc_works_get,
+        _doc_works_update,
+        _doc_works_delete,
+        _doc_catalog_list,
+        _doc_catalog_get,
+        _doc_catalog_purchase,
+        _doc_catalog_asset,
+        _doc_assets_upload,
+        _doc_assets_get
     ),
     components(
         schemas(
@@ -167,7 +593,22 @@ fn _doc_runs_status() {}
             CreateRunRequestV1,
             RunCreatedV1,
             RunsListItemV1,
-            RunsListV1
+            RunsListV1,
+            ArtifactV1,
+            ArtifactsV1,
+            ShareArtifactRequestV1,
+            ArtifactShareV1,
+            WorkAssetV1,
+            WorkV1,
+            WorksV1,
+            PublishRunRequestV1,
+            UpdateWorkRequestV1,
+            CatalogAssetV1,
+            CatalogSellerV1,
+            CatalogWorkV1,
+            CatalogV1,
+            WorkPurchaseV1,
+            AssetV1
         )
     ),
     tags(
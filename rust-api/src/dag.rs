@@ -1,4 +1,5 @@
-use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, VecDeque};
 
 #[derive(Debug, Clone)]
 pub struct DagNode {
@@ -20,6 +21,21 @@ pub enum DagError {
 }
 
 impl Dag {
+    /// The nodes as recorded in `run.json`.
+    pub fn meta(&self) -> crate::run_state::DagMeta {
+        crate::run_state::DagMeta {
+            schema: "css.pipeline.dag.v1".to_string(),
+            nodes: self
+                .nodes
+                .iter()
+                .map(|n| crate::run_state::DagNodeMeta {
+                    name: n.name.to_string(),
+                    deps: n.deps.iter().map(|d| (*d).to_string()).collect(),
+                })
+                .collect(),
+        }
+    }
+
     pub fn topo_order(&self) -> Result<Vec<&'static str>, DagError> {
         let mut node_set = BTreeSet::<&'static str>::new();
         for n in &self.nodes {
@@ -83,6 +99,97 @@ pub fn cssmv_dag_v1() -> Dag {
             DagNode { name: "vocals", deps: &["lyrics", "music"] },
             DagNode { name: "video", deps: &["lyrics", "vocals"] },
             DagNode { name: "render", deps: &["lyrics", "music", "vocals", "video"] },
+            DagNode { name: "package", deps: &["render"] },
+            DagNode { name: "thumbnails", deps: &["render"] },
         ],
     }
 }
+
+/// Stage order for a run: its recorded DAG nodes sorted topologically, ties kept in the
+/// order of the run's current `topo_order`. Stages and dependencies without a node of their
+/// own have no dependencies. A cycle leaves the current order as it is.
+pub fn topo_order_v1(run: &crate::run_state::RunState) -> Vec<String> {
+    let mut deps = BTreeMap::<&str, Vec<&str>>::new();
+    for name in run.topo_order.iter().chain(run.stages.keys()) {
+        deps.entry(name).or_default();
+    }
+    for n in &run.dag.nodes {
+        for d in &n.deps {
+            deps.entry(d).or_default();
+        }
+        deps.entry(&n.name).or_default().extend(n.deps.iter().map(String::as_str));
+    }
+    let rank = |name: &str| {
+        run.topo_order
+            .iter()
+            .position(|s| s == name)
+            .unwrap_or(usize::MAX)
+    };
+
+    let mut indeg = BTreeMap::<&str, usize>::new();
+    let mut adj = BTreeMap::<&str, Vec<&str>>::new();
+    for (&name, ds) in &deps {
+        indeg.insert(name, ds.len());
+        for &d in ds {
+            adj.entry(d).or_default().push(name);
+        }
+    }
+    let mut ready: BinaryHeap<Reverse<(usize, &str)>> = indeg
+        .iter()
+        .filter(|(_, &n)| n == 0)
+        .map(|(&name, _)| Reverse((rank(name), name)))
+        .collect();
+    let mut out = Vec::with_capacity(deps.len());
+    while let Some(Reverse((_, u))) = ready.pop() {
+        out.push(u.to_string());
+        for &v in adj.get(u).map(Vec::as_slice).unwrap_or_default() {
+            let e = indeg.get_mut(v).expect("every node has an in-degree");
+            *e -= 1;
+            if *e == 0 {
+                ready.push(Reverse((rank(v), v)));
+            }
+        }
+    }
+    if out.len() != deps.len() {
+        return run.topo_order.clone();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_topo_order_follows_deps_and_keeps_ties_in_place() {
+        let run: crate::run_state::RunState = serde_json::from_value(serde_json::json!({
+            "schema": "css.pipeline.run.v1",
+            "run_id": "r",
+            "created_at": "",
+            "updated_at": "",
+            "status": "INIT",
+            "ui_lang": "auto",
+            "tier": "free",
+            "cssl": "",
+            "config": {"out_dir": "/tmp/r", "wiki_enabled": false, "civ_linked": false},
+            "retry_policy": {"max_retries": 0, "backoff_base_seconds": 0, "strategy": ""},
+            "dag": {"schema": "css.pipeline.dag.v1", "nodes": [
+                {"name": "render", "deps": ["video_shot_001", "video_shot_000", "music"]},
+                {"name": "music", "deps": ["lyrics"]},
+                {"name": "video_shot_000", "deps": ["video_plan"]},
+                {"name": "video_shot_001", "deps": ["video_plan"]},
+            ]},
+            "topo_order": ["lyrics", "music", "video_plan", "video_shot_000", "video_shot_001", "render"],
+            "stages": {},
+        }))
+        .unwrap();
+        assert_eq!(
+            topo_order_v1(&run),
+            ["lyrics", "music", "video_plan", "video_shot_000", "video_shot_001", "render"]
+        );
+
+        let mut cyclic = run.clone();
+        cyclic.dag.nodes[1].deps.push("render".to_string());
+        assert_eq!(topo_order_v1(&cyclic), run.topo_order);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::artifacts::ArtifactRegistry;
 use crate::dag::Dag;
 use serde_json::json;
 use std::{fs, path::Path};
@@ -55,7 +56,7 @@ pub fn write_dag_json<P: AsRef<Path>>(out_path: P, dag: &Dag, run_state_json: &s
         "schema": "css.pipeline.dag_export.v1",
         "nodes": nodes,
         "edges": edges,
-        "artifacts": run_state_json.get("artifacts").cloned().unwrap_or(json!({}))
+        "artifacts": ArtifactRegistry::from_run_json(run_state_json).to_map_value()
     });
 
     if let Some(parent) = out_path.as_ref().parent() {
@@ -13,3 +13,39 @@ pub async fn connect(database_url: &str) -> Result<PgPool, sqlx::Error> {
 pub async fn migrate(pool: &PgPool) -> Result<(), sqlx::migrate::MigrateError> {
     sqlx::migrate!("./migrations").run(pool).await
 }
+
+/// Pool on a fresh, migrated schema of `TEST_DATABASE_URL`, so tests do not see each
+/// other's rows. Tests that need Postgres return early without it.
+#[cfg(test)]
+pub async fn test_pool() -> Option<PgPool> {
+    use sqlx::{postgres::PgConnectOptions, Executor};
+    use std::str::FromStr;
+
+    let url = std::env::var("TEST_DATABASE_URL").ok()?;
+    let schema = format!("test_{}", uuid::Uuid::new_v4().simple());
+    let admin = connect(&url).await.expect("connect to TEST_DATABASE_URL");
+    admin
+        .execute(format!("CREATE SCHEMA {schema}").as_str())
+        .await
+        .expect("create test schema");
+    admin.close().await;
+    let opts = PgConnectOptions::from_str(&url)
+        .expect("parse TEST_DATABASE_URL")
+        .options([("search_path", schema.as_str())]);
+    let pool = PgPoolOptions::new()
+        .max_connections(5)
+        .connect_with(opts)
+        .await
+        .expect("connect to test schema");
+    migrate(&pool).await.expect("migrate test schema");
+    Some(pool)
+}
+
+/// A fresh user for a test.
+#[cfg(test)]
+pub async fn test_user(pool: &PgPool) -> uuid::Uuid {
+    sqlx::query_scalar("INSERT INTO users DEFAULT VALUES RETURNING id")
+        .fetch_one(pool)
+        .await
+        .expect("insert test user")
+}
@@ -1,29 +1,166 @@
+use crate::audio::mix::MixSpec;
+use std::collections::BTreeMap;
+
+/// Shell commands of the stages, for runs whose stages run as `shell`. Lyrics, music and
+/// vocals only ever run through their executors, so they have no command.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct CompiledCommands {
-    pub lyrics: String,
-    pub music: String,
-    pub vocals: String,
+    #[serde(default)]
+    pub lyrics: Option<String>,
+    #[serde(default)]
+    pub music: Option<String>,
+    #[serde(default)]
+    pub vocals: Option<String>,
     pub video: String,
     pub render: String,
+    #[serde(default = "package_fallback_command")]
+    pub package: String,
+    #[serde(default = "thumbnails_fallback_command")]
+    pub thumbnails: String,
+    /// Song hints from the `lyrics(...)`, `music(...)` and `vocals(...)` arguments: title,
+    /// lang, mood, structure, genre, bpm, key, voice, ... A stage's `backend` argument is
+    /// stored as `<stage>_backend`.
+    #[serde(default)]
+    pub song: serde_json::Value,
+}
+
+/// `key="value"` / `key=value` arguments of the first `stage(...)` call in the DSL.
+pub fn stage_args(dsl: &str, stage: &str) -> BTreeMap<String, String> {
+    let mut out = BTreeMap::new();
+    let lowered = dsl.to_ascii_lowercase();
+    let Some(start) = lowered.find(&format!("{stage}(")) else {
+        return out;
+    };
+    let rest = &dsl[start + stage.len() + 1..];
+    let mut chars = rest.chars().peekable();
+    let mut key = String::new();
+    loop {
+        match chars.next() {
+            None | Some(')') => break,
+            Some('=') => {
+                let mut value = String::new();
+                while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                    chars.next();
+                }
+                if let Some(q) = chars.next_if(|c| *c == '"' || *c == '\'') {
+                    for c in chars.by_ref() {
+                        if c == q {
+                            break;
+                        }
+                        value.push(c);
+                    }
+                } else {
+                    while let Some(c) = chars.next_if(|c| *c != ',' && *c != ')') {
+                        value.push(c);
+                    }
+                }
+                let k = key.trim().to_ascii_lowercase();
+                if !k.is_empty() {
+                    out.insert(k, value.trim().to_string());
+                }
+                key.clear();
+            }
+            Some(',') => key.clear(),
+            Some(c) => key.push(c),
+        }
+    }
+    out
+}
+
+fn song_args(dsl: &str) -> serde_json::Value {
+    let mut song = serde_json::Map::new();
+    for stage in ["lyrics", "music", "vocals"] {
+        for (k, v) in stage_args(dsl, stage) {
+            let k = if k == "backend" {
+                format!("{stage}_backend")
+            } else {
+                k
+            };
+            song.insert(k, serde_json::Value::String(v));
+        }
+    }
+    serde_json::Value::Object(song)
 }
 
 pub fn compile_from_dsl(dsl: &str) -> anyhow::Result<CompiledCommands> {
-    let required = ["lyrics()", "music()", "vocals()", "video()", "render()"];
+    let required = ["lyrics(", "music(", "vocals(", "video(", "render("];
     let lowered = dsl.to_lowercase();
     if !lowered.contains("css") {
         anyhow::bail!("invalid dsl: missing CSS prefix");
     }
     for token in required {
         if !lowered.contains(token) {
-            anyhow::bail!("invalid dsl: missing stage token `{}`", token);
+            anyhow::bail!("invalid dsl: missing stage token `{})`", token);
         }
     }
 
     Ok(CompiledCommands {
-        lyrics: "mkdir -p ./build && printf '%s\\n' '{\"schema\":\"css.lyrics.v1\",\"lines\":[\"demo\"]}' > ./build/lyrics.json".to_string(),
-        music: "mkdir -p ./build && : > ./build/music.wav".to_string(),
-        vocals: "mkdir -p ./build && : > ./build/vocals.wav".to_string(),
+        lyrics: None,
+        music: None,
+        vocals: None,
         video: "echo \"video handled by video executor\"".to_string(),
-        render: "mkdir -p ./build && (cp -f ./build/video/video.mp4 ./build/final_mv.mp4 2>/dev/null || : > ./build/final_mv.mp4)".to_string(),
+        render: render_fallback_command(&MixSpec::default()),
+        package: package_fallback_command(),
+        thumbnails: thumbnails_fallback_command(),
+        song: song_args(dsl),
     })
 }
+
+/// Single-rendition HLS of `final_mv.mp4`; the video executor path encodes the full ladder.
+fn package_fallback_command() -> String {
+    "mkdir -p ./build/package/hls && \"${FFMPEG:-ffmpeg}\" -y -loglevel error -i ./build/final_mv.mp4 -c copy \
+     -f hls -hls_playlist_type vod -hls_time 4 ./build/package/hls/master.m3u8"
+        .to_string()
+}
+
+/// Midpoint poster of `final_mv.mp4`; the video executor path adds shot posters, a contact
+/// sheet and an animated preview.
+fn thumbnails_fallback_command() -> String {
+    "mkdir -p ./build/thumbnails && \"${FFMPEG:-ffmpeg}\" -y -loglevel error -i ./build/final_mv.mp4 \
+     -vf \"thumbnail,scale=640:-2\" -frames:v 1 -q:v 3 ./build/thumbnails/poster.jpg"
+        .to_string()
+}
+
+/// Shell render for runs without the video executor: a single-pass mix of music and vocals
+/// with `spec`'s gains, ducking, fade-in and loudness over `video.mp4`. Only when either
+/// WAV is missing or empty is the video copied without audio; a failing mix fails the stage.
+/// Music stems are not re-summed here. `compile_from_dsl` knows no run, so it uses the
+/// default mix; runs build it again from their `video.mix`.
+pub fn render_fallback_command(spec: &MixSpec) -> String {
+    let graph = crate::audio::mix::pre_mix_graph(spec, true, true, 0.0);
+    format!(
+        "mkdir -p ./build && if [ -s ./build/music.wav ] && [ -s ./build/vocals.wav ]; then \
+         \"${{FFMPEG:-ffmpeg}}\" -y -loglevel error -i ./build/music.wav -i ./build/vocals.wav -i ./build/video/video.mp4 \
+         -filter_complex '{graph};[mix]loudnorm=I={}:TP={}:LRA={}[aout]' \
+         -map 2:v:0 -map '[aout]' -c:v copy -c:a aac -b:a 192k -shortest ./build/final_mv.mp4; \
+         else cp -f ./build/video/video.mp4 ./build/final_mv.mp4; fi",
+        spec.target_lufs, spec.true_peak_db, spec.lra
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compiled_commands_write_no_placeholder_files() {
+        let c = compile_from_dsl("CSS demo :: lyrics()->music()->vocals()->video()->render();")
+            .unwrap();
+        assert!(c.lyrics.is_none() && c.music.is_none() && c.vocals.is_none());
+        for cmd in [&c.video, &c.render, &c.package, &c.thumbnails] {
+            assert!(!cmd.contains(": >"), "{cmd}");
+        }
+    }
+
+    #[test]
+    fn render_fallback_uses_the_mix_and_does_not_hide_ffmpeg_errors() {
+        let spec = MixSpec {
+            music_gain_db: -9.0,
+            target_lufs: -16.0,
+            ..Default::default()
+        };
+        let cmd = render_fallback_command(&spec);
+        assert!(cmd.contains("volume=-9dB") && cmd.contains("loudnorm=I=-16:"), "{cmd}");
+        assert!(!cmd.contains("2>/dev/null") && !cmd.contains("||"), "{cmd}");
+    }
+}
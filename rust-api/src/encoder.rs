@@ -0,0 +1,364 @@
+use serde::{Deserialize, Serialize};
+
+/// Software encoders only, so the same profile gives the same quality on every worker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VideoCodec {
+    X264,
+    X265,
+    Vp9,
+    /// AV1 through SVT-AV1.
+    Av1,
+}
+
+impl VideoCodec {
+    pub fn ffmpeg_encoder(&self) -> &'static str {
+        match self {
+            Self::X264 => "libx264",
+            Self::X265 => "libx265",
+            Self::Vp9 => "libvpx-vp9",
+            Self::Av1 => "libsvtav1",
+        }
+    }
+
+    /// `codec_name` ffprobe reports for the encoded stream.
+    pub fn probe_name(&self) -> &'static str {
+        match self {
+            Self::X264 => "h264",
+            Self::X265 => "hevc",
+            Self::Vp9 => "vp9",
+            Self::Av1 => "av1",
+        }
+    }
+
+    /// Whether `preset` is one the encoder accepts: an x264/x265 preset name short of
+    /// `placebo`, a VP9 `cpu-used` of 0-8 or an SVT-AV1 preset of 0-13.
+    pub fn allows_preset(&self, preset: &str) -> bool {
+        let level = |max: u8| preset.len() <= 2 && preset.parse::<u8>().is_ok_and(|n| n <= max);
+        match self {
+            Self::X264 | Self::X265 => X26X_PRESETS.contains(&preset),
+            Self::Vp9 => level(8),
+            Self::Av1 => level(13),
+        }
+    }
+
+    /// Highest CRF the encoder accepts: 51 for x264/x265, 63 for VP9 and SVT-AV1.
+    pub fn max_crf(&self) -> u32 {
+        match self {
+            Self::X264 | Self::X265 => 51,
+            Self::Vp9 | Self::Av1 => 63,
+        }
+    }
+
+    pub fn default_preset(&self) -> &'static str {
+        match self {
+            Self::X264 | Self::X265 => "medium",
+            Self::Vp9 => "4",
+            Self::Av1 => "8",
+        }
+    }
+}
+
+const X26X_PRESETS: [&str; 9] = [
+    "ultrafast",
+    "superfast",
+    "veryfast",
+    "faster",
+    "fast",
+    "medium",
+    "slow",
+    "slower",
+    "veryslow",
+];
+
+/// Named encoder settings for shots, targets and re-encoded finals.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EncoderProfile {
+    pub name: String,
+    pub codec: VideoCodec,
+    /// x264/x265 preset name, SVT-AV1 preset number or VP9 `cpu-used`.
+    pub preset: String,
+    /// Constant quality; ignored when `bitrate_kbps` is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crf: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bitrate_kbps: Option<u32>,
+    /// Keyframe interval in seconds.
+    pub gop_s: f32,
+    pub pix_fmt: String,
+    /// Frame size relative to the storyboard; below 1.0 for previews.
+    pub scale: f32,
+}
+
+pub const PROFILE_NAMES: [&str; 3] = ["draft", "standard", "master"];
+
+/// Bitrates a run may ask for, in kbit/s.
+const BITRATE_KBPS: std::ops::RangeInclusive<u64> = 100..=100_000;
+
+impl Default for EncoderProfile {
+    fn default() -> Self {
+        Self::standard()
+    }
+}
+
+impl EncoderProfile {
+    /// Fast preview at half resolution.
+    pub fn draft() -> Self {
+        Self {
+            name: "draft".to_string(),
+            codec: VideoCodec::X264,
+            preset: "ultrafast".to_string(),
+            crf: Some(28),
+            bitrate_kbps: None,
+            gop_s: 2.0,
+            pix_fmt: "yuv420p".to_string(),
+            scale: 0.5,
+        }
+    }
+
+    pub fn standard() -> Self {
+        Self {
+            name: "standard".to_string(),
+            codec: VideoCodec::X264,
+            preset: "veryfast".to_string(),
+            crf: Some(18),
+            bitrate_kbps: None,
+            gop_s: 2.0,
+            pix_fmt: "yuv420p".to_string(),
+            scale: 1.0,
+        }
+    }
+
+    pub fn master() -> Self {
+        Self {
+            name: "master".to_string(),
+            codec: VideoCodec::X265,
+            preset: "slow".to_string(),
+            crf: Some(16),
+            bitrate_kbps: None,
+            gop_s: 2.0,
+            pix_fmt: "yuv420p10le".to_string(),
+            scale: 1.0,
+        }
+    }
+
+    pub fn named(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "draft" | "preview" => Some(Self::draft()),
+            "standard" | "default" => Some(Self::standard()),
+            "master" => Some(Self::master()),
+            _ => None,
+        }
+    }
+
+    /// Tier of an account with `role`; signed-out and unknown roles get the lowest one.
+    pub fn account_tier(role: Option<&str>) -> &'static str {
+        match role.map(|r| r.trim().to_ascii_lowercase()).as_deref() {
+            Some("free" | "user") => "free",
+            Some("creator") => "creator",
+            Some("pro") => "pro",
+            Some("prime" | "admin") => "prime",
+            _ => "guest",
+        }
+    }
+
+    /// Best profile a tier may render with.
+    pub fn max_for_tier(tier: &str) -> &'static str {
+        match tier {
+            "free" | "creator" => "standard",
+            "pro" | "prime" | "local" => "master",
+            _ => "draft",
+        }
+    }
+
+    /// Profile used when a run asks for none.
+    pub fn default_for_tier(tier: &str) -> &'static str {
+        match Self::max_for_tier(tier) {
+            "draft" => "draft",
+            _ => "standard",
+        }
+    }
+
+    /// Reads `commands.video.encoder`: a profile name, or an object with `profile` plus
+    /// overrides (`codec`, `preset`, `crf`, `bitrate_kbps`, `gop_s`, `pix_fmt`, `scale`).
+    /// Named profiles above the tier's ceiling fall back to the ceiling; presets and pixel
+    /// formats outside the codec's allow-list are ignored, as are bitrates outside
+    /// 100-100000 kbit/s. CRF is clamped to what the codec accepts.
+    pub fn resolve(v: Option<&serde_json::Value>, tier: &str) -> Self {
+        let rank = |n: &str| PROFILE_NAMES.iter().position(|p| *p == n).unwrap_or(1);
+        let max = Self::max_for_tier(tier);
+        let (requested, overrides) = match v {
+            Some(serde_json::Value::String(s)) => (Some(s.as_str()), None),
+            Some(serde_json::Value::Object(o)) => {
+                (o.get("profile").and_then(|x| x.as_str()), Some(o))
+            }
+            _ => (None, None),
+        };
+        let mut p = requested
+            .and_then(Self::named)
+            .unwrap_or_else(|| Self::named(Self::default_for_tier(tier)).unwrap_or_default());
+        if rank(&p.name) > rank(max) {
+            p = Self::named(max).unwrap_or_default();
+        }
+        let Some(o) = overrides else {
+            return p;
+        };
+        if let Some(c) = o
+            .get("codec")
+            .and_then(|x| serde_json::from_value::<VideoCodec>(x.clone()).ok())
+        {
+            // Anything beyond H.264 is a master-level option.
+            if c == VideoCodec::X264 || rank(max) >= rank("master") {
+                p.codec = c;
+            }
+        }
+        if let Some(s) = o.get("preset").and_then(|x| x.as_str()) {
+            if p.codec.allows_preset(s) {
+                p.preset = s.to_string();
+            }
+        }
+        if !p.codec.allows_preset(&p.preset) {
+            p.preset = p.codec.default_preset().to_string();
+        }
+        if let Some(n) = o.get("crf").and_then(|x| x.as_u64()) {
+            p.crf = Some(n.min(u64::from(p.codec.max_crf())) as u32);
+            p.bitrate_kbps = None;
+        }
+        p.crf = p.crf.map(|c| c.min(p.codec.max_crf()));
+        if let Some(n) = o
+            .get("bitrate_kbps")
+            .and_then(|x| x.as_u64())
+            .filter(|n| BITRATE_KBPS.contains(n))
+        {
+            p.bitrate_kbps = Some(n as u32);
+        }
+        if let Some(n) = o.get("gop_s").and_then(|x| x.as_f64()) {
+            p.gop_s = (n as f32).max(0.1);
+        }
+        if let Some(s) = o.get("pix_fmt").and_then(|x| x.as_str()) {
+            // 10-bit output is a master-level option, like the codecs.
+            if s == "yuv420p" || (s == "yuv420p10le" && rank(max) >= rank("master")) {
+                p.pix_fmt = s.to_string();
+            }
+        }
+        if let Some(n) = o.get("scale").and_then(|x| x.as_f64()) {
+            p.scale = (n as f32).clamp(0.1, 1.0);
+        }
+        p
+    }
+
+    /// `w`x`h` scaled by the profile, rounded down to even sizes for 4:2:0.
+    pub fn frame_size(&self, w: u32, h: u32) -> (u32, u32) {
+        if self.scale >= 1.0 {
+            return (w, h);
+        }
+        let even = |v: u32| (((v as f32 * self.scale) as u32) & !1).max(2);
+        (even(w), even(h))
+    }
+
+    pub fn gop_frames(&self, fps: u32) -> u32 {
+        ((self.gop_s * fps.max(1) as f32).round() as u32).max(1)
+    }
+
+    /// Video encoder arguments for ffmpeg, from `-c:v` through `-pix_fmt`.
+    pub fn ffmpeg_args(&self, fps: u32) -> Vec<String> {
+        let gop = self.gop_frames(fps).to_string();
+        let mut a: Vec<String> = vec!["-c:v".into(), self.codec.ffmpeg_encoder().into()];
+        match self.codec {
+            VideoCodec::X264 | VideoCodec::X265 => {
+                a.extend(["-preset".into(), self.preset.clone()]);
+            }
+            VideoCodec::Vp9 => {
+                a.extend([
+                    "-deadline".into(),
+                    "good".into(),
+                    "-cpu-used".into(),
+                    self.preset.clone(),
+                    "-row-mt".into(),
+                    "1".into(),
+                ]);
+            }
+            VideoCodec::Av1 => {
+                a.extend(["-preset".into(), self.preset.clone()]);
+            }
+        }
+        match (self.bitrate_kbps, self.crf) {
+            (Some(kbps), _) => {
+                a.extend(["-b:v".into(), format!("{kbps}k")]);
+                a.extend(["-maxrate".into(), format!("{kbps}k")]);
+                a.extend(["-bufsize".into(), format!("{}k", kbps.saturating_mul(2))]);
+            }
+            (None, Some(crf)) => {
+                a.extend(["-crf".into(), crf.to_string()]);
+                if self.codec == VideoCodec::Vp9 {
+                    // Constant-quality mode for libvpx.
+                    a.extend(["-b:v".into(), "0".into()]);
+                }
+            }
+            (None, None) => {}
+        }
+        match self.codec {
+            VideoCodec::X265 => {
+                a.extend([
+                    "-x265-params".into(),
+                    format!("keyint={gop}:min-keyint={gop}:log-level=error"),
+                    "-tag:v".into(),
+                    "hvc1".into(),
+                ]);
+            }
+            _ => {
+                a.extend(["-g".into(), gop.clone(), "-keyint_min".into(), gop]);
+            }
+        }
+        a.extend(["-pix_fmt".into(), self.pix_fmt.clone()]);
+        a
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn tiers_come_from_the_account_and_overrides_are_allow_listed() {
+        assert_eq!(EncoderProfile::account_tier(None), "guest");
+        assert_eq!(EncoderProfile::account_tier(Some("user")), "free");
+        assert_eq!(EncoderProfile::account_tier(Some("local")), "guest");
+        assert_eq!(EncoderProfile::max_for_tier("whatever"), "draft");
+
+        let v = json!({"profile": "master", "pix_fmt": "yuv420p10le", "preset": "slow"});
+        let p = EncoderProfile::resolve(Some(&v), "free");
+        assert_eq!(p.name, "standard");
+        assert_eq!(p.pix_fmt, "yuv420p");
+        assert_eq!(p.preset, "slow");
+
+        let v = json!({"preset": "fast; rm -rf /", "pix_fmt": "rgb48le"});
+        let p = EncoderProfile::resolve(Some(&v), "pro");
+        assert_eq!(p.preset, "veryfast");
+        assert_eq!(p.pix_fmt, "yuv420p");
+
+        let v = json!({"profile": "master", "codec": "vp9"});
+        let p = EncoderProfile::resolve(Some(&v), "prime");
+        assert_eq!(p.preset, "4");
+        assert!(p
+            .ffmpeg_args(25)
+            .windows(2)
+            .any(|w| w == ["-cpu-used", "4"]));
+    }
+
+    #[test]
+    fn crf_and_bitrate_stay_in_range_for_the_codec() {
+        let p = EncoderProfile::resolve(Some(&json!({"crf": 63})), "pro");
+        assert_eq!(p.crf, Some(51));
+        let v = json!({"profile": "master", "codec": "av1", "crf": 63});
+        assert_eq!(EncoderProfile::resolve(Some(&v), "pro").crf, Some(63));
+
+        for kbps in [0u64, 99, 100_001, u64::from(u32::MAX) + 1_000] {
+            let p = EncoderProfile::resolve(Some(&json!({"bitrate_kbps": kbps})), "pro");
+            assert_eq!(p.bitrate_kbps, None, "{kbps}");
+        }
+        let p = EncoderProfile::resolve(Some(&json!({"bitrate_kbps": 100_000})), "pro");
+        assert!(p.ffmpeg_args(30).windows(2).any(|w| w == ["-bufsize", "200000k"]));
+    }
+}
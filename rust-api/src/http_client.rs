@@ -0,0 +1,169 @@
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Responses above this are refused rather than buffered.
+const MAX_BODY_BYTES: usize = 512 * 1024 * 1024;
+
+#[derive(thiserror::Error, Debug)]
+pub enum HttpError {
+    #[error("bad url: {0}")]
+    BadUrl(String),
+    #[error("request to {0} failed: {1}")]
+    Request(String, #[source] reqwest::Error),
+    #[error("request to {0} timed out")]
+    Timeout(String),
+    #[error("response from {0} exceeds {MAX_BODY_BYTES} bytes")]
+    TooLarge(String),
+    #[error("{url} returned {status}: {body}")]
+    Status {
+        url: String,
+        status: u16,
+        body: String,
+    },
+    #[error("invalid json from {0}: {1}")]
+    Json(String, #[source] serde_json::Error),
+}
+
+impl HttpError {
+    pub fn from_reqwest(url: &str, e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Self::Timeout(url.to_string())
+        } else if e.is_builder() {
+            Self::BadUrl(url.to_string())
+        } else {
+            Self::Request(url.to_string(), e)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+/// Process-wide client: pooled connections, `http://` and `https://` with the webpki roots.
+pub fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .build()
+            .expect("http client builds with the default tls config")
+    })
+}
+
+/// One request with the body buffered both ways. Non-2xx statuses are returned, not errors.
+pub async fn request(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: Option<&[u8]>,
+    timeout: Duration,
+) -> Result<HttpResponse, HttpError> {
+    let method = reqwest::Method::from_bytes(method.as_bytes())
+        .map_err(|_| HttpError::BadUrl(format!("{method} {url}")))?;
+    let mut req = client().request(method, url).timeout(timeout);
+    for (k, v) in headers {
+        req = req.header(*k, *v);
+    }
+    if let Some(b) = body {
+        req = req.body(b.to_vec());
+    }
+    let err = |e| HttpError::from_reqwest(url, e);
+    let mut res = req.send().await.map_err(err)?;
+    let status = res.status().as_u16();
+    let mut body = Vec::new();
+    while let Some(chunk) = res.chunk().await.map_err(err)? {
+        body.extend_from_slice(&chunk);
+        if body.len() > MAX_BODY_BYTES {
+            return Err(HttpError::TooLarge(url.to_string()));
+        }
+    }
+    Ok(HttpResponse { status, body })
+}
+
+fn auth_headers<'a>(bearer: Option<&'a str>, auth: &'a mut String) -> Vec<(&'a str, &'a str)> {
+    let mut h = vec![("Accept", "application/json")];
+    if let Some(token) = bearer.filter(|t| !t.is_empty()) {
+        *auth = format!("Bearer {token}");
+        h.push(("Authorization", auth.as_str()));
+    }
+    h
+}
+
+fn json_body(url: &str, res: HttpResponse) -> Result<serde_json::Value, HttpError> {
+    if !(200..300).contains(&res.status) {
+        return Err(HttpError::Status {
+            url: url.to_string(),
+            status: res.status,
+            body: String::from_utf8_lossy(&res.body)
+                .chars()
+                .take(500)
+                .collect(),
+        });
+    }
+    serde_json::from_slice(&res.body).map_err(|e| HttpError::Json(url.to_string(), e))
+}
+
+pub async fn post_json(
+    url: &str,
+    bearer: Option<&str>,
+    body: &serde_json::Value,
+    timeout: Duration,
+) -> Result<serde_json::Value, HttpError> {
+    let payload = serde_json::to_vec(body).map_err(|e| HttpError::Json(url.to_string(), e))?;
+    let mut auth = String::new();
+    let mut headers = auth_headers(bearer, &mut auth);
+    headers.push(("Content-Type", "application/json"));
+    let res = request("POST", url, &headers, Some(&payload), timeout).await?;
+    json_body(url, res)
+}
+
+pub async fn get_json(
+    url: &str,
+    bearer: Option<&str>,
+    timeout: Duration,
+) -> Result<serde_json::Value, HttpError> {
+    let mut auth = String::new();
+    let headers = auth_headers(bearer, &mut auth);
+    let res = request("GET", url, &headers, None, timeout).await?;
+    json_body(url, res)
+}
+
+/// Body of a 2xx GET, for downloading generated media.
+pub async fn get_bytes(
+    url: &str,
+    bearer: Option<&str>,
+    timeout: Duration,
+) -> Result<Vec<u8>, HttpError> {
+    let mut auth = String::new();
+    let headers = auth_headers(bearer, &mut auth);
+    let res = request("GET", url, &headers, None, timeout).await?;
+    if !(200..300).contains(&res.status) {
+        return Err(HttpError::Status {
+            url: url.to_string(),
+            status: res.status,
+            body: String::from_utf8_lossy(&res.body)
+                .chars()
+                .take(500)
+                .collect(),
+        });
+    }
+    Ok(res.body)
+}
+
+/// Resolves `reference` (absolute URL or path) against the service `base` URL.
+pub fn join_url(base: &str, reference: &str) -> String {
+    if reference.contains("://") {
+        return reference.to_string();
+    }
+    let origin_end = base
+        .find("://")
+        .and_then(|i| base[i + 3..].find('/').map(|j| i + 3 + j))
+        .unwrap_or(base.len());
+    if reference.starts_with('/') {
+        format!("{}{}", &base[..origin_end], reference)
+    } else {
+        format!("{}/{}", base.trim_end_matches('/'), reference)
+    }
+}
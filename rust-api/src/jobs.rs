@@ -0,0 +1,640 @@
+use crate::models::RunJob;
+use crate::routes::AppState;
+use crate::run_state::{RunState, RunStatus};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+/// Stages of a cssapi run, executed through the video dispatcher.
+pub const JOB_KIND_RUN: &str = "run";
+/// A run started from `/api/pipeline/start`, executed by the pipeline runner.
+pub const JOB_KIND_PIPELINE: &str = "pipeline";
+const MAX_RETRY_DELAY_S: u64 = 300;
+
+static QUEUED: AtomicUsize = AtomicUsize::new(0);
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+/// Jobs waiting in the queue, across every process, as of the last refresh.
+pub fn queued_count() -> usize {
+    QUEUED.load(Ordering::Relaxed)
+}
+
+/// Jobs holding a lease, across every process, as of the last refresh.
+pub fn running_count() -> usize {
+    RUNNING.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone)]
+pub struct QueueConfig {
+    /// Identifies this process in `run_jobs.locked_by`.
+    pub worker_id: String,
+    /// How long a claim lasts without a heartbeat; heartbeats come every third of it.
+    pub lease: Duration,
+    /// Idle wait between polls when nothing wakes the workers.
+    pub poll: Duration,
+    pub max_attempts: i32,
+    /// `false` for API processes that only enqueue.
+    pub workers_enabled: bool,
+}
+
+impl QueueConfig {
+    /// `WORKER_ID`, `JOB_LEASE_S` (default 60), `JOB_POLL_MS` (default 1000),
+    /// `JOB_MAX_ATTEMPTS` (default 3) and `RUN_WORKERS` (`0` disables workers).
+    pub fn from_env() -> Result<Self, String> {
+        let var = |k: &str| std::env::var(k).ok().filter(|v| !v.trim().is_empty());
+        let num = |k: &str, default: u64| -> Result<u64, String> {
+            var(k).map_or(Ok(default), |v| {
+                v.trim()
+                    .parse()
+                    .map_err(|_| format!("{k}={v} is not a number"))
+            })
+        };
+        let worker_id = var("WORKER_ID").unwrap_or_else(|| {
+            let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "worker".to_string());
+            format!("{host}-{}", std::process::id())
+        });
+        Ok(Self {
+            worker_id,
+            lease: Duration::from_secs(num("JOB_LEASE_S", 60)?.max(10)),
+            poll: Duration::from_millis(num("JOB_POLL_MS", 1000)?.max(100)),
+            max_attempts: num("JOB_MAX_ATTEMPTS", 3)?.clamp(1, 100) as i32,
+            workers_enabled: !matches!(
+                var("RUN_WORKERS").as_deref().map(str::trim),
+                Some("0" | "false" | "no" | "off")
+            ),
+        })
+    }
+}
+
+/// Wait before attempt `attempts + 1`: 2^attempts seconds, capped at five minutes.
+pub fn retry_delay(attempts: i32) -> Duration {
+    let exp = attempts.clamp(0, 16) as u32;
+    Duration::from_secs(2u64.saturating_pow(exp).min(MAX_RETRY_DELAY_S))
+}
+
+/// The `run_jobs` table.
+#[derive(Debug, Clone)]
+pub struct JobQueue {
+    pool: PgPool,
+    cfg: QueueConfig,
+}
+
+impl JobQueue {
+    pub fn new(pool: PgPool, cfg: QueueConfig) -> Self {
+        Self { pool, cfg }
+    }
+
+    /// Queues a job unless the run already has a live one, whose id is returned instead.
+    pub async fn enqueue(
+        &self,
+        kind: &str,
+        run_id: &str,
+        payload: Value,
+    ) -> Result<Uuid, sqlx::Error> {
+        match self.insert(kind, run_id, payload).await? {
+            Some(id) => Ok(id),
+            None => {
+                sqlx::query_scalar::<_, Uuid>(
+                    "SELECT id FROM run_jobs WHERE run_id = $1 AND status IN ('queued', 'running')",
+                )
+                .bind(run_id)
+                .fetch_one(&self.pool)
+                .await
+            }
+        }
+    }
+
+    /// Queues a job; `None` when the run already has a live one.
+    pub async fn insert(
+        &self,
+        kind: &str,
+        run_id: &str,
+        payload: Value,
+    ) -> Result<Option<Uuid>, sqlx::Error> {
+        sqlx::query_scalar::<_, Uuid>(
+            "INSERT INTO run_jobs (kind, run_id, payload, max_attempts) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (run_id) WHERE status IN ('queued', 'running') DO NOTHING
+             RETURNING id",
+        )
+        .bind(kind)
+        .bind(run_id)
+        .bind(payload)
+        .bind(self.cfg.max_attempts)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Takes the oldest due job. SKIP LOCKED lets any number of workers poll at once
+    /// without handing the same job to two of them.
+    pub async fn claim(&self) -> Result<Option<RunJob>, sqlx::Error> {
+        sqlx::query_as::<_, RunJob>(
+            "UPDATE run_jobs SET status = 'running', locked_by = $1, attempts = attempts + 1,
+                 lease_expires_at = now() + make_interval(secs => $2), heartbeat_at = now(),
+                 updated_at = now()
+             WHERE id = (
+                 SELECT id FROM run_jobs WHERE status = 'queued' AND run_after <= now()
+                 ORDER BY run_after, created_at
+                 FOR UPDATE SKIP LOCKED
+                 LIMIT 1
+             )
+             RETURNING *",
+        )
+        .bind(&self.cfg.worker_id)
+        .bind(self.cfg.lease.as_secs_f64())
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Extends the lease; `false` once the job is no longer this worker's.
+    pub async fn heartbeat(&self, job_id: Uuid) -> Result<bool, sqlx::Error> {
+        let r = sqlx::query(
+            "UPDATE run_jobs SET lease_expires_at = now() + make_interval(secs => $3),
+                 heartbeat_at = now(), updated_at = now()
+             WHERE id = $1 AND locked_by = $2 AND status = 'running'",
+        )
+        .bind(job_id)
+        .bind(&self.cfg.worker_id)
+        .bind(self.cfg.lease.as_secs_f64())
+        .execute(&self.pool)
+        .await?;
+        Ok(r.rows_affected() == 1)
+    }
+
+    pub async fn complete(&self, job_id: Uuid) -> Result<bool, sqlx::Error> {
+        let r = sqlx::query(
+            "UPDATE run_jobs SET status = 'succeeded', locked_by = NULL, lease_expires_at = NULL,
+                 last_error = NULL, updated_at = now()
+             WHERE id = $1 AND locked_by = $2 AND status = 'running'",
+        )
+        .bind(job_id)
+        .bind(&self.cfg.worker_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(r.rows_affected() == 1)
+    }
+
+    /// Requeues the job with backoff while it has attempts left, else fails it. Returns
+    /// the job's new status, or `None` when it was no longer this worker's.
+    pub async fn fail(&self, job: &RunJob, error: &str) -> Result<Option<String>, sqlx::Error> {
+        let delay = retry_delay(job.attempts);
+        sqlx::query_scalar::<_, String>(
+            "UPDATE run_jobs SET
+                 status = CASE WHEN attempts < max_attempts THEN 'queued' ELSE 'failed' END,
+                 run_after = now() + make_interval(secs => $3),
+                 locked_by = NULL, lease_expires_at = NULL, last_error = $4, updated_at = now()
+             WHERE id = $1 AND locked_by = $2 AND status = 'running'
+             RETURNING status",
+        )
+        .bind(job.id)
+        .bind(&self.cfg.worker_id)
+        .bind(delay.as_secs_f64())
+        .bind(error)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Jobs whose worker stopped heartbeating (crashed, restarted, partitioned) go back to
+    /// the queue, or fail once out of attempts. Returns the jobs that failed for good.
+    pub async fn reap_expired(&self) -> Result<Vec<RunJob>, sqlx::Error> {
+        sqlx::query_as::<_, RunJob>(
+            "UPDATE run_jobs SET
+                 status = CASE WHEN attempts < max_attempts THEN 'queued' ELSE 'failed' END,
+                 last_error = 'lease of ' || coalesce(locked_by, '?') || ' expired',
+                 locked_by = NULL, lease_expires_at = NULL, run_after = now(), updated_at = now()
+             WHERE id IN (
+                 SELECT id FROM run_jobs WHERE status = 'running' AND lease_expires_at < now()
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING *",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map(|jobs| jobs.into_iter().filter(|j| j.status == "failed").collect())
+    }
+
+    /// Refreshes `queued_count` and `running_count`.
+    pub async fn refresh_counts(&self) -> Result<(), sqlx::Error> {
+        let (queued, running) = sqlx::query_as::<_, (i64, i64)>(
+            "SELECT count(*) FILTER (WHERE status = 'queued'),
+                    count(*) FILTER (WHERE status = 'running')
+             FROM run_jobs WHERE status IN ('queued', 'running')",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        QUEUED.store(queued.max(0) as usize, Ordering::Relaxed);
+        RUNNING.store(running.max(0) as usize, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Heartbeats a claimed job until dropped. The lease is lost once another worker may have
+/// taken the job over, and its token cancelled so the holder abandons the running stage.
+pub struct Lease {
+    lost: CancellationToken,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Lease {
+    pub fn start(queue: JobQueue, job_id: Uuid) -> Self {
+        let lost = CancellationToken::new();
+        let flag = lost.clone();
+        let task = tokio::spawn(async move {
+            let mut tick = tokio::time::interval(queue.cfg.lease / 3);
+            tick.tick().await;
+            let mut renewed = Instant::now();
+            loop {
+                tick.tick().await;
+                match queue.heartbeat(job_id).await {
+                    Ok(true) => renewed = Instant::now(),
+                    Ok(false) => {
+                        tracing::warn!("job {job_id}: lease lost");
+                        flag.cancel();
+                        return;
+                    }
+                    // Keep trying until the lease has run out; by then it may be reaped.
+                    Err(e) if renewed.elapsed() < queue.cfg.lease => {
+                        tracing::warn!("job {job_id}: heartbeat failed: {e}")
+                    }
+                    Err(e) => {
+                        tracing::warn!("job {job_id}: lease expired, heartbeat failed: {e}");
+                        flag.cancel();
+                        return;
+                    }
+                }
+            }
+        });
+        Self { lost, task }
+    }
+
+    pub fn lost(&self) -> bool {
+        self.lost.is_cancelled()
+    }
+
+    /// Resolves once the lease is lost.
+    pub async fn wait_lost(&self) {
+        self.lost.cancelled().await
+    }
+
+    /// Cancelled when the lease is lost.
+    pub fn token(&self) -> CancellationToken {
+        self.lost.clone()
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Front of the queue held in `AppState`: enqueues and wakes this process's workers.
+#[derive(Debug, Clone)]
+pub struct SchedulerHandle {
+    queue: JobQueue,
+    wake: Arc<Notify>,
+}
+
+impl SchedulerHandle {
+    pub fn new(pool: PgPool, cfg: QueueConfig) -> Self {
+        Self {
+            queue: JobQueue::new(pool, cfg),
+            wake: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Queues the stages of a run written to `runs_dir`.
+    pub async fn enqueue(&self, run_id: &str) -> Result<Uuid, sqlx::Error> {
+        self.enqueue_job(JOB_KIND_RUN, run_id, json!({})).await
+    }
+
+    pub async fn enqueue_job(
+        &self,
+        kind: &str,
+        run_id: &str,
+        payload: Value,
+    ) -> Result<Uuid, sqlx::Error> {
+        let id = self.queue.enqueue(kind, run_id, payload).await?;
+        self.wake.notify_one();
+        Ok(id)
+    }
+}
+
+async fn execute(app: &AppState, job: &RunJob, lease: &Lease) -> anyhow::Result<()> {
+    match job.kind.as_str() {
+        JOB_KIND_RUN => crate::video_dispatch::run_stages(app, &job.run_id, lease).await,
+        JOB_KIND_PIPELINE => crate::run_worker::run_pipeline_job(app, &job.run_id, lease).await,
+        other => anyhow::bail!("unknown job kind {other}"),
+    }
+}
+
+/// Marks the run failed in its run.json once its job is out of attempts. Until then a
+/// failed attempt leaves the run RUNNING.
+fn mark_run_failed(runs_dir: &Path, job: &RunJob, error: &str) {
+    let path = crate::run_state_io::run_state_path(runs_dir, &job.run_id);
+    crate::run_worker::write_failed_state(&path, error.to_string());
+}
+
+async fn worker_loop(app: AppState, n: usize) {
+    let queue = app.scheduler.queue.clone();
+    let wake = app.scheduler.wake.clone();
+    loop {
+        let job = match queue.claim().await {
+            Ok(Some(job)) => job,
+            Ok(None) => {
+                let _ = tokio::time::timeout(queue.cfg.poll, wake.notified()).await;
+                continue;
+            }
+            Err(e) => {
+                tracing::warn!("worker {n}: claim failed: {e}");
+                tokio::time::sleep(queue.cfg.poll).await;
+                continue;
+            }
+        };
+        tracing::info!(
+            "worker {n}: job {} ({} {}) attempt {}",
+            job.id,
+            job.kind,
+            job.run_id,
+            job.attempts
+        );
+        let lease = Lease::start(queue.clone(), job.id);
+        let result = execute(&app, &job, &lease).await;
+        let lost = lease.lost();
+        drop(lease);
+        if lost {
+            // Whoever holds the job now records its outcome.
+            continue;
+        }
+        let recorded = match result {
+            Ok(()) => queue.complete(job.id).await.map(|_| ()),
+            Err(e) => {
+                let msg = format!("{e:#}");
+                match queue.fail(&job, &msg).await {
+                    Ok(Some(status)) if status == "failed" => {
+                        mark_run_failed(&app.config.runs_dir, &job, &msg);
+                        Ok(())
+                    }
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(e),
+                }
+            }
+        };
+        if let Err(e) = recorded {
+            tracing::warn!("worker {n}: could not record job {}: {e}", job.id);
+        }
+    }
+}
+
+/// A RUNNING run without a live job was stranded by a process that died mid-run and is
+/// queued again. The job that claims it resets the stages left unfinished, so nothing here
+/// touches a run another worker may already be running. Returns the runs requeued.
+async fn requeue_stranded_runs(
+    scheduler: &SchedulerHandle,
+    runs_dir: &Path,
+) -> anyhow::Result<usize> {
+    let root = runs_dir.to_path_buf();
+    let stranded = tokio::task::spawn_blocking(move || {
+        let Ok(rd) = std::fs::read_dir(&root) else {
+            return Vec::new();
+        };
+        rd.flatten()
+            .filter_map(|e| {
+                let st: RunState =
+                    serde_json::from_slice(&std::fs::read(e.path().join("run.json")).ok()?).ok()?;
+                matches!(st.status, RunStatus::RUNNING).then_some(st)
+            })
+            .collect::<Vec<_>>()
+    })
+    .await?;
+
+    let mut n = 0;
+    for st in stranded {
+        let kind = crate::run_worker::job_kind_of(&st);
+        if let Some(id) = scheduler.queue.insert(kind, &st.run_id, json!({})).await? {
+            tracing::info!("requeued stranded run {} as job {id}", st.run_id);
+            n += 1;
+        }
+    }
+    Ok(n)
+}
+
+async fn reap(app: &AppState) {
+    match app.scheduler.queue.reap_expired().await {
+        Ok(failed) => {
+            for job in failed {
+                mark_run_failed(&app.config.runs_dir, &job, "worker lease expired");
+            }
+        }
+        Err(e) => tracing::warn!("reaping expired jobs failed: {e}"),
+    }
+}
+
+/// Starts the queue's background work: every poll interval, expired leases are reaped and
+/// the queue counts refreshed. Unless workers are disabled, stranded runs are recovered
+/// first and `run_worker::concurrency()` workers started.
+pub fn start(app: AppState) {
+    tokio::spawn(async move {
+        let cfg = app.scheduler.queue.cfg.clone();
+        if cfg.workers_enabled {
+            reap(&app).await;
+            if let Err(e) = requeue_stranded_runs(&app.scheduler, &app.config.runs_dir).await {
+                tracing::warn!("recovering stranded runs failed: {e:#}");
+            }
+            for n in 0..crate::run_worker::concurrency() {
+                tokio::spawn(worker_loop(app.clone(), n));
+            }
+        }
+        let mut tick = tokio::time::interval(cfg.poll);
+        loop {
+            tick.tick().await;
+            reap(&app).await;
+            if let Err(e) = app.scheduler.queue.refresh_counts().await {
+                tracing::warn!("queue counts failed: {e}");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_backoff_is_capped() {
+        assert_eq!(retry_delay(0), Duration::from_secs(1));
+        assert_eq!(retry_delay(1), Duration::from_secs(2));
+        assert_eq!(retry_delay(3), Duration::from_secs(8));
+        assert_eq!(retry_delay(9), Duration::from_secs(MAX_RETRY_DELAY_S));
+        assert_eq!(
+            retry_delay(i32::MAX),
+            Duration::from_secs(MAX_RETRY_DELAY_S)
+        );
+    }
+
+    fn cfg(worker_id: &str, lease: Duration, max_attempts: i32) -> QueueConfig {
+        QueueConfig {
+            worker_id: worker_id.to_string(),
+            lease,
+            poll: Duration::from_millis(100),
+            max_attempts,
+            workers_enabled: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_claims_hand_out_each_job_once() {
+        let Some(pool) = crate::db::test_pool().await else { return };
+        let lease = Duration::from_secs(60);
+        let queue = JobQueue::new(pool.clone(), cfg("api", lease, 3));
+        let mut queued = Vec::new();
+        for n in 0..8 {
+            let id = queue.enqueue(JOB_KIND_RUN, &format!("run-{n}"), json!({})).await;
+            queued.push(id.unwrap());
+        }
+        let again = queue.enqueue(JOB_KIND_RUN, "run-0", json!({})).await.unwrap();
+        assert_eq!(again, queued[0]);
+        assert_eq!(queue.insert(JOB_KIND_RUN, "run-0", json!({})).await.unwrap(), None);
+
+        let workers: Vec<_> = (0..4)
+            .map(|w| {
+                let queue = JobQueue::new(pool.clone(), cfg(&format!("w{w}"), lease, 3));
+                tokio::spawn(async move {
+                    let mut claimed = Vec::new();
+                    while let Some(job) = queue.claim().await.unwrap() {
+                        assert_eq!(job.locked_by, Some(queue.cfg.worker_id.clone()));
+                        claimed.push(job.id);
+                    }
+                    claimed
+                })
+            })
+            .collect();
+        let mut claimed = Vec::new();
+        for w in workers {
+            claimed.extend(w.await.unwrap());
+        }
+        claimed.sort();
+        queued.sort();
+        assert_eq!(claimed, queued);
+    }
+
+    #[tokio::test]
+    async fn expired_leases_are_requeued_then_failed() {
+        let Some(pool) = crate::db::test_pool().await else { return };
+        let lease = Duration::from_millis(50);
+        let queue = JobQueue::new(pool.clone(), cfg("w0", lease, 2));
+        let id = queue.enqueue(JOB_KIND_RUN, "run-a", json!({})).await.unwrap();
+
+        assert_eq!(queue.claim().await.unwrap().unwrap().attempts, 1);
+        tokio::time::sleep(lease * 3).await;
+        assert!(queue.reap_expired().await.unwrap().is_empty());
+        let job = queue.claim().await.unwrap().unwrap();
+        assert_eq!((job.id, job.attempts), (id, 2));
+        assert_eq!(job.last_error.as_deref(), Some("lease of w0 expired"));
+
+        tokio::time::sleep(lease * 3).await;
+        let failed = queue.reap_expired().await.unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!((failed[0].id, failed[0].status.as_str()), (id, "failed"));
+        assert!(queue.claim().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn a_reclaimed_job_is_lost_to_its_first_worker() {
+        let Some(pool) = crate::db::test_pool().await else { return };
+        let first = JobQueue::new(pool.clone(), cfg("w0", Duration::from_millis(300), 3));
+        let second = JobQueue::new(pool.clone(), cfg("w1", Duration::from_secs(60), 3));
+        first.enqueue(JOB_KIND_RUN, "run-a", json!({})).await.unwrap();
+        let job = first.claim().await.unwrap().unwrap();
+        let lease = Lease::start(first.clone(), job.id);
+        assert!(first.heartbeat(job.id).await.unwrap());
+
+        // The first worker stalls past its lease and the job moves on.
+        sqlx::query("UPDATE run_jobs SET lease_expires_at = now() - interval '1 second'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        first.reap_expired().await.unwrap();
+        let taken = second.claim().await.unwrap().unwrap();
+        assert_eq!(taken.id, job.id);
+
+        tokio::time::timeout(Duration::from_secs(5), lease.wait_lost())
+            .await
+            .expect("lease lost");
+        assert!(!first.heartbeat(job.id).await.unwrap());
+        assert!(!first.complete(job.id).await.unwrap());
+        assert_eq!(first.fail(&job, "late").await.unwrap(), None);
+        assert!(second.complete(job.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn stranded_runs_are_requeued_once_and_left_to_their_job() {
+        let Some(pool) = crate::db::test_pool().await else { return };
+        let scheduler = SchedulerHandle::new(pool, cfg("w0", Duration::from_secs(60), 3));
+        let runs_dir = std::env::temp_dir().join(format!("css_stranded_{}", Uuid::new_v4()));
+        let write_run = |run_id: &str, status: RunStatus| {
+            let dir = runs_dir.join(run_id);
+            std::fs::create_dir_all(&dir).unwrap();
+            let st = RunState {
+                schema: "css.pipeline.run.v1".to_string(),
+                run_id: run_id.to_string(),
+                created_at: String::new(),
+                updated_at: String::new(),
+                status,
+                ui_lang: "en".to_string(),
+                tier: "local".to_string(),
+                user_id: None,
+                cssl: String::new(),
+                commands: json!({}),
+                config: crate::run_state::RunConfig {
+                    out_dir: dir.clone(),
+                    wiki_enabled: false,
+                    civ_linked: false,
+                },
+                retry_policy: crate::run_state::RetryPolicy {
+                    max_retries: 0,
+                    backoff_base_seconds: 0,
+                    strategy: "job_queue".to_string(),
+                },
+                dag: crate::dag::cssmv_dag_v1().meta(),
+                topo_order: Vec::new(),
+                artifacts: Default::default(),
+                storage: None,
+                stages: Default::default(),
+                video_shots_total: 0,
+                video_shots_ready: 0,
+                video_shots_running: 0,
+            };
+            let bytes = serde_json::to_vec_pretty(&st).unwrap();
+            std::fs::write(dir.join("run.json"), &bytes).unwrap();
+            bytes
+        };
+        let stranded = write_run("run-stranded", RunStatus::RUNNING);
+        write_run("run-done", RunStatus::SUCCEEDED);
+        scheduler.enqueue("run-live").await.unwrap();
+        write_run("run-live", RunStatus::RUNNING);
+
+        assert_eq!(requeue_stranded_runs(&scheduler, &runs_dir).await.unwrap(), 1);
+        assert_eq!(requeue_stranded_runs(&scheduler, &runs_dir).await.unwrap(), 0);
+        let mut claimed = Vec::new();
+        while let Some(job) = scheduler.queue.claim().await.unwrap() {
+            claimed.push((job.kind, job.run_id));
+        }
+        claimed.sort();
+        assert_eq!(
+            claimed,
+            [
+                (JOB_KIND_RUN.to_string(), "run-live".to_string()),
+                (JOB_KIND_RUN.to_string(), "run-stranded".to_string()),
+            ]
+        );
+        let on_disk = std::fs::read(runs_dir.join("run-stranded/run.json")).unwrap();
+        assert_eq!(on_disk, stranded);
+        let _ = std::fs::remove_dir_all(&runs_dir);
+    }
+}
@@ -0,0 +1,42 @@
+pub mod admin_api;
+pub mod artifact_store;
+pub mod artifacts;
+pub mod artifacts_api;
+pub mod asset_store;
+pub mod assets_api;
+pub mod audio;
+pub mod auth;
+pub mod billing;
+pub mod config;
+pub mod cssapi_openapi;
+pub mod runs_api;
+pub mod runs_list;
+pub mod dag;
+pub mod dag_export;
+pub mod dag_viz_html;
+pub mod db;
+pub mod dsl;
+pub mod encoder;
+pub mod http_client;
+pub mod jobs;
+pub mod lyrics;
+pub mod media_probe;
+pub mod models;
+pub mod music;
+pub mod metrics;
+pub mod ready;
+pub mod render_manifest;
+pub mod retention;
+pub mod routes;
+pub mod run_state;
+pub mod run_state_io;
+pub mod run_worker;
+pub mod runner;
+pub mod stage_executor;
+pub mod stage_registry;
+pub mod pipeline_status;
+pub mod subtitles;
+pub mod video;
+pub mod video_dispatch;
+pub mod video_executor;
+pub mod works_api;
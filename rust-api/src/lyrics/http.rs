@@ -0,0 +1,135 @@
+use super::model::{parse_lyrics_text, LyricsV1, SectionKind, LYRICS_SCHEMA};
+use super::{LyricsGenerator, LyricsRequest};
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// Generator backed by a text service at `url`. The service gets a
+/// `css.lyrics.request.v1` body and may answer with lyrics JSON, a `text` field, or an
+/// OpenAI-style completion; free text is split into sections by its headers.
+#[derive(Debug, Clone)]
+pub struct HttpLyricsGenerator {
+    pub url: String,
+    pub token: Option<String>,
+    pub timeout: Duration,
+}
+
+impl HttpLyricsGenerator {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            token: None,
+            timeout: Duration::from_secs(60),
+        }
+    }
+
+    fn request_body(req: &LyricsRequest) -> Value {
+        json!({
+            "schema": "css.lyrics.request.v1",
+            "title": req.title,
+            "lang": req.lang,
+            "mood": req.mood,
+            "structure": req.structure.iter().map(|k| k.as_str()).collect::<Vec<_>>(),
+            "seed": req.seed,
+            "prompt": req.prompt(),
+        })
+    }
+}
+
+fn text_of(v: &Value) -> Option<&str> {
+    ["text", "output", "completion", "lyrics"]
+        .iter()
+        .find_map(|k| v.get(*k).and_then(|t| t.as_str()))
+        .or_else(|| {
+            v.pointer("/choices/0/message/content")
+                .and_then(|t| t.as_str())
+        })
+        .or_else(|| v.pointer("/choices/0/text").and_then(|t| t.as_str()))
+}
+
+/// `sections: [{kind, lines: ["..."]}]`, the nested shape services find easiest to emit.
+fn from_nested_sections(v: &Value, out: &mut LyricsV1) -> bool {
+    let Some(sections) = v.get("sections").and_then(|s| s.as_array()) else {
+        return false;
+    };
+    if !sections
+        .iter()
+        .any(|s| s.get("lines").is_some_and(|l| l.is_array()))
+    {
+        return false;
+    }
+    for s in sections {
+        let kind = s
+            .get("kind")
+            .and_then(|k| k.as_str())
+            .and_then(SectionKind::parse)
+            .unwrap_or(SectionKind::Verse);
+        let idx = out.push_section(kind);
+        for line in s
+            .get("lines")
+            .and_then(|l| l.as_array())
+            .into_iter()
+            .flatten()
+        {
+            if let Some(t) = line
+                .as_str()
+                .or_else(|| line.get("text").and_then(|t| t.as_str()))
+            {
+                out.push_line(idx, t);
+            }
+        }
+    }
+    true
+}
+
+/// Lyrics from any of the accepted response shapes; syllables are always recounted here.
+pub fn lyrics_from_response(v: &Value, req: &LyricsRequest, backend: &str) -> Result<LyricsV1> {
+    let lang = v
+        .get("lang")
+        .and_then(|l| l.as_str())
+        .unwrap_or(&req.lang)
+        .to_string();
+    let mut out = if v.get("schema").and_then(|s| s.as_str()) == Some(LYRICS_SCHEMA)
+        && v.get("lines").is_some_and(|l| l.is_array())
+        && v.get("sections").is_some()
+    {
+        let mut l: LyricsV1 =
+            serde_json::from_value(v.clone()).context("parse css.lyrics.v1 response")?;
+        l.backend = backend.to_string();
+        l.recount();
+        l
+    } else if let Some(text) = text_of(v) {
+        parse_lyrics_text(text, &req.title, &lang, backend)
+    } else {
+        let mut l = LyricsV1::new(&req.title, &lang, None, backend);
+        if !from_nested_sections(v, &mut l) {
+            bail!("lyrics response has neither sections, lines nor text");
+        }
+        l
+    };
+    if out.mood.is_none() {
+        out.mood = req.mood.clone();
+    }
+    if out.title.is_empty() {
+        out.title = req.title.clone();
+    }
+    Ok(out)
+}
+
+#[axum::async_trait]
+impl LyricsGenerator for HttpLyricsGenerator {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    async fn generate(&self, req: &LyricsRequest) -> Result<LyricsV1> {
+        let res = crate::http_client::post_json(
+            &self.url,
+            self.token.as_deref(),
+            &Self::request_body(req),
+            self.timeout,
+        )
+        .await?;
+        lyrics_from_response(&res, req, self.name())
+    }
+}
@@ -0,0 +1,306 @@
+pub mod http;
+pub mod model;
+pub mod template;
+
+use crate::stage_executor::{StageContext, StageExecutor, StageOutcome};
+use anyhow::{bail, Result};
+use model::{write_lyrics_json, LyricsV1, SectionKind};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+/// Song shape used when the DSL gives no `structure`.
+const DEFAULT_STRUCTURE: [SectionKind; 6] = [
+    SectionKind::Verse,
+    SectionKind::Chorus,
+    SectionKind::Verse,
+    SectionKind::Chorus,
+    SectionKind::Bridge,
+    SectionKind::Chorus,
+];
+
+/// What to write, from `commands.song` (the `lyrics(...)` arguments in the DSL).
+#[derive(Debug, Clone)]
+pub struct LyricsRequest {
+    pub title: String,
+    pub lang: String,
+    pub mood: Option<String>,
+    pub structure: Vec<SectionKind>,
+    pub seed: u64,
+    /// Lays the lines on a beat grid when set.
+    pub bpm: Option<f64>,
+    pub offset_s: f64,
+    pub beats_per_line: u32,
+    /// `template` or `http` (`song.lyrics_backend`, or `song.backend` from API callers);
+    /// unset picks `http` when `CSS_LYRICS_URL` is configured.
+    pub backend: Option<String>,
+    /// Extra free-text direction passed through to text services.
+    pub hint: Option<String>,
+}
+
+pub(crate) fn v_str(v: Option<&Value>, key: &str) -> Option<String> {
+    v.and_then(|v| v.get(key))
+        .and_then(|x| x.as_str())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+/// Numbers arrive as JSON numbers from the API and as strings from DSL arguments.
+pub(crate) fn v_f64(v: Option<&Value>, key: &str) -> Option<f64> {
+    let x = v?.get(key)?;
+    x.as_f64()
+        .or_else(|| x.as_str().and_then(|s| s.trim().parse().ok()))
+}
+
+/// `song.seed`, else the video seed, else one derived from the title.
+pub(crate) fn song_seed(commands: &Value, title: &str) -> u64 {
+    v_f64(commands.get("song"), "seed")
+        .map(|s| s as u64)
+        .or_else(|| commands.pointer("/video/seed").and_then(|s| s.as_u64()))
+        .unwrap_or_else(|| {
+            let d = Sha256::digest(title.as_bytes());
+            u64::from_le_bytes(d[..8].try_into().expect("sha256 has 8 bytes"))
+        })
+}
+
+impl LyricsRequest {
+    pub fn from_commands(commands: &Value, ui_lang: &str) -> Self {
+        let song = commands.get("song");
+        let title = v_str(song, "title").unwrap_or_else(|| "Untitled".to_string());
+        let lang = v_str(song, "lang")
+            .or_else(|| Some(ui_lang.trim().to_string()))
+            .filter(|l| !l.is_empty() && l != "auto")
+            .unwrap_or_else(|| "en".to_string());
+        let structure: Vec<SectionKind> = match song.and_then(|s| s.get("structure")) {
+            Some(Value::String(s)) => s
+                .split([',', ' ', '>', '+', '|'])
+                .filter_map(|k| SectionKind::parse(k.trim_matches('-')))
+                .collect(),
+            Some(Value::Array(a)) => a
+                .iter()
+                .filter_map(|x| x.as_str())
+                .filter_map(SectionKind::parse)
+                .collect(),
+            _ => Vec::new(),
+        };
+        let seed = song_seed(commands, &title);
+        Self {
+            mood: v_str(song, "mood"),
+            structure: if structure.is_empty() {
+                DEFAULT_STRUCTURE.to_vec()
+            } else {
+                structure
+            },
+            seed,
+            bpm: v_f64(song, "bpm").filter(|b| *b > 0.0),
+            offset_s: v_f64(song, "offset_s").unwrap_or(0.0),
+            beats_per_line: v_f64(song, "beats_per_line").map(|b| b as u32).unwrap_or(4),
+            backend: v_str(song, "lyrics_backend").or_else(|| v_str(song, "backend")),
+            hint: v_str(song, "hint"),
+            title,
+            lang,
+        }
+    }
+
+    /// Instruction text for services that take a plain prompt.
+    pub fn prompt(&self) -> String {
+        let structure: Vec<&str> = self.structure.iter().map(|k| k.as_str()).collect();
+        let mut p = format!(
+            "Write song lyrics titled \"{}\" in language `{}`. Structure: {}. \
+             Start every section with a header like [Verse] or [Chorus] and put one sung line per row.",
+            self.title,
+            self.lang,
+            structure.join(", ")
+        );
+        if let Some(mood) = &self.mood {
+            p.push_str(&format!(" Mood: {mood}."));
+        }
+        if let Some(hint) = &self.hint {
+            p.push_str(&format!(" {hint}"));
+        }
+        p
+    }
+}
+
+/// Writes the lyrics for a run. Backends only have to produce text and sections; timing,
+/// validation and `lyrics.json` are handled by [`LyricsExecutor`].
+#[axum::async_trait]
+pub trait LyricsGenerator: Send + Sync {
+    fn name(&self) -> &'static str;
+    async fn generate(&self, req: &LyricsRequest) -> Result<LyricsV1>;
+}
+
+/// Generator for `backend`: `template`, `http` (needs `CSS_LYRICS_URL`), or unset for
+/// `http` when configured and `template` otherwise.
+pub fn generator_for(backend: Option<&str>) -> Result<Box<dyn LyricsGenerator>> {
+    let url = std::env::var("CSS_LYRICS_URL")
+        .ok()
+        .filter(|u| !u.trim().is_empty());
+    match (backend.map(|b| b.to_ascii_lowercase()).as_deref(), url) {
+        (Some("template"), _) | (None, None) => Ok(Box::new(template::TemplateLyricsGenerator)),
+        (Some("http") | None, Some(url)) => {
+            let mut g = http::HttpLyricsGenerator::new(url);
+            g.token = std::env::var("CSS_LYRICS_TOKEN").ok();
+            if let Some(s) = std::env::var("CSS_LYRICS_TIMEOUT_S")
+                .ok()
+                .and_then(|s| s.parse::<u64>().ok())
+            {
+                g.timeout = Duration::from_secs(s);
+            }
+            Ok(Box::new(g))
+        }
+        (Some("http"), None) => bail!("lyrics backend http needs CSS_LYRICS_URL"),
+        (Some(other), _) => bail!("unknown lyrics backend {other}"),
+    }
+}
+
+/// Runs a generator, then lays its lines on the beat grid when asked and validates them.
+pub async fn generate_lyrics(gen: &dyn LyricsGenerator, req: &LyricsRequest) -> Result<LyricsV1> {
+    let mut lyrics = gen.generate(req).await?;
+    if lyrics.lines.is_empty() {
+        bail!("{} backend returned no lyric lines", gen.name());
+    }
+    if let Some(bpm) = req.bpm {
+        lyrics.apply_beat_timing(bpm, req.offset_s, req.beats_per_line);
+    }
+    lyrics.validate()?;
+    Ok(lyrics)
+}
+
+/// Stage kind `lyrics`: writes `build/lyrics.json` from `commands.song`.
+#[derive(Debug, Clone, Copy)]
+pub struct LyricsExecutor;
+
+#[axum::async_trait]
+impl StageExecutor for LyricsExecutor {
+    fn kind(&self) -> &'static str {
+        "lyrics"
+    }
+
+    async fn run(&self, ctx: &StageContext) -> Result<StageOutcome> {
+        let req = LyricsRequest::from_commands(&ctx.commands, &ctx.ui_lang);
+        let gen = generator_for(req.backend.as_deref())?;
+        let lyrics = generate_lyrics(gen.as_ref(), &req).await?;
+        let rel = "./build/lyrics.json";
+        write_lyrics_json(&ctx.path(rel), &lyrics)?;
+        Ok(StageOutcome::default()
+            .meta("backend", json!(lyrics.backend))
+            .meta("sections", json!(lyrics.sections.len()))
+            .meta("lines", json!(lyrics.lines.len()))
+            .meta("timed", json!(lyrics.is_timed()))
+            .artifact("lyrics.json", json!(rel))
+            .artifact("lyrics.backend", json!(lyrics.backend)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::http::HttpLyricsGenerator;
+    use super::model::{count_syllables, parse_lyrics_text, SectionKind};
+    use super::template::TemplateLyricsGenerator;
+    use super::*;
+    use std::io::{Read, Write};
+
+    fn request(song: Value) -> LyricsRequest {
+        LyricsRequest::from_commands(&json!({ "song": song }), "auto")
+    }
+
+    #[tokio::test]
+    async fn template_is_deterministic_and_structured() {
+        let req =
+            request(json!({"title": "Night Drive", "mood": "dreamy", "seed": "7", "bpm": "120"}));
+        let a = generate_lyrics(&TemplateLyricsGenerator, &req)
+            .await
+            .unwrap();
+        let b = generate_lyrics(&TemplateLyricsGenerator, &req)
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&a).unwrap(),
+            serde_json::to_value(&b).unwrap()
+        );
+        assert_eq!(a.sections.len(), 6);
+        assert_eq!(a.sections[1].kind, SectionKind::Chorus);
+        assert_eq!(a.sections[3].label, "chorus_2");
+        let chorus = |i: usize| -> Vec<&str> {
+            a.lines
+                .iter()
+                .filter(|l| l.section == i)
+                .map(|l| l.text.as_str())
+                .collect()
+        };
+        assert_eq!(chorus(1), chorus(3));
+        assert!(a.is_timed());
+        assert!(a.lines.iter().all(|l| l.syllables > 0));
+
+        let other = request(json!({"title": "Night Drive", "mood": "dreamy", "seed": 8}));
+        let c = generate_lyrics(&TemplateLyricsGenerator, &other)
+            .await
+            .unwrap();
+        assert_ne!(a.to_text(), c.to_text());
+    }
+
+    #[tokio::test]
+    async fn http_backend_parses_text_sections() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/v1/lyrics", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            let mut req = Vec::new();
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = sock.read(&mut buf).unwrap();
+                req.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&req).to_string();
+                let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                    continue;
+                };
+                let len = head
+                    .lines()
+                    .find_map(|l| l.strip_prefix("Content-Length: "))
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .unwrap_or(0);
+                if n == 0 || body.len() >= len {
+                    break;
+                }
+            }
+            let body = json!({
+                "choices": [{"message": {"content":
+                    "[Verse 1]\nCity lights are calling\nI keep on walking\n\n[Chorus]\nHold on, hold on\n"}}]
+            })
+            .to_string();
+            write!(
+                sock,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+            String::from_utf8_lossy(&req).to_string()
+        });
+
+        let req =
+            request(json!({"title": "Lights", "structure": "verse,chorus", "backend": "http"}));
+        let lyrics = generate_lyrics(&HttpLyricsGenerator::new(url), &req)
+            .await
+            .unwrap();
+        let sent = server.join().unwrap();
+        assert!(sent.starts_with("POST /v1/lyrics HTTP/1.1"));
+        assert!(sent.contains("css.lyrics.request.v1"));
+
+        assert_eq!(lyrics.backend, "http");
+        assert_eq!(lyrics.sections.len(), 2);
+        assert_eq!(lyrics.sections[1].kind, SectionKind::Chorus);
+        assert_eq!(lyrics.lines.len(), 3);
+        assert_eq!(lyrics.lines[2].section, 1);
+        assert_eq!(lyrics.lines[2].syllables, 4);
+    }
+
+    #[test]
+    fn text_without_headers_splits_verses_on_blank_lines() {
+        let l = parse_lyrics_text("one line\ntwo line\n\nthree line\n", "t", "en", "http");
+        assert_eq!(l.sections.len(), 2);
+        assert_eq!(l.lines[2].section, 1);
+        assert_eq!(count_syllables("beautiful little name", "en"), 6);
+        assert_eq!(count_syllables("夜空の星", "ja"), 4);
+    }
+}
@@ -0,0 +1,358 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+pub const LYRICS_SCHEMA: &str = "css.lyrics.v1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SectionKind {
+    Intro,
+    Verse,
+    PreChorus,
+    Chorus,
+    Bridge,
+    Outro,
+}
+
+impl SectionKind {
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim().to_ascii_lowercase().replace(['-', ' '], "_");
+        Some(match s.as_str() {
+            "intro" => Self::Intro,
+            "verse" => Self::Verse,
+            "pre_chorus" | "prechorus" | "pre" => Self::PreChorus,
+            "chorus" | "hook" | "refrain" => Self::Chorus,
+            "bridge" | "middle_8" => Self::Bridge,
+            "outro" | "coda" => Self::Outro,
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Intro => "intro",
+            Self::Verse => "verse",
+            Self::PreChorus => "pre_chorus",
+            Self::Chorus => "chorus",
+            Self::Bridge => "bridge",
+            Self::Outro => "outro",
+        }
+    }
+
+    /// Lines a generated section of this kind gets.
+    pub fn default_lines(&self) -> usize {
+        match self {
+            Self::Intro | Self::PreChorus | Self::Outro => 2,
+            Self::Verse | Self::Chorus | Self::Bridge => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LyricsSection {
+    pub kind: SectionKind,
+    /// `verse_1`, `chorus_2`, ...; numbered per kind in song order.
+    pub label: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_s: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_s: Option<f64>,
+}
+
+/// Also readable as a subtitle line: `text`, `start_s` and `end_s` are what
+/// `subtitles::lyrics` looks at.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LyricsLine {
+    pub text: String,
+    /// Index into `sections`.
+    pub section: usize,
+    pub syllables: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_s: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_s: Option<f64>,
+}
+
+/// `build/lyrics.json`. Lines are flat, in singing order, so subtitle code can read them
+/// without knowing about sections.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LyricsV1 {
+    pub schema: String,
+    pub title: String,
+    pub lang: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mood: Option<String>,
+    /// Generator that wrote the lyrics, e.g. `template` or `http`.
+    pub backend: String,
+    pub sections: Vec<LyricsSection>,
+    pub lines: Vec<LyricsLine>,
+}
+
+impl LyricsV1 {
+    pub fn new(title: &str, lang: &str, mood: Option<&str>, backend: &str) -> Self {
+        Self {
+            schema: LYRICS_SCHEMA.to_string(),
+            title: title.to_string(),
+            lang: lang.to_string(),
+            mood: mood.map(|m| m.to_string()),
+            backend: backend.to_string(),
+            sections: Vec::new(),
+            lines: Vec::new(),
+        }
+    }
+
+    /// Starts a section and returns its index; labels are numbered per kind.
+    pub fn push_section(&mut self, kind: SectionKind) -> usize {
+        let n = self.sections.iter().filter(|s| s.kind == kind).count() + 1;
+        self.sections.push(LyricsSection {
+            kind,
+            label: format!("{}_{}", kind.as_str(), n),
+            start_s: None,
+            end_s: None,
+        });
+        self.sections.len() - 1
+    }
+
+    pub fn push_line(&mut self, section: usize, text: &str) {
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+        self.lines.push(LyricsLine {
+            text: text.to_string(),
+            section,
+            syllables: count_syllables(text, &self.lang),
+            start_s: None,
+            end_s: None,
+        });
+    }
+
+    /// Recounts syllables, e.g. after a backend returned its own counts.
+    pub fn recount(&mut self) {
+        let lang = self.lang.clone();
+        for l in &mut self.lines {
+            l.syllables = count_syllables(&l.text, &lang);
+        }
+    }
+
+    pub fn is_timed(&self) -> bool {
+        !self.lines.is_empty() && self.lines.iter().all(|l| l.start_s.is_some())
+    }
+
+    /// Lays untimed lines on a beat grid: `beats_per_line` beats per line and one bar between
+    /// sections, starting at `offset_s`. Lines that already have timings are left alone.
+    pub fn apply_beat_timing(&mut self, bpm: f64, offset_s: f64, beats_per_line: u32) {
+        if !(bpm.is_finite() && bpm > 0.0) || self.lines.iter().any(|l| l.start_s.is_some()) {
+            return;
+        }
+        let beat = 60.0 / bpm;
+        let line_s = beat * beats_per_line.max(1) as f64;
+        let mut t = offset_s.max(0.0);
+        let mut prev_section = None;
+        for l in &mut self.lines {
+            if prev_section.is_some_and(|p| p != l.section) {
+                t += beat * 4.0;
+            }
+            l.start_s = Some(round_ms(t));
+            l.end_s = Some(round_ms(t + line_s - beat * 0.25));
+            t += line_s;
+            prev_section = Some(l.section);
+        }
+        for (i, s) in self.sections.iter_mut().enumerate() {
+            let mut lines = self.lines.iter().filter(|l| l.section == i);
+            let first = lines.next();
+            let last = lines.next_back().or(first);
+            s.start_s = first.and_then(|l| l.start_s);
+            s.end_s = last.and_then(|l| l.end_s);
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.lines.is_empty() {
+            bail!("lyrics have no lines");
+        }
+        let mut prev_end = f64::NEG_INFINITY;
+        for (i, l) in self.lines.iter().enumerate() {
+            if l.section >= self.sections.len() {
+                bail!("line {i} points at missing section {}", l.section);
+            }
+            match (l.start_s, l.end_s) {
+                (Some(a), Some(b)) => {
+                    if b <= a || a < prev_end - 1e-6 {
+                        bail!("line {i} timing {a:.3}..{b:.3} overlaps or is reversed");
+                    }
+                    prev_end = b;
+                }
+                (None, None) => {}
+                _ => bail!("line {i} has only one of start_s/end_s"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Plain text with `[Verse 1]` style headers.
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        let mut prev = None;
+        for l in &self.lines {
+            if prev != Some(l.section) {
+                if prev.is_some() {
+                    out.push('\n');
+                }
+                let label = &self.sections[l.section].label;
+                let (kind, n) = label.rsplit_once('_').unwrap_or((label.as_str(), ""));
+                out.push_str(&format!(
+                    "[{} {}]\n",
+                    title_case(&kind.replace('_', "-")),
+                    n
+                ));
+                prev = Some(l.section);
+            }
+            out.push_str(&l.text);
+            out.push('\n');
+        }
+        out
+    }
+}
+
+fn round_ms(t: f64) -> f64 {
+    (t * 1000.0).round() / 1000.0
+}
+
+fn title_case(s: &str) -> String {
+    let mut c = s.chars();
+    match c.next() {
+        Some(f) => f.to_uppercase().chain(c).collect(),
+        None => String::new(),
+    }
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xAC00..=0xD7AF | 0xF900..=0xFAFF)
+}
+
+/// Sung syllables in a line: one per CJK character or Hangul block, otherwise vowel groups
+/// per word with a silent final `e` dropped for English.
+pub fn count_syllables(text: &str, lang: &str) -> u32 {
+    let english = lang.is_empty() || lang.starts_with("en") || lang == "auto";
+    let mut total = 0u32;
+    for word in text.split(|c: char| c.is_whitespace() || c == '-') {
+        let cjk = word.chars().filter(|c| is_cjk(*c)).count() as u32;
+        total += cjk;
+        let w: Vec<char> = word
+            .chars()
+            .filter(|c| c.is_alphabetic() && !is_cjk(*c))
+            .flat_map(|c| c.to_lowercase())
+            .collect();
+        if w.is_empty() {
+            continue;
+        }
+        let vowel = |c: char| "aeiouyáéíóúàèìòùâêîôûäëïöüåæøœ".contains(c);
+        let mut groups = 0u32;
+        let mut prev = false;
+        for &c in &w {
+            let v = vowel(c);
+            if v && !prev {
+                groups += 1;
+            }
+            prev = v;
+        }
+        if english && groups > 1 {
+            let n = w.len();
+            let ends_le = n > 2 && w[n - 2] == 'l' && w[n - 1] == 'e' && !vowel(w[n - 3]);
+            if w[n - 1] == 'e' && !ends_le {
+                groups -= 1;
+            }
+        }
+        total += groups.max(1);
+    }
+    total
+}
+
+/// Section header like `[Chorus]`, `[Verse 2]`, `Chorus:` or `(Bridge)`.
+fn section_header(line: &str) -> Option<SectionKind> {
+    let t = line.trim();
+    let inner = t
+        .strip_prefix('[')
+        .and_then(|x| x.strip_suffix(']'))
+        .or_else(|| t.strip_prefix('(').and_then(|x| x.strip_suffix(')')))
+        .or_else(|| t.strip_suffix(':'))?;
+    let word: String = inner
+        .trim()
+        .trim_end_matches(|c: char| c.is_ascii_digit() || c.is_whitespace())
+        .to_string();
+    SectionKind::parse(&word)
+}
+
+/// Lyrics from free text as text services return it. Lines before the first header, or all
+/// lines when there are no headers, go into verses split at blank lines.
+pub fn parse_lyrics_text(text: &str, title: &str, lang: &str, backend: &str) -> LyricsV1 {
+    let mut out = LyricsV1::new(title, lang, None, backend);
+    let mut current: Option<usize> = None;
+    let mut saw_header = false;
+    let mut blank = false;
+    for raw in text.lines() {
+        if let Some(kind) = section_header(raw) {
+            current = Some(out.push_section(kind));
+            saw_header = true;
+            blank = false;
+            continue;
+        }
+        let line = raw.trim();
+        if line.is_empty() {
+            blank = true;
+            continue;
+        }
+        let section = match current {
+            Some(i) if !blank || saw_header => i,
+            _ => out.push_section(SectionKind::Verse),
+        };
+        current = Some(section);
+        blank = false;
+        out.push_line(section, line);
+    }
+    out
+}
+
+pub fn write_lyrics_json(path: &Path, lyrics: &LyricsV1) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, serde_json::to_vec_pretty(lyrics)?)
+        .with_context(|| format!("write lyrics: {}", path.display()))
+}
+
+/// Reads `lyrics.json`, also accepting the older `{"lines": [...]}` shape with plain string
+/// or `{text, start_s, end_s}` lines, which becomes a single verse.
+pub fn read_lyrics_json(path: &Path) -> Result<LyricsV1> {
+    let s = std::fs::read_to_string(path)
+        .with_context(|| format!("read lyrics: {}", path.display()))?;
+    let v: serde_json::Value =
+        serde_json::from_str(&s).with_context(|| format!("parse lyrics: {}", path.display()))?;
+    if let Ok(l) = serde_json::from_value::<LyricsV1>(v.clone()) {
+        return Ok(l);
+    }
+    let lang = v.get("lang").and_then(|x| x.as_str()).unwrap_or("en");
+    let title = v.get("title").and_then(|x| x.as_str()).unwrap_or_default();
+    let mut out = LyricsV1::new(title, lang, None, "legacy");
+    let verse = out.push_section(SectionKind::Verse);
+    for line in v
+        .get("lines")
+        .and_then(|x| x.as_array())
+        .into_iter()
+        .flatten()
+    {
+        let text = line
+            .as_str()
+            .or_else(|| line.get("text").and_then(|t| t.as_str()))
+            .unwrap_or_default();
+        out.push_line(verse, text);
+        if let Some(l) = out.lines.last_mut().filter(|l| l.text == text.trim()) {
+            l.start_s = line.get("start_s").and_then(|x| x.as_f64());
+            l.end_s = line.get("end_s").and_then(|x| x.as_f64());
+        }
+    }
+    Ok(out)
+}
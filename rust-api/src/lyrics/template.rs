@@ -0,0 +1,191 @@
+use super::model::{LyricsV1, SectionKind};
+use super::{LyricsGenerator, LyricsRequest};
+use anyhow::Result;
+
+/// Offline generator: fills per-section line templates from a mood word bank, picked by the
+/// request seed. Same request, same lyrics; every chorus repeats the same hook lines.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TemplateLyricsGenerator;
+
+struct Bank {
+    images: &'static [&'static str],
+    verbs: &'static [&'static str],
+    places: &'static [&'static str],
+    feelings: &'static [&'static str],
+}
+
+const DEFAULT_BANK: Bank = Bank {
+    images: &[
+        "morning light",
+        "open road",
+        "paper sky",
+        "city lights",
+        "rising sun",
+    ],
+    verbs: &["run", "shine", "climb", "sing", "fly"],
+    places: &[
+        "the edge of town",
+        "the riverside",
+        "the highest hill",
+        "the open sea",
+    ],
+    feelings: &["alive", "brand new", "wide awake", "unafraid"],
+};
+
+const BANKS: &[(&str, Bank)] = &[
+    (
+        "melancholic",
+        Bank {
+            images: &[
+                "falling rain",
+                "empty rooms",
+                "fading photographs",
+                "winter glass",
+            ],
+            verbs: &["wait", "drift", "fade", "remember"],
+            places: &[
+                "the quiet street",
+                "the station hall",
+                "the window seat",
+                "the pier",
+            ],
+            feelings: &["alone", "so far away", "half asleep", "lost again"],
+        },
+    ),
+    (
+        "energetic",
+        Bank {
+            images: &[
+                "thunder drums",
+                "neon sparks",
+                "burning wires",
+                "racing hearts",
+            ],
+            verbs: &["jump", "burn", "break through", "ignite"],
+            places: &[
+                "the crowded floor",
+                "the speeding train",
+                "the rooftop",
+                "the stage",
+            ],
+            feelings: &["unstoppable", "on fire", "electric", "wild and free"],
+        },
+    ),
+    (
+        "romantic",
+        Bank {
+            images: &[
+                "candlelight",
+                "your silhouette",
+                "a silver moon",
+                "summer roses",
+            ],
+            verbs: &["hold", "dance", "whisper", "fall"],
+            places: &["your arms", "the garden gate", "the ballroom", "the shore"],
+            feelings: &["in love", "close to you", "yours tonight", "safe and warm"],
+        },
+    ),
+    (
+        "dreamy",
+        Bank {
+            images: &[
+                "floating clouds",
+                "velvet stars",
+                "a glowing haze",
+                "silver waves",
+            ],
+            verbs: &["float", "drift", "glide", "dream"],
+            places: &[
+                "the milky way",
+                "a hidden sea",
+                "the lantern sky",
+                "the twilight",
+            ],
+            feelings: &["weightless", "far away", "half awake", "serene"],
+        },
+    ),
+];
+
+fn bank_for(mood: Option<&str>) -> &'static Bank {
+    let mood = mood.unwrap_or_default().to_ascii_lowercase();
+    BANKS
+        .iter()
+        .find(|(name, _)| mood.contains(name))
+        .map(|(_, b)| b)
+        .unwrap_or(&DEFAULT_BANK)
+}
+
+/// splitmix64; the template only needs a stable stream per seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn pick<'a>(&mut self, xs: &[&'a str]) -> &'a str {
+        xs[(self.next() % xs.len() as u64) as usize]
+    }
+}
+
+fn line_for(kind: SectionKind, i: usize, title: &str, bank: &Bank, rng: &mut Rng) -> String {
+    let image = rng.pick(bank.images);
+    let verb = rng.pick(bank.verbs);
+    let place = rng.pick(bank.places);
+    let feeling = rng.pick(bank.feelings);
+    match (kind, i % 4) {
+        (SectionKind::Intro, _) => format!("Here comes the {image}"),
+        (SectionKind::Verse, 0) => format!("I saw the {image} over {place}"),
+        (SectionKind::Verse, 1) => format!("We {verb} until the night is through"),
+        (SectionKind::Verse, 2) => format!("Every step I take I feel {feeling}"),
+        (SectionKind::Verse, _) => format!("Still I {verb} back to you"),
+        (SectionKind::PreChorus, _) => format!("Can you feel it, {feeling}?"),
+        (SectionKind::Chorus, 0) | (SectionKind::Chorus, 2) => format!("{title}, {title}"),
+        (SectionKind::Chorus, 1) => format!("We {verb} and we feel {feeling}"),
+        (SectionKind::Chorus, _) => format!("Under the {image}, {title}"),
+        (SectionKind::Bridge, 0) => format!("And if the {image} should fade"),
+        (SectionKind::Bridge, 1) => format!("I'll {verb} to {place}"),
+        (SectionKind::Bridge, 2) => "Nothing left to hold me back".to_string(),
+        (SectionKind::Bridge, _) => format!("I am {feeling}"),
+        (SectionKind::Outro, _) => format!("{title}, {feeling}"),
+    }
+}
+
+#[axum::async_trait]
+impl LyricsGenerator for TemplateLyricsGenerator {
+    fn name(&self) -> &'static str {
+        "template"
+    }
+
+    async fn generate(&self, req: &LyricsRequest) -> Result<LyricsV1> {
+        // The word banks are English; `lang` says what the lines are, not what was asked for.
+        let mut out = LyricsV1::new(&req.title, "en", req.mood.as_deref(), self.name());
+        let bank = bank_for(req.mood.as_deref());
+        let mut chorus: Option<Vec<String>> = None;
+        for (n, kind) in req.structure.iter().enumerate() {
+            let section = out.push_section(*kind);
+            let lines = match (kind, &chorus) {
+                (SectionKind::Chorus, Some(lines)) => lines.clone(),
+                _ => {
+                    let mut rng =
+                        Rng(req.seed ^ (n as u64 + 1).wrapping_mul(0x2545_F491_4F6C_DD1D));
+                    let lines: Vec<String> = (0..kind.default_lines())
+                        .map(|i| line_for(*kind, i, &req.title, bank, &mut rng))
+                        .collect();
+                    if *kind == SectionKind::Chorus {
+                        chorus = Some(lines.clone());
+                    }
+                    lines
+                }
+            };
+            for line in &lines {
+                out.push_line(section, line);
+            }
+        }
+        Ok(out)
+    }
+}
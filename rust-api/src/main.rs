@@ -1,25 +1,6 @@
+use cssos_rust_api::{config, db, jobs, retention, routes};
 use tracing_subscriber::EnvFilter;
 
-mod auth;
-mod billing;
-mod config;
-mod cssapi_openapi;
-mod runs_api;
-mod runs_list;
-mod dag;
-mod dag_export;
-mod dag_viz_html;
-mod db;
-mod dsl;
-mod models;
-mod metrics;
-mod routes;
-mod run_state;
-mod run_worker;
-mod runner;
-mod pipeline_status;
-mod video_executor;
-
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt()
@@ -29,8 +10,11 @@ async fn main() {
     let config = config::Config::from_env().expect("DATABASE_URL not configured");
     let pool = db::connect(&config.database_url).await.expect("db connect failed");
     db::migrate(&pool).await.expect("db migrate failed");
+    retention::spawn_gc(pool.clone(), config.runs_dir.clone(), config.retention.clone());
 
-    let state = routes::AppState { pool: pool.clone(), config: config.clone() };
+    let scheduler = jobs::SchedulerHandle::new(pool.clone(), config.jobs.clone());
+    let state = routes::AppState { pool: pool.clone(), config: config.clone(), scheduler };
+    jobs::start(state.clone());
     let app = routes::router(state)
         .layer(tower_http::trace::TraceLayer::new_for_http())
         .layer(axum::extract::Extension(pool))
@@ -0,0 +1,393 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VideoStreamInfo {
+    pub codec: String,
+    pub w: u32,
+    pub h: u32,
+    pub fps: f64,
+    pub pix_fmt: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioStreamInfo {
+    pub codec: String,
+    pub sample_rate: u32,
+    pub channels: u32,
+}
+
+/// What `ffprobe -show_format -show_streams` reports for a file; first stream of each kind.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProbeInfo {
+    pub duration_s: f64,
+    pub bytes: u64,
+    pub video: Option<VideoStreamInfo>,
+    pub audio: Option<AudioStreamInfo>,
+}
+
+/// Expected properties of a media output; `None` fields are not checked.
+#[derive(Debug, Clone, Default)]
+pub struct MediaExpect {
+    pub duration_s: Option<f64>,
+    pub duration_tolerance_s: f64,
+    pub w: Option<u32>,
+    pub h: Option<u32>,
+    pub fps: Option<f64>,
+    /// ffprobe `codec_name`, e.g. `h264`, `hevc`, `vp9`, `av1`.
+    pub video_codec: Option<String>,
+    pub pix_fmt: Option<String>,
+    pub video: bool,
+    /// `Some(true)` requires an audio stream, `Some(false)` forbids one.
+    pub audio: Option<bool>,
+    pub sample_rate: Option<u32>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum VerifyError {
+    #[error("output missing: {0}")]
+    Missing(PathBuf),
+    #[error("output is empty: {0}")]
+    Empty(PathBuf),
+    #[error("ffprobe failed for {path}: {message}")]
+    ProbeFailed { path: PathBuf, message: String },
+    #[error("no video stream in {0}")]
+    NoVideo(PathBuf),
+    #[error("no audio stream in {0}")]
+    NoAudio(PathBuf),
+    #[error("unexpected audio stream in {0}")]
+    UnexpectedAudio(PathBuf),
+    #[error("duration {actual:.3}s outside {expected:.3}s ±{tolerance:.3}s: {path}")]
+    Duration {
+        path: PathBuf,
+        expected: f64,
+        actual: f64,
+        tolerance: f64,
+    },
+    #[error("resolution {actual} != {expected}: {path}")]
+    Resolution {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+    #[error("fps {actual:.3} != {expected:.3}: {path}")]
+    Fps {
+        path: PathBuf,
+        expected: f64,
+        actual: f64,
+    },
+    #[error("video codec {actual} != {expected}: {path}")]
+    Codec {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+    #[error("pixel format {actual} != {expected}: {path}")]
+    PixFmt {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+    #[error("sample rate {actual} != {expected}: {path}")]
+    SampleRate {
+        path: PathBuf,
+        expected: u32,
+        actual: u32,
+    },
+}
+
+impl VerifyError {
+    /// Stable machine-readable reason recorded on the stage.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::Missing(_) => "output_missing",
+            Self::Empty(_) => "output_empty",
+            Self::ProbeFailed { .. } => "probe_failed",
+            Self::NoVideo(_) => "no_video_stream",
+            Self::NoAudio(_) => "no_audio_stream",
+            Self::UnexpectedAudio(_) => "unexpected_audio_stream",
+            Self::Duration { .. } => "duration_mismatch",
+            Self::Resolution { .. } => "resolution_mismatch",
+            Self::Fps { .. } => "fps_mismatch",
+            Self::Codec { .. } => "codec_mismatch",
+            Self::PixFmt { .. } => "pix_fmt_mismatch",
+            Self::SampleRate { .. } => "sample_rate_mismatch",
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Missing(p) | Self::Empty(p) | Self::NoVideo(p) | Self::NoAudio(p) => p,
+            Self::UnexpectedAudio(p) => p,
+            Self::ProbeFailed { path, .. }
+            | Self::Duration { path, .. }
+            | Self::Resolution { path, .. }
+            | Self::Fps { path, .. }
+            | Self::Codec { path, .. }
+            | Self::PixFmt { path, .. }
+            | Self::SampleRate { path, .. } => path,
+        }
+    }
+}
+
+/// `ffprobe` next to the given `ffmpeg` binary.
+pub fn ffprobe_for(ffmpeg_path: &str) -> String {
+    match ffmpeg_path.strip_suffix("ffmpeg") {
+        Some(prefix) => format!("{prefix}ffprobe"),
+        None => "ffprobe".to_string(),
+    }
+}
+
+/// Container formats worth probing; everything else only has to be non-empty.
+pub fn is_media_path(p: &Path) -> bool {
+    matches!(
+        p.extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase())
+            .as_deref(),
+        Some("mp4" | "m4a" | "m4s" | "mov" | "mkv" | "webm" | "wav" | "mp3" | "aac" | "ts")
+    )
+}
+
+/// `30/1`, `30000/1001` or a plain number.
+fn parse_rate(s: &str) -> Option<f64> {
+    match s.split_once('/') {
+        Some((n, d)) => {
+            let (n, d) = (n.parse::<f64>().ok()?, d.parse::<f64>().ok()?);
+            (d > 0.0).then(|| n / d)
+        }
+        None => s.parse().ok(),
+    }
+}
+
+pub fn parse_ffprobe_json(s: &str, bytes: u64) -> Option<ProbeInfo> {
+    let v: serde_json::Value = serde_json::from_str(s).ok()?;
+    let streams = v.get("streams")?.as_array()?;
+    let num = |x: Option<&serde_json::Value>| -> Option<f64> {
+        match x? {
+            serde_json::Value::String(s) => s.parse().ok(),
+            serde_json::Value::Number(n) => n.as_f64(),
+            _ => None,
+        }
+    };
+    let kind = |k: &str| {
+        streams
+            .iter()
+            .find(|st| st.get("codec_type").and_then(|t| t.as_str()) == Some(k))
+    };
+    let str_of = |st: &serde_json::Value, k: &str| {
+        st.get(k)
+            .and_then(|x| x.as_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+
+    let video = kind("video").map(|st| VideoStreamInfo {
+        codec: str_of(st, "codec_name"),
+        w: st.get("width").and_then(|x| x.as_u64()).unwrap_or(0) as u32,
+        h: st.get("height").and_then(|x| x.as_u64()).unwrap_or(0) as u32,
+        fps: ["avg_frame_rate", "r_frame_rate"]
+            .iter()
+            .filter_map(|k| st.get(*k).and_then(|x| x.as_str()).and_then(parse_rate))
+            .find(|r| *r > 0.0)
+            .unwrap_or(0.0),
+        pix_fmt: str_of(st, "pix_fmt"),
+    });
+    let audio = kind("audio").map(|st| AudioStreamInfo {
+        codec: str_of(st, "codec_name"),
+        sample_rate: num(st.get("sample_rate")).unwrap_or(0.0) as u32,
+        channels: st.get("channels").and_then(|x| x.as_u64()).unwrap_or(0) as u32,
+    });
+    let duration_s = num(v.pointer("/format/duration"))
+        .or_else(|| {
+            streams
+                .iter()
+                .filter_map(|st| num(st.get("duration")))
+                .reduce(f64::max)
+        })
+        .unwrap_or(0.0);
+
+    Some(ProbeInfo {
+        duration_s,
+        bytes,
+        video,
+        audio,
+    })
+}
+
+/// WAV header read directly, so audio stages verify without ffprobe.
+fn probe_wav(path: &Path, bytes: u64) -> Result<ProbeInfo, VerifyError> {
+    let failed = |message: String| VerifyError::ProbeFailed {
+        path: path.to_path_buf(),
+        message,
+    };
+    let info = crate::audio::wav::read_wav_info(path).map_err(|e| failed(format!("{e:#}")))?;
+    if info.frames == 0 {
+        return Err(failed("no audio frames".to_string()));
+    }
+    Ok(ProbeInfo {
+        duration_s: info.duration_s(),
+        bytes,
+        video: None,
+        audio: Some(AudioStreamInfo {
+            codec: info.codec_name().to_string(),
+            sample_rate: info.sample_rate,
+            channels: info.channels as u32,
+        }),
+    })
+}
+
+pub fn probe_media(ffprobe: &str, path: &Path) -> Result<ProbeInfo, VerifyError> {
+    let bytes = match std::fs::metadata(path) {
+        Ok(m) => m.len(),
+        Err(_) => return Err(VerifyError::Missing(path.to_path_buf())),
+    };
+    if bytes == 0 {
+        return Err(VerifyError::Empty(path.to_path_buf()));
+    }
+    let is_wav = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("wav"));
+    if is_wav {
+        return probe_wav(path, bytes);
+    }
+    let failed = |message: String| VerifyError::ProbeFailed {
+        path: path.to_path_buf(),
+        message,
+    };
+    let out = Command::new(ffprobe)
+        .args([
+            "-v",
+            "error",
+            "-print_format",
+            "json",
+            "-show_format",
+            "-show_streams",
+        ])
+        .arg(path)
+        .output()
+        .map_err(|e| failed(format!("spawn {ffprobe}: {e}")))?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        return Err(failed(
+            stderr
+                .lines()
+                .last()
+                .unwrap_or("exit status")
+                .trim()
+                .to_string(),
+        ));
+    }
+    let info = parse_ffprobe_json(&String::from_utf8_lossy(&out.stdout), bytes)
+        .ok_or_else(|| failed("unreadable ffprobe output".to_string()))?;
+    if info.video.is_none() && info.audio.is_none() {
+        return Err(failed("no streams".to_string()));
+    }
+    Ok(info)
+}
+
+/// Probes `path` and checks it against `expect`.
+pub fn verify_media(
+    ffprobe: &str,
+    path: &Path,
+    expect: &MediaExpect,
+) -> Result<ProbeInfo, VerifyError> {
+    let info = probe_media(ffprobe, path)?;
+    let p = || path.to_path_buf();
+
+    if let Some(d) = expect.duration_s {
+        let tol = expect.duration_tolerance_s.max(0.05);
+        if (info.duration_s - d).abs() > tol {
+            return Err(VerifyError::Duration {
+                path: p(),
+                expected: d,
+                actual: info.duration_s,
+                tolerance: tol,
+            });
+        }
+    }
+    match (&info.audio, expect.audio) {
+        (None, Some(true)) => return Err(VerifyError::NoAudio(p())),
+        (Some(_), Some(false)) => return Err(VerifyError::UnexpectedAudio(p())),
+        _ => {}
+    }
+    if let Some(sr) = expect.sample_rate {
+        match &info.audio {
+            None => return Err(VerifyError::NoAudio(p())),
+            Some(a) if a.sample_rate != sr => {
+                return Err(VerifyError::SampleRate {
+                    path: p(),
+                    expected: sr,
+                    actual: a.sample_rate,
+                })
+            }
+            Some(_) => {}
+        }
+    }
+
+    let needs_video = expect.video
+        || expect.w.is_some()
+        || expect.h.is_some()
+        || expect.fps.is_some()
+        || expect.video_codec.is_some()
+        || expect.pix_fmt.is_some();
+    let Some(v) = &info.video else {
+        if needs_video {
+            return Err(VerifyError::NoVideo(p()));
+        }
+        return Ok(info);
+    };
+    if expect.w.is_some_and(|w| w != v.w) || expect.h.is_some_and(|h| h != v.h) {
+        return Err(VerifyError::Resolution {
+            path: p(),
+            expected: format!("{}x{}", expect.w.unwrap_or(v.w), expect.h.unwrap_or(v.h)),
+            actual: format!("{}x{}", v.w, v.h),
+        });
+    }
+    if let Some(fps) = expect.fps {
+        if (v.fps - fps).abs() > 0.01 * fps.max(1.0) {
+            return Err(VerifyError::Fps {
+                path: p(),
+                expected: fps,
+                actual: v.fps,
+            });
+        }
+    }
+    if let Some(c) = &expect.video_codec {
+        if !c.eq_ignore_ascii_case(&v.codec) {
+            return Err(VerifyError::Codec {
+                path: p(),
+                expected: c.clone(),
+                actual: v.codec.clone(),
+            });
+        }
+    }
+    if let Some(f) = &expect.pix_fmt {
+        if f != &v.pix_fmt {
+            return Err(VerifyError::PixFmt {
+                path: p(),
+                expected: f.clone(),
+                actual: v.pix_fmt.clone(),
+            });
+        }
+    }
+    Ok(info)
+}
+
+/// Outputs exist and are non-empty, and media files probe cleanly.
+pub fn verify_outputs(ffprobe: &str, outputs: &[PathBuf]) -> Result<(), VerifyError> {
+    for p in outputs {
+        if is_media_path(p) {
+            probe_media(ffprobe, p)?;
+        } else {
+            match std::fs::metadata(p) {
+                Ok(m) if m.len() == 0 => return Err(VerifyError::Empty(p.clone())),
+                Ok(_) => {}
+                Err(_) => return Err(VerifyError::Missing(p.clone())),
+            }
+        }
+    }
+    Ok(())
+}
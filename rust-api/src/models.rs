@@ -75,3 +75,59 @@ pub struct LedgerEntry {
     pub note: Option<String>,
     pub meta: Value,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Work {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// `None` once the seller is deleted; only works someone bought outlive their seller.
+    pub user_id: Option<Uuid>,
+    pub kind: String,
+    pub title: String,
+    pub meta: Value,
+    pub price_cents: i64,
+    pub currency: String,
+    pub is_listed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct WorkAsset {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub work_id: Uuid,
+    pub asset_type: String,
+    pub url: String,
+    pub meta: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct WorkPurchase {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub work_id: Uuid,
+    pub buyer_id: Uuid,
+    pub seller_id: Option<Uuid>,
+    pub price_cents: i64,
+    pub fee_cents: i64,
+    pub currency: String,
+    pub meta: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct RunJob {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub kind: String,
+    pub run_id: String,
+    pub payload: Value,
+    pub status: String,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub run_after: DateTime<Utc>,
+    pub locked_by: Option<String>,
+    pub lease_expires_at: Option<DateTime<Utc>>,
+    pub heartbeat_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
@@ -0,0 +1,175 @@
+use super::{MusicGenerator, MusicRender, MusicRequest, VocalRequest, VocalSynth};
+use crate::http_client::{get_bytes, get_json, join_url, post_json};
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+/// Generic audio job service: `POST {base}/jobs` with a `css.audio.job.v1` body, poll the
+/// job until it finishes, then download the WAV it points at.
+///
+/// The submit and poll responses carry `id` (or `job_id`), `status`, optionally
+/// `status_url`, and once finished `audio_url` (or `result_url`, `url`, `result.url`).
+/// Music jobs asked for stems also list them under `stems`, as `{name: url}` or
+/// `[{name, url}]`.
+#[derive(Debug, Clone)]
+pub struct HttpJobBackend {
+    pub base_url: String,
+    pub token: Option<String>,
+    /// Whole job: submit, polling and download.
+    pub timeout: Duration,
+    pub poll_interval: Duration,
+}
+
+enum JobState {
+    Pending,
+    Done(String),
+}
+
+fn str_at<'a>(v: &'a Value, ptrs: &[&str]) -> Option<&'a str> {
+    ptrs.iter()
+        .find_map(|p| v.pointer(p).and_then(|x| x.as_str()))
+        .filter(|s| !s.is_empty())
+}
+
+fn job_state(v: &Value) -> Result<JobState> {
+    let status = str_at(v, &["/status", "/state"])
+        .unwrap_or("pending")
+        .to_ascii_lowercase();
+    let audio = str_at(
+        v,
+        &[
+            "/audio_url",
+            "/result_url",
+            "/url",
+            "/result/url",
+            "/output/url",
+        ],
+    );
+    match status.as_str() {
+        "succeeded" | "success" | "done" | "completed" | "finished" => match audio {
+            Some(url) => Ok(JobState::Done(url.to_string())),
+            None => bail!("job finished without an audio url"),
+        },
+        "failed" | "error" | "cancelled" | "canceled" => bail!(
+            "job {status}: {}",
+            str_at(v, &["/error", "/message", "/error/message"]).unwrap_or("no reason given")
+        ),
+        _ => Ok(JobState::Pending),
+    }
+}
+
+impl HttpJobBackend {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into(),
+            token: None,
+            timeout: Duration::from_secs(600),
+            poll_interval: Duration::from_secs(1),
+        }
+    }
+
+    /// Submits a `kind` job and returns its final status response with the downloaded audio.
+    pub async fn run_job(&self, kind: &str, mut body: Value) -> Result<(Value, Vec<u8>)> {
+        let deadline = Instant::now() + self.timeout;
+        let left = || deadline.saturating_duration_since(Instant::now());
+        if let Some(obj) = body.as_object_mut() {
+            obj.insert("schema".into(), "css.audio.job.v1".into());
+            obj.insert("kind".into(), kind.into());
+        }
+        let token = self.token.as_deref();
+        let submit_url = join_url(&self.base_url, "jobs");
+        let mut res = post_json(&submit_url, token, &body, left())
+            .await
+            .with_context(|| format!("submit {kind} job"))?;
+        let poll_url = match str_at(&res, &["/status_url"]) {
+            Some(u) => join_url(&self.base_url, u),
+            None => {
+                let id = str_at(&res, &["/id", "/job_id"])
+                    .map(|s| s.to_string())
+                    .or_else(|| {
+                        res.get("id")
+                            .and_then(|x| x.as_u64())
+                            .map(|n| n.to_string())
+                    })
+                    .context("job service returned no job id")?;
+                join_url(&self.base_url, &format!("jobs/{id}"))
+            }
+        };
+        let audio_url = loop {
+            if let JobState::Done(url) = job_state(&res)? {
+                break url;
+            }
+            if left() < self.poll_interval {
+                bail!("{kind} job did not finish within {:?}", self.timeout);
+            }
+            tokio::time::sleep(self.poll_interval).await;
+            res = get_json(&poll_url, token, left())
+                .await
+                .with_context(|| format!("poll {kind} job"))?;
+        };
+        let bytes = get_bytes(&join_url(&self.base_url, &audio_url), token, left())
+            .await
+            .with_context(|| format!("download {kind} audio"))?;
+        Ok((res, bytes))
+    }
+
+    /// Downloads every stem a finished job lists, in the order given.
+    async fn download_stems(&self, res: &Value) -> Result<Vec<(String, Vec<u8>)>> {
+        let listed: Vec<(String, String)> = match res.get("stems") {
+            Some(Value::Object(m)) => m
+                .iter()
+                .filter_map(|(k, v)| Some((k.clone(), v.as_str()?.to_string())))
+                .collect(),
+            Some(Value::Array(a)) => a
+                .iter()
+                .filter_map(|s| {
+                    Some((
+                        str_at(s, &["/name"])?.to_string(),
+                        str_at(s, &["/url", "/audio_url"])?.to_string(),
+                    ))
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        let mut stems = Vec::with_capacity(listed.len());
+        for (name, url) in listed {
+            let bytes = get_bytes(
+                &join_url(&self.base_url, &url),
+                self.token.as_deref(),
+                self.timeout,
+            )
+            .await
+            .with_context(|| format!("download stem {name}"))?;
+            stems.push((name, bytes));
+        }
+        Ok(stems)
+    }
+}
+
+#[axum::async_trait]
+impl MusicGenerator for HttpJobBackend {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    async fn render_music(&self, req: &MusicRequest) -> Result<MusicRender> {
+        let (res, wav) = self.run_job("music", req.to_job_body()).await?;
+        let stems = if req.stems {
+            self.download_stems(&res).await?
+        } else {
+            Vec::new()
+        };
+        Ok(MusicRender { wav, stems })
+    }
+}
+
+#[axum::async_trait]
+impl VocalSynth for HttpJobBackend {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    async fn render_vocals(&self, req: &VocalRequest) -> Result<Vec<u8>> {
+        Ok(self.run_job("vocals", req.to_job_body()).await?.1)
+    }
+}
@@ -0,0 +1,650 @@
+pub mod http;
+pub mod procedural;
+
+use crate::audio::stems::{write_stems_zip, StemsManifest, VOCALS_STEM};
+use crate::audio::wav::read_wav_info;
+use crate::lyrics::model::{read_lyrics_json, LyricsV1};
+use crate::lyrics::{song_seed, v_f64, v_str};
+use crate::media_probe::MediaExpect;
+use crate::stage_executor::{StageContext, StageExecutor, StageOutcome};
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+/// Used when neither the song, the video nor timed lyrics say how long the track is.
+const FALLBACK_DURATION_S: f64 = 30.0;
+
+/// Musical key: root as semitones above C, major or natural minor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Key {
+    pub root: u8,
+    pub minor: bool,
+}
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B",
+];
+
+impl Key {
+    /// `C`, `F#`, `Bb`, `Am`, `c# minor`, `Eb major`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let mut chars = s.chars();
+        let base: i32 = match chars.next()?.to_ascii_uppercase() {
+            'C' => 0,
+            'D' => 2,
+            'E' => 4,
+            'F' => 5,
+            'G' => 7,
+            'A' => 9,
+            'B' => 11,
+            _ => return None,
+        };
+        let mut rest = chars.as_str();
+        let mut root = base;
+        if let Some(r) = rest.strip_prefix(['#', '♯']) {
+            root += 1;
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix(['b', '♭']) {
+            root -= 1;
+            rest = r;
+        }
+        let minor = match rest.trim().to_ascii_lowercase().as_str() {
+            "" | "maj" | "major" => false,
+            "m" | "min" | "minor" => true,
+            _ => return None,
+        };
+        Some(Self {
+            root: root.rem_euclid(12) as u8,
+            minor,
+        })
+    }
+
+    pub fn name(&self) -> String {
+        format!(
+            "{}{}",
+            NOTE_NAMES[self.root as usize % 12],
+            if self.minor { "m" } else { "" }
+        )
+    }
+
+    /// Semitone offsets of the seven scale degrees.
+    pub fn scale(&self) -> [u8; 7] {
+        if self.minor {
+            [0, 2, 3, 5, 7, 8, 10]
+        } else {
+            [0, 2, 4, 5, 7, 9, 11]
+        }
+    }
+}
+
+/// Tempo a genre gets when the DSL names no `bpm`.
+pub fn default_bpm(genre: &str) -> f64 {
+    match genre {
+        "edm" | "electronic" | "house" | "dance" | "techno" => 126.0,
+        "rock" | "punk" => 124.0,
+        "hiphop" | "hip_hop" | "rap" | "trap" => 90.0,
+        "lofi" | "lo_fi" | "chill" => 80.0,
+        "ballad" | "ambient" => 72.0,
+        _ => 112.0,
+    }
+}
+
+/// Track length: `song.duration_s`, else the video's, else the end of timed lyrics plus a
+/// bar, else [`FALLBACK_DURATION_S`].
+pub fn song_duration_s(commands: &Value, lyrics: Option<&LyricsV1>, bpm: f64) -> f64 {
+    let song = commands.get("song");
+    v_f64(song, "duration_s")
+        .or_else(|| v_f64(song, "duration"))
+        .or_else(|| v_f64(commands.get("video"), "duration_s"))
+        .or_else(|| {
+            lyrics
+                .filter(|l| l.is_timed())
+                .and_then(|l| l.lines.iter().filter_map(|x| x.end_s).reduce(f64::max))
+                .map(|end| end + 240.0 / bpm)
+        })
+        .filter(|d| d.is_finite() && *d > 0.0)
+        .unwrap_or(FALLBACK_DURATION_S)
+}
+
+/// DSL flags arrive as `"true"`/`"1"`/`"yes"` strings, API flags as booleans.
+fn v_flag(v: Option<&Value>, key: &str) -> bool {
+    match v.and_then(|v| v.get(key)) {
+        Some(Value::Bool(b)) => *b,
+        Some(Value::String(s)) => matches!(
+            s.trim().to_ascii_lowercase().as_str(),
+            "true" | "1" | "yes" | "on"
+        ),
+        Some(Value::Number(n)) => n.as_f64().is_some_and(|n| n != 0.0),
+        _ => false,
+    }
+}
+
+fn sample_rate_of(song: Option<&Value>) -> u32 {
+    v_f64(song, "sample_rate")
+        .map(|r| r as u32)
+        .filter(|r| (8_000..=192_000).contains(r))
+        .unwrap_or(DEFAULT_SAMPLE_RATE)
+}
+
+/// What the music stage renders, from `commands.song` (the `music(...)` DSL arguments).
+#[derive(Debug, Clone)]
+pub struct MusicRequest {
+    pub title: String,
+    pub genre: String,
+    pub mood: Option<String>,
+    pub bpm: f64,
+    pub key: Key,
+    pub duration_s: f64,
+    pub seed: u64,
+    pub sample_rate: u32,
+    /// Metronome clicks instead of an arrangement (`style=click`).
+    pub click: bool,
+    /// Also render per-instrument stems (`stems=true`).
+    pub stems: bool,
+    /// `procedural` or `http`; unset picks `http` when `CSS_MUSIC_URL` is configured.
+    pub backend: Option<String>,
+}
+
+impl MusicRequest {
+    pub fn from_commands(commands: &Value, lyrics: Option<&LyricsV1>) -> Self {
+        let song = commands.get("song");
+        let title = v_str(song, "title").unwrap_or_else(|| "Untitled".to_string());
+        let genre = v_str(song, "genre")
+            .map(|g| g.to_ascii_lowercase().replace(['-', ' '], "_"))
+            .unwrap_or_else(|| "pop".to_string());
+        let mood = v_str(song, "mood");
+        let bpm = v_f64(song, "bpm")
+            .or_else(|| v_f64(song, "tempo"))
+            .filter(|b| (30.0..=300.0).contains(b))
+            .unwrap_or_else(|| default_bpm(&genre));
+        // Melancholic songs default to A minor, everything else to C major.
+        let sad = mood.as_deref().is_some_and(|m| m.contains("melanchol"));
+        let key = v_str(song, "key")
+            .and_then(|k| Key::parse(&k))
+            .unwrap_or(Key {
+                root: if sad { 9 } else { 0 },
+                minor: sad,
+            });
+        Self {
+            duration_s: song_duration_s(commands, lyrics, bpm),
+            seed: song_seed(commands, &title),
+            sample_rate: sample_rate_of(song),
+            click: v_str(song, "style").is_some_and(|s| s.eq_ignore_ascii_case("click"))
+                || genre == "click",
+            stems: v_flag(song, "stems"),
+            backend: v_str(song, "music_backend"),
+            title,
+            genre,
+            mood,
+            bpm,
+            key,
+        }
+    }
+
+    pub fn to_job_body(&self) -> Value {
+        json!({
+            "title": self.title,
+            "genre": self.genre,
+            "mood": self.mood,
+            "bpm": self.bpm,
+            "key": self.key.name(),
+            "duration_s": self.duration_s,
+            "seed": self.seed,
+            "sample_rate": self.sample_rate,
+            "style": if self.click { "click" } else { "arrangement" },
+            "stems": self.stems,
+        })
+    }
+}
+
+/// What the vocals stage sings: the lyrics on the music's tempo and key.
+#[derive(Debug, Clone)]
+pub struct VocalRequest {
+    pub title: String,
+    pub lang: String,
+    /// `female`, `male` or `robot` for the procedural synth; passed through to services.
+    pub voice: String,
+    pub bpm: f64,
+    pub key: Key,
+    pub duration_s: f64,
+    pub seed: u64,
+    pub sample_rate: u32,
+    /// Lines with timings; untimed lyrics are laid on the beat grid first.
+    pub lyrics: LyricsV1,
+    /// `procedural` or `http`; unset picks `http` when `CSS_VOCALS_URL` is configured.
+    pub backend: Option<String>,
+}
+
+impl VocalRequest {
+    /// `music_duration_s` is the rendered music's length, which the vocals match.
+    pub fn from_commands(
+        commands: &Value,
+        mut lyrics: LyricsV1,
+        music_duration_s: Option<f64>,
+    ) -> Self {
+        let music = MusicRequest::from_commands(commands, Some(&lyrics));
+        let song = commands.get("song");
+        if !lyrics.is_timed() {
+            let beats_per_line = v_f64(song, "beats_per_line").map(|b| b as u32).unwrap_or(4);
+            let offset_s = v_f64(song, "offset_s").unwrap_or(0.0);
+            lyrics.apply_beat_timing(music.bpm, offset_s, beats_per_line);
+        }
+        Self {
+            title: music.title,
+            lang: lyrics.lang.clone(),
+            voice: v_str(song, "voice")
+                .map(|v| v.to_ascii_lowercase())
+                .unwrap_or_else(|| "female".to_string()),
+            bpm: music.bpm,
+            key: music.key,
+            duration_s: music_duration_s
+                .filter(|d| *d > 0.0)
+                .unwrap_or(music.duration_s),
+            seed: music.seed,
+            sample_rate: music.sample_rate,
+            lyrics,
+            backend: v_str(song, "vocals_backend"),
+        }
+    }
+
+    pub fn to_job_body(&self) -> Value {
+        json!({
+            "title": self.title,
+            "lang": self.lang,
+            "voice": self.voice,
+            "bpm": self.bpm,
+            "key": self.key.name(),
+            "duration_s": self.duration_s,
+            "seed": self.seed,
+            "sample_rate": self.sample_rate,
+            "text": self.lyrics.to_text(),
+            "lyrics": self.lyrics,
+        })
+    }
+}
+
+/// A rendered backing track: the full mix and, when the request asked for them, named stems
+/// that sum to it. All WAV bytes.
+#[derive(Debug, Clone, Default)]
+pub struct MusicRender {
+    pub wav: Vec<u8>,
+    pub stems: Vec<(String, Vec<u8>)>,
+}
+
+/// Renders the backing track. Backends return WAV bytes; the stage writes and verifies them.
+#[axum::async_trait]
+pub trait MusicGenerator: Send + Sync {
+    fn name(&self) -> &'static str;
+    async fn render_music(&self, req: &MusicRequest) -> Result<MusicRender>;
+}
+
+/// Sings the lyrics over the backing track; WAV bytes, like [`MusicGenerator`].
+#[axum::async_trait]
+pub trait VocalSynth: Send + Sync {
+    fn name(&self) -> &'static str;
+    async fn render_vocals(&self, req: &VocalRequest) -> Result<Vec<u8>>;
+}
+
+/// `http` backend from `CSS_<PREFIX>_URL`, `_TOKEN`, `_TIMEOUT_S` and `_POLL_MS`.
+fn job_backend_from_env(prefix: &str) -> Option<http::HttpJobBackend> {
+    let env = |k: &str| std::env::var(format!("CSS_{prefix}_{k}")).ok();
+    let url = env("URL").filter(|u| !u.trim().is_empty())?;
+    let mut b = http::HttpJobBackend::new(url);
+    b.token = env("TOKEN");
+    if let Some(s) = env("TIMEOUT_S").and_then(|s| s.parse::<u64>().ok()) {
+        b.timeout = Duration::from_secs(s);
+    }
+    if let Some(ms) = env("POLL_MS").and_then(|s| s.parse::<u64>().ok()) {
+        b.poll_interval = Duration::from_millis(ms);
+    }
+    Some(b)
+}
+
+fn pick_backend(stage: &str, backend: Option<&str>) -> Result<Option<http::HttpJobBackend>> {
+    let prefix = stage.to_ascii_uppercase();
+    match (
+        backend.map(|b| b.to_ascii_lowercase()).as_deref(),
+        job_backend_from_env(&prefix),
+    ) {
+        (Some("procedural" | "local"), _) | (None, None) => Ok(None),
+        (Some("http") | None, Some(b)) => Ok(Some(b)),
+        (Some("http"), None) => bail!("{stage} backend http needs CSS_{prefix}_URL"),
+        (Some(other), _) => bail!("unknown {stage} backend {other}"),
+    }
+}
+
+pub fn music_generator_for(backend: Option<&str>) -> Result<Box<dyn MusicGenerator>> {
+    Ok(match pick_backend("music", backend)? {
+        Some(b) => Box::new(b),
+        None => Box::new(procedural::ProceduralSynth),
+    })
+}
+
+pub fn vocal_synth_for(backend: Option<&str>) -> Result<Box<dyn VocalSynth>> {
+    Ok(match pick_backend("vocals", backend)? {
+        Some(b) => Box::new(b),
+        None => Box::new(procedural::ProceduralSynth),
+    })
+}
+
+fn write_wav(path: &Path, bytes: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, bytes).with_context(|| format!("write wav: {}", path.display()))
+}
+
+/// Real audio at the requested rate and length; checked by the stage's output verification.
+fn wav_expect(duration_s: f64, sample_rate: u32) -> MediaExpect {
+    MediaExpect {
+        duration_s: Some(duration_s),
+        duration_tolerance_s: 0.1,
+        audio: Some(true),
+        sample_rate: Some(sample_rate),
+        ..Default::default()
+    }
+}
+
+const STEMS_DIR: &str = "build/stems";
+const STEMS_MANIFEST: &str = "build/stems/stems.json";
+const STEMS_ZIP: &str = "build/stems.zip";
+
+/// Stem names come from services too; keep them to safe file names.
+fn stem_file_name(name: &str) -> Option<String> {
+    let clean: String = name
+        .trim()
+        .to_ascii_lowercase()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let clean = clean.trim_matches('_').to_string();
+    (!clean.is_empty()).then_some(clean)
+}
+
+/// Writes `build/stems/<name>.wav`, records it in the manifest and rebuilds the zip.
+fn add_stems(ctx: &StageContext, stems: &[(String, Vec<u8>)]) -> Result<StemsManifest> {
+    let manifest_path = ctx.path(STEMS_MANIFEST);
+    let mut manifest = StemsManifest::read(&manifest_path).unwrap_or_default();
+    for (name, bytes) in stems {
+        let name = stem_file_name(name).with_context(|| format!("bad stem name {name:?}"))?;
+        let rel = PathBuf::from(format!("{STEMS_DIR}/{name}.wav"));
+        write_wav(&ctx.path(&rel), bytes)?;
+        manifest.upsert(&ctx.workdir, &name, &rel)?;
+    }
+    manifest.write(&manifest_path)?;
+    write_stems_zip(&manifest_path, &ctx.workdir, &ctx.path(STEMS_ZIP))?;
+    Ok(manifest)
+}
+
+/// Manifest, zip and stem outputs plus the `stems.*` artifacts.
+fn with_stems(mut outcome: StageOutcome, manifest: &StemsManifest) -> StageOutcome {
+    outcome.outputs.push(PathBuf::from(STEMS_MANIFEST));
+    outcome.outputs.push(PathBuf::from(STEMS_ZIP));
+    outcome
+        .outputs
+        .extend(manifest.stems.iter().map(|s| s.path.clone()));
+    outcome
+        .meta("stems", json!(manifest.names()))
+        .artifact("stems.manifest", json!(STEMS_MANIFEST))
+        .artifact("stems.zip", json!(STEMS_ZIP))
+        .artifact("stems.names", json!(manifest.names()))
+}
+
+/// Stage kind `music`: renders `build/music.wav` from `commands.song`, plus stems in
+/// `build/stems/` and `build/stems.zip` with `stems=true`.
+#[derive(Debug, Clone, Copy)]
+pub struct MusicExecutor;
+
+#[axum::async_trait]
+impl StageExecutor for MusicExecutor {
+    fn kind(&self) -> &'static str {
+        "music"
+    }
+
+    async fn run(&self, ctx: &StageContext) -> Result<StageOutcome> {
+        let lyrics = read_lyrics_json(&ctx.path("build/lyrics.json")).ok();
+        let req = MusicRequest::from_commands(&ctx.commands, lyrics.as_ref());
+        let gen = music_generator_for(req.backend.as_deref())?;
+        let render = gen.render_music(&req).await?;
+        let rel = "build/music.wav";
+        write_wav(&ctx.path(rel), &render.wav)?;
+        // Stems of an earlier render would no longer sum to this one.
+        let _ = std::fs::remove_dir_all(ctx.path(STEMS_DIR));
+        let _ = std::fs::remove_file(ctx.path(STEMS_ZIP));
+        let stems = if req.stems {
+            if render.stems.is_empty() {
+                bail!("music backend {} returned no stems", gen.name());
+            }
+            Some(add_stems(ctx, &render.stems)?)
+        } else {
+            None
+        };
+        let outcome = StageOutcome {
+            expect: Some((
+                PathBuf::from(rel),
+                wav_expect(req.duration_s, req.sample_rate),
+            )),
+            ..Default::default()
+        }
+        .meta("backend", json!(gen.name()))
+        .meta("genre", json!(req.genre))
+        .meta("bpm", json!(req.bpm))
+        .meta("key", json!(req.key.name()))
+        .meta("duration_s", json!(req.duration_s))
+        .meta("sample_rate", json!(req.sample_rate))
+        .artifact("music.wav", json!(rel))
+        .artifact("music.backend", json!(gen.name()));
+        Ok(match &stems {
+            Some(manifest) => with_stems(outcome, manifest),
+            None => outcome,
+        })
+    }
+}
+
+/// Stage kind `vocals`: sings `build/lyrics.json` into `build/vocals.wav`, and adds it as the
+/// `vocals` stem when the music stage wrote stems.
+#[derive(Debug, Clone, Copy)]
+pub struct VocalsExecutor;
+
+#[axum::async_trait]
+impl StageExecutor for VocalsExecutor {
+    fn kind(&self) -> &'static str {
+        "vocals"
+    }
+
+    async fn run(&self, ctx: &StageContext) -> Result<StageOutcome> {
+        let lyrics = read_lyrics_json(&ctx.path("build/lyrics.json"))
+            .context("vocals need the lyrics stage output")?;
+        let music_duration_s = read_wav_info(&ctx.path("build/music.wav"))
+            .ok()
+            .map(|i| i.duration_s());
+        let req = VocalRequest::from_commands(&ctx.commands, lyrics, music_duration_s);
+        let synth = vocal_synth_for(req.backend.as_deref())?;
+        let wav = synth.render_vocals(&req).await?;
+        let rel = "build/vocals.wav";
+        write_wav(&ctx.path(rel), &wav)?;
+        let stems = if ctx.path(STEMS_MANIFEST).is_file() {
+            Some(add_stems(ctx, &[(VOCALS_STEM.to_string(), wav)])?)
+        } else {
+            None
+        };
+        let outcome = StageOutcome {
+            expect: Some((
+                PathBuf::from(rel),
+                wav_expect(req.duration_s, req.sample_rate),
+            )),
+            ..Default::default()
+        }
+        .meta("backend", json!(synth.name()))
+        .meta("voice", json!(req.voice))
+        .meta("lines", json!(req.lyrics.lines.len()))
+        .meta("duration_s", json!(req.duration_s))
+        .meta("sample_rate", json!(req.sample_rate))
+        .artifact("vocals.wav", json!(rel))
+        .artifact("vocals.backend", json!(synth.name()));
+        Ok(match &stems {
+            Some(manifest) => with_stems(outcome, manifest),
+            None => outcome,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::http::HttpJobBackend;
+    use super::procedural::ProceduralSynth;
+    use super::*;
+    use crate::lyrics::model::parse_lyrics_text;
+    use crate::media_probe::{verify_media, VerifyError};
+    use std::io::{Read, Write};
+
+    fn commands(song: Value) -> Value {
+        json!({ "song": song })
+    }
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("css_music_{}_{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn key_names() {
+        assert_eq!(
+            Key::parse("Am"),
+            Some(Key {
+                root: 9,
+                minor: true
+            })
+        );
+        assert_eq!(Key::parse("c# minor").unwrap().name(), "C#m");
+        assert_eq!(Key::parse("Bb").unwrap().name(), "Bb");
+        assert_eq!(Key::parse("H"), None);
+    }
+
+    #[tokio::test]
+    async fn procedural_renders_valid_wavs() {
+        let dir = scratch("procedural");
+        let cmds = commands(json!({"genre": "edm", "key": "F#m", "duration_s": "3", "seed": 5}));
+        let music = MusicRequest::from_commands(&cmds, None);
+        assert_eq!(music.bpm, 126.0);
+        let wav = ProceduralSynth.render_music(&music).await.unwrap().wav;
+        assert_eq!(wav, ProceduralSynth.render_music(&music).await.unwrap().wav);
+        let path = dir.join("music.wav");
+        write_wav(&path, &wav).unwrap();
+        let info = verify_media("ffprobe", &path, &wav_expect(3.0, DEFAULT_SAMPLE_RATE)).unwrap();
+        assert_eq!(info.audio.unwrap().channels, 2);
+
+        let lyrics = parse_lyrics_text(
+            "[Verse]\nhello there\n[Chorus]\nsing it loud",
+            "t",
+            "en",
+            "test",
+        );
+        let vocals = VocalRequest::from_commands(&cmds, lyrics, Some(3.0));
+        assert!(vocals.lyrics.is_timed());
+        let wav = ProceduralSynth.render_vocals(&vocals).await.unwrap();
+        let path = dir.join("vocals.wav");
+        write_wav(&path, &wav).unwrap();
+        verify_media("ffprobe", &path, &wav_expect(3.0, DEFAULT_SAMPLE_RATE)).unwrap();
+
+        let err = verify_media("ffprobe", &path, &wav_expect(3.0, 48_000)).unwrap_err();
+        assert_eq!(err.reason(), "sample_rate_mismatch");
+        let err =
+            verify_media("ffprobe", &path, &wav_expect(5.0, DEFAULT_SAMPLE_RATE)).unwrap_err();
+        assert!(matches!(err, VerifyError::Duration { .. }));
+        std::fs::write(&path, b"RIFF....not audio").unwrap();
+        let err =
+            verify_media("ffprobe", &path, &wav_expect(3.0, DEFAULT_SAMPLE_RATE)).unwrap_err();
+        assert_eq!(err.reason(), "probe_failed");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// Reads one request (head plus `Content-Length` body) and returns its request line.
+    fn read_request(sock: &mut std::net::TcpStream) -> String {
+        let mut req = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = sock.read(&mut buf).unwrap();
+            req.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&req).to_string();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let len = head
+                    .lines()
+                    .find_map(|l| l.strip_prefix("Content-Length: "))
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .unwrap_or(0);
+                if body.len() >= len {
+                    return head.lines().next().unwrap_or_default().to_string();
+                }
+            }
+            if n == 0 {
+                return String::new();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn http_job_backend_submits_polls_and_downloads() {
+        let click = MusicRequest::from_commands(
+            &commands(json!({"style": "click", "bpm": 120, "duration_s": 1})),
+            None,
+        );
+        let wav = ProceduralSynth.render_music(&click).await.unwrap().wav;
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}/api", listener.local_addr().unwrap());
+        let served = wav.clone();
+        let server = std::thread::spawn(move || {
+            let mut seen = Vec::new();
+            for (i, reply) in [
+                br#"{"id":"j1","status":"queued"}"#.to_vec(),
+                br#"{"id":"j1","status":"running"}"#.to_vec(),
+                br#"{"id":"j1","status":"succeeded","audio_url":"/files/j1.wav"}"#.to_vec(),
+                served,
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                let (mut sock, _) = listener.accept().unwrap();
+                seen.push(read_request(&mut sock));
+                let ctype = if i == 3 {
+                    "audio/wav"
+                } else {
+                    "application/json"
+                };
+                write!(
+                    sock,
+                    "HTTP/1.1 200 OK\r\nContent-Type: {ctype}\r\nContent-Length: {}\r\n\r\n",
+                    reply.len()
+                )
+                .unwrap();
+                sock.write_all(&reply).unwrap();
+            }
+            seen
+        });
+
+        let mut backend = HttpJobBackend::new(base);
+        backend.poll_interval = Duration::from_millis(10);
+        let got = backend.render_music(&click).await.unwrap();
+        assert_eq!(got.wav, wav);
+        assert_eq!(
+            server.join().unwrap(),
+            vec![
+                "POST /api/jobs HTTP/1.1",
+                "GET /api/jobs/j1 HTTP/1.1",
+                "GET /api/jobs/j1 HTTP/1.1",
+                "GET /files/j1.wav HTTP/1.1",
+            ]
+        );
+    }
+}
@@ -0,0 +1,426 @@
+use super::{Key, MusicGenerator, MusicRender, MusicRequest, VocalRequest, VocalSynth};
+use crate::audio::wav::encode_wav_pcm16;
+use anyhow::Result;
+use std::f32::consts::TAU;
+
+/// Offline backend: renders a drum/bass/pad arrangement or a click track for music, and a
+/// harmonic tone per syllable for vocals. Deterministic for a given request.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProceduralSynth;
+
+/// xorshift64*, for noise and melody choices.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform in [-1, 1).
+    fn noise(&mut self) -> f32 {
+        (self.next() >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+    }
+}
+
+fn midi_hz(note: f32) -> f32 {
+    440.0 * 2f32.powf((note - 69.0) / 12.0)
+}
+
+fn frames(seconds: f64, sample_rate: u32) -> usize {
+    (seconds * sample_rate as f64).round().max(0.0) as usize
+}
+
+fn add_kick(buf: &mut [f32], sr: f32, at: usize, gain: f32) {
+    let mut phase = 0.0f32;
+    for (i, s) in buf
+        .iter_mut()
+        .skip(at)
+        .take((0.35 * sr) as usize)
+        .enumerate()
+    {
+        let t = i as f32 / sr;
+        phase += TAU * (45.0 + 75.0 * (-t * 30.0).exp()) / sr;
+        *s += gain * phase.sin() * (-t * 9.0).exp();
+    }
+}
+
+fn add_snare(buf: &mut [f32], sr: f32, at: usize, gain: f32, rng: &mut Rng) {
+    for (i, s) in buf
+        .iter_mut()
+        .skip(at)
+        .take((0.18 * sr) as usize)
+        .enumerate()
+    {
+        let t = i as f32 / sr;
+        let body = (TAU * 180.0 * t).sin() * (-t * 30.0).exp();
+        *s += gain * (0.7 * rng.noise() * (-t * 25.0).exp() + 0.3 * body);
+    }
+}
+
+fn add_hat(buf: &mut [f32], sr: f32, at: usize, gain: f32, rng: &mut Rng) {
+    let mut prev = 0.0f32;
+    for (i, s) in buf
+        .iter_mut()
+        .skip(at)
+        .take((0.05 * sr) as usize)
+        .enumerate()
+    {
+        let t = i as f32 / sr;
+        let n = rng.noise();
+        *s += gain * (n - prev) * 0.5 * (-t * 90.0).exp();
+        prev = n;
+    }
+}
+
+fn add_click(buf: &mut [f32], sr: f32, at: usize, hz: f32, gain: f32) {
+    for (i, s) in buf
+        .iter_mut()
+        .skip(at)
+        .take((0.03 * sr) as usize)
+        .enumerate()
+    {
+        let t = i as f32 / sr;
+        *s += gain * (TAU * hz * t).sin() * (-t * 150.0).exp();
+    }
+}
+
+/// Additive tone with linear attack and release; `vibrato` is `(rate_hz, depth_semitones)`.
+struct Tone<'a> {
+    note: f32,
+    len_s: f32,
+    gain: f32,
+    harmonics: &'a [f32],
+    attack_s: f32,
+    release_s: f32,
+    vibrato: Option<(f32, f32)>,
+}
+
+fn add_tone(buf: &mut [f32], sr: f32, at: usize, tone: &Tone) {
+    let len = (tone.len_s * sr) as usize;
+    let base = midi_hz(tone.note);
+    let mut phase = 0.0f32;
+    for (i, s) in buf.iter_mut().skip(at).take(len).enumerate() {
+        let t = i as f32 / sr;
+        let hz = match tone.vibrato {
+            // Vibrato fades in after the onset, as a singer's does.
+            Some((rate, depth)) => {
+                let d = depth * ((t - 0.12) / 0.2).clamp(0.0, 1.0);
+                base * 2f32.powf(d * (TAU * rate * t).sin() / 12.0)
+            }
+            None => base,
+        };
+        phase = (phase + TAU * hz / sr) % (TAU * 64.0);
+        let env = (t / tone.attack_s.max(1e-3))
+            .min(1.0)
+            .min((tone.len_s - t) / tone.release_s.max(1e-3))
+            .max(0.0);
+        let v: f32 = tone
+            .harmonics
+            .iter()
+            .enumerate()
+            .map(|(k, a)| a * (phase * (k + 1) as f32).sin())
+            .sum();
+        *s += tone.gain * env * v;
+    }
+}
+
+/// Gain that brings `buf`'s loudest sample to `peak`.
+fn peak_gain(buf: &[f32], peak: f32) -> f32 {
+    let max = buf.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+    if max > 0.0 {
+        peak / max
+    } else {
+        1.0
+    }
+}
+
+/// Scales to `peak` and fades both ends so the file never starts or stops on a click.
+fn finish(buf: &mut [f32], sr: f32, peak: f32, fade_out_s: f32) {
+    let k = peak_gain(buf, peak);
+    buf.iter_mut().for_each(|s| *s *= k);
+    fade(buf, sr, fade_out_s);
+}
+
+fn fade(buf: &mut [f32], sr: f32, fade_out_s: f32) {
+    let fade_in = ((0.01 * sr) as usize).min(buf.len());
+    for (i, s) in buf.iter_mut().take(fade_in).enumerate() {
+        *s *= i as f32 / fade_in as f32;
+    }
+    let fade_out = ((fade_out_s * sr) as usize).min(buf.len());
+    let n = buf.len();
+    for (i, s) in buf.iter_mut().skip(n - fade_out).enumerate() {
+        *s *= 1.0 - i as f32 / fade_out as f32;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Groove {
+    FourOnFloor,
+    Backbeat,
+    HalfTime,
+    Sparse,
+}
+
+fn groove_for(genre: &str) -> Groove {
+    match genre {
+        "edm" | "electronic" | "house" | "dance" | "techno" => Groove::FourOnFloor,
+        "hiphop" | "hip_hop" | "rap" | "trap" | "lofi" | "lo_fi" | "chill" => Groove::HalfTime,
+        "ballad" | "ambient" => Groove::Sparse,
+        _ => Groove::Backbeat,
+    }
+}
+
+/// Chord roots (semitones above the key root) and whether each chord is minor:
+/// I-V-vi-IV in major, i-VI-III-VII in minor.
+fn progression(key: Key) -> [(u8, bool); 4] {
+    if key.minor {
+        [(0, true), (8, false), (3, false), (10, false)]
+    } else {
+        [(0, false), (7, false), (9, true), (5, false)]
+    }
+}
+
+fn stereo(mono: &[f32]) -> Vec<f32> {
+    mono.iter().flat_map(|s| [*s, *s]).collect()
+}
+
+/// The backing track as interleaved stereo layers (`drums`, `bass`, `harmony`, or just
+/// `click`), levelled and faded together so their sum is the finished mix.
+pub fn render_music_layers(req: &MusicRequest) -> Vec<(&'static str, Vec<f32>)> {
+    let sr = req.sample_rate as f32;
+    let n = frames(req.duration_s, req.sample_rate);
+    let beat_s = 60.0 / req.bpm;
+    let beat = |b: f64| frames(b * beat_s, req.sample_rate);
+    let beats = (req.duration_s / beat_s).ceil() as usize;
+    let mut rng = Rng::new(req.seed);
+
+    if req.click {
+        let mut click = vec![0.0f32; n];
+        for b in 0..beats {
+            let hz = if b % 4 == 0 { 1500.0 } else { 1000.0 };
+            add_click(&mut click, sr, beat(b as f64), hz, 0.8);
+        }
+        finish(&mut click, sr, 0.89, 0.0);
+        return vec![("click", stereo(&click))];
+    }
+
+    let mut drums = vec![0.0f32; n];
+    let mut bass = vec![0.0f32; n];
+    let mut pad = vec![0.0f32; n];
+
+    let groove = groove_for(&req.genre);
+    let chords = progression(req.key);
+    let rotate = (req.seed % 4) as usize;
+    let root = req.key.root as f32;
+    for bar in 0..beats.div_ceil(4) {
+        let (offset, minor) = chords[(bar + rotate) % 4];
+        let chord_root = root + offset as f32;
+        let start = (bar * 4) as f64;
+
+        let pad_tone = |note: f32| Tone {
+            note,
+            len_s: (4.0 * beat_s) as f32,
+            gain: 0.08,
+            harmonics: &[1.0, 0.3, 0.1],
+            attack_s: 0.15,
+            release_s: 0.3,
+            vibrato: None,
+        };
+        for interval in [0.0, if minor { 3.0 } else { 4.0 }, 7.0] {
+            add_tone(
+                &mut pad,
+                sr,
+                beat(start),
+                &pad_tone(60.0 + chord_root + interval),
+            );
+        }
+
+        for b in 0..4 {
+            let at = start + b as f64;
+            let bass_note = 36.0 + (chord_root % 12.0);
+            add_tone(
+                &mut bass,
+                sr,
+                beat(at),
+                &Tone {
+                    note: bass_note,
+                    len_s: (0.9 * beat_s) as f32,
+                    gain: 0.22,
+                    harmonics: &[1.0, 0.5, 0.33, 0.25],
+                    attack_s: 0.01,
+                    release_s: 0.08,
+                    vibrato: None,
+                },
+            );
+            let kick = match groove {
+                Groove::FourOnFloor => true,
+                Groove::Backbeat | Groove::HalfTime => b == 0 || b == 2,
+                Groove::Sparse => b == 0,
+            };
+            if kick {
+                add_kick(&mut drums, sr, beat(at), 0.9);
+            }
+            let snare = match groove {
+                Groove::FourOnFloor | Groove::Backbeat => b % 2 == 1,
+                Groove::HalfTime => b == 2,
+                Groove::Sparse => false,
+            };
+            if snare {
+                add_snare(&mut drums, sr, beat(at), 0.45, &mut rng);
+            }
+            if groove != Groove::Sparse {
+                add_hat(&mut drums, sr, beat(at + 0.5), 0.25, &mut rng);
+                if groove != Groove::FourOnFloor {
+                    add_hat(&mut drums, sr, beat(at), 0.15, &mut rng);
+                }
+            }
+        }
+        // An occasional pickup kick keeps repeated bars from sounding pasted.
+        if groove != Groove::Sparse && rng.next().is_multiple_of(3) {
+            add_kick(&mut drums, sr, beat(start + 3.5), 0.6);
+        }
+    }
+
+    // The pad is a few milliseconds late on the right for some width.
+    let haas = (0.012 * sr) as usize;
+    let mut harmony: Vec<f32> = Vec::with_capacity(n * 2);
+    for i in 0..n {
+        harmony.push(pad[i]);
+        harmony.push(if i >= haas { pad[i - haas] } else { 0.0 });
+    }
+    let mut layers = vec![
+        ("drums", stereo(&drums)),
+        ("bass", stereo(&bass)),
+        ("harmony", harmony),
+    ];
+    let k = peak_gain(&sum_layers(&layers), 0.89);
+    for (_, buf) in &mut layers {
+        buf.iter_mut().for_each(|s| *s *= k);
+        fade(buf, sr * 2.0, 1.0_f32.min(req.duration_s as f32 / 4.0));
+    }
+    layers
+}
+
+fn sum_layers(layers: &[(&'static str, Vec<f32>)]) -> Vec<f32> {
+    let mut out = vec![0.0f32; layers.iter().map(|(_, b)| b.len()).max().unwrap_or(0)];
+    for (_, buf) in layers {
+        for (o, s) in out.iter_mut().zip(buf) {
+            *o += s;
+        }
+    }
+    out
+}
+
+/// Mono samples of the lyrics sung one note per syllable on a walk through the key's scale.
+pub fn render_vocals_pcm(req: &VocalRequest) -> Vec<f32> {
+    let sr = req.sample_rate as f32;
+    let n = frames(req.duration_s, req.sample_rate);
+    let mut buf = vec![0.0f32; n];
+    let mut rng = Rng::new(req.seed ^ 0x5EED_F00D);
+    let scale = req.key.scale();
+    let (shift, harmonics): (f32, &[f32]) = match req.voice.as_str() {
+        "male" | "baritone" | "bass" | "tenor" => (-12.0, &[1.0, 0.7, 0.45, 0.3, 0.2, 0.12]),
+        "robot" => (0.0, &[1.0, 0.0, 0.33, 0.0, 0.2, 0.0, 0.14]),
+        _ => (0.0, &[1.0, 0.6, 0.4, 0.25, 0.15, 0.1]),
+    };
+    let vibrato = (req.voice != "robot").then_some((5.5, 0.3));
+    let mut degree: i32 = 0;
+    for line in &req.lyrics.lines {
+        let (Some(start), Some(end)) = (line.start_s, line.end_s) else {
+            continue;
+        };
+        if start >= req.duration_s || end <= start {
+            continue;
+        }
+        let end = end.min(req.duration_s);
+        let syllables = line.syllables.max(1) as usize;
+        let note_s = (end - start) / syllables as f64;
+        for k in 0..syllables {
+            let last = k + 1 == syllables;
+            // Mostly steps, sometimes a leap; each line ends on a note of the tonic chord.
+            degree += match rng.next() % 6 {
+                0 => -2,
+                1 | 2 => -1,
+                3 | 4 => 1,
+                _ => 2,
+            };
+            degree = degree.clamp(-3, 9);
+            if last {
+                degree = [0, 2, 4, 7]
+                    .into_iter()
+                    .min_by_key(|d: &i32| (d - degree).abs())
+                    .unwrap_or(0);
+            }
+            let octave = degree.div_euclid(7);
+            let step = scale[degree.rem_euclid(7) as usize] as i32;
+            let note = 60.0 + req.key.root as f32 + (octave * 12 + step) as f32 + shift;
+            add_tone(
+                &mut buf,
+                sr,
+                frames(start + k as f64 * note_s, req.sample_rate),
+                &Tone {
+                    note,
+                    len_s: (note_s * if last { 1.0 } else { 0.92 }) as f32,
+                    gain: 0.25,
+                    harmonics,
+                    attack_s: 0.02,
+                    release_s: if last { 0.12 } else { 0.04 },
+                    vibrato,
+                },
+            );
+        }
+    }
+    finish(&mut buf, sr, 0.7, 0.05);
+    buf
+}
+
+#[axum::async_trait]
+impl MusicGenerator for ProceduralSynth {
+    fn name(&self) -> &'static str {
+        "procedural"
+    }
+
+    async fn render_music(&self, req: &MusicRequest) -> Result<MusicRender> {
+        let req = req.clone();
+        let render = tokio::task::spawn_blocking(move || {
+            let layers = render_music_layers(&req);
+            let wav = encode_wav_pcm16(req.sample_rate, 2, &sum_layers(&layers));
+            let stems = if req.stems {
+                layers
+                    .iter()
+                    .map(|(name, buf)| {
+                        (name.to_string(), encode_wav_pcm16(req.sample_rate, 2, buf))
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            MusicRender { wav, stems }
+        })
+        .await?;
+        Ok(render)
+    }
+}
+
+#[axum::async_trait]
+impl VocalSynth for ProceduralSynth {
+    fn name(&self) -> &'static str {
+        "procedural"
+    }
+
+    async fn render_vocals(&self, req: &VocalRequest) -> Result<Vec<u8>> {
+        let req = req.clone();
+        let pcm = tokio::task::spawn_blocking(move || {
+            encode_wav_pcm16(req.sample_rate, 1, &render_vocals_pcm(&req))
+        })
+        .await?;
+        Ok(pcm)
+    }
+}
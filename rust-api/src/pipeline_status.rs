@@ -1,3 +1,4 @@
+use crate::artifacts::ArtifactRegistry;
 use crate::dag::{cssmv_dag_v1, Dag};
 use crate::run_worker;
 use serde_json::json;
@@ -48,29 +49,22 @@ fn ready_queue(dag: &Dag, state: &serde_json::Value) -> Vec<String> {
     out
 }
 
-fn artifacts_get<'a>(v: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
-    let mut cur = v;
-    for p in path.split('.').filter(|x| !x.is_empty()) {
-        cur = cur.get(p)?;
-    }
-    Some(cur)
-}
-
 pub fn build_status_json(state_path: &Path) -> anyhow::Result<serde_json::Value> {
     let s = fs::read_to_string(state_path)?;
     let state: serde_json::Value = serde_json::from_str(&s)?;
     let dag = cssmv_dag_v1();
 
     let ready = ready_queue(&dag, &state);
-    let artifacts = state.get("artifacts").cloned().unwrap_or_else(|| json!({}));
+    let artifacts = ArtifactRegistry::from_run_json(&state);
 
-    let shots_n = artifacts_get(&artifacts, "video.shots_count")
+    let shots_n = artifacts
+        .value_of("video.shots_count")
         .and_then(|v| v.as_u64())
         .map(|n| n as i64);
 
-    let storyboard = artifacts_get(&artifacts, "video.storyboard")
-        .and_then(|v| v.as_str())
-        .map(|x| x.to_string());
+    let storyboard = artifacts
+        .path_of("video.storyboard")
+        .map(|p| p.display().to_string());
 
     Ok(json!({
         "schema": "css.pipeline.status.v1",
@@ -81,6 +75,7 @@ pub fn build_status_json(state_path: &Path) -> anyhow::Result<serde_json::Value>
             "queued": run_worker::queued_count() as i64
         },
         "ready": ready,
+        "artifacts_count": artifacts.len() as i64,
         "stages": dag.nodes.iter().map(|n| json!({
             "name": n.name,
             "deps": n.deps,
@@ -1,8 +1,7 @@
 use crate::dag::cssmv_dag_v1;
 use crate::routes::AppState;
 use crate::run_state::{RunState, RunStatus, StageStatus};
-use crate::run_state_io::read_run_state_async;
-use crate::run_store::run_state_path;
+use crate::run_state_io::{read_run_state_async, run_state_path};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
@@ -227,7 +226,7 @@ pub async fn compute_ready_view_by_id(
     runs_root: std::path::PathBuf,
     run_id: String,
 ) -> Result<ReadyView, String> {
-    let path = crate::run_store::run_state_path(&runs_root, &run_id);
+    let path = crate::run_state_io::run_state_path(&runs_root, &run_id);
     let st = crate::run_state_io::read_run_state_async(&path)
         .await
         .map_err(|e| format!("{e}"))?;
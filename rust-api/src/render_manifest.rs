@@ -0,0 +1,248 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::ffi::OsStr;
+use std::io::Read;
+use std::path::Path;
+
+pub const RENDER_MANIFEST_SCHEMA: &str = "css.video.render_manifest.v1";
+
+/// ffmpeg flags that make output depend only on inputs and arguments: one encoder thread,
+/// no version strings, no copied metadata or creation times.
+pub const DETERMINISTIC_ARGS: [&str; 12] = [
+    "-threads",
+    "1",
+    "-fflags",
+    "+bitexact",
+    "-flags:v",
+    "+bitexact",
+    "-flags:a",
+    "+bitexact",
+    "-map_metadata",
+    "-1",
+    "-map_chapters",
+    "-1",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileHash {
+    /// Relative to the render's workdir (or the assets root for inputs).
+    pub path: String,
+    pub sha256: String,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RenderStep {
+    pub id: String,
+    /// Hash of the ffmpeg argv with workdir and assets root replaced by placeholders.
+    pub args_sha256: String,
+    pub output: FileHash,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenderManifest {
+    pub schema: String,
+    pub seed: u64,
+    pub reproducible: bool,
+    pub profile: String,
+    pub storyboard: FileHash,
+    pub inputs: Vec<FileHash>,
+    pub steps: Vec<RenderStep>,
+    pub output: FileHash,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestDiff {
+    /// `storyboard`, `input:<path>`, `args:<step>`, `output:<step>` or `output`.
+    pub item: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerifyReport {
+    pub manifest: String,
+    pub rerender_dir: String,
+    pub matched: bool,
+    pub diffs: Vec<ManifestDiff>,
+}
+
+pub fn sha256_file(path: &Path) -> std::io::Result<(String, u64)> {
+    let mut f = std::fs::File::open(path)?;
+    let mut h = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    let mut n_total = 0u64;
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        h.update(&buf[..n]);
+        n_total += n as u64;
+    }
+    Ok((hex::encode(h.finalize()), n_total))
+}
+
+pub fn file_hash(path: &Path, base: &Path) -> std::io::Result<FileHash> {
+    let (sha256, bytes) = sha256_file(path)?;
+    Ok(FileHash {
+        path: path
+            .strip_prefix(base)
+            .unwrap_or(path)
+            .display()
+            .to_string(),
+        sha256,
+        bytes,
+    })
+}
+
+/// Hashes an argv after swapping each `(prefix, placeholder)` so the same render in another
+/// directory hashes the same.
+pub fn hash_args<'a>(
+    args: impl IntoIterator<Item = &'a OsStr>,
+    replacements: &[(&Path, &str)],
+) -> String {
+    let mut h = Sha256::new();
+    for a in args {
+        let mut s = a.to_string_lossy().into_owned();
+        for (prefix, placeholder) in replacements {
+            let p = prefix.display().to_string();
+            if !p.is_empty() {
+                s = s.replace(&p, placeholder);
+            }
+        }
+        h.update(s.as_bytes());
+        h.update([0u8]);
+    }
+    hex::encode(h.finalize())
+}
+
+/// Every difference between a recorded manifest and a re-render's.
+pub fn diff_manifests(expected: &RenderManifest, actual: &RenderManifest) -> Vec<ManifestDiff> {
+    let mut out = Vec::new();
+    let mut push = |item: String, e: &str, a: &str| {
+        if e != a {
+            out.push(ManifestDiff {
+                item,
+                expected: e.to_string(),
+                actual: a.to_string(),
+            });
+        }
+    };
+    push(
+        "storyboard".to_string(),
+        &expected.storyboard.sha256,
+        &actual.storyboard.sha256,
+    );
+    for e in &expected.inputs {
+        let a = actual.inputs.iter().find(|a| a.path == e.path);
+        push(
+            format!("input:{}", e.path),
+            &e.sha256,
+            a.map(|a| a.sha256.as_str()).unwrap_or("missing"),
+        );
+    }
+    for e in &expected.steps {
+        let a = actual.steps.iter().find(|a| a.id == e.id);
+        push(
+            format!("args:{}", e.id),
+            &e.args_sha256,
+            a.map(|a| a.args_sha256.as_str()).unwrap_or("missing"),
+        );
+        push(
+            format!("output:{}", e.id),
+            &e.output.sha256,
+            a.map(|a| a.output.sha256.as_str()).unwrap_or("missing"),
+        );
+    }
+    for a in &actual.steps {
+        if !expected.steps.iter().any(|e| e.id == a.id) {
+            push(format!("output:{}", a.id), "missing", &a.output.sha256);
+        }
+    }
+    push(
+        "output".to_string(),
+        &expected.output.sha256,
+        &actual.output.sha256,
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fh(path: &str, sha256: &str) -> FileHash {
+        FileHash {
+            path: path.to_string(),
+            sha256: sha256.to_string(),
+            bytes: 1,
+        }
+    }
+
+    fn manifest() -> RenderManifest {
+        RenderManifest {
+            schema: RENDER_MANIFEST_SCHEMA.to_string(),
+            seed: 7,
+            reproducible: true,
+            profile: "standard".to_string(),
+            storyboard: fh("storyboard.json", "sb"),
+            inputs: vec![fh("a.png", "in")],
+            steps: vec![RenderStep {
+                id: "shot_001".to_string(),
+                args_sha256: "args".to_string(),
+                output: fh("shot_001.mp4", "shot"),
+            }],
+            output: fh("final.mp4", "out"),
+        }
+    }
+
+    #[test]
+    fn matching_manifests_have_no_diffs() {
+        assert!(diff_manifests(&manifest(), &manifest()).is_empty());
+    }
+
+    #[test]
+    fn a_changed_step_output_is_the_only_diff() {
+        let mut actual = manifest();
+        actual.steps[0].output.sha256 = "other".to_string();
+        let diffs = diff_manifests(&manifest(), &actual);
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].item, "output:shot_001");
+        assert_eq!(
+            (diffs[0].expected.as_str(), diffs[0].actual.as_str()),
+            ("shot", "other")
+        );
+    }
+
+    #[test]
+    fn args_hash_the_same_in_another_workdir() {
+        let hash = |work: &str, assets: &str| {
+            let args = [
+                format!("{assets}/a.png"),
+                "-y".to_string(),
+                format!("{work}/shot_001.mp4"),
+            ];
+            hash_args(
+                args.iter().map(OsStr::new),
+                &[
+                    (Path::new(work), "$WORKDIR"),
+                    (Path::new(assets), "$ASSETS"),
+                ],
+            )
+        };
+        assert_eq!(
+            hash("/runs/a/build", "/assets"),
+            hash("/tmp/rerender", "/srv/assets")
+        );
+        let literal = ["$ASSETS/a.png", "-y", "$WORKDIR/shot_001.mp4"];
+        assert_eq!(
+            hash("/work", "/assets"),
+            hash_args(literal.iter().map(OsStr::new), &[])
+        );
+        assert_ne!(
+            hash("/work", "/assets"),
+            hash_args(literal[..2].iter().map(OsStr::new), &[])
+        );
+    }
+}
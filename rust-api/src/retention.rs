@@ -0,0 +1,625 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Files the video stages only need while a run is rendering.
+const INTERMEDIATE_FILES: &[&str] = &["concat.txt", "shots.txt"];
+/// Directories of per-shot renders (`build/video/shots`, `build/video/targets/<t>/shots`).
+const INTERMEDIATE_DIRS: &[&str] = &["shots"];
+const DEFAULT_GC_INTERVAL_S: u64 = 3600;
+
+#[derive(thiserror::Error, Debug)]
+pub enum GcError {
+    #[error("gc scan failed: {0}")]
+    Scan(String),
+    #[error(transparent)]
+    Db(#[from] sqlx::Error),
+}
+
+/// What the GC keeps under `runs_dir`. Runs backing a work are never deleted, and only
+/// finished runs are touched. Objects already uploaded to a remote artifact store are
+/// left to the bucket's own lifecycle rules.
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    /// Finished runs are deleted this many days after their last update; `None` keeps them.
+    pub keep_days: Option<u32>,
+    /// Delete shot renders and concat lists once a run has succeeded.
+    pub prune_intermediates: bool,
+    pub max_bytes_per_user: Option<u64>,
+    /// Caps for users on a tier, replacing `max_bytes_per_user`.
+    pub tier_max_bytes: BTreeMap<String, u64>,
+    pub interval: Duration,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            keep_days: None,
+            prune_intermediates: true,
+            max_bytes_per_user: None,
+            tier_max_bytes: BTreeMap::new(),
+            interval: Duration::from_secs(DEFAULT_GC_INTERVAL_S),
+        }
+    }
+}
+
+impl RetentionPolicy {
+    /// `RETENTION_KEEP_DAYS`, `RETENTION_PRUNE_INTERMEDIATES` (default on),
+    /// `RETENTION_MAX_BYTES_PER_USER`, `RETENTION_TIER_MAX_BYTES` (`free=2G,pro=50G`) and
+    /// `RETENTION_GC_INTERVAL_S`.
+    pub fn from_env() -> Result<Self, String> {
+        let var = |k: &str| std::env::var(k).ok().filter(|v| !v.trim().is_empty());
+        let mut p = Self::default();
+        if let Some(v) = var("RETENTION_KEEP_DAYS") {
+            p.keep_days = Some(
+                v.trim()
+                    .parse()
+                    .map_err(|_| format!("RETENTION_KEEP_DAYS={v} is not a number of days"))?,
+            );
+        }
+        if let Some(v) = var("RETENTION_PRUNE_INTERMEDIATES") {
+            p.prune_intermediates = !matches!(v.trim(), "0" | "false" | "no" | "off");
+        }
+        if let Some(v) = var("RETENTION_MAX_BYTES_PER_USER") {
+            p.max_bytes_per_user = Some(
+                parse_bytes(&v)
+                    .ok_or_else(|| format!("RETENTION_MAX_BYTES_PER_USER={v} is not a size"))?,
+            );
+        }
+        if let Some(v) = var("RETENTION_TIER_MAX_BYTES") {
+            p.tier_max_bytes = parse_tier_caps(&v)?;
+        }
+        if let Some(v) = var("RETENTION_GC_INTERVAL_S") {
+            let s: u64 = v
+                .trim()
+                .parse()
+                .map_err(|_| format!("RETENTION_GC_INTERVAL_S={v} is not a number of seconds"))?;
+            p.interval = Duration::from_secs(s.max(60));
+        }
+        Ok(p)
+    }
+
+    pub fn cap_for(&self, tier: &str) -> Option<u64> {
+        self.tier_max_bytes
+            .get(tier)
+            .copied()
+            .or(self.max_bytes_per_user)
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "keep_days": self.keep_days,
+            "prune_intermediates": self.prune_intermediates,
+            "max_bytes_per_user": self.max_bytes_per_user,
+            "tier_max_bytes": self.tier_max_bytes,
+            "interval_s": self.interval.as_secs(),
+        })
+    }
+}
+
+/// `1024`, `500K`, `200M`, `2G`, `1T` (binary units).
+pub fn parse_bytes(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (n, unit) = s.split_at(split);
+    let n: u64 = n.parse().ok()?;
+    let shift = match unit.trim().to_ascii_uppercase().trim_end_matches('B') {
+        "" => 0,
+        "K" | "KI" => 10,
+        "M" | "MI" => 20,
+        "G" | "GI" => 30,
+        "T" | "TI" => 40,
+        _ => return None,
+    };
+    n.checked_mul(1u64 << shift)
+}
+
+fn parse_tier_caps(s: &str) -> Result<BTreeMap<String, u64>, String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let (tier, size) = p
+                .split_once('=')
+                .ok_or_else(|| format!("RETENTION_TIER_MAX_BYTES entry {p} is not tier=size"))?;
+            let bytes = parse_bytes(size)
+                .ok_or_else(|| format!("RETENTION_TIER_MAX_BYTES entry {p} has a bad size"))?;
+            Ok((tier.trim().to_ascii_lowercase(), bytes))
+        })
+        .collect()
+}
+
+/// Disk use of one run directory.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunUsage {
+    pub run_id: String,
+    pub user_id: Option<Uuid>,
+    pub tier: String,
+    /// `run.json` status; empty when the directory has no readable run.json.
+    pub status: String,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub bytes: u64,
+    pub intermediate_bytes: u64,
+    /// Intermediate files and directories, relative to the run directory.
+    #[serde(skip)]
+    pub intermediates: Vec<PathBuf>,
+    /// The run has a queued or running job, e.g. a failed attempt waiting for its retry.
+    #[serde(skip)]
+    pub live_job: bool,
+}
+
+impl RunUsage {
+    pub fn finished(&self) -> bool {
+        !self.live_job && matches!(self.status.as_str(), "SUCCEEDED" | "FAILED" | "CANCELLED")
+    }
+}
+
+pub fn is_intermediate(name: &str, is_dir: bool) -> bool {
+    if is_dir {
+        INTERMEDIATE_DIRS.contains(&name)
+    } else {
+        INTERMEDIATE_FILES.contains(&name)
+    }
+}
+
+/// Bytes under `path` without following symlinks.
+fn tree_bytes(path: &Path) -> u64 {
+    let Ok(meta) = std::fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    std::fs::read_dir(path)
+        .map(|rd| rd.flatten().map(|e| tree_bytes(&e.path())).sum())
+        .unwrap_or(0)
+}
+
+fn walk(dir: &Path, rel: &Path, usage: &mut RunUsage) {
+    let Ok(rd) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for e in rd.flatten() {
+        let Ok(ft) = e.file_type() else {
+            continue;
+        };
+        let name = e.file_name().to_string_lossy().to_string();
+        let path = e.path();
+        let rel = rel.join(&name);
+        if is_intermediate(&name, ft.is_dir()) {
+            let bytes = tree_bytes(&path);
+            usage.bytes += bytes;
+            usage.intermediate_bytes += bytes;
+            usage.intermediates.push(rel);
+        } else if ft.is_dir() {
+            walk(&path, &rel, usage);
+        } else {
+            usage.bytes += e.metadata().map(|m| m.len()).unwrap_or(0);
+        }
+    }
+}
+
+fn read_run_json(dir: &Path) -> Option<Value> {
+    let s = std::fs::read_to_string(dir.join("run.json")).ok()?;
+    serde_json::from_str(&s).ok()
+}
+
+pub fn scan_run(dir: &Path) -> RunUsage {
+    let run = read_run_json(dir).unwrap_or(Value::Null);
+    let str_of = |k: &str| run.get(k).and_then(|v| v.as_str()).unwrap_or_default();
+    let updated_at = DateTime::parse_from_rfc3339(str_of("updated_at"))
+        .map(|t| t.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            std::fs::metadata(dir)
+                .and_then(|m| m.modified())
+                .ok()
+                .map(DateTime::<Utc>::from)
+        });
+    let mut usage = RunUsage {
+        run_id: dir
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        user_id: Uuid::parse_str(str_of("user_id")).ok(),
+        tier: str_of("tier").to_string(),
+        status: str_of("status").to_string(),
+        updated_at,
+        bytes: 0,
+        intermediate_bytes: 0,
+        intermediates: Vec::new(),
+        live_job: false,
+    };
+    walk(dir, Path::new(""), &mut usage);
+    usage
+}
+
+/// Every run directory under `root`, largest first.
+pub fn scan_runs(root: &Path) -> std::io::Result<Vec<RunUsage>> {
+    let mut out = Vec::new();
+    for e in std::fs::read_dir(root)?.flatten() {
+        if e.file_type().is_ok_and(|t| t.is_dir()) {
+            out.push(scan_run(&e.path()));
+        }
+    }
+    out.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.run_id.cmp(&b.run_id)));
+    Ok(out)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GcReason {
+    /// Shot renders and concat lists of a succeeded run.
+    PruneIntermediates,
+    /// Past `keep_days`; the whole run directory.
+    Expired,
+    /// Oldest run of a user over their byte cap; the whole run directory.
+    OverCap,
+}
+
+impl GcReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PruneIntermediates => "prune_intermediates",
+            Self::Expired => "expired",
+            Self::OverCap => "over_cap",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GcAction {
+    pub run_id: String,
+    pub user_id: Option<Uuid>,
+    pub reason: GcReason,
+    pub bytes: u64,
+}
+
+/// What the policy removes from `runs`. `protected` runs (sources of works) keep their
+/// final artifacts but still lose intermediates. Caps evict a user's oldest runs first;
+/// runs without a user only expire.
+pub fn plan(
+    policy: &RetentionPolicy,
+    runs: &[RunUsage],
+    protected: &BTreeSet<String>,
+    now: DateTime<Utc>,
+) -> Vec<GcAction> {
+    let mut actions = Vec::new();
+    let mut remaining: BTreeMap<&str, u64> =
+        runs.iter().map(|r| (r.run_id.as_str(), r.bytes)).collect();
+    let action = |r: &RunUsage, reason, bytes| GcAction {
+        run_id: r.run_id.clone(),
+        user_id: r.user_id,
+        reason,
+        bytes,
+    };
+
+    for r in runs.iter().filter(|r| r.finished()) {
+        let expired = policy.keep_days.is_some_and(|days| {
+            r.updated_at
+                .is_some_and(|t| now - t > chrono::Duration::days(i64::from(days)))
+        });
+        if expired && !protected.contains(&r.run_id) {
+            actions.push(action(r, GcReason::Expired, r.bytes));
+            remaining.remove(r.run_id.as_str());
+        } else if policy.prune_intermediates && r.status == "SUCCEEDED" && r.intermediate_bytes > 0
+        {
+            actions.push(action(
+                r,
+                GcReason::PruneIntermediates,
+                r.intermediate_bytes,
+            ));
+            remaining.insert(&r.run_id, r.bytes - r.intermediate_bytes);
+        }
+    }
+
+    let mut by_user: BTreeMap<Option<Uuid>, Vec<&RunUsage>> = BTreeMap::new();
+    for r in runs {
+        by_user.entry(r.user_id).or_default().push(r);
+    }
+    for (_, mut list) in by_user.into_iter().filter(|(user, _)| user.is_some()) {
+        list.sort_by(|a, b| {
+            a.updated_at
+                .cmp(&b.updated_at)
+                .then_with(|| a.run_id.cmp(&b.run_id))
+        });
+        // A user's tier is the one of their latest run.
+        let tier = list.last().map(|r| r.tier.as_str()).unwrap_or_default();
+        let Some(cap) = policy.cap_for(tier) else {
+            continue;
+        };
+        let mut total: u64 = list
+            .iter()
+            .filter_map(|r| remaining.get(r.run_id.as_str()))
+            .sum();
+        for r in list {
+            if total <= cap {
+                break;
+            }
+            if !r.finished() || protected.contains(&r.run_id) {
+                continue;
+            }
+            let Some(bytes) = remaining.remove(r.run_id.as_str()) else {
+                continue;
+            };
+            total -= bytes;
+            actions.push(action(r, GcReason::OverCap, bytes));
+        }
+    }
+    actions
+}
+
+/// Carries out one action, re-checking that the run is still finished. Returns the bytes
+/// actually freed.
+pub fn apply(root: &Path, usage: &RunUsage, reason: GcReason) -> std::io::Result<u64> {
+    let id = usage.run_id.as_str();
+    if id.is_empty() || id.starts_with('.') || id.contains(['/', '\\']) {
+        return Ok(0);
+    }
+    let dir = root.join(id);
+    if !scan_run(&dir).finished() {
+        return Ok(0);
+    }
+    match reason {
+        GcReason::PruneIntermediates => {
+            let mut freed = 0;
+            for rel in &usage.intermediates {
+                let path = dir.join(rel);
+                let bytes = tree_bytes(&path);
+                let removed = if path.is_dir() {
+                    std::fs::remove_dir_all(&path)
+                } else {
+                    std::fs::remove_file(&path)
+                };
+                match removed {
+                    Ok(()) => freed += bytes,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(freed)
+        }
+        GcReason::Expired | GcReason::OverCap => {
+            let bytes = tree_bytes(&dir);
+            std::fs::remove_dir_all(&dir)?;
+            Ok(bytes)
+        }
+    }
+}
+
+/// Runs that back a work; their final artifacts are what the work's assets point at.
+pub async fn protected_runs(pool: &PgPool) -> Result<BTreeSet<String>, sqlx::Error> {
+    let ids = sqlx::query_scalar::<_, String>(
+        "SELECT DISTINCT meta->>'run_id' FROM works WHERE meta ? 'run_id'",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(ids.into_iter().collect())
+}
+
+/// Runs with a queued or running job; a failed run may still be retried.
+pub async fn live_job_runs(pool: &PgPool) -> Result<BTreeSet<String>, sqlx::Error> {
+    let ids = sqlx::query_scalar::<_, String>(
+        "SELECT DISTINCT run_id FROM run_jobs WHERE status IN ('queued', 'running')",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(ids.into_iter().collect())
+}
+
+/// One GC pass over `runs_dir`; every removal is recorded in `gc_events`.
+pub async fn run_gc(
+    pool: &PgPool,
+    runs_dir: &Path,
+    policy: &RetentionPolicy,
+) -> Result<Vec<GcAction>, GcError> {
+    let protected = protected_runs(pool).await?;
+    let live = live_job_runs(pool).await?;
+    let root = runs_dir.to_path_buf();
+    let policy2 = policy.clone();
+    let done = tokio::task::spawn_blocking(move || {
+        let mut runs = match scan_runs(&root) {
+            Ok(r) => r,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(GcError::Scan(e.to_string())),
+        };
+        for r in &mut runs {
+            r.live_job = live.contains(&r.run_id);
+        }
+        let planned = plan(&policy2, &runs, &protected, Utc::now());
+        let by_id: BTreeMap<&str, &RunUsage> =
+            runs.iter().map(|r| (r.run_id.as_str(), r)).collect();
+        let mut done = Vec::new();
+        for mut a in planned {
+            let usage = by_id[a.run_id.as_str()];
+            match apply(&root, usage, a.reason) {
+                Ok(0) => {}
+                Ok(freed) => {
+                    a.bytes = freed;
+                    done.push((a, usage.intermediates.clone()));
+                }
+                Err(e) => {
+                    tracing::warn!("gc {} of run {} failed: {e}", a.reason.as_str(), a.run_id)
+                }
+            }
+        }
+        Ok(done)
+    })
+    .await
+    .map_err(|e| GcError::Scan(e.to_string()))??;
+
+    let mut actions = Vec::with_capacity(done.len());
+    for (a, intermediates) in done {
+        let meta = match a.reason {
+            GcReason::PruneIntermediates => json!({ "paths": intermediates }),
+            _ => json!({}),
+        };
+        sqlx::query(
+            "INSERT INTO gc_events (run_id, user_id, action, bytes, meta) VALUES ($1, (SELECT id FROM users WHERE id = $2), $3, $4, $5)",
+        )
+        .bind(&a.run_id)
+        .bind(a.user_id)
+        .bind(a.reason.as_str())
+        .bind(a.bytes as i64)
+        .bind(meta)
+        .execute(pool)
+        .await?;
+        actions.push(a);
+    }
+    Ok(actions)
+}
+
+/// Background GC on `policy.interval`; the first pass runs right away.
+pub fn spawn_gc(pool: PgPool, runs_dir: PathBuf, policy: RetentionPolicy) {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(policy.interval);
+        tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tick.tick().await;
+            match run_gc(&pool, &runs_dir, &policy).await {
+                Ok(actions) if !actions.is_empty() => {
+                    let freed: u64 = actions.iter().map(|a| a.bytes).sum();
+                    tracing::info!("gc removed {} items, {freed} bytes", actions.len());
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("{e}"),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(
+        id: &str,
+        user: Option<Uuid>,
+        status: &str,
+        days_ago: i64,
+        bytes: u64,
+        inter: u64,
+    ) -> RunUsage {
+        RunUsage {
+            run_id: id.to_string(),
+            user_id: user,
+            tier: "free".to_string(),
+            status: status.to_string(),
+            updated_at: Some(Utc::now() - chrono::Duration::days(days_ago)),
+            bytes,
+            intermediate_bytes: inter,
+            intermediates: Vec::new(),
+            live_job: false,
+        }
+    }
+
+    #[test]
+    fn sizes_and_tier_caps() {
+        assert_eq!(parse_bytes("1024"), Some(1024));
+        assert_eq!(parse_bytes("2G"), Some(2 << 30));
+        assert_eq!(parse_bytes("500 MiB"), Some(500 << 20));
+        assert_eq!(parse_bytes("5X"), None);
+        let caps = parse_tier_caps("free=1G, Pro=50G").unwrap();
+        assert_eq!(caps["pro"], 50 << 30);
+        assert!(parse_tier_caps("free").is_err());
+    }
+
+    #[test]
+    fn plan_expires_prunes_and_caps() {
+        let u = Some(Uuid::new_v4());
+        let policy = RetentionPolicy {
+            keep_days: Some(30),
+            max_bytes_per_user: Some(100),
+            ..Default::default()
+        };
+        let runs = vec![
+            run("old", u, "SUCCEEDED", 40, 50, 10),
+            run("old_work", u, "SUCCEEDED", 40, 50, 10),
+            run("a", u, "SUCCEEDED", 5, 60, 20),
+            run("b", u, "FAILED", 3, 40, 0),
+            run("live", u, "RUNNING", 1, 70, 30),
+        ];
+        let protected = BTreeSet::from(["old_work".to_string()]);
+        let planned = plan(&policy, &runs, &protected, Utc::now());
+        let got: Vec<(&str, GcReason, u64)> = planned
+            .iter()
+            .map(|a| (a.run_id.as_str(), a.reason, a.bytes))
+            .collect();
+        // After expiry and pruning the user holds 190 bytes. Over the cap of 100, the
+        // oldest deletable runs go first: old_work is protected and live still running,
+        // so a and b are evicted even though that leaves the user at 110.
+        assert_eq!(
+            got,
+            vec![
+                ("old", GcReason::Expired, 50),
+                ("old_work", GcReason::PruneIntermediates, 10),
+                ("a", GcReason::PruneIntermediates, 20),
+                ("a", GcReason::OverCap, 40),
+                ("b", GcReason::OverCap, 40),
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_spares_retrying_and_userless_runs_from_caps() {
+        let u = Some(Uuid::new_v4());
+        let policy = RetentionPolicy {
+            keep_days: Some(30),
+            max_bytes_per_user: Some(10),
+            ..Default::default()
+        };
+        let mut retrying = run("retrying", u, "FAILED", 40, 50, 0);
+        retrying.live_job = true;
+        let runs = vec![
+            retrying,
+            run("failed", u, "FAILED", 2, 50, 0),
+            run("anon_old", None, "FAILED", 40, 50, 0),
+            run("anon_new", None, "FAILED", 2, 50, 0),
+        ];
+        let planned = plan(&policy, &runs, &BTreeSet::new(), Utc::now());
+        let got: Vec<(&str, GcReason)> = planned
+            .iter()
+            .map(|a| (a.run_id.as_str(), a.reason))
+            .collect();
+        assert_eq!(
+            got,
+            vec![
+                ("anon_old", GcReason::Expired),
+                ("failed", GcReason::OverCap),
+            ]
+        );
+    }
+
+    #[test]
+    fn scans_and_prunes_intermediates() {
+        let root = std::env::temp_dir().join(format!("css_gc_{}", Uuid::new_v4()));
+        let dir = root.join("r1");
+        std::fs::create_dir_all(dir.join("build/video/shots")).unwrap();
+        std::fs::write(dir.join("build/video/shots/s0.mp4"), [0u8; 100]).unwrap();
+        std::fs::write(dir.join("build/video/concat.txt"), [0u8; 10]).unwrap();
+        std::fs::write(dir.join("build/final_mv.mp4"), [0u8; 1000]).unwrap();
+        std::fs::write(
+            dir.join("run.json"),
+            r#"{"status":"SUCCEEDED","tier":"pro"}"#,
+        )
+        .unwrap();
+
+        let usage = scan_run(&dir);
+        assert_eq!(usage.intermediate_bytes, 110);
+        assert_eq!(usage.tier, "pro");
+        assert!(usage.finished());
+        assert_eq!(
+            apply(&root, &usage, GcReason::PruneIntermediates).unwrap(),
+            110
+        );
+        assert!(!dir.join("build/video/shots").exists());
+        assert!(dir.join("build/final_mv.mp4").exists());
+        assert_eq!(scan_run(&dir).intermediate_bytes, 0);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
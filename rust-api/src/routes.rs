@@ -28,7 +28,7 @@ async fn metrics_handler() -> axum::response::Response {
             axum::http::header::CONTENT_TYPE,
             "text/plain; version=0.0.4; charset=utf-8",
         )],
-        crate::metrics::gather_text(),
+        crate::metrics::render_prometheus(),
     )
         .into_response()
 }
@@ -53,15 +53,20 @@ use serde::Serialize;
 use serde_json::json;
 use sqlx::PgPool;
 
+use crate::admin_api;
+use crate::artifacts_api;
+use crate::assets_api;
 use crate::auth::AuthSession;
 use crate::billing::{ensure_account, meter_usage, reset_month};
 use crate::config::Config;
 use crate::cssapi_openapi;
-use crate::jobs::SchedulerHandle;
+use crate::jobs::{SchedulerHandle, JOB_KIND_PIPELINE};
 use crate::models::User;
-use crate::run_state::{DagMeta, DagNodeMeta, RetryPolicy, RunConfig, RunState, RunStatus};
-use crate::runner::run_pipeline_default;
+use crate::run_state::{RetryPolicy, RunConfig, RunState, RunStatus};
+use crate::run_state_io::atomic_write_run_state;
+use crate::run_worker::INPUT_COMMANDS;
 use crate::runs_api;
+use crate::works_api;
 
 #[derive(Clone)]
 pub struct AppState {
@@ -109,6 +114,10 @@ pub fn router(state: AppState) -> Router {
     Router::new()
         .merge(cssapi_openapi::router())
         .merge(runs_api::router())
+        .merge(assets_api::router())
+        .merge(artifacts_api::router())
+        .merge(works_api::router())
+        .merge(admin_api::router())
         .route("/metrics", get(metrics_handler))
         .route("/api/health", get(health_handler))
         .route("/api/auth/providers", get(auth_providers))
@@ -132,65 +141,65 @@ struct PipelineStartRequest {
     cssl: String,
     ui_lang: String,
     tier: String,
-    out_dir: Option<String>,
     commands: crate::dsl::compile::CompiledCommands,
     wiki_enabled: Option<bool>,
     civ_linked: Option<bool>,
 }
 
+/// Writes the run under `runs_dir` and queues it; a worker runs the pipeline.
 async fn pipeline_start(
-    State(_state): State<AppState>,
+    State(app): State<AppState>,
     Json(body): Json<PipelineStartRequest>,
 ) -> axum::response::Response {
-    let run_id = format!("run_{}", Utc::now().format("%Y%m%d_%H%M%S"));
+    let run_id = uuid::Uuid::new_v4().to_string();
     let now = Utc::now().to_rfc3339();
-    let out_dir = body.out_dir.unwrap_or_else(|| "./build".to_string());
+    let out_dir = app.config.runs_dir.join(&run_id);
 
-    let state = RunState {
+    let mut state = RunState {
         schema: "css.pipeline.run.v1".to_string(),
-        run_id,
+        run_id: run_id.clone(),
         created_at: now.clone(),
         updated_at: now,
         status: RunStatus::INIT,
         ui_lang: body.ui_lang,
         tier: body.tier,
+        user_id: None,
         cssl: body.cssl,
         commands: serde_json::json!({}),
         config: RunConfig {
-            out_dir: out_dir.into(),
+            out_dir: out_dir.clone(),
             wiki_enabled: body.wiki_enabled.unwrap_or(true),
             civ_linked: body.civ_linked.unwrap_or(true),
         },
+        // The job queue retries the whole run, so stages are not retried in place.
         retry_policy: RetryPolicy {
-            max_retries: 3,
-            backoff_base_seconds: 2,
-            strategy: "exponential".to_string(),
+            max_retries: 0,
+            backoff_base_seconds: 0,
+            strategy: "job_queue".to_string(),
         },
         topo_order: vec![],
-        dag: DagMeta {
-            schema: "css.pipeline.dag.v1".to_string(),
-            nodes: crate::dag::cssmv_dag_v1()
-                .nodes
-                .iter()
-                .map(|n| DagNodeMeta {
-                    name: n.name.to_string(),
-                    deps: n.deps.iter().map(|d| (*d).to_string()).collect(),
-                })
-                .collect(),
-        },
+        dag: crate::dag::cssmv_dag_v1().meta(),
         stages: Default::default(),
-        artifacts: Vec::new(),
+        artifacts: Default::default(),
+        storage: None,
         video_shots_total: 0,
         video_shots_ready: 0,
         video_shots_running: 0,
     };
+    state
+        .artifacts
+        .record(INPUT_COMMANDS, json!(body.commands), None, &out_dir);
 
-    let result =
-        tokio::task::spawn_blocking(move || run_pipeline_default(state, body.commands)).await;
-    match result {
-        Ok(Ok(final_state)) => ok(json!({ "run": final_state })),
-        Ok(Err(err)) => no_data(json!({ "error": format!("{}", err) })),
-        Err(err) => no_data(json!({ "error": format!("{}", err) })),
+    if let Err(err) = atomic_write_run_state(&out_dir.join("run.json"), &state).await {
+        return no_data(json!({ "error": format!("{err:#}") }));
+    }
+    match app
+        .scheduler
+        .enqueue_job(JOB_KIND_PIPELINE, &run_id, json!({}))
+        .await
+    {
+        Ok(job_id) => ok(json!({ "run_id": run_id, "job_id": job_id, "run": state })),
+        Err(err) => no_data(json!({ "error": format!("{}", err), "run_id": run_id })),
     }
 }
 
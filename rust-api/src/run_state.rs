@@ -1,3 +1,5 @@
+use crate::artifact_store::StorageLocation;
+use crate::artifacts::ArtifactRegistry;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 use std::path::PathBuf;
@@ -15,8 +17,16 @@ pub struct RunState {
     pub ui_lang: String,
     pub tier: String,
 
+    /// Signed-in user who created the run; retention caps are counted per user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<uuid::Uuid>,
+
     pub cssl: String,
 
+    /// Resolved `css.pipeline.commands.v1` the run was created with.
+    #[serde(default)]
+    pub commands: serde_json::Value,
+
     pub config: RunConfig,
 
     pub retry_policy: RetryPolicy,
@@ -25,34 +35,43 @@ pub struct RunState {
     pub topo_order: Vec<String>,
 
     #[serde(default)]
-    pub artifacts: serde_json::Value,
+    pub artifacts: ArtifactRegistry,
+
+    /// Artifact store the run's outputs are uploaded to; unset while they are local only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage: Option<StorageLocation>,
 
     pub stages: BTreeMap<String, StageRecord>,
+
+    /// Per-shot stage counts for progress displays.
+    #[serde(default)]
+    pub video_shots_total: u32,
+    #[serde(default)]
+    pub video_shots_ready: u32,
+    #[serde(default)]
+    pub video_shots_running: u32,
 }
 
 impl RunState {
-    pub fn set_artifact_path(&mut self, path: &str, value: serde_json::Value) {
-        let parts: Vec<&str> = path.split('.').filter(|x| !x.is_empty()).collect();
-        if parts.is_empty() {
-            return;
-        }
-
-        if !self.artifacts.is_object() {
-            self.artifacts = serde_json::json!({});
-        }
-
-        let mut cur = self.artifacts.as_object_mut().expect("artifacts object");
-        for key in &parts[..parts.len().saturating_sub(1)] {
-            let entry = cur
-                .entry((*key).to_string())
-                .or_insert_with(|| serde_json::json!({}));
-            if !entry.is_object() {
-                *entry = serde_json::json!({});
+    /// Puts stages an earlier attempt left `RUNNING` or `FAILED` back to `PENDING` so the
+    /// next attempt runs them again; `RUNNING` ones count as a retry. Returns how many.
+    pub fn reset_unfinished_stages(&mut self, reason: &str) -> usize {
+        let mut n = 0;
+        for rec in self.stages.values_mut() {
+            match rec.status {
+                StageStatus::RUNNING => {
+                    rec.retries += 1;
+                    rec.error = Some(reason.to_string());
+                }
+                StageStatus::FAILED => {}
+                _ => continue,
             }
-            cur = entry.as_object_mut().expect("nested artifact object");
+            rec.status = StageStatus::PENDING;
+            rec.ended_at = None;
+            rec.exit_code = None;
+            n += 1;
         }
-
-        cur.insert(parts[parts.len() - 1].to_string(), value);
+        n
     }
 }
 
@@ -73,6 +92,14 @@ pub struct RetryPolicy {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DagMeta {
     pub schema: String,
+    #[serde(default)]
+    pub nodes: Vec<DagNodeMeta>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DagNodeMeta {
+    pub name: String,
+    pub deps: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -101,10 +128,38 @@ pub struct StageRecord {
     pub ended_at: Option<String>,
 
     pub exit_code: Option<i32>,
+    /// Executor kind (`shell`, `video_shot`, `render`, ...); inferred from the stage name
+    /// when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
     pub command: Option<String>,
 
     pub outputs: Vec<PathBuf>,
 
     pub retries: u32,
     pub error: Option<String>,
+
+    /// Why output verification rejected the stage, when it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<StageFailure>,
+
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub meta: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageFailure {
+    pub reason: String,
+    pub message: String,
+    pub path: Option<PathBuf>,
+}
+
+impl StageFailure {
+    pub fn new(reason: &str, message: impl Into<String>, path: Option<PathBuf>) -> Self {
+        Self {
+            reason: reason.to_string(),
+            message: message.into(),
+            path,
+        }
+    }
 }
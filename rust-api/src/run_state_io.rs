@@ -0,0 +1,78 @@
+use crate::run_state::RunState;
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Run ids name directories under the runs dir, so only `[A-Za-z0-9_-]+` is allowed.
+pub fn is_valid_run_id(run_id: &str) -> bool {
+    !run_id.is_empty()
+        && run_id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// `<runs_dir>/<run_id>/run.json`.
+pub fn run_state_path(runs_dir: &Path, run_id: &str) -> PathBuf {
+    runs_dir.join(run_id).join("run.json")
+}
+
+/// Writes `s` next to `path` and renames it into place, so readers never see a partial
+/// file.
+pub fn atomic_write_text(path: &Path, s: &str) -> std::io::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir)?;
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("state");
+    let tmp = dir.join(format!(".{name}.{}.tmp", uuid::Uuid::new_v4()));
+    let written = fs::File::create(&tmp).and_then(|mut f| {
+        f.write_all(s.as_bytes())?;
+        f.sync_all()
+    });
+    match written.and_then(|_| fs::rename(&tmp, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+pub fn save_state_atomic(path: &Path, state: &RunState) -> Result<()> {
+    let json = serde_json::to_string_pretty(state)?;
+    atomic_write_text(path, &json).with_context(|| format!("write {}", path.display()))
+}
+
+pub fn read_run_state(path: &Path) -> Result<RunState> {
+    let s = fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+    serde_json::from_str(&s).with_context(|| format!("parse {}", path.display()))
+}
+
+pub async fn read_run_state_async(path: &Path) -> Result<RunState> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || read_run_state(&path)).await?
+}
+
+pub async fn atomic_write_run_state(path: &Path, state: &RunState) -> Result<()> {
+    let json = serde_json::to_string_pretty(state)?;
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        atomic_write_text(&path, &json).with_context(|| format!("write {}", path.display()))
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn atomic_write_replaces_and_leaves_no_temp_files() {
+        let dir = std::env::temp_dir().join(format!("run_state_io_{}", uuid::Uuid::new_v4()));
+        let path = dir.join("run.json");
+        atomic_write_text(&path, "one").unwrap();
+        atomic_write_text(&path, "two").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "two");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
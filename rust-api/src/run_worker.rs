@@ -1,22 +1,16 @@
 use crate::dsl::compile::CompiledCommands;
-use crate::run_state::{DagMeta, RunConfig, RunState, RunStatus, RetryPolicy};
+use crate::jobs::{Lease, JOB_KIND_PIPELINE, JOB_KIND_RUN};
+use crate::routes::AppState;
+use crate::run_state::{RunState, RunStatus, StageStatus};
+use crate::run_state_io::{read_run_state_async, run_state_path};
 use crate::runner::run_pipeline_default;
-use chrono::Utc;
 use serde_json::Value;
-use std::{
-    fs,
-    path::PathBuf,
-    sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc, OnceLock,
-    },
-};
-use tokio::sync::Semaphore;
+use std::{fs, path::PathBuf, sync::OnceLock};
+
+/// Artifact holding the commands a pipeline run was started with.
+pub const INPUT_COMMANDS: &str = "run.input.commands";
 
-static RUN_SEM: OnceLock<Arc<Semaphore>> = OnceLock::new();
 static RUN_CONCURRENCY: OnceLock<usize> = OnceLock::new();
-static RUNNING: AtomicUsize = AtomicUsize::new(0);
-static QUEUED: AtomicUsize = AtomicUsize::new(0);
 
 fn parse_concurrency() -> usize {
     std::env::var("RUN_CONCURRENCY")
@@ -26,25 +20,20 @@ fn parse_concurrency() -> usize {
         .unwrap_or(2)
 }
 
+/// Jobs each process works on at once.
 pub fn concurrency() -> usize {
     *RUN_CONCURRENCY.get_or_init(parse_concurrency)
 }
 
-fn run_semaphore() -> Arc<Semaphore> {
-    RUN_SEM
-        .get_or_init(|| Arc::new(Semaphore::new(concurrency())))
-        .clone()
-}
-
 pub fn running_count() -> usize {
-    RUNNING.load(Ordering::Relaxed)
+    crate::jobs::running_count()
 }
 
 pub fn queued_count() -> usize {
-    QUEUED.load(Ordering::Relaxed)
+    crate::jobs::queued_count()
 }
 
-fn write_failed_state(state_path: &PathBuf, msg: String) {
+pub(crate) fn write_failed_state(state_path: &PathBuf, msg: String) {
     let mut v: Value = fs::read_to_string(state_path)
         .ok()
         .and_then(|s| serde_json::from_str(&s).ok())
@@ -57,91 +46,75 @@ fn write_failed_state(state_path: &PathBuf, msg: String) {
     let _ = fs::write(state_path, serde_json::to_vec_pretty(&v).unwrap_or_default());
 }
 
-pub fn spawn_run_worker(run_dir: PathBuf, commands: Value) {
-    tokio::spawn(async move {
-        QUEUED.fetch_add(1, Ordering::Relaxed);
-        let _permit = match run_semaphore().acquire_owned().await {
-            Ok(p) => p,
-            Err(_) => {
-                QUEUED.fetch_sub(1, Ordering::Relaxed);
-                return;
-            }
-        };
-        QUEUED.fetch_sub(1, Ordering::Relaxed);
-        RUNNING.fetch_add(1, Ordering::Relaxed);
+/// The job kind that runs `st`: pipeline runs keep their compiled commands in the
+/// [`INPUT_COMMANDS`] artifact, runs API runs are driven by their stage records.
+pub fn job_kind_of(st: &RunState) -> &'static str {
+    match st.artifacts.value_of(INPUT_COMMANDS) {
+        Some(_) => JOB_KIND_PIPELINE,
+        None => JOB_KIND_RUN,
+    }
+}
 
-        let run_id = run_dir
-            .file_name()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string();
-        let state_path = run_dir.join("run.json");
+/// Executes a pipeline job for the run `/api/pipeline/start` wrote. Stages left RUNNING or
+/// FAILED by an earlier attempt start over. Commands that do not compile fail the run
+/// without an error, since retrying them cannot help. A failed stage fails the job and
+/// leaves the run RUNNING for the queue to retry. Losing `lease` abandons the running stage.
+pub async fn run_pipeline_job(app: &AppState, run_id: &str, lease: &Lease) -> anyhow::Result<()> {
+    let state_path = run_state_path(&app.config.runs_dir, run_id);
+    let mut state = read_run_state_async(&state_path).await?;
+    state.reset_unfinished_stages("interrupted before finishing");
+    let commands = state
+        .artifacts
+        .value_of(INPUT_COMMANDS)
+        .cloned()
+        .unwrap_or(Value::Null);
 
-        let compiled: CompiledCommands = match serde_json::from_value(commands.clone()) {
-            Ok(c) => c,
-            Err(_) => match commands.get("dsl").and_then(|v| v.as_str()) {
-                Some(dsl) => match crate::dsl::compile::compile_from_dsl(dsl) {
-                    Ok(c) => c,
-                    Err(e) => {
-                        write_failed_state(&state_path, format!("dsl compile failed: {e}"));
-                        RUNNING.fetch_sub(1, Ordering::Relaxed);
-                        return;
-                    }
-                },
-                None => {
-                    write_failed_state(
-                        &state_path,
-                        "invalid commands payload: expected CompiledCommands or {\"dsl\": \"...\"}"
-                            .to_string(),
-                    );
-                    RUNNING.fetch_sub(1, Ordering::Relaxed);
-                    return;
+    let compiled: CompiledCommands = match serde_json::from_value(commands.clone()) {
+        Ok(c) => c,
+        Err(_) => match commands.get("dsl").and_then(|v| v.as_str()) {
+            Some(dsl) => match crate::dsl::compile::compile_from_dsl(dsl) {
+                Ok(c) => c,
+                Err(e) => {
+                    write_failed_state(&state_path, format!("dsl compile failed: {e}"));
+                    return Ok(());
                 }
             },
-        };
-
-        let now = Utc::now().to_rfc3339();
-        let mut state = RunState {
-            schema: "css.pipeline.run.v1".to_string(),
-            run_id,
-            created_at: now.clone(),
-            updated_at: now,
-            status: RunStatus::INIT,
-            ui_lang: "auto".to_string(),
-            tier: "local".to_string(),
-            cssl: "async-run-worker".to_string(),
-            config: RunConfig {
-                out_dir: run_dir.clone(),
-                wiki_enabled: true,
-                civ_linked: true,
-            },
-            retry_policy: RetryPolicy {
-                max_retries: 3,
-                backoff_base_seconds: 2,
-                strategy: "exponential".to_string(),
-            },
-            dag: DagMeta {
-                schema: "css.pipeline.dag.v1".to_string(),
-            },
-            topo_order: vec![],
-            artifacts: serde_json::json!({}),
-            stages: Default::default(),
-        };
-
-        state.set_artifact_path("run.input.commands", commands);
-        state.set_artifact_path("worker.concurrency", serde_json::json!(concurrency() as i64));
-        let _ = fs::write(
-            &state_path,
-            serde_json::to_vec_pretty(&state).unwrap_or_default(),
-        );
+            None => {
+                write_failed_state(
+                    &state_path,
+                    "invalid commands payload: expected CompiledCommands or {\"dsl\": \"...\"}"
+                        .to_string(),
+                );
+                return Ok(());
+            }
+        },
+    };
 
-        let result = tokio::task::spawn_blocking(move || run_pipeline_default(state, compiled)).await;
+    let run_dir = state.config.out_dir.clone();
+    state.artifacts.record(
+        "worker.concurrency",
+        serde_json::json!(concurrency() as i64),
+        None,
+        &run_dir,
+    );
 
-        match result {
-            Ok(Ok(_)) => {}
-            Ok(Err(e)) => write_failed_state(&state_path, e.to_string()),
-            Err(e) => write_failed_state(&state_path, format!("join error: {e}")),
+    let store = app.config.artifact_store.open();
+    let cancel = lease.token();
+    let state = tokio::task::spawn_blocking(move || {
+        run_pipeline_default(state, compiled, Some(store), cancel)
+    })
+    .await??;
+    if !matches!(state.status, RunStatus::SUCCEEDED) {
+        let failed = state
+            .stages
+            .iter()
+            .find(|(_, rec)| matches!(rec.status, StageStatus::FAILED));
+        match failed {
+            Some((name, rec)) => {
+                anyhow::bail!("stage {name} failed: {}", rec.error.as_deref().unwrap_or(""))
+            }
+            None => anyhow::bail!("run ended {:?}", state.status),
         }
-        RUNNING.fetch_sub(1, Ordering::Relaxed);
-    });
+    }
+    Ok(())
 }
@@ -1,27 +1,93 @@
+use crate::artifact_store::{upload_pending, ArtifactStore, RUN_JSON};
+use crate::asset_store::AssetScope;
 use crate::dag::{cssmv_dag_v1, Dag};
 use crate::dag_viz_html;
 use crate::dag_export;
 use crate::video_executor;
-use crate::run_state::{RunState, RunStatus, StageRecord, StageStatus};
+use crate::encoder::EncoderProfile;
+use crate::media_probe::{ffprobe_for, verify_media, verify_outputs, VerifyError};
+use crate::run_state::{RunState, RunStatus, StageFailure, StageRecord, StageStatus};
+use crate::stage_executor::{
+    block_on, failure_of, stage_kind, ExecOptions, ExecutorRegistry, StageContext,
+    StageExecutor, StageOutcome,
+};
 use anyhow::Result;
 use chrono::Utc;
 use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 
 fn now_rfc3339() -> String {
     Utc::now().to_rfc3339()
 }
 
-fn output_exists(p: &PathBuf) -> bool {
-    p.exists()
+/// Settings for the built-in video stage and output probing.
+#[derive(Debug, Clone)]
+pub struct VideoStageOptions {
+    pub ffmpeg_path: String,
+    pub concurrency: usize,
+    pub assets_root: PathBuf,
+    /// Whose assets backgrounds may use; API runs only see their creator's.
+    pub asset_scope: AssetScope,
+    pub workdir: PathBuf,
+    pub storyboard: PathBuf,
+    /// Where outputs are uploaded after each stage; `None` keeps them local only.
+    pub store: Option<Arc<dyn ArtifactStore>>,
+    /// Whether a failed stage marks the run FAILED. Queued runs stay RUNNING and leave
+    /// that to the job queue, which retries them first.
+    pub fail_run: bool,
+    /// Abandons the running stage, and the run, once cancelled.
+    pub cancel: CancellationToken,
 }
 
-fn stage_done_by_outputs(outputs: &[PathBuf]) -> bool {
-    !outputs.is_empty() && outputs.iter().all(output_exists)
+impl Default for VideoStageOptions {
+    fn default() -> Self {
+        Self {
+            ffmpeg_path: "ffmpeg".to_string(),
+            concurrency: 2,
+            assets_root: crate::asset_store::default_root(),
+            asset_scope: AssetScope::Any,
+            workdir: PathBuf::from("build/video"),
+            storyboard: PathBuf::from("build/video/storyboard.json"),
+            store: None,
+            fail_run: true,
+            cancel: CancellationToken::new(),
+        }
+    }
+}
+
+impl VideoStageOptions {
+    fn ffprobe_path(&self) -> String {
+        std::env::var("FFPROBE").unwrap_or_else(|_| ffprobe_for(&self.ffmpeg_path))
+    }
+
+    /// Marks the run FAILED unless that is left to the job queue.
+    fn fail(&self, state: &mut RunState) {
+        if self.fail_run {
+            state.status = RunStatus::FAILED;
+        }
+    }
+}
+
+fn verify_failure(e: &VerifyError) -> StageFailure {
+    StageFailure::new(e.reason(), e.to_string(), Some(e.path().to_path_buf()))
+}
+
+/// Outputs under `workdir` must exist and be non-empty; media files must also probe cleanly.
+fn stage_done_by_outputs(
+    ffprobe: &str,
+    workdir: &Path,
+    outputs: &[PathBuf],
+) -> Result<(), StageFailure> {
+    if outputs.is_empty() {
+        return Err(StageFailure::new("no_outputs", "stage declares no outputs", None));
+    }
+    let outputs: Vec<PathBuf> = outputs.iter().map(|p| workdir.join(p)).collect();
+    verify_outputs(ffprobe, &outputs).map_err(|e| verify_failure(&e))
 }
 
 fn persist_state(state_path: &Path, state: &RunState) -> Result<()> {
@@ -30,9 +96,57 @@ fn persist_state(state_path: &Path, state: &RunState) -> Result<()> {
     Ok(())
 }
 
+/// Persists the state and copies it to the artifact store, so the run can be found from
+/// another machine.
+fn persist_stored(
+    state_path: &Path,
+    state: &RunState,
+    store: Option<&Arc<dyn ArtifactStore>>,
+) -> Result<()> {
+    persist_state(state_path, state)?;
+    if let Some(store) = store {
+        let key = format!("{}/{RUN_JSON}", state.run_id);
+        block_on(store.put_file(&key, state_path, "application/json"))??;
+    }
+    Ok(())
+}
+
+/// Uploads the file artifacts the store does not have yet. Returns `false`, with `stage`
+/// and the run failed, when an upload fails: outputs that only live on this machine do
+/// not count as done.
+fn upload_outputs(
+    opts: &VideoStageOptions,
+    state: &mut RunState,
+    stage: &str,
+    base: &Path,
+) -> Result<bool> {
+    let Some(store) = &opts.store else {
+        return Ok(true);
+    };
+    match block_on(upload_pending(
+        store.as_ref(),
+        &state.run_id,
+        &mut state.artifacts,
+        base,
+    ))? {
+        Ok(_) => Ok(true),
+        Err(e) => {
+            let rec = state
+                .stages
+                .get_mut(stage)
+                .expect("stage record must exist");
+            rec.status = StageStatus::FAILED;
+            rec.error = Some(format!("stage {stage} outputs not stored: {e}"));
+            rec.failure = Some(StageFailure::new("upload_failed", e.to_string(), None));
+            opts.fail(state);
+            Ok(false)
+        }
+    }
+}
+
 fn stage_plan(
     compiled: &crate::dsl::compile::CompiledCommands,
-) -> BTreeMap<&'static str, (String, Vec<PathBuf>)> {
+) -> BTreeMap<&'static str, (Option<String>, Vec<PathBuf>)> {
     BTreeMap::from([
         (
             "lyrics",
@@ -48,22 +162,36 @@ fn stage_plan(
         ),
         (
             "video",
-            (compiled.video.clone(), vec![PathBuf::from("./build/video/video.mp4")]),
+            (Some(compiled.video.clone()), vec![PathBuf::from("./build/video/video.mp4")]),
         ),
         (
             "render",
-            (compiled.render.clone(), vec![PathBuf::from("./build/final_mv.mp4")]),
+            (Some(compiled.render.clone()), vec![PathBuf::from("./build/final_mv.mp4")]),
+        ),
+        (
+            "package",
+            (
+                Some(compiled.package.clone()),
+                vec![PathBuf::from("./build/package/hls/master.m3u8")],
+            ),
+        ),
+        (
+            "thumbnails",
+            (
+                Some(compiled.thumbnails.clone()),
+                vec![PathBuf::from("./build/thumbnails/poster.jpg")],
+            ),
         ),
     ])
 }
 
-fn deps_satisfied(stage: &str, state: &RunState, dag: &Dag) -> bool {
+fn deps_satisfied(stage: &str, state: &RunState, dag: &Dag, workdir: &Path) -> bool {
     let node = dag.nodes.iter().find(|n| n.name == stage);
     let Some(node) = node else { return false; };
 
     node.deps.iter().all(|dep| {
         if let Some(dep_rec) = state.stages.get(*dep) {
-            dep_rec.outputs.iter().all(|p| p.exists())
+            dep_rec.outputs.iter().all(|p| workdir.join(p).exists())
         } else {
             false
         }
@@ -74,84 +202,157 @@ fn backoff_delay(base: u64, attempt: u32) -> u64 {
     base * (2u64.pow(attempt))
 }
 
+/// Runs the stage, retrying failures with backoff. Errors once `cancel` fires; dropping
+/// the running stage kills its ffmpeg.
 fn run_stage_with_retry(
-    name: &str,
-    cmdline: &str,
-    rec: &mut StageRecord,
-    max_retries: u32,
-    backoff_base: u64,
-) -> Result<bool> {
-    for attempt in 0..=max_retries {
-        rec.status = StageStatus::RUNNING;
-        rec.retries = attempt;
-        rec.started_at = Some(now_rfc3339());
-
-        let status = Command::new("sh").arg("-lc").arg(cmdline).status()?;
-
-        rec.exit_code = status.code();
-        rec.ended_at = Some(now_rfc3339());
-
-        if status.success() {
-            rec.status = StageStatus::SUCCEEDED;
-            rec.error = None;
-            return Ok(true);
-        }
-
-        rec.status = StageStatus::FAILED;
-        rec.error = Some(format!("Attempt {} failed", attempt));
-
-        if attempt < max_retries {
-            let delay = backoff_delay(backoff_base, attempt);
-            println!("Stage {} failed. Retrying in {} seconds...", name, delay);
-            thread::sleep(Duration::from_secs(delay));
-        }
-    }
-
-    Ok(false)
-}
-
-fn run_video_stage_with_retry(
+    exec: &dyn StageExecutor,
+    ctx: &StageContext,
     rec: &mut StageRecord,
     max_retries: u32,
     backoff_base: u64,
-) -> Result<(bool, Option<(std::path::PathBuf, video_executor::VideoExecResult)>)> {
+    cancel: &CancellationToken,
+) -> Result<Option<StageOutcome>> {
     for attempt in 0..=max_retries {
         rec.status = StageStatus::RUNNING;
         rec.retries = attempt;
         rec.started_at = Some(now_rfc3339());
 
-        match run_video_stage_v1() {
-            Ok((storyboard, result)) => {
-                rec.exit_code = Some(0);
-                rec.outputs = vec![result.video_mp4.clone()];
+        let run = block_on(async {
+            tokio::select! {
+                r = exec.run(ctx) => Some(r),
+                _ = cancel.cancelled() => None,
+            }
+        })?;
+        let Some(run) = run else {
+            anyhow::bail!("stage {} cancelled", ctx.stage);
+        };
+        match run {
+            Ok(outcome) => {
+                outcome.apply_to(rec);
                 rec.ended_at = Some(now_rfc3339());
                 rec.status = StageStatus::SUCCEEDED;
                 rec.error = None;
-                return Ok((true, Some((storyboard, result))));
+                rec.failure = None;
+                return Ok(Some(outcome));
             }
             Err(e) => {
                 rec.exit_code = Some(1);
                 rec.ended_at = Some(now_rfc3339());
                 rec.status = StageStatus::FAILED;
-                rec.error = Some(format!("Attempt {} failed: {}", attempt, e));
+                rec.error = Some(format!("Attempt {} failed: {:#}", attempt, e));
+                rec.failure = failure_of(&e);
 
                 if attempt < max_retries {
                     let delay = backoff_delay(backoff_base, attempt);
-                    println!("Stage video failed. Retrying in {} seconds...", delay);
+                    println!("Stage {} failed. Retrying in {} seconds...", ctx.stage, delay);
                     thread::sleep(Duration::from_secs(delay));
                 }
             }
         }
     }
 
-    Ok((false, None))
+    Ok(None)
+}
+
+/// Renders the whole storyboard in one stage; the local runner's stand-in for the
+/// per-shot stages the API scheduler runs.
+#[derive(Debug, Clone)]
+pub struct LocalVideoExecutor {
+    pub opts: VideoStageOptions,
+}
+
+#[axum::async_trait]
+impl StageExecutor for LocalVideoExecutor {
+    fn kind(&self) -> &'static str {
+        "video"
+    }
+
+    async fn run(&self, ctx: &StageContext) -> Result<StageOutcome> {
+        let profile = EncoderProfile::resolve(ctx.commands.pointer("/video/encoder"), &ctx.tier);
+        let reproducible = ctx.reproducible();
+        let mut opts = self.opts.clone();
+        opts.workdir = ctx.path(&opts.workdir);
+        opts.storyboard = ctx.path(&opts.storyboard);
+        let (assets_root, scope) = (ctx.options.assets_root.clone(), ctx.options.asset_scope);
+        let (storyboard, result) = tokio::task::spawn_blocking(move || {
+            run_video_stage_v1(&opts, &assets_root, scope, &profile, reproducible)
+        })
+        .await??;
+
+        let rel = |p: &Path| p.strip_prefix(&ctx.workdir).unwrap_or(p).to_path_buf();
+        let path = |p: &Path| serde_json::json!(rel(p).display().to_string());
+        let mut out = StageOutcome {
+            exit_code: Some(0),
+            replace_outputs: Some(vec![rel(&result.video_mp4)]),
+            ..Default::default()
+        }
+        .artifact("video.storyboard", path(&storyboard))
+        .artifact("video.shots_dir", path(&result.shots_dir))
+        .artifact("video.shots_count", serde_json::json!(result.shots_count))
+        .artifact("video.concat_txt", path(&result.concat_txt))
+        .artifact("video.video_mp4", path(&result.video_mp4))
+        .artifact(
+            "video.shot_metrics",
+            serde_json::to_value(&result.shot_metrics).unwrap_or_else(|_| serde_json::json!([])),
+        );
+        if let Some(m) = &result.manifest {
+            out = out.artifact("video.render_manifest", path(m));
+        }
+        out = out.artifact(
+            "video.encoder",
+            serde_json::to_value(&result.profile).unwrap_or_else(|_| serde_json::json!({})),
+        );
+        if !result.targets.is_empty() {
+            out = out.artifact(
+                "video.targets",
+                serde_json::to_value(&result.targets).unwrap_or_else(|_| serde_json::json!([])),
+            );
+        }
+        Ok(out)
+    }
+}
+
+/// The API's executors plus the whole-storyboard video stage.
+pub fn local_registry(opts: &VideoStageOptions) -> ExecutorRegistry {
+    crate::stage_registry::stage_registry().with(LocalVideoExecutor { opts: opts.clone() })
 }
 
 pub fn run_pipeline(
+    state_path: &Path,
+    state: RunState,
+    compiled: crate::dsl::compile::CompiledCommands,
+) -> Result<RunState> {
+    run_pipeline_with(state_path, state, compiled, &VideoStageOptions::default())
+}
+
+pub fn run_pipeline_with(
+    state_path: &Path,
+    state: RunState,
+    compiled: crate::dsl::compile::CompiledCommands,
+    opts: &VideoStageOptions,
+) -> Result<RunState> {
+    run_pipeline_with_registry(state_path, state, compiled, opts, &local_registry(opts))
+}
+
+/// Runs every stage in DAG order with the executor registered for its kind, in the run's
+/// `out_dir`.
+pub fn run_pipeline_with_registry(
     state_path: &Path,
     mut state: RunState,
     compiled: crate::dsl::compile::CompiledCommands,
+    opts: &VideoStageOptions,
+    registry: &ExecutorRegistry,
 ) -> Result<RunState> {
+    let ffprobe = opts.ffprobe_path();
+    let exec_opts = ExecOptions {
+        ffmpeg_path: opts.ffmpeg_path.clone(),
+        ffprobe_path: ffprobe.clone(),
+        concurrency: opts.concurrency,
+        assets_root: opts.assets_root.clone(),
+        asset_scope: opts.asset_scope,
+        ..Default::default()
+    };
+    let workdir = state.config.out_dir.clone();
     let dag = cssmv_dag_v1();
     let order = dag.topo_order().unwrap_or_default();
 
@@ -159,35 +360,36 @@ pub fn run_pipeline(
 
     {
         let v = serde_json::to_value(&state).unwrap_or_else(|_| serde_json::json!({}));
-        let dag_json_path = std::path::PathBuf::from("build/dag.json");
+        let dag_json_path = workdir.join("build/dag.json");
         let _ = dag_export::write_dag_json(&dag_json_path, &dag, &v);
-        if let Ok(p) = std::fs::canonicalize(&dag_json_path) {
-            state.set_artifact_path("graph.dag_json", serde_json::json!(p.display().to_string()));
-        } else {
-            state.set_artifact_path("graph.dag_json", serde_json::json!("build/dag.json"));
-        }
+        state
+            .artifacts
+            .record("graph.dag_json", serde_json::json!("build/dag.json"), None, &workdir);
 
         let dag_export_json = std::fs::read_to_string(&dag_json_path)
             .ok()
             .and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok())
             .unwrap_or_else(|| serde_json::json!({}));
-        let dag_html_path = std::path::PathBuf::from("build/dag.html");
+        let dag_html_path = workdir.join("build/dag.html");
         let _ = dag_viz_html::write_dag_html(&dag_html_path, &dag_export_json);
-        if let Ok(p) = std::fs::canonicalize(&dag_html_path) {
-            state.set_artifact_path("graph.dag_html", serde_json::json!(p.display().to_string()));
-        } else {
-            state.set_artifact_path("graph.dag_html", serde_json::json!("build/dag.html"));
-        }
+        state
+            .artifacts
+            .record("graph.dag_html", serde_json::json!("build/dag.html"), None, &workdir);
     }
     state.topo_order = order.iter().map(|s| s.to_string()).collect();
+    let store = opts.store.as_ref();
+    state.storage = store.map(|s| s.location(&state.run_id));
 
     state.status = RunStatus::RUNNING;
     state.updated_at = now_rfc3339();
-    persist_state(state_path, &state)?;
+    persist_stored(state_path, &state, store)?;
 
     let plan = stage_plan(&compiled);
 
     for name in order {
+        if opts.cancel.is_cancelled() {
+            anyhow::bail!("run cancelled before stage {name}");
+        }
         let stage = name.to_string();
         let (cmdline, outputs) = plan.get(name).expect("stage in plan").clone();
 
@@ -199,10 +401,13 @@ pub fn run_pipeline(
                 started_at: None,
                 ended_at: None,
                 exit_code: None,
+                kind: None,
                 command: None,
                 outputs: outputs.clone(),
                 retries: 0,
                 error: None,
+                failure: None,
+                meta: Default::default(),
             });
 
         {
@@ -210,13 +415,13 @@ pub fn run_pipeline(
                 .stages
                 .get_mut(&stage)
                 .expect("stage record must exist");
-            rec.command = Some(cmdline.clone());
+            rec.command = cmdline.clone();
             rec.outputs = outputs.clone();
         }
 
         let done_before = {
             let rec = state.stages.get(&stage).expect("stage record must exist");
-            stage_done_by_outputs(&rec.outputs)
+            stage_done_by_outputs(&ffprobe, &workdir, &rec.outputs).is_ok()
         };
         if done_before {
             let rec = state
@@ -224,93 +429,104 @@ pub fn run_pipeline(
                 .get_mut(&stage)
                 .expect("stage record must exist");
             rec.status = StageStatus::SKIPPED;
+            let outputs = rec.outputs.clone();
+            state.artifacts.record_outputs(&stage, &outputs, &workdir);
+            let stored = upload_outputs(opts, &mut state, &stage, &workdir)?;
             state.updated_at = now_rfc3339();
-            persist_state(state_path, &state)?;
+            persist_stored(state_path, &state, store)?;
+            if !stored {
+                return Ok(state);
+            }
             continue;
         }
 
-        if !deps_satisfied(name, &state, &dag) {
+        if !deps_satisfied(name, &state, &dag, &workdir) {
             let rec = state
                 .stages
                 .get_mut(&stage)
                 .expect("stage record must exist");
             rec.status = StageStatus::FAILED;
             rec.error = Some(format!("deps not satisfied for stage {}", name));
-            state.status = RunStatus::FAILED;
+            opts.fail(&mut state);
             state.updated_at = now_rfc3339();
-            persist_state(state_path, &state)?;
+            persist_stored(state_path, &state, store)?;
             return Ok(state);
         }
 
-        let mut video_artifacts: Option<(std::path::PathBuf, video_executor::VideoExecResult)> = None;
-        let success = {
+        let ctx = {
+            let rec = state.stages.get(&stage).expect("stage record must exist");
+            StageContext {
+                stage: stage.clone(),
+                kind: stage_kind(&stage, Some(rec)),
+                workdir: workdir.clone(),
+                tier: state.tier.clone(),
+                ui_lang: state.ui_lang.clone(),
+                commands: state.commands.clone(),
+                command: cmdline.clone(),
+                outputs: rec.outputs.clone(),
+                meta: rec.meta.clone(),
+                options: exec_opts.clone(),
+            }
+        };
+        let outcome = {
             let rec = state
                 .stages
                 .get_mut(&stage)
                 .expect("stage record must exist");
-            if name == "video" {
-                let (ok, artifacts) = run_video_stage_with_retry(
-                    rec,
-                    state.retry_policy.max_retries,
-                    state.retry_policy.backoff_base_seconds,
-                )?;
-                video_artifacts = artifacts;
-                ok
-            } else {
-                run_stage_with_retry(
-                    name,
-                    &cmdline,
+            match registry.get(&ctx.kind) {
+                Some(exec) => run_stage_with_retry(
+                    exec.as_ref(),
+                    &ctx,
                     rec,
                     state.retry_policy.max_retries,
                     state.retry_policy.backoff_base_seconds,
-                )?
+                    &opts.cancel,
+                )?,
+                None => {
+                    rec.error = Some(format!("no executor for stage kind {}", ctx.kind));
+                    None
+                }
             }
         };
+        let success = outcome.is_some();
 
-        if let Some((storyboard, result)) = &video_artifacts {
-            state.set_artifact_path(
-                "video.storyboard",
-                serde_json::json!(storyboard.display().to_string()),
-            );
-            state.set_artifact_path(
-                "video.shots_dir",
-                serde_json::json!(result.shots_dir.display().to_string()),
-            );
-            state.set_artifact_path(
-                "video.shots_count",
-                serde_json::json!(result.shots_count),
-            );
-            state.set_artifact_path(
-                "video.concat_txt",
-                serde_json::json!(result.concat_txt.display().to_string()),
-            );
-            state.set_artifact_path(
-                "video.video_mp4",
-                serde_json::json!(result.video_mp4.display().to_string()),
-            );
-            state.set_artifact_path(
-                "video.shot_metrics",
-                serde_json::to_value(&result.shot_metrics).unwrap_or_else(|_| serde_json::json!([])),
-            );
+        for (key, value) in outcome.iter().flat_map(|o| o.artifacts.iter()) {
+            state
+                .artifacts
+                .record(key, value.clone(), Some(&stage), &ctx.workdir);
         }
 
         state.updated_at = now_rfc3339();
-        persist_state(state_path, &state)?;
+        persist_stored(state_path, &state, store)?;
 
         let done_after = {
             let rec = state.stages.get(&stage).expect("stage record must exist");
-            stage_done_by_outputs(&rec.outputs)
+            stage_done_by_outputs(&ffprobe, &workdir, &rec.outputs).and_then(|_| {
+                match outcome.as_ref().and_then(|o| o.expect.as_ref()) {
+                    Some((p, expect)) => verify_media(&ffprobe, &workdir.join(p), expect)
+                        .map(|_| ())
+                        .map_err(|e| verify_failure(&e)),
+                    None => Ok(()),
+                }
+            })
         };
-        if !success || !done_after {
+        if !success || done_after.is_err() {
             let rec = state
                 .stages
                 .get_mut(&stage)
                 .expect("stage record must exist");
             rec.status = StageStatus::FAILED;
-            rec.error = Some(format!("stage {} failed", name));
-            state.status = RunStatus::FAILED;
+            match done_after {
+                Err(f) if success => {
+                    rec.error = Some(format!("stage {} failed: {}", name, f.message));
+                    rec.failure = Some(f);
+                }
+                _ if rec.error.is_some() => {}
+                _ => rec.error = Some(format!("stage {} failed", name)),
+            }
+            opts.fail(&mut state);
             state.updated_at = now_rfc3339();
-            persist_state(state_path, &state)?;
+            persist_stored(state_path, &state, store)?;
             return Ok(state);
         }
 
@@ -319,30 +535,55 @@ pub fn run_pipeline(
             .get_mut(&stage)
             .expect("stage record must exist");
         rec.status = StageStatus::SUCCEEDED;
+        let outputs = rec.outputs.clone();
+        state.artifacts.record_outputs(&stage, &outputs, &ctx.workdir);
+        let stored = upload_outputs(opts, &mut state, &stage, &ctx.workdir)?;
         state.updated_at = now_rfc3339();
-        persist_state(state_path, &state)?;
+        persist_stored(state_path, &state, store)?;
+        if !stored {
+            return Ok(state);
+        }
     }
 
     state.status = RunStatus::SUCCEEDED;
     state.updated_at = now_rfc3339();
-    persist_state(state_path, &state)?;
+    persist_stored(state_path, &state, store)?;
     Ok(state)
 }
 
+/// Runs a queued pipeline in its `out_dir`; backgrounds may only use the creator's assets.
+/// Failures leave the run RUNNING for the job queue, and `cancel` abandons it.
 pub fn run_pipeline_default(
     state: RunState,
     compiled: crate::dsl::compile::CompiledCommands,
+    store: Option<Arc<dyn ArtifactStore>>,
+    cancel: CancellationToken,
 ) -> Result<RunState> {
     let out_dir = state.config.out_dir.clone();
     fs::create_dir_all(&out_dir)?;
     let state_path = out_dir.join("run.json");
-    run_pipeline(&state_path, state, compiled)
+    let opts = VideoStageOptions {
+        asset_scope: AssetScope::User(state.user_id),
+        store,
+        fail_run: false,
+        cancel,
+        ..Default::default()
+    };
+    run_pipeline_with(&state_path, state, compiled, &opts)
 }
 
-fn run_video_stage_v1() -> anyhow::Result<(std::path::PathBuf, video_executor::VideoExecResult)> {
-    let sb_path = std::path::PathBuf::from("build/storyboard.json");
+fn run_video_stage_v1(
+    opts: &VideoStageOptions,
+    assets_root: &Path,
+    asset_scope: AssetScope,
+    profile: &EncoderProfile,
+    reproducible: bool,
+) -> anyhow::Result<(std::path::PathBuf, video_executor::VideoExecResult)> {
+    let sb_path = opts.storyboard.clone();
     if !sb_path.exists() {
-        std::fs::create_dir_all("build")?;
+        if let Some(dir) = sb_path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
         let v = serde_json::json!({
             "schema":"css.video.storyboard.v1",
             "seed":123,
@@ -365,10 +606,72 @@ fn run_video_stage_v1() -> anyhow::Result<(std::path::PathBuf, video_executor::V
     let out = video_executor::run_video_executor_v1(
         sb_path.as_path(),
         video_executor::VideoExecConfig {
-            ffmpeg_path: "ffmpeg".to_string(),
-            concurrency: 2,
-            workdir: std::path::PathBuf::from("build/video"),
+            ffmpeg_path: opts.ffmpeg_path.clone(),
+            concurrency: opts.concurrency,
+            workdir: opts.workdir.clone(),
+            assets_root: assets_root.to_path_buf(),
+            asset_scope,
+            profile: profile.clone(),
+            reproducible,
         },
     )?;
     Ok((sb_path, out))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_planned_stage_has_a_local_executor() {
+        let compiled = crate::dsl::compile::compile_from_dsl(
+            "CSS demo :: lyrics()->music()->vocals()->video()->render();",
+        )
+        .unwrap();
+        let registry = local_registry(&VideoStageOptions::default());
+        for stage in stage_plan(&compiled).keys() {
+            let kind = stage_kind(stage, None);
+            assert!(registry.get(&kind).is_some(), "{stage}: no executor for {kind}");
+        }
+        for node in cssmv_dag_v1().nodes {
+            assert!(stage_plan(&compiled).contains_key(node.name), "{}", node.name);
+        }
+    }
+
+    #[test]
+    fn cancelling_abandons_the_running_stage() {
+        let ctx = StageContext {
+            stage: "music".to_string(),
+            kind: "shell".to_string(),
+            workdir: PathBuf::from("."),
+            tier: "local".to_string(),
+            ui_lang: "en".to_string(),
+            commands: serde_json::json!({}),
+            command: Some("sleep 30".to_string()),
+            outputs: Vec::new(),
+            meta: Default::default(),
+            options: ExecOptions::default(),
+        };
+        let mut rec = StageRecord {
+            status: StageStatus::PENDING,
+            started_at: None,
+            ended_at: None,
+            exit_code: None,
+            kind: None,
+            command: None,
+            outputs: Vec::new(),
+            retries: 0,
+            error: None,
+            failure: None,
+            meta: Default::default(),
+        };
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let started = std::time::Instant::now();
+        let exec = crate::stage_executor::ShellExecutor;
+        let r = run_stage_with_retry(&exec, &ctx, &mut rec, 3, 1, &cancel);
+        assert!(r.unwrap_err().to_string().contains("cancelled"));
+        assert_eq!(rec.retries, 0);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
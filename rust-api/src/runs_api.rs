@@ -1,5 +1,9 @@
+use crate::artifacts::{ArtifactKind, ArtifactRegistry, ARTIFACTS_SCHEMA};
+use crate::audio::mix::MixSpec;
+use crate::auth::AuthSession;
 use crate::dag::topo_order_v1;
 use crate::dsl::compile::CompiledCommands;
+use crate::encoder::EncoderProfile;
 use crate::metrics;
 use crate::routes::AppState;
 use crate::run_state::{
@@ -7,10 +11,16 @@ use crate::run_state::{
 };
 use crate::run_state_io::save_state_atomic;
 use crate::runs_list;
+use crate::subtitles::lang::{langs_from_value, normalize_lang, valid_lang};
+use crate::subtitles::model::SubtitleFormat;
+use crate::video::package::PackageSpec;
+use crate::video::render::SubtitleMode;
+use crate::video::storyboard::output_targets_from_value;
+use crate::video::thumbnails::ThumbnailSpec;
 use axum::{
-    extract::{Json, Path, State},
+    extract::{Json, Path, Query, State},
     http::StatusCode,
-    response::IntoResponse,
+    response::{IntoResponse, Response},
     routing::get,
     Router,
 };
@@ -27,6 +37,8 @@ pub struct CreateRunRequest {
     pub commands: serde_json::Value,
     #[serde(default)]
     pub video: serde_json::Value,
+    #[serde(default)]
+    pub ui_lang: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -56,9 +68,9 @@ fn video_defaults() -> Value {
         },
         "seed": std::env::var("VIDEO_SEED").ok().and_then(|v| v.parse::<u64>().ok()).unwrap_or(123),
         "duration_s": std::env::var("VIDEO_DURATION_S").ok().and_then(|v| v.parse::<f64>().ok()).unwrap_or(24.0),
+        "cuts": std::env::var("VIDEO_CUTS").unwrap_or_else(|_| "bar".to_string()),
         "subtitles": {
-            "format": "ass",
-            "burnin": false
+            "format": "ass"
         }
     })
 }
@@ -104,8 +116,32 @@ fn v_set(obj: &mut Value, path: &[&str], value: Value) {
         .insert(path[path.len() - 1].to_string(), value);
 }
 
+/// Seed derived from the request body, so resubmitting the same reproducible run gives the
+/// same storyboard.
+fn request_seed(req: &CreateRunRequest) -> u64 {
+    use sha2::{Digest, Sha256};
+    let body = json!({"input": req.input, "commands": req.commands, "video": req.video});
+    let digest = Sha256::digest(body.to_string().as_bytes());
+    u64::from_be_bytes(digest[..8].try_into().expect("sha256 has 8 bytes"))
+}
+
+/// `commands.video.mix`, or the 400 that rejects the run when it is malformed.
+fn mix_spec(commands: &Value) -> Result<MixSpec, (StatusCode, Json<Value>)> {
+    MixSpec::from_value(commands.pointer("/video/mix")).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "schema":"css.error.v1",
+                "code":"RUN_INVALID_MIX",
+                "message":e
+            })),
+        )
+    })
+}
+
 pub async fn create_run(
     State(state): State<AppState>,
+    AuthSession { user_id }: AuthSession,
     Json(req): Json<CreateRunRequest>,
 ) -> impl IntoResponse {
     let run_id = Uuid::new_v4().to_string();
@@ -139,16 +175,21 @@ pub async fn create_run(
                     );
                 }
             },
-            None => crate::dsl::compile::compile_from_dsl(
+            None => match crate::dsl::compile::compile_from_dsl(
                 "CSS demo :: lyrics()->music()->vocals()->video()->render();",
-            )
-            .unwrap_or(CompiledCommands {
-                lyrics: "mkdir -p ./build && : > ./build/lyrics.json".to_string(),
-                music: "mkdir -p ./build && : > ./build/music.wav".to_string(),
-                vocals: "mkdir -p ./build && : > ./build/vocals.wav".to_string(),
-                video: "mkdir -p ./build/video && : > ./build/video/video.mp4".to_string(),
-                render: "mkdir -p ./build && : > ./build/final_mv.mp4".to_string(),
-            }),
+            ) {
+                Ok(c) => c,
+                Err(e) => {
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(json!({
+                            "schema":"css.error.v1",
+                            "code":"RUN_COMPILE_FAILED",
+                            "message": e.to_string()
+                        })),
+                    );
+                }
+            },
         },
     };
 
@@ -164,6 +205,14 @@ pub async fn create_run(
     if req.video.is_object() {
         v_set(&mut commands, &["video"], req.video.clone());
     }
+    let mut song = match &compiled.song {
+        Value::Object(m) => m.clone(),
+        _ => Default::default(),
+    };
+    if let Some(extra) = req.commands.get("song").and_then(|v| v.as_object()) {
+        song.extend(extra.clone());
+    }
+    v_set(&mut commands, &["song"], Value::Object(song));
 
     let shots_n = v_get_u64(&commands, &["video", "shots_n"])
         .or_else(|| {
@@ -173,8 +222,16 @@ pub async fn create_run(
         })
         .unwrap_or(8) as usize;
     let fps = v_get_u32(&commands, &["video", "fps"]).unwrap_or(30);
+    let reproducible = commands
+        .pointer("/video/reproducible")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
     let seed = v_get_u64(&commands, &["video", "seed"]).unwrap_or_else(|| {
-        (uuid::Uuid::new_v4().as_u128() as u64) ^ (chrono::Utc::now().timestamp_millis() as u64)
+        if reproducible {
+            request_seed(&req)
+        } else {
+            (uuid::Uuid::new_v4().as_u128() as u64) ^ (chrono::Utc::now().timestamp_millis() as u64)
+        }
     });
     let duration_s = v_get_f64(&commands, &["video", "duration_s"]).unwrap_or(8.0);
     let w = v_get_u32(&commands, &["video", "w"])
@@ -187,20 +244,114 @@ pub async fn create_run(
     v_set(&mut commands, &["video", "shots_n"], json!(shots_n));
     v_set(&mut commands, &["video", "fps"], json!(fps));
     v_set(&mut commands, &["video", "seed"], json!(seed));
+    v_set(&mut commands, &["video", "reproducible"], json!(reproducible));
     v_set(&mut commands, &["video", "duration_s"], json!(duration_s));
     v_set(&mut commands, &["video", "w"], json!(w));
     v_set(&mut commands, &["video", "h"], json!(h));
     v_set(&mut commands, &["video", "resolution", "w"], json!(w));
     v_set(&mut commands, &["video", "resolution", "h"], json!(h));
+    let subtitle_formats = SubtitleFormat::list_from_value(
+        commands.pointer("/video/subtitles/format"),
+    );
     v_set(
         &mut commands,
         &["video", "subtitles", "format"],
-        json!("ass"),
+        json!(subtitle_formats),
     );
+    let ui_lang = req
+        .ui_lang
+        .as_deref()
+        .map(normalize_lang)
+        .filter(|s| valid_lang(s))
+        .unwrap_or_else(|| "auto".to_string());
+    let subtitle_langs =
+        match langs_from_value(commands.pointer("/video/subtitles/langs"), &ui_lang) {
+            Ok(l) => l,
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "schema":"css.error.v1",
+                        "code":"RUN_INVALID_LANG",
+                        "message":e
+                    })),
+                );
+            }
+        };
+    v_set(
+        &mut commands,
+        &["video", "subtitles", "langs"],
+        json!(subtitle_langs),
+    );
+    let subtitle_mode = SubtitleMode::from_value(commands.pointer("/video/subtitles/burnin"));
     v_set(
         &mut commands,
         &["video", "subtitles", "burnin"],
-        json!(false),
+        json!(subtitle_mode.as_str()),
+    );
+    // The account's tier bounds the encoder profile and retention caps, so it is never
+    // taken from the request.
+    let role = match user_id {
+        Some(id) => sqlx::query_scalar::<_, String>("SELECT role FROM users WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&state.pool)
+            .await
+            .unwrap_or_else(|e| {
+                tracing::warn!("role of user {id} not readable: {e}");
+                None
+            }),
+        None => None,
+    };
+    let tier = EncoderProfile::account_tier(role.as_deref()).to_string();
+    let encoder = EncoderProfile::resolve(commands.pointer("/video/encoder"), &tier);
+    v_set(&mut commands, &["video", "encoder"], json!(encoder));
+    let output_targets = match output_targets_from_value(commands.pointer("/video/outputs")) {
+        Ok(t) => t,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "schema":"css.error.v1",
+                    "code":"RUN_INVALID_OUTPUTS",
+                    "message":e
+                })),
+            );
+        }
+    };
+    v_set(&mut commands, &["video", "outputs"], json!(output_targets));
+    let package = match PackageSpec::from_value(commands.pointer("/video/package")) {
+        Ok(p) => p,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "schema":"css.error.v1",
+                    "code":"RUN_INVALID_PACKAGE",
+                    "message":e
+                })),
+            );
+        }
+    };
+    let package = package
+        .planned(encoder.frame_size(w, h).1)
+        .link_targets(
+            &output_targets
+                .iter()
+                .map(|t| t.name.clone())
+                .collect::<Vec<_>>(),
+        );
+    v_set(&mut commands, &["video", "package"], json!(package));
+    let thumbnails = ThumbnailSpec::from_value(commands.pointer("/video/thumbnails"));
+    v_set(&mut commands, &["video", "thumbnails"], json!(thumbnails));
+    let mix = match mix_spec(&commands) {
+        Ok(m) => m,
+        Err(resp) => return resp,
+    };
+    v_set(&mut commands, &["video", "mix"], json!(mix));
+    v_set(
+        &mut commands,
+        &["render_cmd"],
+        json!(crate::dsl::compile::render_fallback_command(&mix)),
     );
     let mut order: Vec<String> = vec![
         "lyrics".into(),
@@ -213,6 +364,8 @@ pub async fn create_run(
     }
     order.push("video_assemble".into());
     order.push("render".into());
+    order.push("package".into());
+    order.push("thumbnails".into());
     let now = chrono::Utc::now().to_rfc3339();
     let mut run = RunState {
         schema: "css.pipeline.run.v1".to_string(),
@@ -220,8 +373,9 @@ pub async fn create_run(
         created_at: now.clone(),
         updated_at: now,
         status: RunStatus::INIT,
-        ui_lang: "auto".to_string(),
-        tier: "local".to_string(),
+        ui_lang,
+        tier,
+        user_id,
         cssl: "cssapi.runs.v1".to_string(),
         commands: commands.clone(),
         config: RunConfig {
@@ -272,17 +426,21 @@ pub async fn create_run(
                         "video_assemble".into(),
                     ],
                 });
+                nodes.push(DagNodeMeta {
+                    name: "package".into(),
+                    deps: vec!["render".into()],
+                });
+                nodes.push(DagNodeMeta {
+                    name: "thumbnails".into(),
+                    deps: vec!["render".into()],
+                });
                 nodes
             },
         },
         topo_order: order,
         stages: Default::default(),
-        artifacts: vec![
-            PathBuf::from("./build/subtitles/subtitles.ass"),
-            PathBuf::from("./build/subtitles/subtitles.srt"),
-            PathBuf::from("./build/video/video.mp4"),
-            PathBuf::from("./build/final_mv.mp4"),
-        ],
+        artifacts: Default::default(),
+        storage: None,
         video_shots_total: shots_n as u32,
         video_shots_ready: 0,
         video_shots_running: 0,
@@ -295,10 +453,12 @@ pub async fn create_run(
             started_at: None,
             ended_at: None,
             exit_code: None,
-            command: Some(compiled.lyrics.clone()),
+            kind: Some("lyrics".into()),
+            command: compiled.lyrics.clone(),
             outputs: vec![PathBuf::from("./build/lyrics.json")],
             retries: 0,
             error: None,
+            failure: None,
             meta: Default::default(),
         },
     );
@@ -309,10 +469,12 @@ pub async fn create_run(
             started_at: None,
             ended_at: None,
             exit_code: None,
-            command: Some(compiled.music.clone()),
+            kind: Some("music".into()),
+            command: compiled.music.clone(),
             outputs: vec![PathBuf::from("./build/music.wav")],
             retries: 0,
             error: None,
+            failure: None,
             meta: Default::default(),
         },
     );
@@ -323,6 +485,7 @@ pub async fn create_run(
             started_at: None,
             ended_at: None,
             exit_code: None,
+            kind: Some("video_plan".into()),
             command: None,
             outputs: vec![
                 PathBuf::from("./build/video/storyboard.json"),
@@ -330,6 +493,7 @@ pub async fn create_run(
             ],
             retries: 0,
             error: None,
+            failure: None,
             meta: {
                 let mut m = std::collections::BTreeMap::new();
                 m.insert(
@@ -350,10 +514,12 @@ pub async fn create_run(
             started_at: None,
             ended_at: None,
             exit_code: None,
-            command: Some(compiled.vocals.clone()),
+            kind: Some("vocals".into()),
+            command: compiled.vocals.clone(),
             outputs: vec![PathBuf::from("./build/vocals.wav")],
             retries: 0,
             error: None,
+            failure: None,
             meta: Default::default(),
         },
     );
@@ -367,11 +533,17 @@ pub async fn create_run(
                 started_at: None,
                 ended_at: None,
                 exit_code: None,
+                kind: Some("video_shot".into()),
                 command: None,
                 outputs: vec![out],
                 retries: 0,
                 error: None,
-                meta: Default::default(),
+                failure: None,
+                meta: {
+                    let mut m = std::collections::BTreeMap::new();
+                    m.insert("encoder_profile".to_string(), json!(encoder.name));
+                    m
+                },
             },
         );
     }
@@ -382,10 +554,12 @@ pub async fn create_run(
             started_at: None,
             ended_at: None,
             exit_code: None,
+            kind: Some("video_assemble".into()),
             command: None,
             outputs: vec![PathBuf::from("./build/video/video.mp4")],
             retries: 0,
             error: None,
+            failure: None,
             meta: {
                 let mut m = std::collections::BTreeMap::new();
                 m.insert("mode".to_string(), json!("concat_copy_then_encode"));
@@ -400,26 +574,78 @@ pub async fn create_run(
             started_at: None,
             ended_at: None,
             exit_code: None,
+            kind: Some("render".into()),
             command: None,
-            outputs: vec![
-                PathBuf::from("./build/final_mv.mp4"),
-                PathBuf::from("./build/subtitles.ass"),
-                PathBuf::from("./build/subtitles.srt"),
-            ],
+            outputs: std::iter::once(PathBuf::from("./build/final_mv.mp4"))
+                .chain(
+                    subtitle_formats
+                        .iter()
+                        .map(|f| PathBuf::from(format!("./build/subtitles.{}", f.ext()))),
+                )
+                .collect(),
             retries: 0,
             error: None,
+            failure: None,
             meta: {
                 let mut m = std::collections::BTreeMap::new();
                 m.insert("mode".to_string(), json!("copy_then_encode"));
+                m.insert("encoder_profile".to_string(), json!(encoder.name));
                 m.insert(
                     "subtitles".to_string(),
-                    json!({"format":"ass","burnin":false}),
+                    json!({"format":subtitle_formats,"langs":subtitle_langs,"burnin":subtitle_mode.as_str()}),
                 );
                 m
             },
         },
     );
 
+    run.stages.insert(
+        "package".into(),
+        StageRecord {
+            status: StageStatus::PENDING,
+            started_at: None,
+            ended_at: None,
+            exit_code: None,
+            kind: Some("package".into()),
+            command: None,
+            outputs: package.outputs(),
+            retries: 0,
+            error: None,
+            failure: None,
+            meta: {
+                let mut m = std::collections::BTreeMap::new();
+                m.insert(
+                    "renditions".to_string(),
+                    json!(package.renditions.iter().map(|r| &r.name).collect::<Vec<_>>()),
+                );
+                m.insert("hls".to_string(), json!(package.hls));
+                m.insert("dash".to_string(), json!(package.dash));
+                m
+            },
+        },
+    );
+    run.stages.insert(
+        "thumbnails".into(),
+        StageRecord {
+            status: StageStatus::PENDING,
+            started_at: None,
+            ended_at: None,
+            exit_code: None,
+            kind: Some("thumbnails".into()),
+            command: None,
+            outputs: thumbnails.outputs(),
+            retries: 0,
+            error: None,
+            failure: None,
+            meta: {
+                let mut m = std::collections::BTreeMap::new();
+                m.insert("contact_sheet".to_string(), json!(thumbnails.contact_sheet));
+                m.insert("preview".to_string(), json!(thumbnails.preview));
+                m
+            },
+        },
+    );
+
     v_set(&mut commands, &["video", "shots_total"], json!(shots_n));
     run.commands = commands.clone();
     for rec in run.stages.values_mut() {
@@ -431,7 +657,17 @@ pub async fn create_run(
     match save_state_atomic(&run_json_path, &run) {
         Ok(_) => {
             metrics::incr_runs_created();
-            state.scheduler.enqueue(run_id.clone());
+            if let Err(e) = state.scheduler.enqueue(&run_id).await {
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    Json(json!({
+                        "schema":"css.error.v1",
+                        "code":"RUN_ENQUEUE_FAILED",
+                        "message":e.to_string(),
+                        "run_id":run_id
+                    })),
+                );
+            }
             (
                 StatusCode::CREATED,
                 Json(json!(RunResponse {
@@ -493,6 +729,71 @@ pub async fn get_run_status(
     }
 }
 
+#[derive(Debug, Default, Deserialize)]
+pub struct ArtifactsQuery {
+    pub stage: Option<String>,
+    pub kind: Option<String>,
+}
+
+pub async fn get_run_artifacts(
+    State(state): State<AppState>,
+    Path(run_id): Path<String>,
+    Query(q): Query<ArtifactsQuery>,
+    AuthSession { user_id }: AuthSession,
+) -> Response {
+    let kind = match q.kind.as_deref().map(|k| (k, ArtifactKind::parse(k))) {
+        Some((k, None)) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "schema":"css.error.v1",
+                    "code":"BAD_ARTIFACT_KIND",
+                    "message":format!("unknown artifact kind {k}")
+                })),
+            )
+                .into_response();
+        }
+        Some((_, kind)) => kind,
+        None => None,
+    };
+    let store = state.config.artifact_store.open();
+    let run = match crate::artifacts_api::load_run(&state, store.as_ref(), &run_id).await {
+        Ok(run) => run,
+        Err(resp) => return resp,
+    };
+    let Some(user_id) = user_id else {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({
+                "schema":"css.error.v1",
+                "code":"AUTH_REQUIRED",
+                "message":"sign in to list run artifacts"
+            })),
+        )
+            .into_response();
+    };
+    let access = match crate::artifacts_api::check_access(&state, user_id, &run_id, &run).await {
+        Ok(a) => a,
+        Err(resp) => return resp,
+    };
+    let registry = ArtifactRegistry::from_run_json(&run);
+    let artifacts: Vec<_> = registry
+        .iter()
+        .filter(|a| access.allows(&a.key))
+        .filter(|a| q.stage.is_none() || a.produced_by_stage == q.stage)
+        .filter(|a| kind.is_none_or(|k| a.kind == k))
+        .collect();
+    (
+        StatusCode::OK,
+        Json(json!({
+            "schema": ARTIFACTS_SCHEMA,
+            "run_id": run_id,
+            "artifacts": artifacts,
+        })),
+    )
+        .into_response()
+}
+
 pub async fn get_run_ready(
     axum::extract::State(app): axum::extract::State<AppState>,
     Path(run_id): Path<String>,
@@ -515,4 +816,19 @@ pub fn router() -> Router<AppState> {
         .route("/cssapi/v1/runs/:run_id", get(get_run))
         .route("/cssapi/v1/runs/:run_id/status", get(get_run_status))
         .route("/cssapi/v1/runs/:run_id/ready", get(get_run_ready))
+        .route("/cssapi/v1/runs/:run_id/artifacts", get(get_run_artifacts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_mix_rejects_the_run() {
+        let (status, Json(body)) =
+            mix_spec(&json!({"video": {"mix": {"fade_in_s": "slow"}}})).unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "RUN_INVALID_MIX");
+        assert!(mix_spec(&json!({"video": {}})).is_ok());
+    }
 }
@@ -0,0 +1,341 @@
+use crate::asset_store::AssetScope;
+use crate::media_probe::{verify_media, verify_outputs, MediaExpect, VerifyError};
+use crate::run_state::{StageFailure, StageRecord};
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Kind of the stages that run the compiled DSL command through `sh -lc`.
+pub const SHELL_KIND: &str = "shell";
+
+/// Kind a stage runs as when its record does not declare one; covers runs created before
+/// stages carried a `kind`.
+pub fn default_kind_for(stage: &str) -> &'static str {
+    match stage {
+        "lyrics" => "lyrics",
+        "music" => "music",
+        "vocals" => "vocals",
+        "video" => "video",
+        "video_plan" => "video_plan",
+        "video_assemble" => "video_assemble",
+        "render" => "render",
+        "package" => "package",
+        "thumbnails" => "thumbnails",
+        s if s.starts_with("video_shot_") => "video_shot",
+        _ => SHELL_KIND,
+    }
+}
+
+pub fn stage_kind(stage: &str, rec: Option<&StageRecord>) -> String {
+    rec.and_then(|r| r.kind.clone())
+        .unwrap_or_else(|| default_kind_for(stage).to_string())
+}
+
+/// Tools and directories an executor may need besides the run itself.
+#[derive(Debug, Clone)]
+pub struct ExecOptions {
+    pub ffmpeg_path: String,
+    pub ffprobe_path: String,
+    pub concurrency: usize,
+    pub assets_root: PathBuf,
+    /// Whose assets the run may use: its creator's for API runs, any for local ones.
+    pub asset_scope: AssetScope,
+    pub fonts_dir: Option<PathBuf>,
+}
+
+impl Default for ExecOptions {
+    fn default() -> Self {
+        Self {
+            ffmpeg_path: "ffmpeg".to_string(),
+            ffprobe_path: "ffprobe".to_string(),
+            concurrency: 2,
+            assets_root: crate::asset_store::default_root(),
+            asset_scope: AssetScope::Any,
+            fonts_dir: None,
+        }
+    }
+}
+
+/// Everything an executor gets to run one stage; a snapshot, so executors never hold the
+/// run state lock or file.
+#[derive(Debug, Clone)]
+pub struct StageContext {
+    pub stage: String,
+    pub kind: String,
+    /// Directory the stage's `./build/...` paths are relative to.
+    pub workdir: PathBuf,
+    pub tier: String,
+    pub ui_lang: String,
+    pub commands: Value,
+    /// Shell command for `shell` stages.
+    pub command: Option<String>,
+    pub outputs: Vec<PathBuf>,
+    /// The stage's meta when it started; executors merge into it rather than replace it.
+    pub meta: BTreeMap<String, Value>,
+    pub options: ExecOptions,
+}
+
+impl StageContext {
+    pub fn path(&self, rel: impl AsRef<Path>) -> PathBuf {
+        self.workdir.join(rel)
+    }
+
+    pub fn fps(&self) -> u32 {
+        self.commands
+            .pointer("/video/fps")
+            .and_then(|v| v.as_u64())
+            .and_then(|v| u32::try_from(v).ok())
+            .unwrap_or(30)
+    }
+
+    pub fn reproducible(&self) -> bool {
+        self.commands
+            .pointer("/video/reproducible")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false)
+    }
+}
+
+/// What a successful stage leaves behind for the run state.
+#[derive(Debug, Clone, Default)]
+pub struct StageOutcome {
+    pub exit_code: Option<i32>,
+    /// Outputs found while running (subtitle tracks, targets), added to the declared ones.
+    pub outputs: Vec<PathBuf>,
+    /// Replaces the declared outputs when set.
+    pub replace_outputs: Option<Vec<PathBuf>>,
+    pub meta: BTreeMap<String, Value>,
+    /// `(key, value)` pairs for `ArtifactRegistry::record`.
+    pub artifacts: Vec<(String, Value)>,
+    /// Stricter check of one output than "non-empty and probes".
+    pub expect: Option<(PathBuf, MediaExpect)>,
+}
+
+impl StageOutcome {
+    pub fn artifact(mut self, path: &str, value: Value) -> Self {
+        self.artifacts.push((path.to_string(), value));
+        self
+    }
+
+    pub fn meta(mut self, key: &str, value: Value) -> Self {
+        self.meta.insert(key.to_string(), value);
+        self
+    }
+
+    /// Copies exit code, outputs and meta onto the stage record.
+    pub fn apply_to(&self, rec: &mut StageRecord) {
+        rec.exit_code = self.exit_code.or(Some(0));
+        if let Some(outputs) = &self.replace_outputs {
+            rec.outputs = outputs.clone();
+        }
+        for p in &self.outputs {
+            if !rec.outputs.contains(p) {
+                rec.outputs.push(p.clone());
+            }
+        }
+        rec.meta.extend(self.meta.clone());
+    }
+}
+
+/// Runs one kind of stage. Implementations are looked up by `kind()` in an
+/// [`ExecutorRegistry`], so a new stage type only needs an executor and a registration.
+#[axum::async_trait]
+pub trait StageExecutor: Send + Sync {
+    fn kind(&self) -> &'static str;
+    async fn run(&self, ctx: &StageContext) -> Result<StageOutcome>;
+}
+
+#[derive(Clone, Default)]
+pub struct ExecutorRegistry {
+    by_kind: BTreeMap<&'static str, Arc<dyn StageExecutor>>,
+}
+
+impl ExecutorRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registry with the shell executor, the fallback for DSL command stages.
+    pub fn with_shell() -> Self {
+        Self::new().with(ShellExecutor)
+    }
+
+    /// Adds or replaces the executor for its kind.
+    pub fn with(mut self, exec: impl StageExecutor + 'static) -> Self {
+        self.register(exec);
+        self
+    }
+
+    pub fn register(&mut self, exec: impl StageExecutor + 'static) {
+        self.by_kind.insert(exec.kind(), Arc::new(exec));
+    }
+
+    pub fn get(&self, kind: &str) -> Option<Arc<dyn StageExecutor>> {
+        self.by_kind.get(kind).cloned()
+    }
+
+    pub fn kinds(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.by_kind.keys().copied()
+    }
+}
+
+impl std::fmt::Debug for ExecutorRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.kinds()).finish()
+    }
+}
+
+/// Runs the stage's DSL command with `sh -lc` in the workdir, with `FFMPEG` set to the
+/// configured ffmpeg.
+#[derive(Debug, Clone, Copy)]
+pub struct ShellExecutor;
+
+#[axum::async_trait]
+impl StageExecutor for ShellExecutor {
+    fn kind(&self) -> &'static str {
+        SHELL_KIND
+    }
+
+    async fn run(&self, ctx: &StageContext) -> Result<StageOutcome> {
+        let cmdline = ctx
+            .command
+            .as_deref()
+            .filter(|c| !c.trim().is_empty())
+            .with_context(|| format!("stage {} has no command", ctx.stage))?;
+        let status = tokio::process::Command::new("sh")
+            .kill_on_drop(true)
+            .arg("-lc")
+            .arg(cmdline)
+            .current_dir(&ctx.workdir)
+            .env("FFMPEG", &ctx.options.ffmpeg_path)
+            .status()
+            .await
+            .with_context(|| format!("spawn sh for stage {}", ctx.stage))?;
+        if !status.success() {
+            anyhow::bail!(
+                "command exited with {}",
+                status
+                    .code()
+                    .map(|c| c.to_string())
+                    .unwrap_or_else(|| "signal".to_string())
+            );
+        }
+        Ok(StageOutcome {
+            exit_code: status.code(),
+            ..Default::default()
+        })
+    }
+}
+
+/// Declared outputs are non-empty and probe cleanly, plus the outcome's stricter check.
+pub fn verify_outcome(
+    ffprobe: &str,
+    workdir: &Path,
+    outputs: &[PathBuf],
+    outcome: &StageOutcome,
+) -> Result<(), VerifyError> {
+    let outputs: Vec<PathBuf> = outputs.iter().map(|p| workdir.join(p)).collect();
+    verify_outputs(ffprobe, &outputs)?;
+    if let Some((p, expect)) = &outcome.expect {
+        verify_media(ffprobe, &workdir.join(p), expect)?;
+    }
+    Ok(())
+}
+
+/// Structured reason for a stage error that came from output verification.
+pub fn failure_of(e: &anyhow::Error) -> Option<StageFailure> {
+    e.chain()
+        .find_map(|c| c.downcast_ref::<VerifyError>())
+        .map(|v| StageFailure::new(v.reason(), v.to_string(), Some(v.path().to_path_buf())))
+}
+
+/// Drives an executor future to completion from blocking code: on the current runtime when
+/// called from a blocking task, otherwise on a throwaway one.
+pub fn block_on<F: std::future::Future>(f: F) -> Result<F::Output> {
+    match tokio::runtime::Handle::try_current() {
+        Ok(h) => Ok(h.block_on(f)),
+        Err(_) => Ok(tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("start runtime")?
+            .block_on(f)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_and_short_outputs_are_rejected() {
+        let dir = std::env::temp_dir().join(format!("css_verify_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("empty.json"), b"").unwrap();
+        std::fs::write(dir.join("empty.mp4"), b"").unwrap();
+        let second = vec![0.0f32; 48_000];
+        std::fs::write(
+            dir.join("short.wav"),
+            crate::audio::wav::encode_wav_pcm16(48_000, 1, &second),
+        )
+        .unwrap();
+        // Empty and missing files fail before probing and WAVs are read directly, so none
+        // of these cases needs an ffprobe binary.
+        let verify = |outputs: &[&str], outcome: &StageOutcome| {
+            let outputs: Vec<PathBuf> = outputs.iter().map(PathBuf::from).collect();
+            verify_outcome("/nonexistent/ffprobe", &dir, &outputs, outcome)
+                .map_err(|e| e.reason())
+        };
+        let none = StageOutcome::default();
+
+        assert_eq!(verify(&["empty.json"], &none), Err("output_empty"));
+        assert_eq!(verify(&["empty.mp4"], &none), Err("output_empty"));
+        assert_eq!(verify(&["gone.mp4"], &none), Err("output_missing"));
+        assert_eq!(verify(&["short.wav"], &none), Ok(()));
+        let expect_4s = StageOutcome {
+            expect: Some((
+                "short.wav".into(),
+                MediaExpect {
+                    duration_s: Some(4.0),
+                    duration_tolerance_s: 0.1,
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        };
+        assert_eq!(verify(&["short.wav"], &expect_4s), Err("duration_mismatch"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn unknown_kinds_have_no_executor() {
+        let registry = ExecutorRegistry::with_shell();
+        assert_eq!(registry.kinds().collect::<Vec<_>>(), vec![SHELL_KIND]);
+        assert!(registry.get(SHELL_KIND).is_some());
+        assert!(registry.get("render").is_none());
+        assert!(registry.get("").is_none());
+        assert_eq!(default_kind_for("mystery"), SHELL_KIND);
+    }
+
+    #[test]
+    fn shell_stages_without_a_command_are_rejected() {
+        for command in [None, Some(""), Some("  ")] {
+            let ctx = StageContext {
+                stage: "lyrics".into(),
+                kind: SHELL_KIND.into(),
+                workdir: std::env::temp_dir(),
+                tier: String::new(),
+                ui_lang: String::new(),
+                commands: serde_json::json!({}),
+                command: command.map(String::from),
+                outputs: Vec::new(),
+                meta: Default::default(),
+                options: ExecOptions::default(),
+            };
+            let err = block_on(ShellExecutor.run(&ctx)).unwrap().unwrap_err();
+            assert!(err.to_string().contains("has no command"), "{err}");
+        }
+    }
+}
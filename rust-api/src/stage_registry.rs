@@ -0,0 +1,564 @@
+use crate::audio::beats::ensure_beats_json;
+use crate::audio::mix::MixSpec;
+use crate::encoder::EncoderProfile;
+use crate::media_probe::MediaExpect;
+use crate::stage_executor::{
+    ExecOptions, ExecutorRegistry, StageContext, StageExecutor, StageOutcome,
+};
+use crate::subtitles::ass::{render_ass_layout, AssLayout};
+use crate::subtitles::ensure_subtitle_tracks;
+use crate::subtitles::lang::langs_from_value;
+use crate::subtitles::model::SubtitleFormat;
+use crate::subtitles::parse_cues;
+use crate::subtitles::translate::translator_from_env;
+use crate::video::duration::probe_media_duration_s;
+use crate::video::ffmpeg::{concat_dual_path, concat_list_path};
+use crate::video::package::{package_renditions, PackageSpec};
+use crate::video::render::{render_final, RenderSpec, SubtitleInput, SubtitleMode};
+use crate::video::storyboard::{
+    ensure_storyboard_auto, load_storyboard_v1, output_targets_from_value, AutoPlan, CutMode,
+};
+use crate::video::thumbnails::{generate_thumbnails, ThumbnailSpec};
+use crate::video_executor::{render_shot_v1, render_target_v1, VideoExecConfig};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+fn v_get_u32(v: &Value, path: &[&str]) -> Option<u32> {
+    let mut cur = v;
+    for k in path {
+        cur = cur.get(*k)?;
+    }
+    cur.as_u64().and_then(|x| u32::try_from(x).ok())
+}
+
+pub async fn run_video_assemble_stage(
+    ffmpeg: &str,
+    out_dir: std::path::PathBuf,
+) -> anyhow::Result<String> {
+    let list_txt = concat_list_path(&out_dir);
+    let out_mp4 = out_dir.join("build").join("video").join("video.mp4");
+    let shots_dir = out_dir.join("build").join("video").join("shots");
+
+    let parent = out_mp4
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("bad out dir"))?;
+    let _ = tokio::fs::create_dir_all(parent).await;
+    let _ = tokio::fs::create_dir_all(&shots_dir).await;
+
+    let mut entries = tokio::fs::read_dir(&shots_dir).await?;
+    let mut shots = Vec::<String>::new();
+    while let Some(ent) = entries.next_entry().await? {
+        let p = ent.path();
+        if p.extension().and_then(|e| e.to_str()) == Some("mp4") {
+            shots.push(p.display().to_string());
+        }
+    }
+    shots.sort();
+    let mut list = String::new();
+    for p in shots {
+        list.push_str("file '");
+        list.push_str(&p.replace('\'', "'\\''"));
+        list.push_str("'\n");
+    }
+    tokio::fs::write(&list_txt, list).await?;
+
+    let mode = concat_dual_path(ffmpeg, &list_txt, &out_mp4).await?;
+    Ok(mode.to_string())
+}
+
+pub async fn run_video_plan_stage(
+    ffprobe: &str,
+    run_dir: PathBuf,
+    commands: serde_json::Value,
+) -> anyhow::Result<BTreeMap<String, serde_json::Value>> {
+    let v = commands
+        .get("video")
+        .cloned()
+        .unwrap_or_else(|| serde_json::json!({}));
+
+    let shots_n = v.get("shots_n").and_then(|x| x.as_u64()).unwrap_or(8) as usize;
+    let w = v.get("w").and_then(|x| x.as_u64()).unwrap_or(1280) as u32;
+    let h = v.get("h").and_then(|x| x.as_u64()).unwrap_or(720) as u32;
+    let fps = v.get("fps").and_then(|x| x.as_u64()).unwrap_or(30) as u32;
+    let seed = v.get("seed").and_then(|x| x.as_u64()).unwrap_or(123);
+    let dur_s_env = v.get("duration_s").and_then(|x| x.as_f64());
+
+    let vocals_wav = run_dir.join("build").join("vocals.wav");
+    let probed = probe_media_duration_s(ffprobe, &vocals_wav).await.ok().flatten();
+    let duration_s = dur_s_env.or(probed).unwrap_or((shots_n as f64) * 4.0);
+
+    let out_dir = run_dir.join("build").join("video");
+    let shots_dir = out_dir.join("shots");
+    let _ = tokio::fs::create_dir_all(&shots_dir).await;
+
+    let cut_mode = v
+        .get("cuts")
+        .and_then(|x| x.as_str())
+        .and_then(CutMode::parse)
+        .unwrap_or(CutMode::Bar);
+
+    let mut meta = BTreeMap::new();
+    meta.insert("cut_mode".to_string(), serde_json::json!(cut_mode.as_str()));
+
+    let beats = if cut_mode == CutMode::Even {
+        None
+    } else {
+        let music_wav = run_dir.join("build").join("music.wav");
+        let beats_json = run_dir.join("build").join("audio").join("beats.json");
+        let analyzed =
+            tokio::task::spawn_blocking(move || ensure_beats_json(&music_wav, &beats_json))
+                .await?;
+        match analyzed {
+            Ok(b) => {
+                meta.insert("tempo_bpm".to_string(), serde_json::json!(b.tempo_bpm));
+                meta.insert(
+                    "beats_json".to_string(),
+                    serde_json::json!("./build/audio/beats.json"),
+                );
+                Some(b)
+            }
+            Err(e) => {
+                meta.insert(
+                    "beats_error".to_string(),
+                    serde_json::json!(format!("{e}")),
+                );
+                None
+            }
+        }
+    };
+
+    let plan = AutoPlan {
+        seed,
+        duration_s,
+        shots_n,
+        fps,
+        w,
+        h,
+        cut_mode,
+        outputs: output_targets_from_value(commands.pointer("/video/outputs"))
+            .map_err(anyhow::Error::msg)?,
+    };
+    let storyboard_path = out_dir.join("storyboard.json");
+    let _ = ensure_storyboard_auto(&storyboard_path, &plan, beats.as_ref())?;
+
+    write_shots_txt(&out_dir, shots_n).await?;
+    Ok(meta)
+}
+
+async fn write_shots_txt(out_dir: &Path, shots_n: usize) -> anyhow::Result<()> {
+    let mut s = String::new();
+    for i in 0..shots_n {
+        s.push_str(&format!("file 'shots/video_shot_{:03}.mp4'\n", i));
+    }
+    let p = out_dir.join("shots.txt");
+    tokio::fs::write(p, s).await?;
+    Ok(())
+}
+
+/// Finals for the storyboard's extra aspect targets. Target shots are rendered here when the
+/// executor has not produced them, and ASS subtitles are re-laid out for each frame.
+async fn render_output_targets(
+    options: &ExecOptions,
+    out_dir: &Path,
+    sb_path: &Path,
+    master: &RenderSpec,
+) -> anyhow::Result<Vec<Value>> {
+    let Ok(sb) = load_storyboard_v1(sb_path) else {
+        return Ok(Vec::new());
+    };
+    let mut out = Vec::with_capacity(sb.outputs.len());
+    for target in &sb.outputs {
+        let video_mp4 = out_dir
+            .join("build/video/targets")
+            .join(&target.name)
+            .join("video.mp4");
+        if !video_mp4.exists() {
+            let cfg = VideoExecConfig {
+                ffmpeg_path: options.ffmpeg_path.clone(),
+                concurrency: options.concurrency,
+                workdir: out_dir.join("build/video"),
+                assets_root: options.assets_root.clone(),
+                asset_scope: options.asset_scope,
+                profile: master.encoder.clone(),
+                reproducible: master.reproducible,
+            };
+            let (sb2, target2) = (sb.clone(), target.clone());
+            tokio::task::spawn_blocking(move || render_target_v1(&sb2, &target2, &cfg)).await??;
+        }
+
+        let dir = out_dir.join("build/targets").join(&target.name);
+        let (tw, th) = master
+            .encoder
+            .frame_size(target.resolution.w, target.resolution.h);
+        let layout = AssLayout::for_frame(tw, th);
+        let mut subtitles = Vec::with_capacity(master.subtitles.len());
+        for sub in &master.subtitles {
+            let Some(format) = sub
+                .path
+                .extension()
+                .and_then(|e| e.to_str())
+                .and_then(SubtitleFormat::parse)
+            else {
+                continue;
+            };
+            let cues = parse_cues(&tokio::fs::read_to_string(&sub.path).await?, format)?;
+            let path = dir.join(format!("subtitles/{}.ass", sub.lang));
+            tokio::fs::create_dir_all(dir.join("subtitles")).await?;
+            tokio::fs::write(&path, render_ass_layout(&cues, &layout)).await?;
+            subtitles.push(SubtitleInput {
+                path,
+                lang: sub.lang.clone(),
+            });
+        }
+
+        let spec = RenderSpec {
+            video_mp4,
+            out_mp4: dir.join("final_mv.mp4"),
+            subtitles,
+            ..master.clone()
+        };
+        let report = render_final(&options.ffmpeg_path, &spec)
+            .await
+            .map_err(anyhow::Error::msg)?;
+        out.push(serde_json::json!({
+            "name": target.name,
+            "w": tw,
+            "h": th,
+            "reframe": target.reframe,
+            "path": format!("build/targets/{}/final_mv.mp4", target.name),
+            "subtitles": {
+                "mode": report.subtitle_mode.as_str(),
+                "tracks": report.subtitle_tracks,
+            },
+        }));
+    }
+    Ok(out)
+}
+
+/// Executors for every stage kind: shell commands plus the lyrics, audio and video stages.
+/// The API scheduler dispatches through this registry as is; the local runner adds its
+/// whole-storyboard `video` stage on top.
+pub fn stage_registry() -> ExecutorRegistry {
+    ExecutorRegistry::with_shell()
+        .with(crate::lyrics::LyricsExecutor)
+        .with(crate::music::MusicExecutor)
+        .with(crate::music::VocalsExecutor)
+        .with(VideoPlanExecutor)
+        .with(VideoShotExecutor)
+        .with(VideoAssembleExecutor)
+        .with(RenderExecutor)
+        .with(PackageExecutor)
+        .with(ThumbnailsExecutor)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VideoPlanExecutor;
+
+#[axum::async_trait]
+impl StageExecutor for VideoPlanExecutor {
+    fn kind(&self) -> &'static str {
+        "video_plan"
+    }
+
+    async fn run(&self, ctx: &StageContext) -> anyhow::Result<StageOutcome> {
+        let meta = run_video_plan_stage(
+            &ctx.options.ffprobe_path,
+            ctx.workdir.clone(),
+            ctx.commands.clone(),
+        )
+        .await?;
+        crate::subtitles::write_ass_stub(&ctx.workdir)?;
+        Ok(StageOutcome {
+            meta,
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VideoShotExecutor;
+
+#[axum::async_trait]
+impl StageExecutor for VideoShotExecutor {
+    fn kind(&self) -> &'static str {
+        "video_shot"
+    }
+
+    async fn run(&self, ctx: &StageContext) -> anyhow::Result<StageOutcome> {
+        let idx = ctx
+            .stage
+            .strip_prefix("video_shot_")
+            .and_then(|s| s.parse::<usize>().ok())
+            .ok_or_else(|| anyhow::anyhow!("bad shot stage"))?;
+        let fps = ctx.fps();
+        let encoder = EncoderProfile::resolve(ctx.commands.pointer("/video/encoder"), &ctx.tier);
+        let cfg = VideoExecConfig {
+            ffmpeg_path: ctx.options.ffmpeg_path.clone(),
+            concurrency: 1,
+            workdir: ctx.path("build/video"),
+            assets_root: ctx.options.assets_root.clone(),
+            asset_scope: ctx.options.asset_scope,
+            profile: encoder.clone(),
+            reproducible: ctx.reproducible(),
+        };
+        let storyboard = ctx.path("build/video/storyboard.json");
+        let metric =
+            tokio::task::spawn_blocking(move || render_shot_v1(&storyboard, idx, &cfg)).await??;
+        Ok(StageOutcome {
+            expect: ctx.outputs.first().cloned().map(|p| {
+                let expect = MediaExpect {
+                    fps: Some(fps as f64),
+                    video: true,
+                    ..Default::default()
+                };
+                (p, expect)
+            }),
+            ..Default::default()
+        }
+        .meta("ffmpeg_args_hash", serde_json::json!(metric.args_sha256))
+        .meta("render_ms", serde_json::json!(metric.duration_ms))
+        .meta("encoder_profile", serde_json::json!(encoder.name))
+        .meta("encoder", serde_json::json!(encoder.codec.ffmpeg_encoder())))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VideoAssembleExecutor;
+
+#[axum::async_trait]
+impl StageExecutor for VideoAssembleExecutor {
+    fn kind(&self) -> &'static str {
+        "video_assemble"
+    }
+
+    async fn run(&self, ctx: &StageContext) -> anyhow::Result<StageOutcome> {
+        let mode = run_video_assemble_stage(&ctx.options.ffmpeg_path, ctx.workdir.clone()).await?;
+        Ok(StageOutcome::default().meta("assemble_mode", serde_json::json!(mode)))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RenderExecutor;
+
+#[axum::async_trait]
+impl StageExecutor for RenderExecutor {
+    fn kind(&self) -> &'static str {
+        "render"
+    }
+
+    async fn run(&self, ctx: &StageContext) -> anyhow::Result<StageOutcome> {
+        let commands = &ctx.commands;
+        let out_dir = &ctx.workdir;
+        let fps = ctx.fps();
+        let encoder = EncoderProfile::resolve(commands.pointer("/video/encoder"), &ctx.tier);
+        let formats = SubtitleFormat::list_from_value(commands.pointer("/video/subtitles/format"));
+        let subtitle_mode = SubtitleMode::from_value(commands.pointer("/video/subtitles/burnin"));
+        let langs = langs_from_value(commands.pointer("/video/subtitles/langs"), &ctx.ui_lang)
+            .map_err(anyhow::Error::msg)?;
+        let translator = translator_from_env()?;
+        let tracks = ensure_subtitle_tracks(out_dir, &langs, &formats, translator.as_deref())?;
+        let rel = |p: &Path| {
+            p.strip_prefix(out_dir)
+                .map(|x| x.display().to_string())
+                .unwrap_or_else(|_| p.display().to_string())
+        };
+        let mut outputs: Vec<PathBuf> = tracks
+            .iter()
+            .map(|t| {
+                t.path
+                    .strip_prefix(out_dir)
+                    .map(|x| Path::new(".").join(x))
+                    .unwrap_or(t.path.clone())
+            })
+            .collect();
+
+        // One file per language goes into the container; ASS keeps karaoke styling when burned in.
+        let mut subtitles: Vec<SubtitleInput> = Vec::new();
+        for t in &tracks {
+            if subtitles.iter().any(|s| s.lang == t.lang) {
+                continue;
+            }
+            let pick = tracks
+                .iter()
+                .find(|x| x.lang == t.lang && x.format == SubtitleFormat::Ass)
+                .unwrap_or(t);
+            subtitles.push(SubtitleInput {
+                path: pick.path.clone(),
+                lang: t.lang.clone(),
+            });
+        }
+        let spec = RenderSpec {
+            video_mp4: out_dir.join("build/video/video.mp4"),
+            music_wav: out_dir.join("build/music.wav"),
+            vocals_wav: out_dir.join("build/vocals.wav"),
+            out_mp4: out_dir.join("build/final_mv.mp4"),
+            subtitles,
+            subtitle_mode,
+            fonts_dir: ctx.options.fonts_dir.clone(),
+            mix: MixSpec::from_value(commands.pointer("/video/mix")).map_err(anyhow::Error::msg)?,
+            encoder: encoder.clone(),
+            fps,
+            reproducible: ctx.reproducible(),
+        };
+        let report = render_final(&ctx.options.ffmpeg_path, &spec)
+            .await
+            .map_err(anyhow::Error::msg)?;
+        let sb_path = out_dir.join("build/video/storyboard.json");
+        let targets =
+            render_output_targets(&ctx.options, out_dir, &sb_path, &spec).await?;
+
+        let burned = report.subtitle_file.as_deref().map(|p| rel(Path::new(p)));
+        let track_list: Vec<Value> = tracks
+            .iter()
+            .map(|t| {
+                serde_json::json!({
+                    "lang": t.lang,
+                    "format": t.format,
+                    "mime": t.format.mime(),
+                    "path": rel(&t.path),
+                    "untranslated": t.untranslated,
+                    "muxed": report.subtitle_tracks.contains(&t.lang)
+                        && spec.subtitles.iter().any(|i| i.path == t.path),
+                    "burned": burned.as_deref() == Some(rel(&t.path).as_str()),
+                })
+            })
+            .collect();
+        let mut subs_meta = ctx
+            .meta
+            .get("subtitles")
+            .and_then(|v| v.as_object())
+            .cloned()
+            .unwrap_or_default();
+        subs_meta.insert("burnin".to_string(), serde_json::json!(report.subtitle_mode.as_str()));
+        subs_meta.insert("file".to_string(), serde_json::json!(burned));
+        subs_meta.insert("tracks".to_string(), serde_json::json!(report.subtitle_tracks));
+
+        for t in &targets {
+            if let Some(p) = t.get("path").and_then(|p| p.as_str()) {
+                outputs.push(Path::new(".").join(p));
+            }
+        }
+        let mut out = StageOutcome {
+            outputs,
+            expect: Some((
+                PathBuf::from("build/final_mv.mp4"),
+                MediaExpect {
+                    fps: Some(fps as f64),
+                    video: true,
+                    audio: Some(!report.audio_inputs.is_empty()),
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        }
+        .meta("subtitles", Value::Object(subs_meta))
+        .meta("video_codec", serde_json::json!(report.video_codec))
+        .meta("encoder_profile", serde_json::json!(encoder));
+        if let Some(mix) = &report.mix {
+            out = out
+                .meta("mix", serde_json::json!(mix.spec))
+                .meta("ducking", serde_json::json!(mix.ducking))
+                .meta("loudness", serde_json::json!(mix.loudness));
+        }
+        out = out
+            .artifact(
+                "video.final_mv",
+                serde_json::json!({
+                    "path": "build/final_mv.mp4",
+                    "subtitles": {
+                        "mode": report.subtitle_mode.as_str(),
+                        "file": burned,
+                        "tracks": report.subtitle_tracks,
+                    },
+                }),
+            )
+            .artifact("video.subtitles", serde_json::json!(track_list));
+        if !targets.is_empty() {
+            out = out.artifact("video.targets", serde_json::json!(targets));
+        }
+        Ok(out)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PackageExecutor;
+
+#[axum::async_trait]
+impl StageExecutor for PackageExecutor {
+    fn kind(&self) -> &'static str {
+        "package"
+    }
+
+    async fn run(&self, ctx: &StageContext) -> anyhow::Result<StageOutcome> {
+        let commands = &ctx.commands;
+        let h = v_get_u32(commands, &["video", "resolution", "h"]).unwrap_or(720);
+        let w = v_get_u32(commands, &["video", "resolution", "w"]).unwrap_or(1280);
+        let encoder = EncoderProfile::resolve(commands.pointer("/video/encoder"), &ctx.tier);
+        let spec = PackageSpec::from_value(commands.pointer("/video/package"))
+            .map_err(anyhow::Error::msg)?
+            .planned(encoder.frame_size(w, h).1);
+        let report = package_renditions(
+            &ctx.options.ffmpeg_path,
+            &ctx.workdir,
+            &ctx.path("build/final_mv.mp4"),
+            &spec,
+            ctx.fps(),
+        )
+        .await
+        .map_err(anyhow::Error::msg)?;
+        let mut out = StageOutcome::default()
+            .meta("renditions", serde_json::json!(report.renditions))
+            .artifact("package.renditions", serde_json::json!(report.renditions));
+        if let Some(m) = &report.hls_master {
+            out = out.artifact("package.hls_master", serde_json::json!(m));
+        }
+        if let Some(m) = &report.dash_manifest {
+            out = out.artifact("package.dash_manifest", serde_json::json!(m));
+        }
+        Ok(out)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ThumbnailsExecutor;
+
+#[axum::async_trait]
+impl StageExecutor for ThumbnailsExecutor {
+    fn kind(&self) -> &'static str {
+        "thumbnails"
+    }
+
+    async fn run(&self, ctx: &StageContext) -> anyhow::Result<StageOutcome> {
+        let spec = ThumbnailSpec::from_value(ctx.commands.pointer("/video/thumbnails"));
+        let (shots, poster_t) = match load_storyboard_v1(&ctx.path("build/video/storyboard.json"))
+        {
+            Ok(sb) => (sb.shots, sb.poster_t),
+            Err(_) => (Vec::new(), None),
+        };
+        let report = generate_thumbnails(
+            &ctx.options.ffmpeg_path,
+            &ctx.workdir,
+            &ctx.path("build/final_mv.mp4"),
+            &shots,
+            poster_t,
+            &spec,
+        )
+        .await
+        .map_err(anyhow::Error::msg)?;
+        let outputs = std::iter::once(&report.poster)
+            .chain(&report.shots)
+            .chain(&report.contact_sheet)
+            .chain(&report.preview)
+            .map(|t| Path::new(".").join(&t.path))
+            .collect();
+        Ok(StageOutcome {
+            outputs,
+            ..Default::default()
+        }
+        .meta("poster", serde_json::json!(report.poster))
+        .meta("shots", serde_json::json!(report.shots.len()))
+        .artifact("video.thumbnails", serde_json::json!(report)))
+    }
+}
+
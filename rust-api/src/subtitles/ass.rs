@@ -1,38 +1,78 @@
+use crate::subtitles::model::{parse_clock, Cue, KaraokeUnit, DEFAULT_STYLE};
+use anyhow::{bail, Result};
 use std::path::Path;
 
+/// `h:mm:ss.cc`, rounded to whole centiseconds before splitting so `cc` never reaches 100.
 fn ass_time(t: f64) -> String {
-    let mut x = t;
-    if x < 0.0 {
-        x = 0.0;
-    }
-    let h = (x / 3600.0).floor() as i64;
-    x -= (h as f64) * 3600.0;
-    let m = (x / 60.0).floor() as i64;
-    x -= (m as f64) * 60.0;
-    let s = x.floor() as i64;
-    let cs = ((x - (s as f64)) * 100.0).round() as i64;
+    let total = (t.max(0.0) * 100.0).round() as u64;
+    let (h, m, s, cs) = (total / 360_000, total / 6000 % 60, total / 100 % 60, total % 100);
     format!("{h}:{m:02}:{s:02}.{cs:02}")
 }
 
-pub fn write_ass(path: &Path, lines: &[String], duration_s: f64) -> std::io::Result<()> {
-    let n = lines.len().max(1);
-    let step = (duration_s / (n as f64)).max(0.6);
+/// Script resolution, font size and margins of the default style for one output frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AssLayout {
+    pub play_res_x: u32,
+    pub play_res_y: u32,
+    pub font_size: u32,
+    pub margin_lr: u32,
+    pub margin_v: u32,
+    /// Narrow frames use smart wrapping instead of a single long line.
+    pub wrap_style: u8,
+}
+
+impl AssLayout {
+    /// Sizes follow the short edge so text keeps its proportion across aspect ratios; on
+    /// portrait frames captions sit above the bottom fifth that platform UI covers.
+    pub fn for_frame(w: u32, h: u32) -> Self {
+        let short = w.min(h).max(1);
+        let portrait = h > w;
+        Self {
+            play_res_x: w,
+            play_res_y: h,
+            font_size: short / 15,
+            margin_lr: if portrait { w / 12 } else { w * 3 / 64 },
+            margin_v: if portrait { h / 5 } else { short / 15 },
+            wrap_style: if portrait || w == h { 0 } else { 2 },
+        }
+    }
+}
+
+impl Default for AssLayout {
+    fn default() -> Self {
+        Self::for_frame(1280, 720)
+    }
+}
+
+fn ass_header(layout: &AssLayout) -> String {
     let mut out = String::new();
     out.push_str("[Script Info]\n");
     out.push_str("ScriptType: v4.00+\n");
-    out.push_str("WrapStyle: 2\n");
+    out.push_str(&format!("PlayResX: {}\n", layout.play_res_x));
+    out.push_str(&format!("PlayResY: {}\n", layout.play_res_y));
+    out.push_str(&format!("WrapStyle: {}\n", layout.wrap_style));
     out.push_str("ScaledBorderAndShadow: yes\n");
     out.push('\n');
     out.push_str("[V4+ Styles]\n");
     out.push_str("Format: Name,Fontname,Fontsize,PrimaryColour,SecondaryColour,OutlineColour,BackColour,Bold,Italic,Underline,StrikeOut,ScaleX,ScaleY,Spacing,Angle,BorderStyle,Outline,Shadow,Alignment,MarginL,MarginR,MarginV,Encoding\n");
-    out.push_str("Style: Default,Arial,48,&H00FFFFFF,&H000000FF,&H80000000,&H80000000,0,0,0,0,100,100,0,0,1,2.5,0.8,2,60,60,48,1\n");
+    out.push_str(&format!(
+        "Style: Default,Arial,{},&H00FFFFFF,&H000000FF,&H80000000,&H80000000,0,0,0,0,100,100,0,0,1,2.5,0.8,2,{},{},{},1\n",
+        layout.font_size, layout.margin_lr, layout.margin_lr, layout.margin_v
+    ));
     out.push('\n');
     out.push_str("[Events]\n");
     out.push_str("Format: Layer,Start,End,Style,Name,MarginL,MarginR,MarginV,Effect,Text\n");
+    out
+}
+
+pub fn write_ass(path: &Path, lines: &[String], duration_s: f64) -> std::io::Result<()> {
+    let n = lines.len().max(1);
+    let step = (duration_s / (n as f64)).max(0.6);
+    let mut out = ass_header(&AssLayout::default());
     for (i, line) in lines.iter().enumerate() {
         let t0 = (i as f64) * step;
         let t1 = ((i as f64) * step + step).min(duration_s.max(t0 + 0.6));
-        let text = line.replace('\n', " ").replace('\r', " ");
+        let text = line.replace(['\n', '\r'], " ");
         out.push_str(&format!(
             "Dialogue: 0,{},{},Default,,0,0,0,,{}\n",
             ass_time(t0),
@@ -44,6 +84,127 @@ pub fn write_ass(path: &Path, lines: &[String], duration_s: f64) -> std::io::Res
     std::fs::write(path, out.as_bytes())
 }
 
+fn escape_ass_text(s: &str) -> String {
+    s.replace('\r', "")
+        .replace('{', "\\{")
+        .replace('}', "\\}")
+        .replace('\n', "\\N")
+}
+
+/// One Dialogue event per cue. Karaoke cues carry `\k` tags so players highlight word by word
+/// (SecondaryColour is the not-yet-sung colour).
+pub fn render_ass(cues: &[Cue]) -> String {
+    render_ass_layout(cues, &AssLayout::default())
+}
+
+pub fn render_ass_layout(cues: &[Cue], layout: &AssLayout) -> String {
+    let mut out = ass_header(layout);
+    for cue in cues {
+        let text = if cue.karaoke.is_empty() {
+            escape_ass_text(&cue.text)
+        } else {
+            cue.karaoke
+                .iter()
+                .map(|u| format!("{{\\k{}}}{}", u.cs, escape_ass_text(&u.text)))
+                .collect()
+        };
+        out.push_str(&format!(
+            "Dialogue: 0,{},{},{},,0,0,0,,{}\n",
+            ass_time(cue.start_s),
+            ass_time(cue.end_s),
+            cue.style,
+            text
+        ));
+    }
+    out
+}
+
+/// Splits an event text into plain text and karaoke units; other override blocks are dropped.
+fn parse_ass_text(raw: &str) -> (String, Vec<KaraokeUnit>) {
+    let mut text = String::new();
+    let mut units: Vec<KaraokeUnit> = Vec::new();
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        let piece = match c {
+            '\\' => match chars.next() {
+                Some('N') | Some('n') => "\n".to_string(),
+                Some('h') => "\u{a0}".to_string(),
+                Some(other @ ('{' | '}')) => other.to_string(),
+                Some(other) => format!("\\{other}"),
+                None => "\\".to_string(),
+            },
+            '{' => {
+                let block: String = chars.by_ref().take_while(|&b| b != '}').collect();
+                for tag in block.split('\\').filter(|t| !t.is_empty()) {
+                    let digits = tag
+                        .strip_prefix("kf")
+                        .or_else(|| tag.strip_prefix("ko"))
+                        .or_else(|| tag.strip_prefix('k'))
+                        .or_else(|| tag.strip_prefix('K'));
+                    if let Some(cs) = digits.and_then(|d| d.trim().parse::<u32>().ok()) {
+                        units.push(KaraokeUnit { text: String::new(), cs });
+                    }
+                }
+                continue;
+            }
+            other => other.to_string(),
+        };
+        text.push_str(&piece);
+        if let Some(u) = units.last_mut() {
+            u.text.push_str(&piece);
+        }
+    }
+    (text, units)
+}
+
+pub fn parse_ass(s: &str) -> Result<Vec<Cue>> {
+    let mut fields: Vec<String> = "Layer,Start,End,Style,Name,MarginL,MarginR,MarginV,Effect,Text"
+        .split(',')
+        .map(|x| x.to_string())
+        .collect();
+    let mut in_events = false;
+    let mut cues = Vec::new();
+    for line in s.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim_end_matches('\r');
+        if line.starts_with('[') {
+            in_events = line.eq_ignore_ascii_case("[Events]");
+            continue;
+        }
+        if !in_events {
+            continue;
+        }
+        if let Some(fmt) = line.strip_prefix("Format:") {
+            fields = fmt.split(',').map(|x| x.trim().to_string()).collect();
+            continue;
+        }
+        let Some(body) = line.strip_prefix("Dialogue:") else {
+            continue;
+        };
+        let values: Vec<&str> = body.trim_start().splitn(fields.len(), ',').collect();
+        let get = |name: &str| {
+            fields
+                .iter()
+                .position(|f| f.eq_ignore_ascii_case(name))
+                .and_then(|i| values.get(i).copied())
+        };
+        let (Some(start), Some(end)) = (
+            get("Start").and_then(|v| parse_clock(v, '.')),
+            get("End").and_then(|v| parse_clock(v, '.')),
+        ) else {
+            bail!("bad ass dialogue timing: {line:?}");
+        };
+        let (text, karaoke) = parse_ass_text(get("Text").unwrap_or_default());
+        cues.push(Cue {
+            start_s: start,
+            end_s: end,
+            text,
+            style: get("Style").unwrap_or(DEFAULT_STYLE).trim().to_string(),
+            karaoke,
+        });
+    }
+    Ok(cues)
+}
+
 pub fn write_ass_minimal(out: &Path, text: &str, duration_s: f64) -> Result<(), String> {
     let dir = out.parent().ok_or_else(|| "no parent".to_string())?;
     std::fs::create_dir_all(dir).map_err(|e| format!("{e}"))?;
@@ -0,0 +1,77 @@
+/// Lowercases a BCP 47 tag and uses `-` as the separator (`pt_BR` -> `pt-br`).
+pub fn normalize_lang(tag: &str) -> String {
+    tag.trim().replace('_', "-").to_ascii_lowercase()
+}
+
+/// Whether a normalized tag looks like BCP 47 (`[a-z0-9-]{1,35}`); tags name files, so
+/// nothing else is accepted.
+pub fn valid_lang(tag: &str) -> bool {
+    (1..=35).contains(&tag.len())
+        && tag
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+}
+
+/// Primary subtag: `zh-hant` -> `zh`.
+pub fn base_lang(tag: &str) -> &str {
+    tag.split('-').next().unwrap_or(tag)
+}
+
+/// ISO 639-2/B code written into container `language` metadata; `und` when unknown.
+pub fn iso639_2(tag: &str) -> &'static str {
+    match base_lang(&normalize_lang(tag)) {
+        "en" => "eng",
+        "es" => "spa",
+        "fr" => "fre",
+        "de" => "ger",
+        "it" => "ita",
+        "pt" => "por",
+        "nl" => "dut",
+        "ru" => "rus",
+        "uk" => "ukr",
+        "pl" => "pol",
+        "tr" => "tur",
+        "ar" => "ara",
+        "he" => "heb",
+        "hi" => "hin",
+        "th" => "tha",
+        "vi" => "vie",
+        "id" => "ind",
+        "ja" => "jpn",
+        "ko" => "kor",
+        "zh" => "chi",
+        _ => "und",
+    }
+}
+
+/// Requested subtitle languages from `commands.video.subtitles.langs` (`"en,es"` or
+/// `["en","es"]`). Falls back to `ui_lang`; `auto` means the lyrics' own language.
+/// Errors on the first requested tag that is not BCP 47.
+pub fn langs_from_value(
+    v: Option<&serde_json::Value>,
+    ui_lang: &str,
+) -> Result<Vec<String>, String> {
+    let requested: Vec<&str> = match v {
+        Some(serde_json::Value::String(s)) => s.split([',', '+', ' ']).collect(),
+        Some(serde_json::Value::Array(a)) => a.iter().filter_map(|x| x.as_str()).collect(),
+        _ => Vec::new(),
+    };
+    let mut out: Vec<String> = Vec::new();
+    for tag in requested.into_iter().map(normalize_lang) {
+        if tag.is_empty() || out.contains(&tag) {
+            continue;
+        }
+        if !valid_lang(&tag) {
+            return Err(format!("subtitle language {tag:?} is not a BCP 47 tag"));
+        }
+        out.push(tag);
+    }
+    if out.is_empty() {
+        out.push(normalize_lang(ui_lang));
+        out.retain(|s| valid_lang(s));
+    }
+    if out.is_empty() {
+        out.push("auto".to_string());
+    }
+    Ok(out)
+}
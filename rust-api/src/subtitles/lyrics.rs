@@ -0,0 +1,305 @@
+use anyhow::{Context, Result};
+use crate::subtitles::lang::{base_lang, normalize_lang};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::Path};
+
+const MIN_LINE_S: f64 = 0.6;
+const LINE_GAP_S: f64 = 0.05;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LyricWord {
+    pub text: String,
+    #[serde(default, alias = "start")]
+    pub start_s: Option<f64>,
+    #[serde(default, alias = "end")]
+    pub end_s: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LyricLine {
+    pub text: String,
+    #[serde(default, alias = "start")]
+    pub start_s: Option<f64>,
+    #[serde(default, alias = "end")]
+    pub end_s: Option<f64>,
+    #[serde(default)]
+    pub words: Vec<LyricWord>,
+    /// Translated text keyed by language tag.
+    #[serde(default)]
+    pub translations: BTreeMap<String, String>,
+}
+
+impl LyricLine {
+    pub fn is_timed(&self) -> bool {
+        matches!((self.start_s, self.end_s), (Some(a), Some(b)) if b > a)
+    }
+
+    /// Translation for `lang`, trying the exact tag before its primary subtag.
+    pub fn translation(&self, lang: &str) -> Option<&str> {
+        let lang = normalize_lang(lang);
+        self.translations
+            .iter()
+            .find(|(k, _)| normalize_lang(k) == lang)
+            .or_else(|| {
+                self.translations
+                    .iter()
+                    .find(|(k, _)| normalize_lang(k) == base_lang(&lang))
+            })
+            .map(|(_, v)| v.as_str())
+            .filter(|v| !v.trim().is_empty())
+    }
+
+    fn words_timed(&self) -> bool {
+        !self.words.is_empty()
+            && self
+                .words
+                .iter()
+                .all(|w| matches!((w.start_s, w.end_s), (Some(a), Some(b)) if b >= a))
+    }
+}
+
+/// `lyrics.json` lines may be plain strings or objects carrying timestamps.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum RawLine {
+    Text(String),
+    Timed(LyricLine),
+}
+
+/// Lines may also be translated in bulk with a top-level `translations` object mapping a
+/// language tag to an array aligned with `lines`.
+pub fn parse_lyric_lines(v: &serde_json::Value) -> Vec<LyricLine> {
+    let Some(arr) = v.get("lines").and_then(|x| x.as_array()) else {
+        return Vec::new();
+    };
+    let bulk = v.get("translations").and_then(|x| x.as_object());
+    arr.iter()
+        .enumerate()
+        .filter_map(|(i, x)| {
+            let mut line = match serde_json::from_value::<RawLine>(x.clone()).ok()? {
+                RawLine::Text(text) => LyricLine {
+                    text,
+                    ..Default::default()
+                },
+                RawLine::Timed(line) => line,
+            };
+            for (lang, texts) in bulk.into_iter().flatten() {
+                if let Some(t) = texts.get(i).and_then(|t| t.as_str()) {
+                    line.translations
+                        .entry(lang.clone())
+                        .or_insert_with(|| t.to_string());
+                }
+            }
+            Some(line)
+        })
+        .filter(|l| !l.text.trim().is_empty())
+        .collect()
+}
+
+/// Language of the original lines (`lang` in `lyrics.json`).
+pub fn lyrics_lang(v: &serde_json::Value) -> Option<String> {
+    v.get("lang")
+        .and_then(|x| x.as_str())
+        .map(normalize_lang)
+        .filter(|s| !s.is_empty() && s != "auto")
+}
+
+/// Source language and lines of a `lyrics.json`.
+pub fn load_lyrics(path: &Path) -> Result<(Option<String>, Vec<LyricLine>)> {
+    let s = fs::read_to_string(path).with_context(|| format!("read lyrics: {}", path.display()))?;
+    let v: serde_json::Value = serde_json::from_str(&s)?;
+    Ok((lyrics_lang(&v), parse_lyric_lines(&v)))
+}
+
+/// Onsets that follow at least `min_gap_s` of silence are taken as the start of a sung phrase.
+pub fn vocal_phrase_starts(onsets: &[f64], min_gap_s: f64) -> Vec<f64> {
+    let mut out = Vec::new();
+    let mut prev: Option<f64> = None;
+    for &t in onsets {
+        if prev.map(|p| t - p >= min_gap_s).unwrap_or(true) {
+            out.push(t);
+        }
+        prev = Some(t);
+    }
+    out
+}
+
+/// Gives every untimed line a start/end. Each run of untimed lines is spread evenly over the
+/// gap between its timed neighbours, and each start is then pulled to the nearest unused
+/// phrase start within half a slot.
+pub fn align_untimed(lines: &mut [LyricLine], phrase_starts: &[f64], duration_s: f64) {
+    let mut i = 0;
+    while i < lines.len() {
+        if lines[i].is_timed() {
+            i += 1;
+            continue;
+        }
+        let run_start = i;
+        while i < lines.len() && !lines[i].is_timed() {
+            i += 1;
+        }
+        let run_end = i;
+
+        let gap_start = run_start
+            .checked_sub(1)
+            .and_then(|p| lines[p].end_s)
+            .unwrap_or(0.0);
+        let gap_end = lines
+            .get(run_end)
+            .and_then(|l| l.start_s)
+            .unwrap_or(duration_s)
+            .max(gap_start + MIN_LINE_S * (run_end - run_start) as f64);
+
+        let n = run_end - run_start;
+        let slot = (gap_end - gap_start) / n as f64;
+        let mut starts = Vec::with_capacity(n);
+        let mut cursor = 0usize;
+        for k in 0..n {
+            let target = gap_start + slot * k as f64;
+            let floor = starts.last().map(|&s: &f64| s + MIN_LINE_S).unwrap_or(gap_start);
+            let pick = phrase_starts
+                .iter()
+                .enumerate()
+                .skip(cursor)
+                .filter(|(_, &t)| t >= floor && (t - target).abs() <= slot / 2.0)
+                .min_by(|a, b| (a.1 - target).abs().total_cmp(&(b.1 - target).abs()));
+            match pick {
+                Some((idx, &t)) => {
+                    starts.push(t);
+                    cursor = idx + 1;
+                }
+                None => starts.push(target.max(floor)),
+            }
+        }
+
+        for k in 0..n {
+            let start = starts[k];
+            let next = starts.get(k + 1).copied().unwrap_or(gap_end);
+            let end = (next - LINE_GAP_S).max(start + MIN_LINE_S);
+            let line = &mut lines[run_start + k];
+            line.start_s = Some(start);
+            line.end_s = Some(end);
+        }
+    }
+}
+
+fn syllable_weight(word: &str) -> u32 {
+    let mut groups = 0u32;
+    let mut in_vowel = false;
+    for c in word.chars() {
+        if !c.is_ascii() && c.is_alphabetic() {
+            // CJK and other scripts: one sung syllable per character.
+            groups += 1;
+            in_vowel = false;
+            continue;
+        }
+        let v = matches!(c.to_ascii_lowercase(), 'a' | 'e' | 'i' | 'o' | 'u' | 'y');
+        if v && !in_vowel {
+            groups += 1;
+        }
+        in_vowel = v;
+    }
+    groups.max(1)
+}
+
+/// Units keep their trailing separator so concatenating them reproduces the line.
+fn split_units(text: &str) -> Vec<String> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    if words.len() == 1 && words[0].chars().all(|c| !c.is_ascii()) {
+        return words[0].chars().map(|c| c.to_string()).collect();
+    }
+    let last = words.len().saturating_sub(1);
+    words
+        .into_iter()
+        .enumerate()
+        .map(|(i, w)| if i < last { format!("{w} ") } else { w.to_string() })
+        .collect()
+}
+
+/// Karaoke units for a timed line as (text, centiseconds). Word timings are used when every
+/// word has them; otherwise the line duration is split by estimated syllable count.
+pub fn karaoke_units(line: &LyricLine) -> Vec<(String, u32)> {
+    let (Some(start), Some(end)) = (line.start_s, line.end_s) else {
+        return vec![(line.text.clone(), 0)];
+    };
+    let total_cs = ((end - start).max(0.0) * 100.0).round() as u32;
+
+    if line.words_timed() {
+        let mut out = Vec::with_capacity(line.words.len());
+        let mut cursor = start;
+        for (i, w) in line.words.iter().enumerate() {
+            let w_end = line
+                .words
+                .get(i + 1)
+                .and_then(|n| n.start_s)
+                .unwrap_or(end)
+                .max(w.end_s.unwrap_or(cursor));
+            let cs = ((w_end - cursor).max(0.0) * 100.0).round() as u32;
+            let sep = if i + 1 < line.words.len() { " " } else { "" };
+            out.push((format!("{}{}", w.text, sep), cs));
+            cursor = w_end;
+        }
+        return out;
+    }
+
+    let units = split_units(&line.text);
+    if units.is_empty() {
+        return Vec::new();
+    }
+    let weights: Vec<u32> = units.iter().map(|u| syllable_weight(u)).collect();
+    let sum: u32 = weights.iter().sum();
+    let n = units.len();
+    let mut out = Vec::with_capacity(n);
+    let mut used = 0u32;
+    for (k, (u, w)) in units.into_iter().zip(weights).enumerate() {
+        let cs = if k + 1 == n {
+            total_cs.saturating_sub(used)
+        } else {
+            total_cs * w / sum.max(1)
+        };
+        used += cs;
+        out.push((u, cs));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn untimed_lines_fill_the_gap_at_phrase_starts() {
+        let v = json!({
+            "lines": [{"text": "a", "start": 0.0, "end": 2.0}, "b", "", "c",
+                      {"text": "d", "start": 8.0, "end": 10.0}],
+            "translations": {"fr": ["A", "B", "", "C", "D"]}
+        });
+        let mut lines = parse_lyric_lines(&v);
+        let texts: Vec<_> = lines.iter().map(|l| l.text.as_str()).collect();
+        assert_eq!(texts, ["a", "b", "c", "d"]);
+        assert_eq!(lines[2].translation("fr-CA"), Some("C"));
+
+        align_untimed(&mut lines, &vocal_phrase_starts(&[2.2, 2.4, 4.6, 9.0], 0.5), 10.0);
+        let times: Vec<_> = lines.iter().map(|l| (l.start_s.unwrap(), l.end_s.unwrap())).collect();
+        assert_eq!(times[1].0, 2.2);
+        assert_eq!(times[2].0, 4.6);
+        assert!((times[1].1 - (4.6 - LINE_GAP_S)).abs() < 1e-9);
+        assert!((times[2].1 - (8.0 - LINE_GAP_S)).abs() < 1e-9);
+        assert_eq!(times[3], (8.0, 10.0));
+    }
+
+    #[test]
+    fn karaoke_units_split_by_syllables_and_fill_the_line() {
+        let line = LyricLine {
+            text: "hello world".to_string(),
+            start_s: Some(1.0),
+            end_s: Some(2.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            karaoke_units(&line),
+            vec![("hello ".to_string(), 66), ("world".to_string(), 34)]
+        );
+    }
+}
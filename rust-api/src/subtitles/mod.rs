@@ -1,5 +1,17 @@
 pub mod ass;
+pub mod lang;
+pub mod lyrics;
+pub mod model;
+pub mod srt;
+pub mod translate;
+pub mod vtt;
+
+use crate::audio::{beats::onset_times, wav::decode_wav_mono};
+use lyrics::LyricLine;
+use model::{Cue, SubtitleFormat};
+use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
+use translate::Translator;
 
 fn env_f64(k: &str, d: f64) -> f64 {
     std::env::var(k)
@@ -8,25 +20,141 @@ fn env_f64(k: &str, d: f64) -> f64 {
         .unwrap_or(d)
 }
 
-pub fn ensure_ass_from_state(out_dir: &Path, cssl: &str) -> std::io::Result<PathBuf> {
-    let burnin = std::env::var("CSS_SUBTITLES_BURNIN").unwrap_or_else(|_| "0".to_string());
-    if burnin != "0" {
-        return Ok(out_dir.join("build/subtitles.ass"));
+const PHRASE_GAP_S: f64 = 0.35;
+
+pub fn render_cues(cues: &[Cue], format: SubtitleFormat) -> String {
+    match format {
+        SubtitleFormat::Ass => ass::render_ass(cues),
+        SubtitleFormat::Srt => srt::render_srt(cues),
+        SubtitleFormat::Vtt => vtt::render_vtt(cues),
     }
-    let duration_s = env_f64("CSS_VIDEO_DURATION_S", 12.0);
-    let mut lines = Vec::<String>::new();
-    for s in cssl.split('\n') {
-        let t = s.trim();
-        if !t.is_empty() {
-            lines.push(t.to_string());
-        }
+}
+
+pub fn parse_cues(s: &str, format: SubtitleFormat) -> anyhow::Result<Vec<Cue>> {
+    match format {
+        SubtitleFormat::Ass => ass::parse_ass(s),
+        SubtitleFormat::Srt => srt::parse_srt(s),
+        SubtitleFormat::Vtt => vtt::parse_vtt(s),
     }
-    if lines.is_empty() {
-        lines.push(String::new());
+}
+
+pub fn write_cues(path: &Path, cues: &[Cue], format: SubtitleFormat) -> std::io::Result<()> {
+    std::fs::create_dir_all(path.parent().unwrap_or_else(|| Path::new(".")))?;
+    std::fs::write(path, render_cues(cues, format).as_bytes())
+}
+
+/// Lines from `build/lyrics.json` with their source language. Lines with timestamps keep
+/// them; the rest are aligned against phrase onsets detected in `build/vocals.wav`.
+pub fn aligned_lines_from_state(out_dir: &Path) -> (Option<String>, Vec<LyricLine>) {
+    let (source_lang, mut lines) =
+        lyrics::load_lyrics(&out_dir.join("build/lyrics.json")).unwrap_or_default();
+    let vocals = decode_wav_mono(&out_dir.join("build/vocals.wav")).ok();
+
+    let timed_end = lines
+        .iter()
+        .filter_map(|l| l.end_s)
+        .fold(0.0f64, f64::max);
+    let duration_s = vocals
+        .as_ref()
+        .map(|p| p.info.duration_s())
+        .filter(|d| *d > 0.0)
+        .unwrap_or_else(|| env_f64("CSS_VIDEO_DURATION_S", 12.0))
+        .max(timed_end);
+
+    if lines.iter().any(|l| !l.is_timed()) {
+        let onsets = vocals.as_ref().map(onset_times).unwrap_or_default();
+        let phrase_starts = lyrics::vocal_phrase_starts(&onsets, PHRASE_GAP_S);
+        lyrics::align_untimed(&mut lines, &phrase_starts, duration_s);
     }
-    let path = out_dir.join("build/subtitles.ass");
-    ass::write_ass(&path, &lines, duration_s)?;
-    Ok(path)
+    (source_lang, lines)
+}
+
+/// One language/format file under `build/subtitles/`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubtitleTrack {
+    pub lang: String,
+    pub format: SubtitleFormat,
+    pub path: PathBuf,
+    /// Lines left in the source language because no translation was available.
+    pub untranslated: usize,
+}
+
+/// Lines re-texted for `lang`: `lyrics.json` translations first, then the translator, then
+/// the source text. Timings are kept; word timings only apply to the source text.
+pub fn translate_lines(
+    lines: &[LyricLine],
+    lang: &str,
+    source_lang: &str,
+    translator: Option<&dyn Translator>,
+) -> (Vec<LyricLine>, usize) {
+    if lang::normalize_lang(lang) == lang::normalize_lang(source_lang) {
+        return (lines.to_vec(), 0);
+    }
+    let mut untranslated = 0;
+    let out = lines
+        .iter()
+        .map(|l| {
+            let text = l
+                .translation(lang)
+                .map(str::to_string)
+                .or_else(|| translator.and_then(|t| t.translate(&l.text, source_lang, lang)));
+            match text {
+                Some(text) => LyricLine {
+                    text,
+                    words: Vec::new(),
+                    ..l.clone()
+                },
+                None => {
+                    untranslated += 1;
+                    l.clone()
+                }
+            }
+        })
+        .collect();
+    (out, untranslated)
+}
+
+/// Writes `build/subtitles/<lang>.<ext>` for every language and format. The first language is
+/// also written to `build/subtitles.<ext>`. `auto` resolves to the lyrics' own language.
+pub fn ensure_subtitle_tracks(
+    out_dir: &Path,
+    langs: &[String],
+    formats: &[SubtitleFormat],
+    translator: Option<&dyn Translator>,
+) -> anyhow::Result<Vec<SubtitleTrack>> {
+    let (source_lang, lines) = aligned_lines_from_state(out_dir);
+    let source_lang = source_lang
+        .map(|l| lang::normalize_lang(&l))
+        .filter(|l| lang::valid_lang(l))
+        .unwrap_or_else(|| "und".to_string());
+    let mut tracks = Vec::new();
+    for (i, requested) in langs.iter().enumerate() {
+        let lang = if requested == "auto" {
+            source_lang.clone()
+        } else {
+            lang::normalize_lang(requested)
+        };
+        anyhow::ensure!(lang::valid_lang(&lang), "invalid subtitle language {lang:?}");
+        if tracks.iter().any(|t: &SubtitleTrack| t.lang == lang) {
+            continue;
+        }
+        let (translated, untranslated) = translate_lines(&lines, &lang, &source_lang, translator);
+        let cues = model::cues_from_lyrics(&translated);
+        for f in formats {
+            let path = out_dir.join(format!("build/subtitles/{lang}.{}", f.ext()));
+            write_cues(&path, &cues, *f)?;
+            if i == 0 {
+                write_cues(&out_dir.join(format!("build/subtitles.{}", f.ext())), &cues, *f)?;
+            }
+            tracks.push(SubtitleTrack {
+                lang: lang.clone(),
+                format: *f,
+                path,
+                untranslated,
+            });
+        }
+    }
+    Ok(tracks)
 }
 
 pub fn write_ass_stub(run_dir: &Path) -> anyhow::Result<PathBuf> {
@@ -52,3 +180,162 @@ Dialogue: 0,0:00:00.00,0:00:02.00,Default,,0,0,0,,cssMV demo subtitles
     crate::run_state_io::atomic_write_text(&path, s)?;
     Ok(path)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::model::{Cue, KaraokeUnit, SubtitleFormat};
+    use super::{parse_cues, render_cues};
+
+    fn sample() -> Vec<Cue> {
+        let mut styled = Cue::new(3.25, 5.5, "second line\nwith a break");
+        styled.style = "Chorus".to_string();
+        vec![
+            Cue::new(0.0, 2.5, "plain {braces} & <angles> --> arrows"),
+            styled,
+            Cue {
+                start_s: 61.1,
+                end_s: 3725.37,
+                text: "sing it now".to_string(),
+                style: "Default".to_string(),
+                karaoke: vec![
+                    KaraokeUnit { text: "sing ".to_string(), cs: 40 },
+                    KaraokeUnit { text: "it ".to_string(), cs: 25 },
+                    KaraokeUnit { text: "now".to_string(), cs: 366362 },
+                ],
+            },
+        ]
+    }
+
+    fn assert_close(a: &[Cue], b: &[Cue], tol: f64, styles: bool, karaoke: bool) {
+        assert_eq!(a.len(), b.len());
+        for (x, y) in a.iter().zip(b) {
+            assert!((x.start_s - y.start_s).abs() <= tol, "{} vs {}", x.start_s, y.start_s);
+            assert!((x.end_s - y.end_s).abs() <= tol, "{} vs {}", x.end_s, y.end_s);
+            assert_eq!(x.text, y.text);
+            if styles {
+                assert_eq!(x.style, y.style);
+            }
+            if karaoke {
+                assert_eq!(x.karaoke, y.karaoke);
+            }
+        }
+    }
+
+    #[test]
+    fn ass_round_trip() {
+        let cues = sample();
+        let parsed = parse_cues(&render_cues(&cues, SubtitleFormat::Ass), SubtitleFormat::Ass).unwrap();
+        assert_close(&cues, &parsed, 0.005, true, true);
+    }
+
+    #[test]
+    fn ass_times_round_into_the_next_second() {
+        let cues = vec![Cue::new(0.0, 2.997, "almost three"), Cue::new(59.999, 3599.996, "x")];
+        let s = render_cues(&cues, SubtitleFormat::Ass);
+        assert!(s.contains("0:00:03.00") && s.contains("0:01:00.00") && s.contains("1:00:00.00"));
+        let parsed = parse_cues(&s, SubtitleFormat::Ass).unwrap();
+        assert_close(&cues, &parsed, 0.005, false, false);
+    }
+
+    #[test]
+    fn srt_round_trip() {
+        let cues = sample();
+        let s = render_cues(&cues, SubtitleFormat::Srt);
+        assert!(s.contains("01:02:05,370"));
+        let parsed = parse_cues(&s, SubtitleFormat::Srt).unwrap();
+        assert_close(&cues, &parsed, 0.0005, false, false);
+    }
+
+    #[test]
+    fn vtt_round_trip() {
+        let cues = sample();
+        let s = render_cues(&cues, SubtitleFormat::Vtt);
+        assert!(s.starts_with("WEBVTT\n"));
+        assert!(s.contains("--&gt; arrows"));
+        assert!(s.contains("01:02:05.370"));
+        let parsed = parse_cues(&s, SubtitleFormat::Vtt).unwrap();
+        assert_close(&cues, &parsed, 0.0005, true, true);
+    }
+
+    #[test]
+    fn format_list_parsing() {
+        let v = serde_json::json!("ass, vtt");
+        assert_eq!(
+            SubtitleFormat::list_from_value(Some(&v)),
+            vec![SubtitleFormat::Ass, SubtitleFormat::Vtt]
+        );
+        let v = serde_json::json!(["srt", "srt", "webvtt"]);
+        assert_eq!(
+            SubtitleFormat::list_from_value(Some(&v)),
+            vec![SubtitleFormat::Srt, SubtitleFormat::Vtt]
+        );
+        assert_eq!(SubtitleFormat::list_from_value(None), vec![SubtitleFormat::Ass]);
+    }
+
+    #[test]
+    fn translations_from_lyrics_then_dictionary() {
+        use super::lyrics::parse_lyric_lines;
+        use super::translate::DictionaryTranslator;
+        use super::translate_lines;
+
+        let v = serde_json::json!({
+            "lang": "en",
+            "lines": [
+                {"text": "hello world", "start_s": 0.0, "end_s": 2.0,
+                 "translations": {"es": "hola mundo"}},
+                {"text": "good night", "start_s": 2.0, "end_s": 4.0},
+                {"text": "stay", "start_s": 4.0, "end_s": 5.0}
+            ],
+            "translations": {"fr": ["bonjour le monde", "bonne nuit"]}
+        });
+        let lines = parse_lyric_lines(&v);
+        let mut dict = DictionaryTranslator::new();
+        dict.insert("es", "night", "noche");
+
+        let (es, missing) = translate_lines(&lines, "es-MX", "en", Some(&dict));
+        assert_eq!(es[0].text, "hola mundo");
+        assert_eq!(es[1].text, "good noche");
+        assert_eq!(es[2].text, "stay");
+        assert_eq!(missing, 1);
+        assert_eq!(es[1].start_s, Some(2.0));
+
+        let (fr, missing) = translate_lines(&lines, "fr", "en", None);
+        assert_eq!(fr[0].text, "bonjour le monde");
+        assert_eq!(fr[1].text, "bonne nuit");
+        assert_eq!(missing, 1);
+
+        let (en, missing) = translate_lines(&lines, "EN", "en", Some(&dict));
+        assert_eq!(en[1].text, "good night");
+        assert_eq!(missing, 0);
+    }
+
+    #[test]
+    fn reads_structured_lyrics_json() {
+        use super::lyrics::parse_lyric_lines;
+        use crate::lyrics::model::parse_lyrics_text;
+
+        let mut l = parse_lyrics_text("[Verse]\nfirst line\nsecond line\n[Chorus]\nhook", "t", "en", "http");
+        l.apply_beat_timing(120.0, 1.0, 4);
+        let lines = parse_lyric_lines(&serde_json::to_value(&l).unwrap());
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[2].text, "hook");
+        assert_eq!(lines[0].start_s, Some(1.0));
+        assert!(lines.iter().all(|x| x.is_timed()));
+    }
+
+    #[test]
+    fn lang_list_and_tags() {
+        use super::lang::{iso639_2, langs_from_value};
+        let v = serde_json::json!(["en", "pt_BR", "EN"]);
+        assert_eq!(langs_from_value(Some(&v), "auto").unwrap(), vec!["en", "pt-br"]);
+        assert_eq!(langs_from_value(None, "ja").unwrap(), vec!["ja"]);
+        assert_eq!(langs_from_value(None, "").unwrap(), vec!["auto"]);
+        assert_eq!(langs_from_value(None, "../x").unwrap(), vec!["auto"]);
+        for bad in ["../../etc/passwd", "en/x", "zh.hans", &"a".repeat(36)] {
+            let v = serde_json::json!([bad]);
+            assert!(langs_from_value(Some(&v), "en").is_err(), "{bad}");
+        }
+        assert_eq!(iso639_2("pt-br"), "por");
+        assert_eq!(iso639_2("xx"), "und");
+    }
+}
@@ -0,0 +1,129 @@
+use crate::subtitles::lyrics::{karaoke_units, LyricLine};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_STYLE: &str = "Default";
+
+/// A karaoke segment: text sung for `cs` centiseconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KaraokeUnit {
+    pub text: String,
+    pub cs: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cue {
+    pub start_s: f64,
+    pub end_s: f64,
+    /// Plain text; lines are separated by `\n`. Formats apply their own escaping.
+    pub text: String,
+    pub style: String,
+    #[serde(default)]
+    pub karaoke: Vec<KaraokeUnit>,
+}
+
+impl Cue {
+    pub fn new(start_s: f64, end_s: f64, text: impl Into<String>) -> Self {
+        Self {
+            start_s,
+            end_s,
+            text: text.into(),
+            style: DEFAULT_STYLE.to_string(),
+            karaoke: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubtitleFormat {
+    Ass,
+    Srt,
+    Vtt,
+}
+
+impl SubtitleFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "ass" | "ssa" => Some(Self::Ass),
+            "srt" => Some(Self::Srt),
+            "vtt" | "webvtt" => Some(Self::Vtt),
+            _ => None,
+        }
+    }
+
+    pub fn ext(&self) -> &'static str {
+        match self {
+            Self::Ass => "ass",
+            Self::Srt => "srt",
+            Self::Vtt => "vtt",
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            Self::Ass => "text/x-ssa",
+            Self::Srt => "application/x-subrip",
+            Self::Vtt => "text/vtt",
+        }
+    }
+
+    /// Accepts `"ass"`, `"ass,srt"` or `["ass","vtt"]`; falls back to ASS only.
+    pub fn list_from_value(v: Option<&serde_json::Value>) -> Vec<Self> {
+        let mut out: Vec<Self> = Vec::new();
+        let mut push = |s: &str| {
+            if let Some(f) = Self::parse(s) {
+                if !out.contains(&f) {
+                    out.push(f);
+                }
+            }
+        };
+        match v {
+            Some(serde_json::Value::String(s)) => s.split([',', '+', ' ']).for_each(&mut push),
+            Some(serde_json::Value::Array(a)) => a.iter().filter_map(|x| x.as_str()).for_each(&mut push),
+            _ => {}
+        }
+        if out.is_empty() {
+            out.push(Self::Ass);
+        }
+        out
+    }
+}
+
+pub fn cues_from_lyrics(lines: &[LyricLine]) -> Vec<Cue> {
+    lines
+        .iter()
+        .filter_map(|l| {
+            let (start_s, end_s) = (l.start_s?, l.end_s?);
+            let karaoke = karaoke_units(l)
+                .into_iter()
+                .map(|(text, cs)| KaraokeUnit { text, cs })
+                .collect();
+            Some(Cue {
+                start_s,
+                end_s,
+                text: l.text.clone(),
+                style: DEFAULT_STYLE.to_string(),
+                karaoke,
+            })
+        })
+        .collect()
+}
+
+/// Splits seconds into (h, m, s, ms) using integer milliseconds so formats never emit `60`.
+pub(crate) fn split_ms(t: f64) -> (u64, u64, u64, u64) {
+    let total = (t.max(0.0) * 1000.0).round() as u64;
+    (total / 3_600_000, total / 60_000 % 60, total / 1000 % 60, total % 1000)
+}
+
+pub(crate) fn parse_clock(s: &str, frac_sep: char) -> Option<f64> {
+    let (hms, frac) = s.trim().split_once(frac_sep)?;
+    let parts: Vec<&str> = hms.split(':').collect();
+    let (h, m, sec) = match parts.as_slice() {
+        [h, m, s] => (h.parse::<u64>().ok()?, m.parse::<u64>().ok()?, s.parse::<u64>().ok()?),
+        [m, s] => (0, m.parse::<u64>().ok()?, s.parse::<u64>().ok()?),
+        _ => return None,
+    };
+    let digits = frac.len() as i32;
+    let frac_v = frac.parse::<u64>().ok()? as f64 / 10f64.powi(digits);
+    Some((h * 3600 + m * 60 + sec) as f64 + frac_v)
+}
@@ -0,0 +1,52 @@
+use crate::subtitles::model::{parse_clock, split_ms, Cue};
+use anyhow::{bail, Result};
+
+fn srt_time(t: f64) -> String {
+    let (h, m, s, ms) = split_ms(t);
+    format!("{h:02}:{m:02}:{s:02},{ms:03}")
+}
+
+/// A blank line ends an SRT block, so empty lines inside a cue are dropped.
+fn escape_srt(text: &str) -> String {
+    text.replace('\r', "")
+        .lines()
+        .map(str::trim_end)
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn render_srt(cues: &[Cue]) -> String {
+    let mut out = String::new();
+    for (i, cue) in cues.iter().enumerate() {
+        out.push_str(&format!(
+            "{}\n{} --> {}\n{}\n\n",
+            i + 1,
+            srt_time(cue.start_s),
+            srt_time(cue.end_s),
+            escape_srt(&cue.text)
+        ));
+    }
+    out
+}
+
+pub fn parse_srt(s: &str) -> Result<Vec<Cue>> {
+    let normalized = s.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+    let mut cues = Vec::new();
+    for block in normalized.split("\n\n").map(str::trim).filter(|b| !b.is_empty()) {
+        let mut lines = block.lines();
+        let mut timing = lines.next().unwrap_or_default();
+        if !timing.contains("-->") {
+            timing = lines.next().unwrap_or_default();
+        }
+        let Some((a, b)) = timing.split_once("-->") else {
+            bail!("srt block without timing line: {block:?}");
+        };
+        let (Some(start_s), Some(end_s)) = (parse_clock(a, ','), parse_clock(b, ',')) else {
+            bail!("bad srt timing: {timing:?}");
+        };
+        let text = lines.collect::<Vec<_>>().join("\n");
+        cues.push(Cue::new(start_s, end_s, text));
+    }
+    Ok(cues)
+}
@@ -0,0 +1,88 @@
+use crate::subtitles::lang::{base_lang, normalize_lang};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Translates lyric lines for subtitle tracks that `lyrics.json` has no translation for.
+pub trait Translator: Send + Sync {
+    fn translate(&self, text: &str, from: &str, to: &str) -> Option<String>;
+}
+
+/// Offline phrase/word dictionary keyed by target language.
+///
+/// Whole lines are looked up first; otherwise known words are replaced one by one and
+/// unknown words are kept. Returns `None` when nothing matched.
+#[derive(Debug, Clone, Default)]
+pub struct DictionaryTranslator {
+    entries: HashMap<String, HashMap<String, String>>,
+}
+
+impl DictionaryTranslator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, to: &str, src: &str, dst: &str) {
+        self.entries
+            .entry(normalize_lang(to))
+            .or_default()
+            .insert(src.trim().to_lowercase(), dst.to_string());
+    }
+
+    /// Loads `{"es": {"hello": "hola", ...}, ...}`.
+    pub fn from_json_file(path: &Path) -> Result<Self> {
+        let s = std::fs::read_to_string(path)
+            .with_context(|| format!("read dictionary: {}", path.display()))?;
+        let raw: HashMap<String, HashMap<String, String>> = serde_json::from_str(&s)?;
+        let mut d = Self::new();
+        for (to, pairs) in raw {
+            for (src, dst) in pairs {
+                d.insert(&to, &src, &dst);
+            }
+        }
+        Ok(d)
+    }
+
+    fn table(&self, to: &str) -> Option<&HashMap<String, String>> {
+        let to = normalize_lang(to);
+        self.entries
+            .get(&to)
+            .or_else(|| self.entries.get(base_lang(&to)))
+    }
+}
+
+impl Translator for DictionaryTranslator {
+    fn translate(&self, text: &str, _from: &str, to: &str) -> Option<String> {
+        let table = self.table(to)?;
+        if let Some(hit) = table.get(&text.trim().to_lowercase()) {
+            return Some(hit.clone());
+        }
+        let mut hits = 0usize;
+        let words: Vec<String> = text
+            .split_whitespace()
+            .map(|w| {
+                let core = w.trim_matches(|c: char| !c.is_alphanumeric());
+                match table.get(&core.to_lowercase()) {
+                    Some(t) if !core.is_empty() => {
+                        hits += 1;
+                        w.replacen(core, t, 1)
+                    }
+                    _ => w.to_string(),
+                }
+            })
+            .collect();
+        (hits > 0).then(|| words.join(" "))
+    }
+}
+
+/// `CSS_SUBTITLES_DICT` points at a dictionary JSON; without it only `lyrics.json`
+/// translations are used.
+pub fn translator_from_env() -> Result<Option<Box<dyn Translator>>> {
+    match std::env::var("CSS_SUBTITLES_DICT") {
+        Ok(path) if !path.is_empty() => {
+            let d = DictionaryTranslator::from_json_file(Path::new(&path))?;
+            Ok(Some(Box::new(d)))
+        }
+        _ => Ok(None),
+    }
+}
@@ -0,0 +1,145 @@
+use crate::subtitles::model::{parse_clock, split_ms, Cue, KaraokeUnit, DEFAULT_STYLE};
+use anyhow::{bail, Result};
+
+fn vtt_time(t: f64) -> String {
+    let (h, m, s, ms) = split_ms(t);
+    format!("{h:02}:{m:02}:{s:02}.{ms:03}")
+}
+
+fn escape_vtt(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\r', "")
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn unescape_vtt(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", "\u{a0}")
+        .replace("&amp;", "&")
+}
+
+/// Cue payload: karaoke cues use inline timestamp tags, non-default styles a `<c.style>` span.
+fn payload(cue: &Cue) -> String {
+    let body = if cue.karaoke.is_empty() {
+        escape_vtt(&cue.text)
+    } else {
+        let mut s = String::new();
+        let mut t = cue.start_s;
+        for (i, unit) in cue.karaoke.iter().enumerate() {
+            if i > 0 {
+                s.push_str(&format!("<{}>", vtt_time(t)));
+            }
+            s.push_str(&escape_vtt(&unit.text));
+            t += unit.cs as f64 / 100.0;
+        }
+        s
+    };
+    if cue.style == DEFAULT_STYLE {
+        body
+    } else {
+        format!("<c.{}>{}</c>", cue.style, body)
+    }
+}
+
+pub fn render_vtt(cues: &[Cue]) -> String {
+    let mut out = String::from("WEBVTT\n\n");
+    for cue in cues {
+        out.push_str(&format!(
+            "{} --> {}\n{}\n\n",
+            vtt_time(cue.start_s),
+            vtt_time(cue.end_s),
+            payload(cue)
+        ));
+    }
+    out
+}
+
+fn parse_payload(start_s: f64, end_s: f64, raw: &str) -> Cue {
+    let mut style = DEFAULT_STYLE.to_string();
+    let mut body = raw;
+    if let Some(rest) = raw.strip_prefix("<c.") {
+        if let Some((name, inner)) = rest.split_once('>') {
+            style = name.to_string();
+            body = inner.strip_suffix("</c>").unwrap_or(inner);
+        }
+    }
+
+    let mut text = String::new();
+    let mut marks: Vec<(f64, String)> = vec![(start_s, String::new())];
+    let mut rest = body;
+    while let Some(open) = rest.find('<') {
+        let chunk = &rest[..open];
+        text.push_str(chunk);
+        marks.last_mut().expect("marks").1.push_str(chunk);
+        let Some(close) = rest[open..].find('>') else {
+            break;
+        };
+        let tag = &rest[open + 1..open + close];
+        if let Some(t) = parse_clock(tag, '.') {
+            marks.push((t, String::new()));
+        }
+        rest = &rest[open + close + 1..];
+    }
+    text.push_str(rest);
+    marks.last_mut().expect("marks").1.push_str(rest);
+
+    let karaoke = if marks.len() > 1 {
+        let mut units = Vec::with_capacity(marks.len());
+        for (i, (t, seg)) in marks.iter().enumerate() {
+            let next = marks.get(i + 1).map(|m| m.0).unwrap_or(end_s);
+            units.push(KaraokeUnit {
+                text: unescape_vtt(seg),
+                cs: ((next - t).max(0.0) * 100.0).round() as u32,
+            });
+        }
+        units
+    } else {
+        Vec::new()
+    };
+
+    Cue {
+        start_s,
+        end_s,
+        text: unescape_vtt(&text),
+        style,
+        karaoke,
+    }
+}
+
+pub fn parse_vtt(s: &str) -> Result<Vec<Cue>> {
+    let normalized = s.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+    let mut blocks = normalized.split("\n\n").map(str::trim).filter(|b| !b.is_empty());
+    match blocks.next() {
+        Some(h) if h.starts_with("WEBVTT") => {}
+        _ => bail!("missing WEBVTT header"),
+    }
+
+    let mut cues = Vec::new();
+    for block in blocks {
+        if block.starts_with("NOTE") || block.starts_with("STYLE") || block.starts_with("REGION") {
+            continue;
+        }
+        let mut lines = block.lines();
+        let mut timing = lines.next().unwrap_or_default();
+        if !timing.contains("-->") {
+            timing = lines.next().unwrap_or_default();
+        }
+        let Some((a, b)) = timing.split_once("-->") else {
+            bail!("vtt block without timing line: {block:?}");
+        };
+        // Cue settings may follow the end timestamp.
+        let b = b.split_whitespace().next().unwrap_or_default();
+        let (Some(start_s), Some(end_s)) = (parse_clock(a, '.'), parse_clock(b, '.')) else {
+            bail!("bad vtt timing: {timing:?}");
+        };
+        let raw = lines.collect::<Vec<_>>().join("\n");
+        cues.push(parse_payload(start_s, end_s, &raw));
+    }
+    Ok(cues)
+}
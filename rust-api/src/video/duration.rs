@@ -0,0 +1,14 @@
+use crate::media_probe::{probe_media, VerifyError};
+use std::path::Path;
+
+/// Length of a media file in seconds; `None` when the file does not exist or is empty,
+/// e.g. a stage that has not produced it yet.
+pub async fn probe_media_duration_s(ffprobe: &str, path: &Path) -> anyhow::Result<Option<f64>> {
+    let (ffprobe, path) = (ffprobe.to_string(), path.to_path_buf());
+    tokio::task::spawn_blocking(move || match probe_media(&ffprobe, &path) {
+        Ok(info) if info.duration_s > 0.0 => Ok(Some(info.duration_s)),
+        Ok(_) | Err(VerifyError::Missing(_) | VerifyError::Empty(_)) => Ok(None),
+        Err(e) => Err(e.into()),
+    })
+    .await?
+}
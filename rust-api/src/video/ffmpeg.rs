@@ -0,0 +1,67 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// How `concat_dual_path` joined the shots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConcatMode {
+    /// Stream copy; shots were encoded with identical parameters.
+    Copy,
+    /// Re-encoded after the stream copy failed, e.g. shots from different profiles.
+    Encode,
+}
+
+impl fmt::Display for ConcatMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Copy => "copy",
+            Self::Encode => "encode",
+        })
+    }
+}
+
+/// `<run_dir>/build/video/concat.txt`, the concat demuxer list for the run's shots.
+pub fn concat_list_path(run_dir: &Path) -> PathBuf {
+    run_dir.join("build").join("video").join("concat.txt")
+}
+
+async fn concat(ffmpeg: &str, list_txt: &Path, out_mp4: &Path, copy: bool) -> anyhow::Result<()> {
+    let mut cmd = Command::new(ffmpeg);
+    cmd.kill_on_drop(true);
+    cmd.args(["-y", "-v", "error", "-f", "concat", "-safe", "0", "-i"])
+        .arg(list_txt);
+    if copy {
+        cmd.args(["-c", "copy"]);
+    } else {
+        cmd.args([
+            "-c:v", "libx264", "-preset", "veryfast", "-crf", "18", "-pix_fmt", "yuv420p", "-an",
+        ]);
+    }
+    let out = cmd.arg(out_mp4).output().await?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        anyhow::bail!(
+            "ffmpeg concat ({}) failed: {}",
+            if copy { "copy" } else { "encode" },
+            stderr.lines().last().unwrap_or("exit status").trim()
+        );
+    }
+    Ok(())
+}
+
+/// Joins the files in `list_txt` into `out_mp4` with a stream copy, falling back to a
+/// re-encode when the copy fails.
+pub async fn concat_dual_path(
+    ffmpeg: &str,
+    list_txt: &Path,
+    out_mp4: &Path,
+) -> anyhow::Result<ConcatMode> {
+    match concat(ffmpeg, list_txt, out_mp4, true).await {
+        Ok(()) => Ok(ConcatMode::Copy),
+        Err(e) => {
+            tracing::warn!("{e}; re-encoding");
+            concat(ffmpeg, list_txt, out_mp4, false).await?;
+            Ok(ConcatMode::Encode)
+        }
+    }
+}
@@ -0,0 +1,6 @@
+pub mod duration;
+pub mod ffmpeg;
+pub mod package;
+pub mod render;
+pub mod storyboard;
+pub mod thumbnails;
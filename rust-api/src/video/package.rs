@@ -0,0 +1,416 @@
+use super::storyboard::valid_output_name;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+pub const RENDITION_SCHEMA: &str = "css.video.rendition.v1";
+
+/// How a rendition's frame is derived from the master when the aspect ratio differs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Framing {
+    /// Letterbox/pillarbox to the target size.
+    Fit,
+    /// Center crop to fill the target size.
+    Crop,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rendition {
+    pub name: String,
+    pub w: u32,
+    pub h: u32,
+    pub video_bitrate_kbps: u32,
+    #[serde(default = "default_audio_kbps")]
+    pub audio_bitrate_kbps: u32,
+    #[serde(default = "default_framing")]
+    pub framing: Framing,
+    /// Part of the adaptive HLS/DASH ladder. Social crops are standalone MP4s.
+    #[serde(default = "default_true")]
+    pub ladder: bool,
+    /// Encode from `build/targets/<target>/final_mv.mp4` (a reframed storyboard output)
+    /// instead of cropping the master.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+fn default_audio_kbps() -> u32 {
+    128
+}
+
+fn default_framing() -> Framing {
+    Framing::Fit
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Rendition {
+    fn new(
+        name: &str,
+        w: u32,
+        h: u32,
+        video_bitrate_kbps: u32,
+        framing: Framing,
+        ladder: bool,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            w,
+            h,
+            video_bitrate_kbps,
+            audio_bitrate_kbps: default_audio_kbps(),
+            framing,
+            ladder,
+            target: None,
+        }
+    }
+
+    pub fn aspect(&self) -> String {
+        fn gcd(a: u32, b: u32) -> u32 {
+            if b == 0 {
+                a
+            } else {
+                gcd(b, a % b)
+            }
+        }
+        let g = gcd(self.w, self.h).max(1);
+        format!("{}:{}", self.w / g, self.h / g)
+    }
+
+    pub fn bandwidth_bps(&self) -> u64 {
+        (self.video_bitrate_kbps as u64 + self.audio_bitrate_kbps as u64) * 1000
+    }
+
+    /// Scale (and pad or crop) to exactly `w`x`h`.
+    pub fn video_filter(&self) -> String {
+        let (w, h) = (self.w, self.h);
+        match self.framing {
+            Framing::Fit => format!(
+                "scale={w}:{h}:force_original_aspect_ratio=decrease,pad={w}:{h}:(ow-iw)/2:(oh-ih)/2,setsar=1"
+            ),
+            Framing::Crop => format!(
+                "scale={w}:{h}:force_original_aspect_ratio=increase,crop={w}:{h},setsar=1"
+            ),
+        }
+    }
+}
+
+/// `commands.video.package`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PackageSpec {
+    pub renditions: Vec<Rendition>,
+    pub hls: bool,
+    pub dash: bool,
+    pub segment_s: u32,
+    /// Keep ladder renditions taller than the master.
+    pub allow_upscale: bool,
+}
+
+impl Default for PackageSpec {
+    fn default() -> Self {
+        Self {
+            renditions: vec![
+                Rendition::new("1080p", 1920, 1080, 5000, Framing::Fit, true),
+                Rendition::new("720p", 1280, 720, 2800, Framing::Fit, true),
+                Rendition::new("480p", 854, 480, 1200, Framing::Fit, true),
+                Rendition::new("vertical", 1080, 1920, 4500, Framing::Crop, false),
+                Rendition::new("square", 1080, 1080, 3500, Framing::Crop, false),
+            ],
+            hls: true,
+            dash: false,
+            segment_s: 4,
+            allow_upscale: false,
+        }
+    }
+}
+
+impl PackageSpec {
+    /// Errors when a rendition name or target is not `[A-Za-z0-9_-]+`, since both name
+    /// files and directories.
+    pub fn from_value(v: Option<&serde_json::Value>) -> Result<Self, String> {
+        let spec: Self = v
+            .and_then(|x| serde_json::from_value(x.clone()).ok())
+            .unwrap_or_default();
+        for r in &spec.renditions {
+            for name in std::iter::once(&r.name).chain(&r.target) {
+                if !valid_output_name(name) {
+                    return Err(format!(
+                        "rendition name {name:?} must only use letters, digits, '_' and '-'"
+                    ));
+                }
+            }
+        }
+        Ok(spec)
+    }
+
+    /// Drops ladder renditions above the master height (unless upscaling is allowed) while
+    /// always keeping at least the smallest one, and makes names unique.
+    pub fn planned(mut self, master_h: u32) -> Self {
+        if !self.allow_upscale {
+            let smallest = self
+                .renditions
+                .iter()
+                .filter(|r| r.ladder)
+                .map(|r| r.h)
+                .min();
+            self.renditions
+                .retain(|r| !r.ladder || r.h <= master_h || Some(r.h) == smallest);
+        }
+        let mut seen = std::collections::BTreeSet::new();
+        self.renditions.retain(|r| seen.insert(r.name.clone()));
+        self.segment_s = self.segment_s.max(1);
+        self
+    }
+
+    /// Points renditions at the storyboard output target of the same name, if any.
+    pub fn link_targets(mut self, targets: &[String]) -> Self {
+        for r in &mut self.renditions {
+            if r.target.is_none() && targets.contains(&r.name) {
+                r.target = Some(r.name.clone());
+            }
+        }
+        self
+    }
+
+    pub fn ladder(&self) -> impl Iterator<Item = &Rendition> {
+        self.renditions.iter().filter(|r| r.ladder)
+    }
+
+    /// Stage outputs relative to the run dir.
+    pub fn outputs(&self) -> Vec<PathBuf> {
+        let mut out: Vec<PathBuf> = self
+            .renditions
+            .iter()
+            .map(|r| PathBuf::from(format!("./build/package/{}.mp4", r.name)))
+            .collect();
+        if self.hls && self.ladder().next().is_some() {
+            out.push(PathBuf::from("./build/package/hls/master.m3u8"));
+        }
+        if self.dash && self.ladder().next().is_some() {
+            out.push(PathBuf::from("./build/package/dash/manifest.mpd"));
+        }
+        out
+    }
+}
+
+/// A packaged rendition as recorded in the run artifacts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenditionArtifact {
+    pub schema: String,
+    pub name: String,
+    pub path: String,
+    pub container: String,
+    pub video_codec: String,
+    pub audio_codec: String,
+    pub w: u32,
+    pub h: u32,
+    pub aspect: String,
+    pub framing: Framing,
+    pub source: String,
+    pub video_bitrate_kbps: u32,
+    pub audio_bitrate_kbps: u32,
+    pub bytes: u64,
+    pub hls_playlist: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackageReport {
+    pub renditions: Vec<RenditionArtifact>,
+    pub hls_master: Option<String>,
+    pub dash_manifest: Option<String>,
+}
+
+async fn run_ffmpeg(cmd: &mut Command, what: &str) -> Result<(), String> {
+    let status = cmd
+        .status()
+        .await
+        .map_err(|e| format!("spawn ffmpeg {what}: {e}"))?;
+    if !status.success() {
+        return Err(format!("ffmpeg {what} failed: exit={:?}", status.code()));
+    }
+    Ok(())
+}
+
+fn rel(out_dir: &Path, p: &Path) -> String {
+    p.strip_prefix(out_dir).unwrap_or(p).display().to_string()
+}
+
+pub fn hls_master_playlist(ladder: &[&Rendition]) -> String {
+    let mut s = String::from("#EXTM3U\n#EXT-X-VERSION:3\n");
+    for r in ladder {
+        s.push_str(&format!(
+            "#EXT-X-STREAM-INF:BANDWIDTH={},AVERAGE-BANDWIDTH={},RESOLUTION={}x{},CODECS=\"avc1.640028,mp4a.40.2\"\n{}/index.m3u8\n",
+            r.bandwidth_bps() * 11 / 10,
+            r.bandwidth_bps(),
+            r.w,
+            r.h,
+            r.name
+        ));
+    }
+    s
+}
+
+/// Encodes every rendition from `final_mp4` into `build/package/`, then segments the ladder
+/// into HLS (and DASH when enabled). GOPs are aligned to the segment length so all
+/// renditions switch cleanly.
+pub async fn package_renditions(
+    ffmpeg: &str,
+    out_dir: &Path,
+    final_mp4: &Path,
+    spec: &PackageSpec,
+    fps: u32,
+) -> Result<PackageReport, String> {
+    let pkg_dir = out_dir.join("build/package");
+    tokio::fs::create_dir_all(&pkg_dir)
+        .await
+        .map_err(|e| format!("create package dir: {e}"))?;
+    let gop = (fps.max(1) * spec.segment_s).to_string();
+
+    let mut renditions = Vec::with_capacity(spec.renditions.len());
+    for r in &spec.renditions {
+        let path = pkg_dir.join(format!("{}.mp4", r.name));
+        let source = match &r.target {
+            Some(t) => out_dir.join("build/targets").join(t).join("final_mv.mp4"),
+            None => final_mp4.to_path_buf(),
+        };
+        let vb = format!("{}k", r.video_bitrate_kbps);
+        let mut cmd = Command::new(ffmpeg);
+        cmd.kill_on_drop(true);
+        cmd.arg("-y")
+            .arg("-i")
+            .arg(&source)
+            .args(["-map", "0:v:0", "-map", "0:a:0?"])
+            .arg("-vf")
+            .arg(r.video_filter())
+            .args([
+                "-c:v",
+                "libx264",
+                "-profile:v",
+                "high",
+                "-preset",
+                "medium",
+                "-pix_fmt",
+                "yuv420p",
+            ])
+            .args(["-b:v", &vb, "-maxrate", &vb])
+            .args(["-bufsize", &format!("{}k", r.video_bitrate_kbps * 2)])
+            .args(["-g", &gop, "-keyint_min", &gop, "-sc_threshold", "0"])
+            .args([
+                "-c:a",
+                "aac",
+                "-b:a",
+                &format!("{}k", r.audio_bitrate_kbps),
+                "-ac",
+                "2",
+            ])
+            .args(["-movflags", "+faststart"])
+            .arg(&path);
+        run_ffmpeg(&mut cmd, &format!("rendition {}", r.name)).await?;
+
+        let bytes = tokio::fs::metadata(&path)
+            .await
+            .map(|m| m.len())
+            .unwrap_or(0);
+        renditions.push(RenditionArtifact {
+            schema: RENDITION_SCHEMA.to_string(),
+            name: r.name.clone(),
+            path: rel(out_dir, &path),
+            container: "mp4".to_string(),
+            video_codec: "h264".to_string(),
+            audio_codec: "aac".to_string(),
+            w: r.w,
+            h: r.h,
+            aspect: r.aspect(),
+            framing: r.framing,
+            source: rel(out_dir, &source),
+            video_bitrate_kbps: r.video_bitrate_kbps,
+            audio_bitrate_kbps: r.audio_bitrate_kbps,
+            bytes,
+            hls_playlist: None,
+        });
+    }
+
+    let ladder: Vec<&Rendition> = spec.ladder().collect();
+    let mut hls_master = None;
+    if spec.hls && !ladder.is_empty() {
+        let hls_dir = pkg_dir.join("hls");
+        for r in &ladder {
+            let dir = hls_dir.join(&r.name);
+            tokio::fs::create_dir_all(&dir)
+                .await
+                .map_err(|e| format!("create hls dir: {e}"))?;
+            let mut cmd = Command::new(ffmpeg);
+            cmd.kill_on_drop(true);
+            cmd.arg("-y")
+                .arg("-i")
+                .arg(pkg_dir.join(format!("{}.mp4", r.name)))
+                .args(["-c", "copy", "-f", "hls", "-hls_playlist_type", "vod"])
+                .args(["-hls_time", &spec.segment_s.to_string()])
+                .arg("-hls_segment_filename")
+                .arg(dir.join("seg_%05d.ts"))
+                .arg(dir.join("index.m3u8"));
+            run_ffmpeg(&mut cmd, &format!("hls {}", r.name)).await?;
+            if let Some(a) = renditions.iter_mut().find(|a| a.name == r.name) {
+                a.hls_playlist = Some(rel(out_dir, &dir.join("index.m3u8")));
+            }
+        }
+        let master = hls_dir.join("master.m3u8");
+        tokio::fs::write(&master, hls_master_playlist(&ladder))
+            .await
+            .map_err(|e| format!("write hls master: {e}"))?;
+        hls_master = Some(rel(out_dir, &master));
+    }
+
+    let mut dash_manifest = None;
+    if spec.dash && !ladder.is_empty() {
+        let dash_dir = pkg_dir.join("dash");
+        tokio::fs::create_dir_all(&dash_dir)
+            .await
+            .map_err(|e| format!("create dash dir: {e}"))?;
+        let mut cmd = Command::new(ffmpeg);
+        cmd.kill_on_drop(true);
+        cmd.arg("-y");
+        for r in &ladder {
+            cmd.arg("-i").arg(pkg_dir.join(format!("{}.mp4", r.name)));
+        }
+        for i in 0..ladder.len() {
+            cmd.args(["-map", &format!("{i}:v:0")]);
+        }
+        cmd.args(["-map", "0:a:0?", "-c", "copy", "-f", "dash"])
+            .args(["-seg_duration", &spec.segment_s.to_string()])
+            .args(["-use_template", "1", "-use_timeline", "1"])
+            .args(["-adaptation_sets", "id=0,streams=v id=1,streams=a"])
+            .arg(dash_dir.join("manifest.mpd"));
+        run_ffmpeg(&mut cmd, "dash").await?;
+        dash_manifest = Some(rel(out_dir, &dash_dir.join("manifest.mpd")));
+    }
+
+    Ok(PackageReport {
+        renditions,
+        hls_master,
+        dash_manifest,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn rendition_names_must_be_plain() {
+        let spec = PackageSpec::from_value(None).unwrap();
+        assert_eq!(spec.renditions.len(), 5);
+        let ok = json!({"renditions": [{"name": "720p_v-2", "w": 1280, "h": 720, "video_bitrate_kbps": 2800}]});
+        assert!(PackageSpec::from_value(Some(&ok)).is_ok());
+        for bad in ["../x", "a/b", "", "a b", "x.mp4"] {
+            let v = json!({"renditions": [{"name": bad, "w": 2, "h": 2, "video_bitrate_kbps": 1}]});
+            assert!(PackageSpec::from_value(Some(&v)).is_err(), "{bad:?}");
+        }
+        let v = json!({"renditions": [{"name": "v", "w": 2, "h": 2, "video_bitrate_kbps": 1, "target": "../../etc"}]});
+        assert!(PackageSpec::from_value(Some(&v)).is_err());
+    }
+}
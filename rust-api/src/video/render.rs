@@ -0,0 +1,281 @@
+use crate::audio::mix::{mix_tracks, MixReport, MixSpec};
+use crate::audio::stems::{rebalance_music, RebalanceReport, StemsManifest, VOCALS_STEM};
+use crate::audio::wav::read_wav_info;
+use crate::encoder::EncoderProfile;
+use crate::render_manifest::DETERMINISTIC_ARGS;
+use crate::subtitles::lang::iso639_2;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// How subtitles end up in `final_mv.mp4`. Sidecar files are written in every mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubtitleMode {
+    /// Sidecar files only.
+    None,
+    /// Rendered into the picture with the `ass`/`subtitles` filter.
+    Hard,
+    /// Embedded as `mov_text` tracks.
+    Soft,
+}
+
+impl SubtitleMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Hard => "hard",
+            Self::Soft => "soft",
+        }
+    }
+
+    /// Reads `commands.video.subtitles.burnin`: `true`/`"hard"`, `"soft"`, `false`/`"none"`.
+    /// When unset, `CSS_SUBTITLES_BURNIN=1` selects hard subs.
+    pub fn from_value(v: Option<&serde_json::Value>) -> Self {
+        match v {
+            Some(serde_json::Value::Bool(true)) => Self::Hard,
+            Some(serde_json::Value::Bool(false)) => Self::None,
+            Some(serde_json::Value::String(s)) => match s.trim().to_ascii_lowercase().as_str() {
+                "hard" | "burnin" | "burn" | "1" | "true" => Self::Hard,
+                "soft" | "embed" | "mov_text" => Self::Soft,
+                _ => Self::None,
+            },
+            _ => match std::env::var("CSS_SUBTITLES_BURNIN").as_deref() {
+                Ok("1") | Ok("hard") => Self::Hard,
+                Ok("soft") => Self::Soft,
+                _ => Self::None,
+            },
+        }
+    }
+}
+
+/// A subtitle file for one language.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubtitleInput {
+    pub path: PathBuf,
+    pub lang: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderSpec {
+    pub video_mp4: PathBuf,
+    pub music_wav: PathBuf,
+    pub vocals_wav: PathBuf,
+    pub out_mp4: PathBuf,
+    /// One file per language; the first is the one burned in for hard subs.
+    pub subtitles: Vec<SubtitleInput>,
+    pub subtitle_mode: SubtitleMode,
+    pub fonts_dir: Option<PathBuf>,
+    pub mix: MixSpec,
+    /// Used when the picture is re-encoded to burn in subtitles.
+    pub encoder: EncoderProfile,
+    pub fps: u32,
+    /// Bitexact, single-threaded output without metadata timestamps.
+    pub reproducible: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenderReport {
+    pub subtitle_mode: SubtitleMode,
+    /// File rendered into the picture (hard mode only).
+    pub subtitle_file: Option<String>,
+    /// Languages muxed as soft tracks, in stream order.
+    pub subtitle_tracks: Vec<String>,
+    pub video_codec: String,
+    pub audio_inputs: Vec<String>,
+    pub mix: Option<MixReport>,
+}
+
+/// Escapes a path for use as a filter option value inside a filtergraph: first for the
+/// option value (`\`, `'`, `:`), then for the graph (`\`, `'`, `,`, `;`, `[`, `]`).
+pub fn escape_filter_path(p: &Path) -> String {
+    let mut opt = String::new();
+    for c in p.display().to_string().chars() {
+        if matches!(c, '\\' | '\'' | ':') {
+            opt.push('\\');
+        }
+        opt.push(c);
+    }
+    let mut out = String::new();
+    for c in opt.chars() {
+        if matches!(c, '\\' | '\'' | ',' | ';' | '[' | ']') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn subtitle_filter(subs: &Path, fonts_dir: Option<&Path>) -> String {
+    let is_ass = subs
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.eq_ignore_ascii_case("ass"))
+        .unwrap_or(false);
+    let mut f = if is_ass {
+        format!("ass=filename={}", escape_filter_path(subs))
+    } else {
+        format!("subtitles=filename={}", escape_filter_path(subs))
+    };
+    if let Some(dir) = fonts_dir {
+        f.push_str(&format!(":fontsdir={}", escape_filter_path(dir)));
+    }
+    f
+}
+
+fn usable_wav(p: &Path) -> bool {
+    read_wav_info(p).map(|i| i.frames > 0).unwrap_or(false)
+}
+
+/// With music stem gains in the mix spec and a stems manifest next to the music
+/// (`build/stems/stems.json`), re-sums the stems into `audio/music_stems.wav`.
+async fn rebalance_stems(
+    spec: &RenderSpec,
+    out_dir: &Path,
+) -> Result<Option<(PathBuf, RebalanceReport)>, String> {
+    if spec.mix.stems.keys().all(|k| k == VOCALS_STEM) {
+        return Ok(None);
+    }
+    let manifest_path = spec.music_wav.with_file_name("stems/stems.json");
+    if !manifest_path.is_file() {
+        return Ok(None);
+    }
+    // Stem paths are relative to the run directory, which holds `build/`.
+    let base = spec
+        .music_wav
+        .parent()
+        .and_then(Path::parent)
+        .unwrap_or(Path::new("."))
+        .to_path_buf();
+    let out_wav = out_dir.join("audio/music_stems.wav");
+    let gains = spec.mix.stems.clone();
+    tokio::task::spawn_blocking(move || {
+        let manifest = StemsManifest::read(&manifest_path)?;
+        let report = rebalance_music(&manifest, &base, &gains, &out_wav)?;
+        Ok::<_, anyhow::Error>(Some((out_wav, report)))
+    })
+    .await
+    .map_err(|e| format!("rebalance stems: {e}"))?
+    .map_err(|e| format!("rebalance stems: {e:#}"))
+}
+
+pub async fn render_final(ffmpeg: &str, spec: &RenderSpec) -> Result<RenderReport, String> {
+    if let Some(parent) = spec.out_mp4.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| format!("create out dir: {e}"))?;
+    }
+
+    let subs: Vec<&SubtitleInput> = match spec.subtitle_mode {
+        SubtitleMode::None => Vec::new(),
+        _ => spec.subtitles.iter().filter(|s| s.path.exists()).collect(),
+    };
+    let mode = if subs.is_empty() { SubtitleMode::None } else { spec.subtitle_mode };
+    // Hard subs burn the first language and keep any others as selectable tracks.
+    let (burned, muxed): (Option<&SubtitleInput>, &[&SubtitleInput]) = match mode {
+        SubtitleMode::Hard => (subs.first().copied(), &subs[1..]),
+        SubtitleMode::Soft => (None, &subs[..]),
+        SubtitleMode::None => (None, &[]),
+    };
+
+    let mut cmd = Command::new(ffmpeg);
+    cmd.kill_on_drop(true);
+    cmd.arg("-y").arg("-i").arg(&spec.video_mp4);
+
+    let out_dir = spec.out_mp4.parent().unwrap_or(Path::new("."));
+    let rebalanced = rebalance_stems(spec, out_dir).await?;
+    let music = match &rebalanced {
+        Some((wav, _)) => Some(wav.as_path()),
+        None => Some(spec.music_wav.as_path()).filter(|p| usable_wav(p)),
+    };
+    let vocals = Some(spec.vocals_wav.as_path()).filter(|p| usable_wav(p));
+    let audio_inputs: Vec<String> = [music, vocals]
+        .into_iter()
+        .flatten()
+        .map(|p| p.display().to_string())
+        .collect();
+    let mix = if music.is_some() || vocals.is_some() {
+        let mix_wav = out_dir.join("audio/mix.wav");
+        let mut report = mix_tracks(ffmpeg, music, vocals, &mix_wav, &spec.mix).await?;
+        report.stems = rebalanced.map(|(_, r)| r);
+        cmd.arg("-i").arg(&mix_wav);
+        Some(report)
+    } else {
+        None
+    };
+    let audio_n = usize::from(mix.is_some());
+    for s in muxed {
+        cmd.arg("-i").arg(&s.path);
+    }
+
+    cmd.args(["-map", "0:v:0"]);
+    if mix.is_some() {
+        cmd.args(["-map", "1:a:0"]);
+    }
+
+    let video_codec = match burned {
+        Some(s) => {
+            cmd.arg("-vf").arg(subtitle_filter(&s.path, spec.fonts_dir.as_deref()));
+            cmd.args(spec.encoder.ffmpeg_args(spec.fps));
+            spec.encoder.codec.ffmpeg_encoder()
+        }
+        None => {
+            cmd.args(["-c:v", "copy"]);
+            "copy"
+        }
+    };
+    if mix.is_some() {
+        cmd.args(["-c:a", "aac", "-b:a", "192k"]);
+    }
+    for (k, s) in muxed.iter().enumerate() {
+        let idx = audio_n + 1 + k;
+        cmd.args(["-map", &format!("{idx}:s:0")]);
+        cmd.arg(format!("-metadata:s:s:{k}"))
+            .arg(format!("language={}", iso639_2(&s.lang)));
+        cmd.arg(format!("-metadata:s:s:{k}")).arg(format!("title={}", s.lang));
+        cmd.arg(format!("-disposition:s:{k}"))
+            .arg(if k == 0 { "default" } else { "0" });
+    }
+    if !muxed.is_empty() {
+        cmd.args(["-c:s", "mov_text"]);
+    }
+    cmd.args(["-movflags", "+faststart", "-shortest"]);
+    if spec.reproducible {
+        cmd.args(DETERMINISTIC_ARGS);
+    }
+    cmd.arg(&spec.out_mp4);
+
+    let status = cmd
+        .status()
+        .await
+        .map_err(|e| format!("spawn ffmpeg render: {e}"))?;
+    if !status.success() {
+        return Err(format!("ffmpeg render failed: exit={:?}", status.code()));
+    }
+
+    Ok(RenderReport {
+        subtitle_mode: mode,
+        subtitle_file: burned.map(|s| s.path.display().to_string()),
+        subtitle_tracks: muxed.iter().map(|s| s.lang.clone()).collect(),
+        video_codec: video_codec.to_string(),
+        audio_inputs,
+        mix,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subtitle_filter_escapes_paths() {
+        assert_eq!(
+            subtitle_filter(Path::new("/runs/a:b/subs.en.ass"), Some(Path::new("/fonts"))),
+            r"ass=filename=/runs/a\\:b/subs.en.ass:fontsdir=/fonts"
+        );
+        assert_eq!(
+            subtitle_filter(Path::new("/runs/it's,[x].srt"), None),
+            r"subtitles=filename=/runs/it\\\'s\,\[x\].srt"
+        );
+    }
+}
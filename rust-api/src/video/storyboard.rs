@@ -0,0 +1,280 @@
+use crate::audio::beats::BeatsV1;
+use crate::video_executor::{
+    BgSpec, CameraSpec, OutputTarget, OverlaySpec, Reframe, Resolution, ShotV1, StoryboardV1,
+};
+use anyhow::{bail, Context, Result};
+use std::{fs, path::Path};
+
+pub const STORYBOARD_SCHEMA: &str = "css.video.storyboard.v1";
+
+const MIN_SHOT_S: f64 = 0.5;
+const BARS_PER_PHRASE: usize = 4;
+
+const PALETTE: &[&str] = &[
+    "#101820", "#0B1020", "#120B20", "#071A12", "#1A1407", "#0D0D0D", "#0A1320", "#20110A",
+];
+const MOVES: &[&str] = &["push_in", "pan_right", "pan_left", "pull_out", "static"];
+
+/// Where shot boundaries are allowed to land.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CutMode {
+    Even,
+    Beat,
+    Bar,
+    Phrase,
+}
+
+impl CutMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "even" => Some(Self::Even),
+            "beat" => Some(Self::Beat),
+            "bar" => Some(Self::Bar),
+            "phrase" => Some(Self::Phrase),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Even => "even",
+            Self::Beat => "beat",
+            Self::Bar => "bar",
+            Self::Phrase => "phrase",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AutoPlan {
+    pub seed: u64,
+    pub duration_s: f64,
+    pub shots_n: usize,
+    pub fps: u32,
+    pub w: u32,
+    pub h: u32,
+    pub cut_mode: CutMode,
+    pub outputs: Vec<OutputTarget>,
+}
+
+fn preset_target(name: &str) -> Option<OutputTarget> {
+    let (name, w, h) = match name.trim().to_ascii_lowercase().as_str() {
+        "vertical" | "9:16" | "shorts" | "tiktok" => ("vertical", 1080, 1920),
+        "square" | "1:1" => ("square", 1080, 1080),
+        "portrait" | "4:5" => ("portrait", 1080, 1350),
+        _ => return None,
+    };
+    Some(OutputTarget {
+        name: name.to_string(),
+        resolution: Resolution { w, h },
+        reframe: Reframe::Focus,
+    })
+}
+
+/// Whether `name` may become a file or directory name under `build/`: `[A-Za-z0-9_-]+`.
+pub fn valid_output_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+/// `commands.video.outputs`: preset names (`"vertical"`, `"square"`, `"portrait"` or their
+/// ratios) or full targets `{"name","w","h","reframe"}`. Unknown entries are skipped;
+/// target names that are not [`valid_output_name`]s are an error.
+pub fn output_targets_from_value(
+    v: Option<&serde_json::Value>,
+) -> Result<Vec<OutputTarget>, String> {
+    let mut out: Vec<OutputTarget> = Vec::new();
+    for item in v.and_then(|x| x.as_array()).into_iter().flatten() {
+        let target = match item {
+            serde_json::Value::String(s) => preset_target(s),
+            serde_json::Value::Object(o) => {
+                let name = o.get("name").and_then(|x| x.as_str()).unwrap_or_default();
+                if !name.is_empty() && !valid_output_name(name) {
+                    return Err(format!(
+                        "output target name {name:?} must only use letters, digits, '_' and '-'"
+                    ));
+                }
+                let dim = |k: &str| {
+                    o.get(k)
+                        .or_else(|| o.get("resolution").and_then(|r| r.get(k)))
+                        .and_then(|x| x.as_u64())
+                        .map(|x| x as u32)
+                };
+                let reframe = o
+                    .get("reframe")
+                    .and_then(|x| serde_json::from_value::<Reframe>(x.clone()).ok());
+                match (dim("w"), dim("h")) {
+                    (Some(w), Some(h)) if w > 0 && h > 0 && !name.is_empty() => Some(OutputTarget {
+                        name: name.to_string(),
+                        resolution: Resolution { w: w & !1, h: h & !1 },
+                        reframe: reframe.unwrap_or_default(),
+                    }),
+                    _ => preset_target(name).map(|mut t| {
+                        if let Some(r) = reframe {
+                            t.reframe = r;
+                        }
+                        t
+                    }),
+                }
+            }
+            _ => None,
+        };
+        if let Some(t) = target.filter(|t| !out.iter().any(|o| o.name == t.name)) {
+            out.push(t);
+        }
+    }
+    Ok(out)
+}
+
+pub fn load_storyboard_v1(path: &Path) -> Result<StoryboardV1> {
+    let s = fs::read_to_string(path)
+        .with_context(|| format!("read storyboard: {}", path.display()))?;
+    let sb: StoryboardV1 = serde_json::from_str(&s)?;
+    if sb.schema != STORYBOARD_SCHEMA {
+        bail!("unsupported storyboard schema: {}", sb.schema);
+    }
+    if let Some(t) = sb.outputs.iter().find(|t| !valid_output_name(&t.name)) {
+        bail!("storyboard output target name {:?} is not allowed", t.name);
+    }
+    Ok(sb)
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+pub fn cut_grid(beats: &BeatsV1, mode: CutMode) -> Vec<f64> {
+    match mode {
+        CutMode::Even => Vec::new(),
+        CutMode::Beat => beats.beats.clone(),
+        CutMode::Bar => beats.downbeats.clone(),
+        CutMode::Phrase => beats.phrases(BARS_PER_PHRASE),
+    }
+}
+
+/// Returns `shots_n - 1` cut times: the even split, with each cut moved to the nearest grid
+/// point that still leaves at least `MIN_SHOT_S` for every shot on both sides.
+pub fn snap_cuts(duration_s: f64, shots_n: usize, grid: &[f64]) -> Vec<f64> {
+    let n = shots_n.max(1);
+    let step = duration_s / n as f64;
+    let mut cuts = Vec::with_capacity(n - 1);
+    let mut prev = 0.0f64;
+    for k in 1..n {
+        let target = step * k as f64;
+        let lo = prev + MIN_SHOT_S;
+        let hi = duration_s - MIN_SHOT_S * (n - k) as f64;
+        let snapped = grid
+            .iter()
+            .copied()
+            .filter(|&t| t >= lo && t <= hi)
+            .min_by(|a, b| (a - target).abs().total_cmp(&(b - target).abs()));
+        let cut = snapped.unwrap_or_else(|| target.clamp(lo, hi.max(lo)));
+        cuts.push(cut);
+        prev = cut;
+    }
+    cuts
+}
+
+fn shot_durations(plan: &AutoPlan, beats: Option<&BeatsV1>) -> Vec<f64> {
+    let grid = beats.map(|b| cut_grid(b, plan.cut_mode)).unwrap_or_default();
+    let cuts = snap_cuts(plan.duration_s, plan.shots_n, &grid);
+
+    // Cuts land on frame boundaries so concat does not drift against the music.
+    let fps = plan.fps.max(1) as f64;
+    let mut bounds = vec![0.0f64];
+    bounds.extend(cuts.iter().map(|t| (t * fps).round() / fps));
+    bounds.push((plan.duration_s * fps).round() / fps);
+    bounds.windows(2).map(|w| (w[1] - w[0]).max(1.0 / fps)).collect()
+}
+
+pub fn build_storyboard_auto(plan: &AutoPlan, beats: Option<&BeatsV1>) -> StoryboardV1 {
+    let mut rng = plan.seed;
+    let shots = shot_durations(plan, beats)
+        .into_iter()
+        .enumerate()
+        .map(|(i, d)| {
+            let color = PALETTE[(splitmix64(&mut rng) % PALETTE.len() as u64) as usize];
+            let mv = MOVES[(splitmix64(&mut rng) % MOVES.len() as u64) as usize];
+            ShotV1 {
+                id: format!("video_shot_{:03}", i),
+                duration_s: d as f32,
+                prompt: None,
+                bg: BgSpec::Color { value: color.to_string() },
+                camera: CameraSpec {
+                    r#move: mv.to_string(),
+                    strength: if mv == "static" { 0.0 } else { 0.4 },
+                },
+                overlay: Some(OverlaySpec {
+                    enabled: false,
+                    text: None,
+                    position: None,
+                }),
+                focus: None,
+                poster_t: None,
+            }
+        })
+        .collect();
+
+    StoryboardV1 {
+        schema: STORYBOARD_SCHEMA.to_string(),
+        seed: plan.seed,
+        fps: plan.fps,
+        resolution: Resolution { w: plan.w, h: plan.h },
+        shots,
+        outputs: plan.outputs.clone(),
+        poster_t: None,
+    }
+}
+
+pub fn ensure_storyboard_auto(
+    path: &Path,
+    plan: &AutoPlan,
+    beats: Option<&BeatsV1>,
+) -> Result<StoryboardV1> {
+    if path.exists() {
+        return load_storyboard_v1(path);
+    }
+    let sb = build_storyboard_auto(plan, beats);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serde_json::to_vec_pretty(&sb)?)
+        .with_context(|| format!("write storyboard: {}", path.display()))?;
+    Ok(sb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn cuts_snap_to_the_grid_but_keep_shots_long_enough() {
+        // 2.6 is too close to the cut at 2.4 and 7.9 too close to the end.
+        let grid = [1.1, 2.4, 2.6, 7.9];
+        assert_eq!(snap_cuts(8.0, 4, &grid), vec![2.4, 4.0, 6.0]);
+        assert_eq!(snap_cuts(10.0, 2, &[9.8]), vec![5.0]);
+        assert_eq!(snap_cuts(6.0, 3, &[]), vec![2.0, 4.0]);
+    }
+
+    #[test]
+    fn output_target_names_are_checked() {
+        let v = json!(["vertical", {"name": "wide_2-1", "w": 1920, "h": 960}, {"w": 1, "h": 1}]);
+        let names: Vec<_> = output_targets_from_value(Some(&v))
+            .unwrap()
+            .into_iter()
+            .map(|t| t.name)
+            .collect();
+        assert_eq!(names, ["vertical", "wide_2-1"]);
+        for bad in ["../up", "a/b", "c d", "e.f"] {
+            let v = json!([{"name": bad, "w": 640, "h": 640}]);
+            assert!(output_targets_from_value(Some(&v)).is_err(), "{bad:?}");
+        }
+    }
+}
@@ -0,0 +1,429 @@
+use crate::video_executor::ShotV1;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+pub const THUMBNAIL_SCHEMA: &str = "css.video.thumbnail.v1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewFormat {
+    Gif,
+    Webp,
+}
+
+impl PreviewFormat {
+    pub fn ext(&self) -> &'static str {
+        match self {
+            Self::Gif => "gif",
+            Self::Webp => "webp",
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            Self::Gif => "image/gif",
+            Self::Webp => "image/webp",
+        }
+    }
+}
+
+/// `commands.video.thumbnails`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThumbnailSpec {
+    /// Width of poster frames; height follows the video's aspect ratio.
+    pub width: u32,
+    pub contact_sheet: bool,
+    pub columns: u32,
+    pub tile_width: u32,
+    pub preview: Option<PreviewFormat>,
+    pub preview_width: u32,
+    pub preview_fps: u32,
+    /// Length of each shot's excerpt in the animated preview.
+    pub preview_clip_s: f32,
+    /// Frames scoring at or above this are treated as cuts and never chosen as posters.
+    pub scene_threshold: f32,
+}
+
+impl Default for ThumbnailSpec {
+    fn default() -> Self {
+        Self {
+            width: 640,
+            contact_sheet: true,
+            columns: 4,
+            tile_width: 320,
+            preview: Some(PreviewFormat::Webp),
+            preview_width: 320,
+            preview_fps: 10,
+            preview_clip_s: 1.0,
+            scene_threshold: 0.3,
+        }
+    }
+}
+
+impl ThumbnailSpec {
+    pub fn from_value(v: Option<&serde_json::Value>) -> Self {
+        v.and_then(|x| serde_json::from_value(x.clone()).ok())
+            .unwrap_or_default()
+    }
+
+    /// Stage outputs relative to the run dir.
+    pub fn outputs(&self) -> Vec<PathBuf> {
+        let mut out = vec![PathBuf::from("./build/thumbnails/poster.jpg")];
+        if self.contact_sheet {
+            out.push(PathBuf::from("./build/thumbnails/contact_sheet.jpg"));
+        }
+        if let Some(f) = self.preview {
+            out.push(PathBuf::from(format!(
+                "./build/thumbnails/preview.{}",
+                f.ext()
+            )));
+        }
+        out
+    }
+}
+
+/// A still or animated image as recorded under `video.thumbnails`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThumbnailArtifact {
+    pub schema: String,
+    /// `poster`, `shot_poster`, `contact_sheet` or `preview`.
+    pub kind: String,
+    pub path: String,
+    pub mime: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shot_id: Option<String>,
+    /// Time in the source video the frame was taken from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub t: Option<f64>,
+    /// `storyboard`, `scene` or `midpoint`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pick: Option<String>,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThumbnailReport {
+    pub source: String,
+    pub poster: ThumbnailArtifact,
+    pub shots: Vec<ThumbnailArtifact>,
+    pub contact_sheet: Option<ThumbnailArtifact>,
+    pub preview: Option<ThumbnailArtifact>,
+}
+
+/// `(pts_time, scene_score)` pairs printed by `metadata=print` after `select='gte(scene,0)'`.
+pub fn parse_scene_scores(stdout: &str) -> Vec<(f64, f64)> {
+    let mut out = Vec::new();
+    let mut t: Option<f64> = None;
+    for line in stdout.lines() {
+        if let Some(rest) = line.split("pts_time:").nth(1) {
+            t = rest.split_whitespace().next().and_then(|x| x.parse().ok());
+        } else if let Some(v) = line.trim().strip_prefix("lavfi.scene_score=") {
+            if let (Some(t), Ok(score)) = (t.take(), v.trim().parse::<f64>()) {
+                out.push((t, score));
+            }
+        }
+    }
+    out
+}
+
+/// Picks a poster time in `[start, end)`: the frame with the most change that is not a cut,
+/// away from the edges where transitions land. Falls back to the midpoint.
+pub fn pick_poster_time(
+    scores: &[(f64, f64)],
+    start: f64,
+    end: f64,
+    threshold: f64,
+) -> (f64, &'static str) {
+    let len = (end - start).max(0.0);
+    let (lo, hi) = (start + len * 0.1, end - len * 0.1);
+    let cuts: Vec<f64> = scores
+        .iter()
+        .filter(|(_, s)| *s >= threshold)
+        .map(|(t, _)| *t)
+        .collect();
+    scores
+        .iter()
+        .filter(|(t, s)| *t >= lo && *t < hi && *s < threshold)
+        .filter(|(t, _)| !cuts.iter().any(|c| (t - c).abs() < 0.25))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(t, _)| (*t, "scene"))
+        .unwrap_or((start + len / 2.0, "midpoint"))
+}
+
+async fn run_ffmpeg(cmd: &mut Command, what: &str) -> Result<(), String> {
+    let status = cmd
+        .status()
+        .await
+        .map_err(|e| format!("spawn ffmpeg {what}: {e}"))?;
+    if !status.success() {
+        return Err(format!("ffmpeg {what} failed: exit={:?}", status.code()));
+    }
+    Ok(())
+}
+
+async fn scene_scores(ffmpeg: &str, video: &Path) -> Result<Vec<(f64, f64)>, String> {
+    let out = Command::new(ffmpeg)
+        .kill_on_drop(true)
+        .args(["-hide_banner", "-nostats", "-i"])
+        .arg(video)
+        .args([
+            "-an",
+            "-vf",
+            "select='gte(scene,0)',metadata=print:key=lavfi.scene_score:file=-",
+            "-f",
+            "null",
+            "-",
+        ])
+        .output()
+        .await
+        .map_err(|e| format!("spawn ffmpeg scene scores: {e}"))?;
+    if !out.status.success() {
+        return Err(format!(
+            "ffmpeg scene scores failed: exit={:?}",
+            out.status.code()
+        ));
+    }
+    Ok(parse_scene_scores(&String::from_utf8_lossy(&out.stdout)))
+}
+
+async fn extract_frame(ffmpeg: &str, video: &Path, t: f64, width: u32, out: &Path) -> Result<(), String> {
+    let mut cmd = Command::new(ffmpeg);
+    cmd.kill_on_drop(true);
+    cmd.args(["-y", "-loglevel", "error", "-ss", &format!("{t:.3}"), "-i"])
+        .arg(video)
+        .args(["-frames:v", "1", "-vf", &format!("scale={width}:-2")])
+        .args(["-q:v", "3"])
+        .arg(out);
+    run_ffmpeg(&mut cmd, &format!("poster at {t:.3}s")).await
+}
+
+async fn artifact(
+    out_dir: &Path,
+    path: &Path,
+    kind: &str,
+    mime: &str,
+    shot_id: Option<String>,
+    t: Option<f64>,
+    pick: Option<&str>,
+) -> ThumbnailArtifact {
+    ThumbnailArtifact {
+        schema: THUMBNAIL_SCHEMA.to_string(),
+        kind: kind.to_string(),
+        path: path
+            .strip_prefix(out_dir)
+            .unwrap_or(path)
+            .display()
+            .to_string(),
+        mime: mime.to_string(),
+        shot_id,
+        t,
+        pick: pick.map(str::to_string),
+        bytes: tokio::fs::metadata(path)
+            .await
+            .map(|m| m.len())
+            .unwrap_or(0),
+    }
+}
+
+/// Writes poster frames for every shot and for the whole video, a contact sheet of the shot
+/// posters and an animated preview into `build/thumbnails/`. Shot windows come from the
+/// storyboard's durations laid end to end over `video`.
+pub async fn generate_thumbnails(
+    ffmpeg: &str,
+    out_dir: &Path,
+    video: &Path,
+    shots: &[ShotV1],
+    poster_t: Option<f32>,
+    spec: &ThumbnailSpec,
+) -> Result<ThumbnailReport, String> {
+    let dir = out_dir.join("build/thumbnails");
+    tokio::fs::create_dir_all(dir.join("shots"))
+        .await
+        .map_err(|e| format!("create thumbnails dir: {e}"))?;
+
+    let threshold = spec.scene_threshold as f64;
+    let needs_scores = poster_t.is_none() || shots.iter().any(|s| s.poster_t.is_none());
+    let scores = if needs_scores {
+        scene_scores(ffmpeg, video).await?
+    } else {
+        Vec::new()
+    };
+
+    let mut windows = Vec::with_capacity(shots.len());
+    let mut start = 0.0f64;
+    for s in shots {
+        let end = start + s.duration_s.max(0.0) as f64;
+        windows.push((start, end));
+        start = end;
+    }
+    let total = start;
+
+    let mut shot_posters = Vec::with_capacity(shots.len());
+    for (i, (shot, (start, end))) in shots.iter().zip(&windows).enumerate() {
+        let (t, pick) = match shot.poster_t {
+            Some(t) => ((start + t.max(0.0) as f64).min(*end), "storyboard"),
+            None => pick_poster_time(&scores, *start, *end, threshold),
+        };
+        let path = dir.join(format!("shots/{i:03}.jpg"));
+        extract_frame(ffmpeg, video, t, spec.width, &path).await?;
+        shot_posters.push(
+            artifact(
+                out_dir,
+                &path,
+                "shot_poster",
+                "image/jpeg",
+                Some(shot.id.clone()),
+                Some(t),
+                Some(pick),
+            )
+            .await,
+        );
+    }
+
+    let (t, pick) = match poster_t {
+        Some(t) => (t.max(0.0) as f64, "storyboard"),
+        None => pick_poster_time(&scores, 0.0, total, threshold),
+    };
+    let poster_path = dir.join("poster.jpg");
+    extract_frame(ffmpeg, video, t, spec.width, &poster_path).await?;
+    let poster = artifact(
+        out_dir,
+        &poster_path,
+        "poster",
+        "image/jpeg",
+        None,
+        Some(t),
+        Some(pick),
+    )
+    .await;
+
+    let mut contact_sheet = None;
+    if spec.contact_sheet && !shot_posters.is_empty() {
+        let n = shot_posters.len() as u32;
+        let cols = spec.columns.clamp(1, n);
+        let rows = n.div_ceil(cols);
+        let path = dir.join("contact_sheet.jpg");
+        let mut cmd = Command::new(ffmpeg);
+        cmd.kill_on_drop(true);
+        cmd.args(["-y", "-loglevel", "error", "-framerate", "1", "-i"])
+            .arg(dir.join("shots/%03d.jpg"))
+            .arg("-vf")
+            .arg(format!(
+                "scale={}:-2,tile={cols}x{rows}:padding=4:margin=4:color=black",
+                spec.tile_width
+            ))
+            .args(["-frames:v", "1", "-q:v", "3"])
+            .arg(&path);
+        run_ffmpeg(&mut cmd, "contact sheet").await?;
+        contact_sheet = Some(
+            artifact(
+                out_dir,
+                &path,
+                "contact_sheet",
+                "image/jpeg",
+                None,
+                None,
+                None,
+            )
+            .await,
+        );
+    }
+
+    let mut preview = None;
+    if let Some(format) = spec.preview {
+        // A short excerpt around each shot's poster time, at most twelve shots.
+        let half = (spec.preview_clip_s.max(0.2) / 2.0) as f64;
+        let step = shot_posters.len().div_ceil(12).max(1);
+        let ranges: Vec<String> = shot_posters
+            .iter()
+            .zip(&windows)
+            .step_by(step)
+            .filter_map(|(p, (start, end))| {
+                let t = p.t?;
+                let a = (t - half).max(*start);
+                let b = (t + half).min(*end);
+                Some(format!("between(t,{a:.3},{b:.3})"))
+            })
+            .collect();
+        let select = if ranges.is_empty() {
+            format!("between(t,0,{:.3})", spec.preview_clip_s.max(0.2) * 4.0)
+        } else {
+            ranges.join("+")
+        };
+        let base = format!(
+            "select='{select}',setpts=N/({fps}*TB),fps={fps},scale={w}:-2:flags=lanczos",
+            fps = spec.preview_fps.max(1),
+            w = spec.preview_width
+        );
+        let path = dir.join(format!("preview.{}", format.ext()));
+        let mut cmd = Command::new(ffmpeg);
+        cmd.kill_on_drop(true);
+        cmd.args(["-y", "-loglevel", "error", "-i"])
+            .arg(video)
+            .arg("-an");
+        match format {
+            PreviewFormat::Gif => {
+                cmd.arg("-filter_complex").arg(format!(
+                    "[0:v]{base},split[a][b];[a]palettegen=stats_mode=diff[p];[b][p]paletteuse=dither=bayer"
+                ));
+                cmd.args(["-loop", "0"]);
+            }
+            PreviewFormat::Webp => {
+                cmd.arg("-vf").arg(base);
+                cmd.args([
+                    "-c:v",
+                    "libwebp",
+                    "-lossless",
+                    "0",
+                    "-q:v",
+                    "60",
+                    "-loop",
+                    "0",
+                ]);
+            }
+        }
+        cmd.arg(&path);
+        run_ffmpeg(&mut cmd, "animated preview").await?;
+        preview = Some(artifact(out_dir, &path, "preview", format.mime(), None, None, None).await);
+    }
+
+    Ok(ThumbnailReport {
+        source: video
+            .strip_prefix(out_dir)
+            .unwrap_or(video)
+            .display()
+            .to_string(),
+        poster,
+        shots: shot_posters,
+        contact_sheet,
+        preview,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scene_scores_pair_times_with_scores() {
+        let out = "frame:0    pts:0      pts_time:0\nlavfi.scene_score=0.000000\n\
+                   frame:1    pts:512    pts_time:1.5\nlavfi.scene_score=0.420000\n";
+        assert_eq!(parse_scene_scores(out), vec![(0.0, 0.0), (1.5, 0.42)]);
+    }
+
+    #[test]
+    fn poster_avoids_cuts_and_edges() {
+        // 9.0 is a cut, 8.9 sits next to it, 0.5 is inside the leading 10% margin.
+        let scores = [(0.5, 0.3), (3.0, 0.1), (5.0, 0.2), (8.9, 0.35), (9.0, 0.9)];
+        assert_eq!(pick_poster_time(&scores, 0.0, 10.0, 0.4), (5.0, "scene"));
+    }
+
+    #[test]
+    fn poster_falls_back_to_the_midpoint() {
+        assert_eq!(pick_poster_time(&[], 2.0, 6.0, 0.4), (4.0, "midpoint"));
+        let only_edges = [(2.1, 0.2), (5.9, 0.2)];
+        assert_eq!(pick_poster_time(&only_edges, 2.0, 6.0, 0.4), (4.0, "midpoint"));
+    }
+}
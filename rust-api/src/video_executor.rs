@@ -1,3 +1,10 @@
+use crate::asset_store::AssetScope;
+use crate::encoder::EncoderProfile;
+use crate::media_probe::{ffprobe_for, verify_media, MediaExpect};
+use crate::render_manifest::{
+    diff_manifests, file_hash, hash_args, ManifestDiff, RenderManifest, RenderStep, VerifyReport,
+    DETERMINISTIC_ARGS, RENDER_MANIFEST_SCHEMA,
+};
 use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
 use std::{
@@ -17,6 +24,12 @@ pub struct StoryboardV1 {
     pub fps: u32,
     pub resolution: Resolution,
     pub shots: Vec<ShotV1>,
+    /// Extra aspect targets rendered from the same shots, e.g. 9:16 and 1:1 for social.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<OutputTarget>,
+    /// Poster frame time in the final video, in seconds; picked automatically when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poster_t: Option<f32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,6 +46,38 @@ pub struct ShotV1 {
     pub bg: BgSpec,
     pub camera: CameraSpec,
     pub overlay: Option<OverlaySpec>,
+    /// Point of interest kept in frame when cropping to another aspect ratio.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focus: Option<FocusPoint>,
+    /// Poster frame time within the shot, in seconds from its start.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poster_t: Option<f32>,
+}
+
+/// Normalized frame coordinates, `0.0..=1.0` from the top-left corner.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct FocusPoint {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reframe {
+    #[default]
+    CenterCrop,
+    /// Whole source frame over a blurred, cropped copy of itself.
+    BlurPad,
+    /// Crop around the shot's `focus`; center when a shot has none.
+    Focus,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputTarget {
+    pub name: String,
+    pub resolution: Resolution,
+    #[serde(default)]
+    pub reframe: Reframe,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -41,7 +86,15 @@ pub enum BgSpec {
     #[serde(rename = "color")]
     Color { value: String },
     #[serde(rename = "image")]
-    Image { path: String },
+    Image { asset: String },
+    #[serde(rename = "video")]
+    Video {
+        asset: String,
+        #[serde(default)]
+        trim_start: f32,
+        #[serde(default)]
+        r#loop: bool,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -54,6 +107,9 @@ pub struct CameraSpec {
 pub struct OverlaySpec {
     pub enabled: bool,
     pub text: Option<String>,
+    /// `top` (default), `center` or `bottom`; laid out inside the target's safe area.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -61,6 +117,13 @@ pub struct VideoExecConfig {
     pub ffmpeg_path: String,
     pub concurrency: usize,
     pub workdir: PathBuf,
+    pub assets_root: PathBuf,
+    /// Whose assets backgrounds may use.
+    pub asset_scope: AssetScope,
+    pub profile: EncoderProfile,
+    /// Single-threaded, bitexact encodes with metadata stripped, so the same storyboard and
+    /// assets give byte-identical files.
+    pub reproducible: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -70,6 +133,13 @@ pub struct ShotMetric {
     pub ended_at: OffsetDateTime,
     pub duration_ms: i64,
     pub output_mp4: String,
+    /// Encoder profile name and ffmpeg encoder the shot was rendered with.
+    #[serde(default)]
+    pub profile: String,
+    #[serde(default)]
+    pub encoder: String,
+    #[serde(default)]
+    pub args_sha256: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -79,6 +149,24 @@ pub struct VideoExecResult {
     pub concat_txt: PathBuf,
     pub video_mp4: PathBuf,
     pub shot_metrics: Vec<ShotMetric>,
+    #[serde(default)]
+    pub targets: Vec<TargetResult>,
+    #[serde(default)]
+    pub profile: EncoderProfile,
+    /// Frame size actually rendered; smaller than the storyboard's for draft profiles.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolution: Option<Resolution>,
+    /// `<workdir>/render_manifest.json`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TargetResult {
+    pub name: String,
+    pub resolution: Resolution,
+    pub reframe: Reframe,
+    pub video_mp4: PathBuf,
 }
 
 pub fn run_video_executor_v1(storyboard_path: &Path, cfg: VideoExecConfig) -> Result<VideoExecResult> {
@@ -89,10 +177,272 @@ pub fn run_video_executor_v1(storyboard_path: &Path, cfg: VideoExecConfig) -> Re
         bail!("unsupported storyboard schema: {}", sb.schema);
     }
 
+    let res = profile_resolution(&cfg.profile, &sb.resolution);
     let shots_dir = cfg.workdir.join("shots");
-    fs::create_dir_all(&shots_dir).context("create shots dir")?;
+    let m = render_shots(&sb.shots, &res, sb.fps, &cfg, &shots_dir, None)?;
+
+    let concat_path = cfg.workdir.join("concat.txt");
+    write_concat_list(&concat_path, &sb.shots, &shots_dir)?;
+
+    let out_video = cfg.workdir.join("video.mp4");
+    ffmpeg_concat(&cfg.ffmpeg_path, &concat_path, &out_video, cfg.reproducible)
+        .context("ffmpeg concat")?;
+
+    let manifest = build_manifest(&sb, storyboard_path, &cfg, &m, &out_video)?;
+    let manifest_path = cfg.workdir.join("render_manifest.json");
+    fs::write(&manifest_path, serde_json::to_vec_pretty(&manifest)?)
+        .context("write render manifest")?;
+
+    let mut targets = Vec::with_capacity(sb.outputs.len());
+    for target in &sb.outputs {
+        targets.push(render_target_v1(&sb, target, &cfg)?);
+    }
+
+    Ok(VideoExecResult {
+        shots_count: sb.shots.len(),
+        shots_dir,
+        concat_txt: concat_path,
+        video_mp4: out_video,
+        shot_metrics: m,
+        targets,
+        profile: cfg.profile.clone(),
+        resolution: Some(res),
+        manifest: Some(manifest_path),
+    })
+}
+
+fn build_manifest(
+    sb: &StoryboardV1,
+    storyboard_path: &Path,
+    cfg: &VideoExecConfig,
+    metrics: &[ShotMetric],
+    out_video: &Path,
+) -> Result<RenderManifest> {
+    let mut inputs = Vec::new();
+    for shot in &sb.shots {
+        let asset = match &shot.bg {
+            BgSpec::Image { asset } | BgSpec::Video { asset, .. } => asset,
+            BgSpec::Color { .. } => continue,
+        };
+        let path = resolve_bg_asset(cfg, &shot.id, asset)?;
+        let h = file_hash(&path, &cfg.assets_root)
+            .with_context(|| format!("hash asset {}", path.display()))?;
+        if !inputs.contains(&h) {
+            inputs.push(h);
+        }
+    }
+    let base = storyboard_path.parent().unwrap_or(Path::new(""));
+    let mut steps = Vec::with_capacity(metrics.len());
+    for m in metrics {
+        steps.push(RenderStep {
+            id: m.id.clone(),
+            args_sha256: m.args_sha256.clone(),
+            output: file_hash(Path::new(&m.output_mp4), &cfg.workdir)
+                .with_context(|| format!("hash shot {}", m.id))?,
+        });
+    }
+    Ok(RenderManifest {
+        schema: RENDER_MANIFEST_SCHEMA.to_string(),
+        seed: sb.seed,
+        reproducible: cfg.reproducible,
+        profile: cfg.profile.name.clone(),
+        storyboard: file_hash(storyboard_path, base).context("hash storyboard")?,
+        inputs,
+        steps,
+        output: file_hash(out_video, &cfg.workdir).context("hash video")?,
+    })
+}
+
+/// Re-renders the storyboard into `<workdir>/verify` with the recorded mode and compares the
+/// result with the manifest at `manifest_path`.
+pub fn verify_render_v1(
+    storyboard_path: &Path,
+    cfg: VideoExecConfig,
+    manifest_path: &Path,
+) -> Result<VerifyReport> {
+    let expected: RenderManifest = read_json(manifest_path)
+        .with_context(|| format!("read manifest: {}", manifest_path.display()))?;
+    let dir = cfg.workdir.join("verify");
+    run_video_executor_v1(
+        storyboard_path,
+        VideoExecConfig {
+            workdir: dir.clone(),
+            reproducible: expected.reproducible,
+            ..cfg
+        },
+    )?;
+    let actual: RenderManifest = read_json(&dir.join("render_manifest.json"))?;
+    let mut diffs = diff_manifests(&expected, &actual);
+    if expected.profile != actual.profile {
+        diffs.insert(
+            0,
+            ManifestDiff {
+                item: "profile".to_string(),
+                expected: expected.profile.clone(),
+                actual: actual.profile.clone(),
+            },
+        );
+    }
+    Ok(VerifyReport {
+        manifest: manifest_path.display().to_string(),
+        rerender_dir: dir.display().to_string(),
+        matched: diffs.is_empty(),
+        diffs,
+    })
+}
+
+/// Problems that would make a render fail or come out wrong; empty when the storyboard is
+/// renderable. Background assets are resolved against `assets_root`.
+pub fn validate_storyboard_v1(sb: &StoryboardV1, assets_root: &Path) -> Vec<String> {
+    let mut errors = Vec::new();
+    if sb.schema != "css.video.storyboard.v1" {
+        errors.push(format!("unsupported storyboard schema: {}", sb.schema));
+    }
+    if sb.fps == 0 {
+        errors.push("fps must be positive".to_string());
+    }
+    let check_res = |errors: &mut Vec<String>, what: &str, r: &Resolution| {
+        if r.w < 2 || r.h < 2 || !r.w.is_multiple_of(2) || !r.h.is_multiple_of(2) {
+            errors.push(format!("{what}: resolution {}x{} must be even and at least 2x2", r.w, r.h));
+        }
+    };
+    check_res(&mut errors, "storyboard", &sb.resolution);
+    if sb.shots.is_empty() {
+        errors.push("storyboard has no shots".to_string());
+    }
+
+    let mut ids = std::collections::BTreeSet::new();
+    let mut total_s = 0.0f32;
+    for (i, shot) in sb.shots.iter().enumerate() {
+        let label = if shot.id.is_empty() {
+            format!("shot #{i}")
+        } else {
+            format!("shot {}", shot.id)
+        };
+        if shot.id.is_empty() {
+            errors.push(format!("{label}: empty id"));
+        } else if !ids.insert(shot.id.as_str()) {
+            errors.push(format!("{label}: duplicate id"));
+        }
+        if !(shot.duration_s.is_finite() && shot.duration_s > 0.0) {
+            errors.push(format!("{label}: duration_s must be positive"));
+        } else {
+            total_s += shot.duration_s;
+        }
+        if let Some(t) = shot.poster_t {
+            if !(0.0..=shot.duration_s).contains(&t) {
+                errors.push(format!("{label}: poster_t {t} outside the shot"));
+            }
+        }
+        if let Some(f) = shot.focus {
+            if !(0.0..=1.0).contains(&f.x) || !(0.0..=1.0).contains(&f.y) {
+                errors.push(format!("{label}: focus must be within 0..1"));
+            }
+        }
+        match &shot.bg {
+            BgSpec::Color { value } if value.trim().is_empty() => {
+                errors.push(format!("{label}: empty bg color"));
+            }
+            BgSpec::Color { .. } => {}
+            BgSpec::Image { asset } | BgSpec::Video { asset, .. } => {
+                let resolved =
+                    crate::asset_store::resolve_asset_path(assets_root, asset, AssetScope::Any);
+                if let Err(e) = resolved {
+                    errors.push(format!("{label}: bg asset {asset}: {e}"));
+                }
+            }
+        }
+    }
+    if let Some(t) = sb.poster_t {
+        if t < 0.0 || t > total_s {
+            errors.push(format!("poster_t {t} outside the video ({total_s:.3}s)"));
+        }
+    }
+
+    let mut names = std::collections::BTreeSet::new();
+    for target in &sb.outputs {
+        if target.name.is_empty() {
+            errors.push("output target with empty name".to_string());
+        } else if !names.insert(target.name.as_str()) {
+            errors.push(format!("output {}: duplicate name", target.name));
+        }
+        check_res(&mut errors, &format!("output {}", target.name), &target.resolution);
+    }
+    errors
+}
+
+/// Frame size for `res` under `profile` (reduced for draft renders).
+pub fn profile_resolution(profile: &EncoderProfile, res: &Resolution) -> Resolution {
+    let (w, h) = profile.frame_size(res.w, res.h);
+    Resolution { w, h }
+}
+
+/// Renders shot `idx` of the storyboard into `<workdir>/shots/<id>.mp4`, for schedulers
+/// that run each shot as its own stage.
+pub fn render_shot_v1(storyboard_path: &Path, idx: usize, cfg: &VideoExecConfig) -> Result<ShotMetric> {
+    let sb: StoryboardV1 = read_json(storyboard_path)
+        .with_context(|| format!("read storyboard: {}", storyboard_path.display()))?;
+    if sb.schema != "css.video.storyboard.v1" {
+        bail!("unsupported storyboard schema: {}", sb.schema);
+    }
+    let Some(shot) = sb.shots.get(idx) else {
+        bail!("storyboard has {} shots, no shot #{idx}", sb.shots.len());
+    };
+    let res = profile_resolution(&cfg.profile, &sb.resolution);
+    let mut m = render_shots(
+        std::slice::from_ref(shot),
+        &res,
+        sb.fps,
+        cfg,
+        &cfg.workdir.join("shots"),
+        None,
+    )?;
+    m.pop().context("shot rendered without metrics")
+}
+
+/// Renders every shot again at the target's resolution and concatenates them into
+/// `<workdir>/targets/<name>/video.mp4`.
+pub fn render_target_v1(
+    sb: &StoryboardV1,
+    target: &OutputTarget,
+    cfg: &VideoExecConfig,
+) -> Result<TargetResult> {
+    if !crate::video::storyboard::valid_output_name(&target.name) {
+        bail!("output target name {:?} is not allowed", target.name);
+    }
+    let dir = cfg.workdir.join("targets").join(&target.name);
+    let shots_dir = dir.join("shots");
+    let res = profile_resolution(&cfg.profile, &target.resolution);
+    render_shots(&sb.shots, &res, sb.fps, cfg, &shots_dir, Some(target.reframe))
+        .with_context(|| format!("render target {}", target.name))?;
 
-    let jobs = sb.shots.clone();
+    let concat_path = dir.join("concat.txt");
+    write_concat_list(&concat_path, &sb.shots, &shots_dir)?;
+    let video_mp4 = dir.join("video.mp4");
+    ffmpeg_concat(&cfg.ffmpeg_path, &concat_path, &video_mp4, cfg.reproducible)
+        .with_context(|| format!("ffmpeg concat target {}", target.name))?;
+
+    Ok(TargetResult {
+        name: target.name.clone(),
+        resolution: res,
+        reframe: target.reframe,
+        video_mp4,
+    })
+}
+
+/// Renders `shots` into `shots_dir` on `cfg.concurrency` worker threads. `reframe` is set
+/// when the frame is an extra aspect target rather than the storyboard's own resolution.
+fn render_shots(
+    shots: &[ShotV1],
+    res: &Resolution,
+    fps: u32,
+    cfg: &VideoExecConfig,
+    shots_dir: &Path,
+    reframe: Option<Reframe>,
+) -> Result<Vec<ShotMetric>> {
+    fs::create_dir_all(shots_dir).context("create shots dir")?;
+
+    let jobs = shots.to_vec();
 
     let errs: Arc<Mutex<Vec<anyhow::Error>>> = Arc::new(Mutex::new(Vec::new()));
     let metrics: Arc<Mutex<Vec<ShotMetric>>> = Arc::new(Mutex::new(Vec::new()));
@@ -106,8 +456,8 @@ pub fn run_video_executor_v1(storyboard_path: &Path, cfg: VideoExecConfig) -> Re
         let errs = Arc::clone(&errs);
         let metrics = Arc::clone(&metrics);
         let cfg2 = cfg.clone();
-        let res = sb.resolution.clone();
-        let fps = sb.fps;
+        let res = res.clone();
+        let shots_dir = shots_dir.to_path_buf();
 
         workers.push(thread::spawn(move || {
             loop {
@@ -123,14 +473,15 @@ pub fn run_video_executor_v1(storyboard_path: &Path, cfg: VideoExecConfig) -> Re
                 let started_at = OffsetDateTime::now_utc();
                 let t0 = Instant::now();
 
-                let out_mp4 = cfg2.workdir.join("shots").join(format!("{}.mp4", shot.id));
-                let r = render_shot_ffmpeg(&shot, &res, fps, &cfg2, &cfg2.workdir.join("shots"));
+                let out_mp4 = shots_dir.join(format!("{}.mp4", shot.id));
+                let r = render_shot_ffmpeg(&shot, &res, fps, &cfg2, &shots_dir, reframe)
+                    .and_then(|h| verify_shot(&shot, &res, fps, &cfg2, &out_mp4).map(|_| h));
 
                 let ended_at = OffsetDateTime::now_utc();
                 let dur_ms = t0.elapsed().as_millis() as i64;
 
                 match r {
-                    Ok(_) => {
+                    Ok(args_sha256) => {
                         let mut g = metrics.lock().unwrap();
                         g.push(ShotMetric {
                             id: shot.id.clone(),
@@ -138,6 +489,9 @@ pub fn run_video_executor_v1(storyboard_path: &Path, cfg: VideoExecConfig) -> Re
                             ended_at,
                             duration_ms: dur_ms,
                             output_mp4: out_mp4.display().to_string(),
+                            profile: cfg2.profile.name.clone(),
+                            encoder: cfg2.profile.codec.ffmpeg_encoder().to_string(),
+                            args_sha256,
                         });
                     }
                     Err(e) => {
@@ -158,27 +512,17 @@ pub fn run_video_executor_v1(storyboard_path: &Path, cfg: VideoExecConfig) -> Re
         let _ = w.join();
     }
 
-    let g = errs.lock().unwrap();
+    let mut g = errs.lock().unwrap();
     if !g.is_empty() {
-        bail!("video executor failed: {} shot error(s): {}", g.len(), g[0]);
+        let n = g.len();
+        return Err(g.remove(0).context(format!("video executor failed: {n} shot error(s)")));
     }
 
-    let concat_path = cfg.workdir.join("concat.txt");
-    write_concat_list(&concat_path, &sb.shots, &shots_dir)?;
-
-    let out_video = cfg.workdir.join("video.mp4");
-    ffmpeg_concat(&cfg.ffmpeg_path, &concat_path, &out_video).context("ffmpeg concat")?;
+    drop(g);
 
     let mut m = metrics.lock().unwrap().clone();
     m.sort_by(|a, b| a.id.cmp(&b.id));
-
-    Ok(VideoExecResult {
-        shots_count: sb.shots.len(),
-        shots_dir,
-        concat_txt: concat_path,
-        video_mp4: out_video,
-        shot_metrics: m,
-    })
+    Ok(m)
 }
 
 fn read_json<T: for<'de> Deserialize<'de>>(p: &Path) -> Result<T> {
@@ -196,16 +540,20 @@ fn write_concat_list(concat_path: &Path, shots: &[ShotV1], shots_dir: &Path) ->
     Ok(())
 }
 
-fn ffmpeg_concat(ffmpeg: &str, concat_txt: &Path, out_mp4: &Path) -> Result<()> {
-    let status = Command::new(ffmpeg)
-        .args([
-            "-y",
-            "-f","concat",
-            "-safe","0",
-            "-i", concat_txt.to_str().unwrap(),
-            "-c","copy",
-            out_mp4.to_str().unwrap(),
-        ])
+fn ffmpeg_concat(ffmpeg: &str, concat_txt: &Path, out_mp4: &Path, reproducible: bool) -> Result<()> {
+    let mut cmd = Command::new(ffmpeg);
+    cmd.args([
+        "-y",
+        "-f","concat",
+        "-safe","0",
+        "-i", concat_txt.to_str().unwrap(),
+        "-c","copy",
+    ]);
+    if reproducible {
+        cmd.args(DETERMINISTIC_ARGS);
+    }
+    let status = cmd
+        .arg(out_mp4.to_str().unwrap())
         .status()
         .context("spawn ffmpeg concat")?;
 
@@ -221,7 +569,8 @@ fn render_shot_ffmpeg(
     fps: u32,
     cfg: &VideoExecConfig,
     shots_dir: &Path,
-) -> Result<()> {
+    reframe: Option<Reframe>,
+) -> Result<String> {
     let out = shots_dir.join(format!("{}.mp4", shot.id));
     let dur = shot.duration_s.max(0.2);
 
@@ -234,35 +583,195 @@ fn render_shot_ffmpeg(
             cmd.arg("-i");
             cmd.arg(format!("color=c={}:s={}x{}:r={}:d={}", value, res.w, res.h, fps, dur));
         }
-        BgSpec::Image { path } => {
+        BgSpec::Image { asset } => {
+            let path = resolve_bg_asset(cfg, &shot.id, asset)?;
             cmd.args(["-loop","1","-t"]);
             cmd.arg(format!("{dur}"));
             cmd.arg("-i");
             cmd.arg(path);
         }
+        BgSpec::Video { asset, trim_start, r#loop } => {
+            let path = resolve_bg_asset(cfg, &shot.id, asset)?;
+            if *trim_start > 0.0 {
+                cmd.arg("-ss");
+                cmd.arg(format!("{}", trim_start));
+            }
+            if *r#loop {
+                cmd.args(["-stream_loop","-1"]);
+            }
+            cmd.arg("-t");
+            cmd.arg(format!("{dur}"));
+            cmd.arg("-i");
+            cmd.arg(path);
+            cmd.arg("-an");
+        }
     }
 
-    let vf = build_vf(shot, res, fps)?;
+    let vf = build_vf(shot, res, fps, reframe)?;
     cmd.args(["-vf", &vf]);
     cmd.args(["-r", &fps.to_string()]);
-    cmd.args(["-pix_fmt","yuv420p"]);
-    cmd.args(["-c:v","libx264","-preset","veryfast","-crf","18"]);
+    cmd.args(cfg.profile.ffmpeg_args(fps));
+    // zoompan emits `d` frames per input frame; cap the output at the shot length.
+    cmd.arg("-t").arg(format!("{dur}"));
+    if cfg.reproducible {
+        cmd.args(DETERMINISTIC_ARGS);
+    }
     cmd.arg(out.to_str().unwrap());
+    let args_sha256 = hash_args(
+        cmd.get_args(),
+        &[(&cfg.workdir, "$WORKDIR"), (&cfg.assets_root, "$ASSETS")],
+    );
 
     let status = cmd.status().with_context(|| format!("spawn ffmpeg for {}", shot.id))?;
     if !status.success() {
         bail!("ffmpeg shot {} failed: exit={:?}", shot.id, status.code());
     }
+    Ok(args_sha256)
+}
+
+/// Probes a rendered shot: duration within two frames, exact frame size and rate, the
+/// profile's codec and pixel format, and no audio stream.
+fn verify_shot(
+    shot: &ShotV1,
+    res: &Resolution,
+    fps: u32,
+    cfg: &VideoExecConfig,
+    out_mp4: &Path,
+) -> Result<()> {
+    let expect = MediaExpect {
+        duration_s: Some(shot.duration_s.max(0.2) as f64),
+        duration_tolerance_s: 2.0 / fps.max(1) as f64,
+        w: Some(res.w),
+        h: Some(res.h),
+        fps: Some(fps as f64),
+        video_codec: Some(cfg.profile.codec.probe_name().to_string()),
+        pix_fmt: Some(cfg.profile.pix_fmt.clone()),
+        video: true,
+        audio: Some(false),
+        sample_rate: None,
+    };
+    verify_media(&ffprobe_for(&cfg.ffmpeg_path), out_mp4, &expect)
+        .with_context(|| format!("verify shot {}", shot.id))?;
     Ok(())
 }
 
-fn build_vf(shot: &ShotV1, res: &Resolution, fps: u32) -> Result<String> {
+fn resolve_bg_asset(cfg: &VideoExecConfig, shot_id: &str, asset: &str) -> Result<PathBuf> {
+    crate::asset_store::resolve_asset_path(&cfg.assets_root, asset, cfg.asset_scope)
+        .with_context(|| format!("resolve bg asset for {}", shot_id))
+}
+
+/// Scales the source to exactly `res`: a crop (centered, or around the shot's focus point)
+/// or the whole frame padded over a blurred copy of itself.
+fn reframe_filter(shot: &ShotV1, res: &Resolution, reframe: Reframe) -> String {
+    let (w, h) = (res.w, res.h);
+    match (reframe, shot.focus) {
+        (Reframe::BlurPad, _) => format!(
+            "split=2[rf_bg][rf_fg];\
+             [rf_bg]scale={w}:{h}:force_original_aspect_ratio=increase,crop={w}:{h},boxblur=20:2[rf_b];\
+             [rf_fg]scale={w}:{h}:force_original_aspect_ratio=decrease[rf_f];\
+             [rf_b][rf_f]overlay=(W-w)/2:(H-h)/2,setsar=1"
+        ),
+        (Reframe::Focus, Some(f)) => {
+            let (fx, fy) = (f.x.clamp(0.0, 1.0), f.y.clamp(0.0, 1.0));
+            format!(
+                "scale={w}:{h}:force_original_aspect_ratio=increase,\
+                 crop={w}:{h}:x='min(max(iw*{fx}-ow/2,0),iw-ow)':y='min(max(ih*{fy}-oh/2,0),ih-oh)',setsar=1"
+            )
+        }
+        _ => format!("scale={w}:{h}:force_original_aspect_ratio=increase,crop={w}:{h},setsar=1"),
+    }
+}
+
+/// Escapes text for a filter option value inside a filtergraph (two levels of quoting).
+fn escape_filter_text(s: &str) -> String {
+    let mut opt = String::new();
+    for c in s.chars() {
+        if matches!(c, '\\' | '\'' | ':') {
+            opt.push('\\');
+        }
+        opt.push(c);
+    }
+    let mut out = String::new();
+    for c in opt.chars() {
+        if matches!(c, '\\' | '\'' | ',' | ';' | '[' | ']') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Overlay text laid out for the frame: font size follows the short edge and shrinks for
+/// long text on narrow frames; the position stays inside the safe area, which is deeper on
+/// portrait frames where platform UI covers the top and bottom.
+fn overlay_filter(shot: &ShotV1, res: &Resolution) -> Option<String> {
+    let ov = shot.overlay.as_ref().filter(|o| o.enabled)?;
+    let text = ov.text.as_deref().map(str::trim).filter(|t| !t.is_empty())?;
+    let (w, h) = (res.w as f32, res.h as f32);
+    let chars = text.chars().count().max(1) as f32;
+    let fontsize = (w.min(h) / 14.0).min(w * 0.8 / (0.55 * chars)).max(12.0).round() as u32;
+    let (top, bottom) = if res.h > res.w { (0.15, 0.20) } else { (0.10, 0.10) };
+    let y = match ov.position.as_deref() {
+        Some("center") => "(h-text_h)/2".to_string(),
+        Some("bottom") => format!("h*{:.2}-text_h", 1.0 - bottom),
+        _ => format!("h*{top:.2}"),
+    };
+    Some(format!(
+        "drawtext=text={}:expansion=none:fontsize={fontsize}:fontcolor=white:borderw={}:bordercolor=black@0.6:x=(w-text_w)/2:y={y}",
+        escape_filter_text(text),
+        (fontsize / 16).max(1)
+    ))
+}
+
+/// `reframe` is `None` for the storyboard's own resolution and `Some` for extra aspect targets,
+/// where the source is first reframed to the target and camera moves run inside that frame.
+fn build_vf(shot: &ShotV1, res: &Resolution, fps: u32, reframe: Option<Reframe>) -> Result<String> {
+    let mut vf = base_vf(shot, res, fps, reframe);
+    if let Some(ov) = overlay_filter(shot, res) {
+        vf.push(',');
+        vf.push_str(&ov);
+    }
+    Ok(vf)
+}
+
+fn base_vf(shot: &ShotV1, res: &Resolution, fps: u32, reframe: Option<Reframe>) -> String {
+    // zoompan emits `d` frames per input frame, so moving footage is only fitted to the frame.
+    if matches!(shot.bg, BgSpec::Video { .. }) {
+        return format!(
+            "{},fps={fps}",
+            reframe_filter(shot, res, reframe.unwrap_or(Reframe::Focus))
+        );
+    }
+
     let mv = shot.camera.r#move.as_str();
     let strength = shot.camera.strength.clamp(0.0, 1.0);
     let zoom = 1.0 + 0.10 * strength as f64;
     let frames = (shot.duration_s.max(0.2) * fps as f32).round() as i32;
 
-    let vf = match mv {
+    if let (Some(r), BgSpec::Image { .. }) = (reframe, &shot.bg) {
+        let pre = reframe_filter(shot, res, r);
+        let (w, h) = (res.w, res.h);
+        let dz = (zoom - 1.0) / frames.max(1) as f64;
+        // The reframed image fills the target exactly, so pans need some zoom to travel.
+        let pan_zoom = zoom.max(1.05);
+        return match mv {
+            "push_in" => format!(
+                "{pre},zoompan=z='min(zoom+{dz},{zoom})':d={frames}:x='iw/2-(iw/zoom/2)':y='ih/2-(ih/zoom/2)':s={w}x{h}"
+            ),
+            "pull_out" => format!(
+                "{pre},zoompan=z='if(eq(on,0),{zoom},max(zoom-{dz},1.0))':d={frames}:x='iw/2-(iw/zoom/2)':y='ih/2-(ih/zoom/2)':s={w}x{h}"
+            ),
+            "pan_left" => format!(
+                "{pre},zoompan=z={pan_zoom}:d={frames}:x='(iw-iw/zoom)*(1-on/{frames})':y='ih/2-(ih/zoom/2)':s={w}x{h}"
+            ),
+            "pan_right" => format!(
+                "{pre},zoompan=z={pan_zoom}:d={frames}:x='(iw-iw/zoom)*(on/{frames})':y='ih/2-(ih/zoom/2)':s={w}x{h}"
+            ),
+            _ => pre,
+        };
+    }
+
+    match mv {
         "static" => format!("scale={}x{}", res.w, res.h),
         "push_in" => format!(
             "zoompan=z='min(zoom+{dz},{zmax})':d={frames}:x='iw/2-(iw/zoom/2)':y='ih/2-(ih/zoom/2)',scale={w}x{h}",
@@ -281,7 +790,5 @@ fn build_vf(shot: &ShotV1, res: &Resolution, fps: u32) -> Result<String> {
             frames=frames, w=res.w, h=res.h
         ),
         _ => format!("scale={}x{}", res.w, res.h),
-    };
-
-    Ok(vf)
+    }
 }
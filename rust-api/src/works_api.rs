@@ -0,0 +1,1259 @@
+use crate::artifacts::{Artifact, ArtifactKind, ArtifactRegistry};
+use crate::artifacts_api::{artifact_path, load_run, signed_path};
+use crate::auth::AuthSession;
+use crate::billing::{purchase_work, PurchaseError};
+use crate::models::{Work, WorkAsset};
+use crate::routes::AppState;
+use axum::{
+    extract::{Json, Path, Query, State},
+    http::header,
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+pub const WORK_SCHEMA: &str = "css.work.v1";
+pub const WORKS_SCHEMA: &str = "css.works.v1";
+pub const WORK_KINDS: &[&str] = &["mv", "opera"];
+const MAX_TITLE_CHARS: usize = 200;
+const MAX_PRICE_CENTS: i64 = 1_000_000;
+const DEFAULT_LIST_LIMIT: i64 = 50;
+const MAX_LIST_LIMIT: i64 = 200;
+
+fn error(status: StatusCode, code: &str, message: impl Into<String>) -> Response {
+    (
+        status,
+        Json(json!({
+            "schema":"css.error.v1",
+            "code": code,
+            "message": message.into()
+        })),
+    )
+        .into_response()
+}
+
+fn db_error(e: sqlx::Error) -> Response {
+    error(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "WORK_DB_FAILED",
+        e.to_string(),
+    )
+}
+
+fn auth_required() -> Response {
+    error(
+        StatusCode::UNAUTHORIZED,
+        "AUTH_REQUIRED",
+        "sign in to manage works",
+    )
+}
+
+fn work_not_found(id: &str) -> Response {
+    error(
+        StatusCode::NOT_FOUND,
+        "WORK_NOT_FOUND",
+        format!("work {id} not found"),
+    )
+}
+
+/// A `work_assets` row before it is inserted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetDraft {
+    /// `video`, `stream`, `thumbnail`, `subtitles` or `audio`.
+    pub asset_type: &'static str,
+    pub url: String,
+    pub meta: Value,
+}
+
+fn draft(run_id: &str, a: &Artifact, asset_type: &'static str, extra: Value) -> AssetDraft {
+    let mut meta = json!({
+        "run_id": run_id,
+        "artifact_key": a.key,
+        "mime": a.mime,
+        "size": a.size,
+        "sha256": a.sha256,
+    });
+    if let (Some(m), Value::Object(extra)) = (meta.as_object_mut(), extra) {
+        m.extend(extra);
+    }
+    AssetDraft {
+        asset_type,
+        url: artifact_path(run_id, &a.key),
+        meta,
+    }
+}
+
+fn under(a: &Artifact, dir: &str) -> bool {
+    a.path.as_deref().is_some_and(|p| p.starts_with(dir))
+}
+
+/// `path -> entry` for the objects in a report value (`video.thumbnails`, `video.subtitles`).
+fn entries_by_path(v: Option<&Value>) -> BTreeMap<String, Value> {
+    let mut out = BTreeMap::new();
+    let mut visit = |e: &Value| {
+        if let Some(p) = e.get("path").and_then(|p| p.as_str()) {
+            out.insert(p.trim_start_matches("./").to_string(), e.clone());
+        }
+    };
+    match v {
+        Some(Value::Array(items)) => items.iter().for_each(&mut visit),
+        Some(Value::Object(m)) => {
+            for e in m.values() {
+                match e {
+                    Value::Array(items) => items.iter().for_each(&mut visit),
+                    e => visit(e),
+                }
+            }
+        }
+        _ => {}
+    }
+    out
+}
+
+/// What a published work carries from its run: the final video and its streaming
+/// manifests, the thumbnails (poster first), subtitle tracks and the audio. Only file
+/// artifacts are used, so every URL resolves through the artifact endpoint.
+pub fn work_assets_from_run(run_id: &str, reg: &ArtifactRegistry) -> Vec<AssetDraft> {
+    let file = |key: &str| reg.get(key).filter(|a| a.is_file());
+    let rel = |a: &Artifact| {
+        a.path
+            .as_deref()
+            .map(|p| p.display().to_string())
+            .unwrap_or_default()
+    };
+    let mut out = Vec::new();
+
+    if let Some(a) = file("video.final_mv") {
+        out.push(draft(run_id, a, "video", json!({"role": "final"})));
+    }
+    for (key, role) in [
+        ("package.hls_master", "hls"),
+        ("package.dash_manifest", "dash"),
+    ] {
+        if let Some(a) = file(key) {
+            out.push(draft(run_id, a, "stream", json!({ "role": role })));
+        }
+    }
+
+    let thumbs = entries_by_path(reg.value_of("video.thumbnails"));
+    let mut images: Vec<(&Artifact, Value)> = reg
+        .iter()
+        .filter(|a| a.is_file() && a.kind == ArtifactKind::Image && under(a, "build/thumbnails"))
+        .map(|a| {
+            let e = thumbs.get(&rel(a));
+            let extra = json!({
+                "role": e.and_then(|e| e.get("kind")).cloned().unwrap_or(json!("thumbnail")),
+                "shot_id": e.and_then(|e| e.get("shot_id")),
+                "t": e.and_then(|e| e.get("t")),
+            });
+            (a, extra)
+        })
+        .collect();
+    images.sort_by_key(|(_, extra)| extra["role"] != "poster");
+    for (a, extra) in images {
+        out.push(draft(run_id, a, "thumbnail", extra));
+    }
+
+    let tracks = entries_by_path(reg.value_of("video.subtitles"));
+    for a in reg
+        .iter()
+        .filter(|a| a.is_file() && a.kind == ArtifactKind::Subtitles && under(a, "build/subtitles"))
+    {
+        let path = a.path.as_deref().expect("file artifacts have a path");
+        let e = tracks.get(&rel(a));
+        let lang = e
+            .and_then(|e| e.get("lang").cloned())
+            .unwrap_or_else(|| json!(path.file_stem().and_then(|s| s.to_str())));
+        let format = e
+            .and_then(|e| e.get("format").cloned())
+            .unwrap_or_else(|| json!(path.extension().and_then(|s| s.to_str())));
+        out.push(draft(
+            run_id,
+            a,
+            "subtitles",
+            json!({ "lang": lang, "format": format }),
+        ));
+    }
+
+    for (key, role) in [
+        ("music.wav", "music"),
+        ("vocals.wav", "vocals"),
+        ("stems.zip", "stems"),
+    ] {
+        if let Some(a) = file(key) {
+            out.push(draft(run_id, a, "audio", json!({ "role": role })));
+        }
+    }
+    out
+}
+
+fn check_title(title: &str) -> Result<(), String> {
+    if title.trim().is_empty() {
+        return Err("title must not be empty".to_string());
+    }
+    if title.chars().count() > MAX_TITLE_CHARS {
+        return Err(format!("title is longer than {MAX_TITLE_CHARS} characters"));
+    }
+    Ok(())
+}
+
+fn check_price(price_cents: i64) -> Result<(), String> {
+    if !(0..=MAX_PRICE_CENTS).contains(&price_cents) {
+        return Err(format!(
+            "price_cents must be between 0 and {MAX_PRICE_CENTS}"
+        ));
+    }
+    Ok(())
+}
+
+fn check_currency(currency: &str) -> Result<(), String> {
+    if currency.len() != 3 || !currency.bytes().all(|b| b.is_ascii_uppercase()) {
+        return Err("currency must be a three-letter ISO code like USD".to_string());
+    }
+    Ok(())
+}
+
+fn check_kind(kind: &str) -> Result<(), String> {
+    if !WORK_KINDS.contains(&kind) {
+        return Err(format!("kind must be one of {}", WORK_KINDS.join(", ")));
+    }
+    Ok(())
+}
+
+/// Top-level merge: `null` removes a key. `run_id` and `source` link the work to its run
+/// and are not editable.
+fn merge_meta(meta: &mut Value, patch: &Map<String, Value>) {
+    if !meta.is_object() {
+        *meta = json!({});
+    }
+    let m = meta.as_object_mut().expect("meta is an object");
+    for (k, v) in patch {
+        if k == "run_id" || k == "source" {
+            continue;
+        }
+        if v.is_null() {
+            m.remove(k);
+        } else {
+            m.insert(k.clone(), v.clone());
+        }
+    }
+}
+
+fn asset_json(a: &WorkAsset) -> Value {
+    json!({
+        "id": a.id,
+        "asset_type": a.asset_type,
+        "url": a.url,
+        "meta": a.meta,
+        "created_at": a.created_at.to_rfc3339(),
+    })
+}
+
+pub fn work_json(w: &Work, assets: &[WorkAsset]) -> Value {
+    json!({
+        "schema": WORK_SCHEMA,
+        "id": w.id,
+        "kind": w.kind,
+        "title": w.title,
+        "status": if w.is_listed { "published" } else { "ready" },
+        "run_id": w.meta.get("run_id"),
+        "meta": w.meta,
+        "price_cents": w.price_cents,
+        "currency": w.currency,
+        "is_listed": w.is_listed,
+        "created_at": w.created_at.to_rfc3339(),
+        "updated_at": w.updated_at.to_rfc3339(),
+        "assets": assets.iter().map(asset_json).collect::<Vec<_>>(),
+    })
+}
+
+async fn assets_of(pool: &sqlx::PgPool, work_ids: &[Uuid]) -> Result<Vec<WorkAsset>, sqlx::Error> {
+    sqlx::query_as::<_, WorkAsset>(
+        "SELECT * FROM work_assets WHERE work_id = ANY($1) ORDER BY created_at, id",
+    )
+    .bind(work_ids)
+    .fetch_all(pool)
+    .await
+}
+
+async fn owned_work(pool: &sqlx::PgPool, user_id: Uuid, work_id: &str) -> Result<Work, Response> {
+    let Ok(id) = Uuid::parse_str(work_id) else {
+        return Err(work_not_found(work_id));
+    };
+    sqlx::query_as::<_, Work>("SELECT * FROM works WHERE id = $1 AND user_id = $2")
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| work_not_found(work_id))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PublishRequest {
+    /// `mv` (default) or `opera`.
+    pub kind: Option<String>,
+    /// Defaults to the song title.
+    pub title: Option<String>,
+    pub meta: Option<Map<String, Value>>,
+    pub price_cents: Option<i64>,
+    pub currency: Option<String>,
+    pub is_listed: Option<bool>,
+}
+
+/// Creates a work owned by the caller from a succeeded run of theirs, with its final video,
+/// thumbnails, subtitles and audio as assets. A run is published once.
+pub async fn publish_run(
+    State(state): State<AppState>,
+    Path(run_id): Path<String>,
+    AuthSession { user_id }: AuthSession,
+    body: Option<Json<PublishRequest>>,
+) -> Response {
+    let Some(user_id) = user_id else {
+        return auth_required();
+    };
+    let req = body.map(|Json(b)| b).unwrap_or_default();
+    let store = state.config.artifact_store.open();
+    let run = match load_run(&state, store.as_ref(), &run_id).await {
+        Ok(v) => v,
+        Err(resp) => return resp,
+    };
+    let owner = run
+        .get("user_id")
+        .and_then(|v| v.as_str())
+        .and_then(|s| Uuid::parse_str(s).ok());
+    if owner != Some(user_id) {
+        return error(
+            StatusCode::FORBIDDEN,
+            "RUN_NOT_OWNED",
+            format!("run {run_id} was not created by you"),
+        );
+    }
+    if run.get("status").and_then(|s| s.as_str()) != Some("SUCCEEDED") {
+        return error(
+            StatusCode::CONFLICT,
+            "RUN_NOT_COMPLETED",
+            format!("run {run_id} has not succeeded"),
+        );
+    }
+    let drafts = work_assets_from_run(&run_id, &ArtifactRegistry::from_run_json(&run));
+    if !drafts.iter().any(|d| d.asset_type == "video") {
+        return error(
+            StatusCode::CONFLICT,
+            "RUN_NOT_PUBLISHABLE",
+            format!("run {run_id} has no final video"),
+        );
+    }
+
+    let kind = req.kind.unwrap_or_else(|| "mv".to_string());
+    let title = req
+        .title
+        .or_else(|| {
+            run.pointer("/commands/song/title")
+                .and_then(|t| t.as_str())
+                .map(str::to_string)
+        })
+        .unwrap_or_else(|| run_id.clone());
+    let price_cents = req.price_cents.unwrap_or(0);
+    let currency = req.currency.unwrap_or_else(|| "USD".to_string());
+    let checked = check_kind(&kind)
+        .and_then(|_| check_title(&title))
+        .and_then(|_| check_price(price_cents))
+        .and_then(|_| check_currency(&currency));
+    if let Err(msg) = checked {
+        return error(StatusCode::BAD_REQUEST, "WORK_INVALID", msg);
+    }
+    let mut meta = json!({});
+    merge_meta(&mut meta, &req.meta.unwrap_or_default());
+    meta["run_id"] = json!(run_id);
+    meta["source"] = json!("run");
+    if let Some(song) = run.pointer("/commands/song") {
+        meta.as_object_mut()
+            .expect("meta is an object")
+            .entry("song")
+            .or_insert_with(|| song.clone());
+    }
+
+    let existing = sqlx::query_scalar::<_, Uuid>(
+        "SELECT id FROM works WHERE meta->>'run_id' = $1",
+    )
+    .bind(&run_id)
+    .fetch_optional(&state.pool)
+    .await;
+    match existing {
+        Ok(Some(id)) => {
+            return (
+                StatusCode::CONFLICT,
+                Json(json!({
+                    "schema":"css.error.v1",
+                    "code":"WORK_ALREADY_PUBLISHED",
+                    "message": format!("run {run_id} is already published as work {id}"),
+                    "work_id": id
+                })),
+            )
+                .into_response()
+        }
+        Ok(None) => {}
+        Err(e) => return db_error(e),
+    }
+
+    let created = async {
+        let mut tx = state.pool.begin().await?;
+        let work = sqlx::query_as::<_, Work>(
+            "INSERT INTO works (user_id, kind, title, meta, price_cents, currency, is_listed)
+             VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *",
+        )
+        .bind(user_id)
+        .bind(&kind)
+        .bind(title.trim())
+        .bind(&meta)
+        .bind(price_cents)
+        .bind(&currency)
+        .bind(req.is_listed.unwrap_or(false))
+        .fetch_one(&mut *tx)
+        .await?;
+        let mut assets = Vec::with_capacity(drafts.len());
+        for d in &drafts {
+            let a = sqlx::query_as::<_, WorkAsset>(
+                "INSERT INTO work_assets (work_id, asset_type, url, meta)
+                 VALUES ($1, $2, $3, $4) RETURNING *",
+            )
+            .bind(work.id)
+            .bind(d.asset_type)
+            .bind(&d.url)
+            .bind(&d.meta)
+            .fetch_one(&mut *tx)
+            .await?;
+            assets.push(a);
+        }
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>((work, assets))
+    }
+    .await;
+    match created {
+        Ok((work, assets)) => {
+            (StatusCode::CREATED, Json(work_json(&work, &assets))).into_response()
+        }
+        // Lost a race with another publish of the same run (works_run_uniq).
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => error(
+            StatusCode::CONFLICT,
+            "WORK_ALREADY_PUBLISHED",
+            format!("run {run_id} is already published"),
+        ),
+        Err(e) => db_error(e),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WorksQuery {
+    pub listed: Option<bool>,
+    pub limit: Option<i64>,
+}
+
+/// The caller's works, newest first.
+pub async fn list_works(
+    State(state): State<AppState>,
+    AuthSession { user_id }: AuthSession,
+    Query(q): Query<WorksQuery>,
+) -> Response {
+    let Some(user_id) = user_id else {
+        return auth_required();
+    };
+    let limit = q
+        .limit
+        .unwrap_or(DEFAULT_LIST_LIMIT)
+        .clamp(1, MAX_LIST_LIMIT);
+    let works = sqlx::query_as::<_, Work>(
+        "SELECT * FROM works WHERE user_id = $1 AND ($2::boolean IS NULL OR is_listed = $2)
+         ORDER BY created_at DESC LIMIT $3",
+    )
+    .bind(user_id)
+    .bind(q.listed)
+    .bind(limit)
+    .fetch_all(&state.pool)
+    .await;
+    let works = match works {
+        Ok(w) => w,
+        Err(e) => return db_error(e),
+    };
+    let ids: Vec<Uuid> = works.iter().map(|w| w.id).collect();
+    let assets = match assets_of(&state.pool, &ids).await {
+        Ok(a) => a,
+        Err(e) => return db_error(e),
+    };
+    let items: Vec<Value> = works
+        .iter()
+        .map(|w| {
+            let mine: Vec<WorkAsset> = assets
+                .iter()
+                .filter(|a| a.work_id == w.id)
+                .cloned()
+                .collect();
+            work_json(w, &mine)
+        })
+        .collect();
+    (
+        StatusCode::OK,
+        Json(json!({
+            "schema": WORKS_SCHEMA,
+            "limit": limit,
+            "items": items,
+        })),
+    )
+        .into_response()
+}
+
+pub async fn get_work(
+    State(state): State<AppState>,
+    Path(work_id): Path<String>,
+    AuthSession { user_id }: AuthSession,
+) -> Response {
+    let Some(user_id) = user_id else {
+        return auth_required();
+    };
+    let work = match owned_work(&state.pool, user_id, &work_id).await {
+        Ok(w) => w,
+        Err(resp) => return resp,
+    };
+    match assets_of(&state.pool, &[work.id]).await {
+        Ok(assets) => (StatusCode::OK, Json(work_json(&work, &assets))).into_response(),
+        Err(e) => db_error(e),
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct UpdateWorkRequest {
+    pub title: Option<String>,
+    pub kind: Option<String>,
+    /// Merged into the stored meta; `null` values remove keys.
+    pub meta: Option<Map<String, Value>>,
+    pub price_cents: Option<i64>,
+    pub currency: Option<String>,
+    /// Lists or unlists the work in the public catalog.
+    pub is_listed: Option<bool>,
+}
+
+pub async fn update_work(
+    State(state): State<AppState>,
+    Path(work_id): Path<String>,
+    AuthSession { user_id }: AuthSession,
+    Json(req): Json<UpdateWorkRequest>,
+) -> Response {
+    let Some(user_id) = user_id else {
+        return auth_required();
+    };
+    let Ok(id) = Uuid::parse_str(&work_id) else {
+        return work_not_found(&work_id);
+    };
+    // Locked so concurrent edits of the same work merge their meta instead of losing one.
+    let mut tx = match state.pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return db_error(e),
+    };
+    let locked =
+        sqlx::query_as::<_, Work>("SELECT * FROM works WHERE id = $1 AND user_id = $2 FOR UPDATE")
+            .bind(id)
+            .bind(user_id)
+            .fetch_optional(&mut *tx)
+            .await;
+    let mut work = match locked {
+        Ok(Some(w)) => w,
+        Ok(None) => return work_not_found(&work_id),
+        Err(e) => return db_error(e),
+    };
+    let assets = match assets_of(&state.pool, &[work.id]).await {
+        Ok(a) => a,
+        Err(e) => return db_error(e),
+    };
+
+    if let Some(t) = req.title {
+        work.title = t.trim().to_string();
+    }
+    if let Some(k) = req.kind {
+        work.kind = k;
+    }
+    if let Some(p) = req.price_cents {
+        work.price_cents = p;
+    }
+    if let Some(c) = req.currency {
+        work.currency = c;
+    }
+    if let Some(l) = req.is_listed {
+        work.is_listed = l;
+    }
+    if let Some(patch) = &req.meta {
+        merge_meta(&mut work.meta, patch);
+    }
+    let checked = check_kind(&work.kind)
+        .and_then(|_| check_title(&work.title))
+        .and_then(|_| check_price(work.price_cents))
+        .and_then(|_| check_currency(&work.currency));
+    if let Err(msg) = checked {
+        return error(StatusCode::BAD_REQUEST, "WORK_INVALID", msg);
+    }
+    if work.is_listed && !assets.iter().any(|a| a.asset_type == "video") {
+        return error(
+            StatusCode::CONFLICT,
+            "WORK_NOT_LISTABLE",
+            "a work needs a video asset to be listed",
+        );
+    }
+
+    let updated = async {
+        let w = sqlx::query_as::<_, Work>(
+            "UPDATE works
+             SET title = $2, kind = $3, meta = $4, price_cents = $5, currency = $6,
+                 is_listed = $7, updated_at = now()
+             WHERE id = $1 RETURNING *",
+        )
+        .bind(work.id)
+        .bind(&work.title)
+        .bind(&work.kind)
+        .bind(&work.meta)
+        .bind(work.price_cents)
+        .bind(&work.currency)
+        .bind(work.is_listed)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(w)
+    }
+    .await;
+    match updated {
+        Ok(w) => (StatusCode::OK, Json(work_json(&w, &assets))).into_response(),
+        Err(e) => db_error(e),
+    }
+}
+
+/// Deletes a work, or only unlists it once someone bought it so buyers keep their
+/// purchase and its entitlements.
+pub async fn delete_work(
+    State(state): State<AppState>,
+    Path(work_id): Path<String>,
+    AuthSession { user_id }: AuthSession,
+) -> Response {
+    let Some(user_id) = user_id else {
+        return auth_required();
+    };
+    let Ok(id) = Uuid::parse_str(&work_id) else {
+        return work_not_found(&work_id);
+    };
+    // The row lock waits out purchases in flight, which hold it FOR SHARE.
+    let removed = async {
+        let mut tx = state.pool.begin().await?;
+        let owned = sqlx::query_scalar::<_, Uuid>(
+            "SELECT id FROM works WHERE id = $1 AND user_id = $2 FOR UPDATE",
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if owned.is_none() {
+            return Ok(None);
+        }
+        let sold = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM work_purchases WHERE work_id = $1)",
+        )
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+        let unlisted = if sold {
+            let w = sqlx::query_as::<_, Work>(
+                "UPDATE works SET is_listed = false, updated_at = now() WHERE id = $1 RETURNING *",
+            )
+            .bind(id)
+            .fetch_one(&mut *tx)
+            .await?;
+            Some(w)
+        } else {
+            sqlx::query("DELETE FROM works WHERE id = $1")
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            None
+        };
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Some(unlisted))
+    }
+    .await;
+    match removed {
+        Ok(None) => work_not_found(&work_id),
+        Ok(Some(None)) => StatusCode::NO_CONTENT.into_response(),
+        Ok(Some(Some(w))) => match assets_of(&state.pool, &[w.id]).await {
+            Ok(assets) => (StatusCode::OK, Json(work_json(&w, &assets))).into_response(),
+            Err(e) => db_error(e),
+        },
+        Err(e) => db_error(e),
+    }
+}
+
+pub const CATALOG_SCHEMA: &str = "css.catalog.v1";
+pub const PURCHASE_SCHEMA: &str = "css.work.purchase.v1";
+/// Lifetime of the signed artifact link a work asset request is redirected to.
+const WORK_ASSET_LINK_TTL_S: i64 = 300;
+const DEFAULT_CATALOG_LIMIT: i64 = 24;
+const MAX_CATALOG_LIMIT: i64 = 100;
+
+/// `ORDER BY` for a catalog `sort`; `None` for unknown values.
+fn catalog_order(sort: Option<&str>) -> Option<&'static str> {
+    match sort.unwrap_or("newest") {
+        "newest" => Some("created_at DESC, id"),
+        "oldest" => Some("created_at ASC, id"),
+        "price_asc" => Some("price_cents ASC, created_at DESC, id"),
+        "price_desc" => Some("price_cents DESC, created_at DESC, id"),
+        _ => None,
+    }
+}
+
+/// `ILIKE` pattern matching `q` anywhere, with `%`, `_` and `\` taken literally.
+fn like_pattern(q: &str) -> String {
+    let mut out = String::with_capacity(q.len() + 2);
+    out.push('%');
+    for c in q.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push('%');
+    out
+}
+
+/// What a viewer may do with a work's files.
+#[derive(Debug, Clone, Copy, Default)]
+struct Access {
+    owned: bool,
+    purchased: bool,
+}
+
+impl Access {
+    fn entitled(&self, w: &Work) -> bool {
+        self.owned || self.purchased || w.price_cents == 0
+    }
+}
+
+async fn purchased_ids(
+    pool: &sqlx::PgPool,
+    user_id: Option<Uuid>,
+    work_ids: &[Uuid],
+) -> Result<Vec<Uuid>, sqlx::Error> {
+    let Some(user_id) = user_id else {
+        return Ok(Vec::new());
+    };
+    sqlx::query_scalar::<_, Uuid>(
+        "SELECT work_id FROM work_purchases WHERE buyer_id = $1 AND work_id = ANY($2)",
+    )
+    .bind(user_id)
+    .bind(work_ids)
+    .fetch_all(pool)
+    .await
+}
+
+fn catalog_asset_url(work_id: Uuid, asset_id: Uuid) -> String {
+    format!("/api/works/{work_id}/assets/{asset_id}")
+}
+
+/// Thumbnails are the storefront; everything else needs the work owned, bought or free.
+fn asset_public(a: &WorkAsset) -> bool {
+    a.asset_type == "thumbnail"
+}
+
+/// Public view of a work: no run id, and asset URLs go through the entitlement check.
+fn catalog_json(
+    w: &Work,
+    assets: &[WorkAsset],
+    seller_name: Option<&str>,
+    access: Access,
+    with_assets: bool,
+) -> Value {
+    let mut meta = w.meta.clone();
+    if let Some(m) = meta.as_object_mut() {
+        m.remove("run_id");
+        m.remove("source");
+    }
+    let entitled = access.entitled(w);
+    let poster = assets
+        .iter()
+        .find(|a| a.asset_type == "thumbnail" && a.meta["role"] == "poster")
+        .or_else(|| assets.iter().find(|a| a.asset_type == "thumbnail"))
+        .map(|a| catalog_asset_url(w.id, a.id));
+    let mut out = json!({
+        "schema": WORK_SCHEMA,
+        "id": w.id,
+        "kind": w.kind,
+        "title": w.title,
+        "meta": meta,
+        "price_cents": w.price_cents,
+        "currency": w.currency,
+        "seller": { "id": w.user_id, "display_name": seller_name },
+        "poster_url": poster,
+        "owned": access.owned,
+        "purchased": access.purchased,
+        "entitled": entitled,
+        "created_at": w.created_at.to_rfc3339(),
+    });
+    if with_assets {
+        out["assets"] = assets
+            .iter()
+            .map(|a| {
+                json!({
+                    "id": a.id,
+                    "asset_type": a.asset_type,
+                    "url": catalog_asset_url(w.id, a.id),
+                    "locked": !entitled && !asset_public(a),
+                    "role": a.meta.get("role"),
+                    "lang": a.meta.get("lang"),
+                    "mime": a.meta.get("mime"),
+                    "size": a.meta.get("size"),
+                })
+            })
+            .collect();
+    }
+    out
+}
+
+async fn seller_names(
+    pool: &sqlx::PgPool,
+    user_ids: &[Uuid],
+) -> Result<BTreeMap<Uuid, String>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, (Uuid, Option<String>)>(
+        "SELECT id, display_name FROM users WHERE id = ANY($1)",
+    )
+    .bind(user_ids)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(id, name)| Some((id, name?)))
+        .collect())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CatalogQuery {
+    /// Matches anywhere in the title, case-insensitively.
+    pub q: Option<String>,
+    pub kind: Option<String>,
+    /// `newest` (default), `oldest`, `price_asc` or `price_desc`.
+    pub sort: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// Listed works of every user; no session needed. Signed-in viewers see which they own.
+pub async fn catalog(
+    State(state): State<AppState>,
+    AuthSession { user_id }: AuthSession,
+    Query(q): Query<CatalogQuery>,
+) -> Response {
+    let Some(order) = catalog_order(q.sort.as_deref()) else {
+        return error(
+            StatusCode::BAD_REQUEST,
+            "CATALOG_INVALID",
+            "sort must be newest, oldest, price_asc or price_desc",
+        );
+    };
+    let limit = q
+        .limit
+        .unwrap_or(DEFAULT_CATALOG_LIMIT)
+        .clamp(1, MAX_CATALOG_LIMIT);
+    let offset = q.offset.unwrap_or(0).max(0);
+    let pattern =
+        q.q.as_deref()
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(like_pattern);
+    let filter = "is_listed AND ($1::text IS NULL OR title ILIKE $1 ESCAPE '\\')
+                  AND ($2::text IS NULL OR kind = $2)";
+
+    let total = sqlx::query_scalar::<_, i64>(&format!("SELECT count(*) FROM works WHERE {filter}"))
+        .bind(&pattern)
+        .bind(&q.kind)
+        .fetch_one(&state.pool)
+        .await;
+    let total = match total {
+        Ok(n) => n,
+        Err(e) => return db_error(e),
+    };
+    let works = sqlx::query_as::<_, Work>(&format!(
+        "SELECT * FROM works WHERE {filter} ORDER BY {order} LIMIT $3 OFFSET $4"
+    ))
+    .bind(&pattern)
+    .bind(&q.kind)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(&state.pool)
+    .await;
+    let works = match works {
+        Ok(w) => w,
+        Err(e) => return db_error(e),
+    };
+
+    let ids: Vec<Uuid> = works.iter().map(|w| w.id).collect();
+    let sellers: Vec<Uuid> = works.iter().filter_map(|w| w.user_id).collect();
+    let loaded = async {
+        Ok::<_, sqlx::Error>((
+            assets_of(&state.pool, &ids).await?,
+            seller_names(&state.pool, &sellers).await?,
+            purchased_ids(&state.pool, user_id, &ids).await?,
+        ))
+    }
+    .await;
+    let (assets, names, purchased) = match loaded {
+        Ok(x) => x,
+        Err(e) => return db_error(e),
+    };
+    let items: Vec<Value> = works
+        .iter()
+        .map(|w| {
+            let mine: Vec<WorkAsset> = assets
+                .iter()
+                .filter(|a| a.work_id == w.id)
+                .cloned()
+                .collect();
+            let access = Access {
+                owned: user_id.is_some() && user_id == w.user_id,
+                purchased: purchased.contains(&w.id),
+            };
+            catalog_json(
+                w,
+                &mine,
+                w.user_id.and_then(|id| names.get(&id)).map(String::as_str),
+                access,
+                false,
+            )
+        })
+        .collect();
+    let next_offset = (offset + limit < total).then_some(offset + limit);
+    (
+        StatusCode::OK,
+        Json(json!({
+            "schema": CATALOG_SCHEMA,
+            "q": q.q,
+            "kind": q.kind,
+            "sort": q.sort.as_deref().unwrap_or("newest"),
+            "limit": limit,
+            "offset": offset,
+            "total": total,
+            "next_offset": next_offset,
+            "items": items,
+        })),
+    )
+        .into_response()
+}
+
+/// A listed work, or one the caller owns or bought, with the viewer's access to it.
+async fn visible_work(
+    state: &AppState,
+    user_id: Option<Uuid>,
+    work_id: &str,
+) -> Result<(Work, Access), Response> {
+    let Ok(id) = Uuid::parse_str(work_id) else {
+        return Err(work_not_found(work_id));
+    };
+    let work = sqlx::query_as::<_, Work>(
+        "SELECT * FROM works w WHERE id = $1 AND (is_listed OR user_id = $2 OR EXISTS (
+             SELECT 1 FROM work_purchases p WHERE p.work_id = w.id AND p.buyer_id = $2))",
+    )
+    .bind(id)
+    .bind(user_id)
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(db_error)?
+    .ok_or_else(|| work_not_found(work_id))?;
+    let purchased = purchased_ids(&state.pool, user_id, &[work.id])
+        .await
+        .map_err(db_error)?;
+    let access = Access {
+        owned: user_id.is_some() && user_id == work.user_id,
+        purchased: !purchased.is_empty(),
+    };
+    Ok((work, access))
+}
+
+pub async fn catalog_work(
+    State(state): State<AppState>,
+    Path(work_id): Path<String>,
+    AuthSession { user_id }: AuthSession,
+) -> Response {
+    let (work, access) = match visible_work(&state, user_id, &work_id).await {
+        Ok(x) => x,
+        Err(resp) => return resp,
+    };
+    let loaded = async {
+        Ok::<_, sqlx::Error>((
+            assets_of(&state.pool, &[work.id]).await?,
+            seller_names(&state.pool, work.user_id.as_slice()).await?,
+        ))
+    }
+    .await;
+    match loaded {
+        Ok((assets, names)) => {
+            let name = work.user_id.and_then(|id| names.get(&id)).map(String::as_str);
+            (
+                StatusCode::OK,
+                Json(catalog_json(&work, &assets, name, access, true)),
+            )
+                .into_response()
+        }
+        Err(e) => db_error(e),
+    }
+}
+
+/// Redirects to a short-lived signed link of the asset's run artifact once the viewer is
+/// entitled to it.
+pub async fn catalog_asset(
+    State(state): State<AppState>,
+    Path((work_id, asset_id)): Path<(String, String)>,
+    AuthSession { user_id }: AuthSession,
+) -> Response {
+    let (work, access) = match visible_work(&state, user_id, &work_id).await {
+        Ok(x) => x,
+        Err(resp) => return resp,
+    };
+    let asset_not_found = || {
+        error(
+            StatusCode::NOT_FOUND,
+            "WORK_ASSET_NOT_FOUND",
+            format!("work {work_id} has no asset {asset_id}"),
+        )
+    };
+    let Ok(aid) = Uuid::parse_str(&asset_id) else {
+        return asset_not_found();
+    };
+    let asset =
+        sqlx::query_as::<_, WorkAsset>("SELECT * FROM work_assets WHERE id = $1 AND work_id = $2")
+            .bind(aid)
+            .bind(work.id)
+            .fetch_optional(&state.pool)
+            .await;
+    let asset = match asset {
+        Ok(Some(a)) => a,
+        Ok(None) => return asset_not_found(),
+        Err(e) => return db_error(e),
+    };
+    if !asset_public(&asset) && !access.entitled(&work) {
+        return error(
+            if user_id.is_some() {
+                StatusCode::FORBIDDEN
+            } else {
+                StatusCode::UNAUTHORIZED
+            },
+            "WORK_PURCHASE_REQUIRED",
+            format!("purchase work {work_id} to access this asset"),
+        );
+    }
+    let (Some(run_id), Some(key)) = (
+        work.meta.get("run_id").and_then(|v| v.as_str()),
+        asset.meta.get("artifact_key").and_then(|v| v.as_str()),
+    ) else {
+        return asset_not_found();
+    };
+    let Some(secret) = state.config.artifact_signing_key.as_deref() else {
+        return error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "ARTIFACT_SHARING_DISABLED",
+            "ARTIFACT_SIGNING_KEY is not configured",
+        );
+    };
+    let expires = chrono::Utc::now().timestamp() + WORK_ASSET_LINK_TTL_S;
+    (
+        [(header::CACHE_CONTROL, "private, no-store")],
+        Redirect::temporary(&signed_path(secret.as_bytes(), run_id, key, expires)),
+    )
+        .into_response()
+}
+
+pub async fn purchase(
+    State(state): State<AppState>,
+    Path(work_id): Path<String>,
+    AuthSession { user_id }: AuthSession,
+) -> Response {
+    let Some(user_id) = user_id else {
+        return error(
+            StatusCode::UNAUTHORIZED,
+            "AUTH_REQUIRED",
+            "sign in to buy works",
+        );
+    };
+    let Ok(id) = Uuid::parse_str(&work_id) else {
+        return work_not_found(&work_id);
+    };
+    let result = purchase_work(
+        &state.pool,
+        user_id,
+        id,
+        state.config.platform_fee_bps,
+        state.config.platform_fee_user_id,
+    )
+    .await;
+    let receipt = match result {
+        Ok(r) => r,
+        Err(e) => {
+            let (status, code) = match &e {
+                PurchaseError::NotFound | PurchaseError::NotListed => {
+                    return work_not_found(&work_id)
+                }
+                PurchaseError::OwnWork => (StatusCode::CONFLICT, "WORK_OWNED"),
+                PurchaseError::AlreadyPurchased => (StatusCode::CONFLICT, "WORK_ALREADY_PURCHASED"),
+                PurchaseError::CurrencyMismatch { .. } => {
+                    (StatusCode::CONFLICT, "CURRENCY_MISMATCH")
+                }
+                PurchaseError::InsufficientBalance { .. } => {
+                    (StatusCode::PAYMENT_REQUIRED, "INSUFFICIENT_BALANCE")
+                }
+                PurchaseError::MonthlyLimit { .. } => {
+                    (StatusCode::PAYMENT_REQUIRED, "MONTHLY_LIMIT")
+                }
+                PurchaseError::Db(_) => (StatusCode::INTERNAL_SERVER_ERROR, "WORK_DB_FAILED"),
+            };
+            return error(status, code, e.to_string());
+        }
+    };
+    let p = &receipt.purchase;
+    (
+        StatusCode::CREATED,
+        Json(json!({
+            "schema": PURCHASE_SCHEMA,
+            "id": p.id,
+            "work_id": p.work_id,
+            "price_cents": p.price_cents,
+            "fee_cents": p.fee_cents,
+            "currency": p.currency,
+            "balance_cents": receipt.balance_cents,
+            "created_at": p.created_at.to_rfc3339(),
+        })),
+    )
+        .into_response()
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/cssapi/v1/runs/:run_id/publish", post(publish_run))
+        .route("/cssapi/v1/works", get(list_works))
+        .route(
+            "/cssapi/v1/works/:work_id",
+            get(get_work).patch(update_work).delete(delete_work),
+        )
+        .route("/api/works", get(catalog))
+        .route("/api/works/:work_id", get(catalog_work))
+        .route("/api/works/:work_id/purchase", post(purchase))
+        .route("/api/works/:work_id/assets/:asset_id", get(catalog_asset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn file(key: &str, path: &str, kind: ArtifactKind) -> Value {
+        json!({
+            "key": key,
+            "kind": kind,
+            "path": path,
+            "mime": crate::artifacts::kind_and_mime(&PathBuf::from(path)).1,
+            "size": 10,
+            "sha256": "ab",
+            "created_at": "2026-10-19T00:00:00Z",
+        })
+    }
+
+    #[test]
+    fn assets_come_from_run_artifacts() {
+        let reg = ArtifactRegistry::from_value(&json!([
+            file("video.final_mv", "build/final_mv.mp4", ArtifactKind::Video),
+            file("build/video/video.mp4", "build/video/video.mp4", ArtifactKind::Video),
+            file("package.hls_master", "build/package/hls/master.m3u8", ArtifactKind::Playlist),
+            file("build/thumbnails/shot_000.jpg", "build/thumbnails/shot_000.jpg", ArtifactKind::Image),
+            file("build/thumbnails/poster.jpg", "build/thumbnails/poster.jpg", ArtifactKind::Image),
+            {"key": "video.thumbnails", "kind": "value", "created_at": "",
+             "value": {"poster": {"kind": "poster", "path": "build/thumbnails/poster.jpg"},
+                       "shots": [{"kind": "shot_poster", "shot_id": "s0",
+                                  "path": "build/thumbnails/shot_000.jpg"}]}},
+            file("build/subtitles/en.vtt", "build/subtitles/en.vtt", ArtifactKind::Subtitles),
+            file("build/subtitles.ass", "build/subtitles.ass", ArtifactKind::Subtitles),
+            file("music.wav", "build/music.wav", ArtifactKind::Audio),
+            file("build/stems/bass.wav", "build/stems/bass.wav", ArtifactKind::Audio),
+            file("stems.zip", "build/stems.zip", ArtifactKind::Archive),
+        ]));
+        let got = work_assets_from_run("r1", &reg);
+        let summary: Vec<(&str, String, Value)> = got
+            .iter()
+            .map(|d| (d.asset_type, d.url.clone(), d.meta["role"].clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    "video",
+                    "/cssapi/v1/runs/r1/artifacts/video.final_mv".into(),
+                    json!("final")
+                ),
+                (
+                    "stream",
+                    "/cssapi/v1/runs/r1/artifacts/package.hls_master".into(),
+                    json!("hls")
+                ),
+                (
+                    "thumbnail",
+                    "/cssapi/v1/runs/r1/artifacts/build/thumbnails/poster.jpg".into(),
+                    json!("poster")
+                ),
+                (
+                    "thumbnail",
+                    "/cssapi/v1/runs/r1/artifacts/build/thumbnails/shot_000.jpg".into(),
+                    json!("shot_poster")
+                ),
+                (
+                    "subtitles",
+                    "/cssapi/v1/runs/r1/artifacts/build/subtitles/en.vtt".into(),
+                    Value::Null
+                ),
+                (
+                    "audio",
+                    "/cssapi/v1/runs/r1/artifacts/music.wav".into(),
+                    json!("music")
+                ),
+                (
+                    "audio",
+                    "/cssapi/v1/runs/r1/artifacts/stems.zip".into(),
+                    json!("stems")
+                ),
+            ]
+        );
+        assert_eq!(got[4].meta["lang"], "en");
+        assert_eq!(got[4].meta["format"], "vtt");
+        assert_eq!(got[3].meta["shot_id"], "s0");
+        assert_eq!(got[0].meta["sha256"], "ab");
+    }
+
+    #[test]
+    fn validation_and_meta_merge() {
+        assert!(check_title("  ").is_err());
+        assert!(check_title(&"x".repeat(201)).is_err());
+        assert!(check_price(-1).is_err() && check_price(0).is_ok());
+        assert!(check_currency("usd").is_err() && check_currency("EUR").is_ok());
+        assert!(check_kind("opera").is_ok() && check_kind("album").is_err());
+
+        let mut meta = json!({"run_id": "r1", "source": "run", "genre": "lofi"});
+        let patch = json!({"run_id": "other", "genre": null, "mood": "calm"});
+        merge_meta(&mut meta, patch.as_object().unwrap());
+        assert_eq!(
+            meta,
+            json!({"run_id": "r1", "source": "run", "mood": "calm"})
+        );
+    }
+
+    #[test]
+    fn catalog_search_and_sort() {
+        assert_eq!(like_pattern("lo-fi"), "%lo-fi%");
+        assert_eq!(like_pattern("100%_a\\b"), "%100\\%\\_a\\\\b%");
+        assert_eq!(catalog_order(None), Some("created_at DESC, id"));
+        assert!(catalog_order(Some("price_asc")).is_some());
+        assert_eq!(catalog_order(Some("title; DROP TABLE works")), None);
+    }
+}